@@ -112,6 +112,12 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
                 operand: r0,
                 arg0: r1,
             }),
+            5 => Some(kernel::syscall::Syscall::ALLOW_READONLY {
+                driver_number: r0,
+                subdriver_number: r1,
+                allow_address: r2 as *const u8,
+                allow_size: r3,
+            }),
             _ => None,
         }
     }
@@ -386,6 +392,21 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
         }
     }
 
+    unsafe fn fault_registers(
+        &self,
+        stack_pointer: *const usize,
+        _state: &CortexMStoredState,
+    ) -> kernel::debug::FaultRegisters {
+        kernel::debug::FaultRegisters {
+            pc: read_volatile(stack_pointer.offset(6)) as u32,
+            lr: read_volatile(stack_pointer.offset(5)) as u32,
+            cfsr: SCB_REGISTERS[1],
+            hfsr: SCB_REGISTERS[2],
+            mmfar: SCB_REGISTERS[3],
+            bfar: SCB_REGISTERS[4],
+        }
+    }
+
     unsafe fn process_detail_fmt(
         &self,
         stack_pointer: *const usize,
@@ -137,6 +137,18 @@ impl kernel::SysTick for SysTick {
         SYSTICK_BASE.syst_csr.is_set(ControlAndStatus::COUNTFLAG)
     }
 
+    fn get_value(&self) -> u32 {
+        let hertz = self.hertz() as u64;
+        if hertz == 0 {
+            return 0;
+        }
+
+        // Convert from native tics back to microseconds. As in `set_timer`, use
+        // 64-bit arithmetic to avoid overflowing.
+        let tics = SYSTICK_BASE.syst_cvr.read(CurrentValue::CURRENT) as u64;
+        (tics * 1_000_000 / hertz) as u32
+    }
+
     fn reset(&self) {
         SYSTICK_BASE.syst_csr.set(0);
         SYSTICK_BASE.syst_rvr.set(0);
@@ -235,6 +235,15 @@ unsafe fn kernel_hardfault(faulting_stack: *mut u32) {
     let vecttbl = (hfsr & 0x02) == 0x02;
     let forced = (hfsr & 0x40000000) == 0x40000000;
 
+    kernel::debug::set_kernel_fault(kernel::debug::FaultRegisters {
+        pc: stacked_pc,
+        lr: stacked_lr,
+        cfsr: cfsr,
+        hfsr: hfsr,
+        mmfar: mmfar,
+        bfar: bfar,
+    });
+
     let ici_it = (((stacked_xpsr >> 25) & 0x3) << 6) | ((stacked_xpsr >> 10) & 0x3f);
     let thumb_bit = ((stacked_xpsr >> 24) & 0x1) == 1;
     let exception_number = (stacked_xpsr & 0x1ff) as usize;
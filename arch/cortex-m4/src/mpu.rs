@@ -136,6 +136,10 @@ pub struct CortexMConfig {
 
 const APP_MEMORY_REGION_NUM: usize = 0;
 
+/// The guard region has the highest region number so that it takes precedence
+/// over the app memory region it overlaps.
+const GUARD_REGION_NUM: usize = 7;
+
 impl Default for CortexMConfig {
     fn default() -> CortexMConfig {
         CortexMConfig {
@@ -156,7 +160,7 @@ impl Default for CortexMConfig {
 impl CortexMConfig {
     fn unused_region_number(&self) -> Option<usize> {
         for (number, region) in self.regions.iter().enumerate() {
-            if number == APP_MEMORY_REGION_NUM {
+            if number == APP_MEMORY_REGION_NUM || number == GUARD_REGION_NUM {
                 continue;
             }
             if let None = region.location() {
@@ -239,6 +243,27 @@ impl CortexMRegion {
         }
     }
 
+    /// A region that only privileged code can access. `start` must be aligned
+    /// to `size`, which must be a power of two of at least 32 bytes.
+    fn guard(start: *const u8, size: usize, region_num: usize) -> CortexMRegion {
+        let base_address = RegionBaseAddress::ADDR.val((start as u32) >> 5)
+            + RegionBaseAddress::VALID::UseRBAR
+            + RegionBaseAddress::REGION.val(region_num as u32);
+
+        let size_value = math::log_base_two(size as u32) - 1;
+
+        let attributes = RegionAttributes::ENABLE::SET
+            + RegionAttributes::SIZE.val(size_value)
+            + RegionAttributes::AP::PrivilegedOnly
+            + RegionAttributes::XN::Disable;
+
+        CortexMRegion {
+            location: Some((start, size)),
+            base_address: base_address,
+            attributes: attributes,
+        }
+    }
+
     fn empty(region_num: usize) -> CortexMRegion {
         CortexMRegion {
             location: None,
@@ -599,6 +624,35 @@ impl kernel::mpu::MPU for MPU {
         Ok(())
     }
 
+    fn allocate_guard_region(
+        &self,
+        guard_memory_start: *const u8,
+        guard_memory_size: usize,
+        min_guard_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<mpu::Region> {
+        // Regions must be a power of two of at least 32 bytes, and aligned to
+        // their size.
+        let size = cmp::max(math::closest_power_of_two(min_guard_size as u32) as usize, 32);
+        let mut start = guard_memory_start as usize;
+        if start % size != 0 {
+            start += size - (start % size);
+        }
+
+        if start + size > (guard_memory_start as usize) + guard_memory_size {
+            return None;
+        }
+
+        config.regions[GUARD_REGION_NUM] =
+            CortexMRegion::guard(start as *const u8, size, GUARD_REGION_NUM);
+
+        Some(mpu::Region::new(start as *const u8, size))
+    }
+
+    fn remove_guard_region(&self, config: &mut Self::MpuConfig) {
+        config.regions[GUARD_REGION_NUM] = CortexMRegion::empty(GUARD_REGION_NUM);
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig) {
         let regs = &*self.0;
 
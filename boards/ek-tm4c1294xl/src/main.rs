@@ -14,6 +14,7 @@ extern crate tm4c129x;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_uart::{UartDevice, UartMux};
 use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
 use kernel::hil;
 use kernel::hil::Controller;
 use kernel::Chip;
@@ -49,7 +50,7 @@ struct EkTm4c1294xl {
     console: &'static capsules::console::Console<'static, UartDevice<'static>>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
-        VirtualMuxAlarm<'static, tm4c129x::gpt::AlarmTimer>,
+        ExtendedAlarm<'static, VirtualMuxAlarm<'static, tm4c129x::gpt::AlarmTimer>>,
     >,
     gpio: &'static capsules::gpio::GPIO<'static, tm4c129x::gpio::GPIOPin>,
     ipc: kernel::ipc::IPC,
@@ -92,15 +93,31 @@ pub unsafe fn reset_handler() {
     let main_loop_capability = create_capability!(capabilities::MainLoopCapability);
     let memory_allocation_capability = create_capability!(capabilities::MemoryAllocationCapability);
 
+    // Deferred calls for capsules, which the UART mux uses to start
+    // transmissions.
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 1], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    board_kernel.set_dynamic_deferred_call(dynamic_deferred_caller, &main_loop_capability);
+
     // Create a shared UART channel for the console and for kernel debug.
     let uart_mux = static_init!(
         UartMux<'static>,
         UartMux::new(
             &tm4c129x::uart::UART0,
             &mut capsules::virtual_uart::RX_BUF,
-            115200
+            115200,
+            dynamic_deferred_caller
         )
     );
+    uart_mux.initialize_callback_handle(
+        dynamic_deferred_caller
+            .register(uart_mux)
+            .expect("no deferred call slot available for uart mux"),
+    );
     hil::uart::UART::set_client(&tm4c129x::uart::UART0, uart_mux);
 
     // Create a UartDevice for the console.
@@ -150,14 +167,23 @@ pub unsafe fn reset_handler() {
         VirtualMuxAlarm<'static, tm4c129x::gpt::AlarmTimer>,
         VirtualMuxAlarm::new(mux_alarm)
     );
+    let extended_alarm = static_init!(
+        ExtendedAlarm<'static, VirtualMuxAlarm<'static, tm4c129x::gpt::AlarmTimer>>,
+        ExtendedAlarm::new(virtual_alarm1, 32)
+    );
     let alarm = static_init!(
-        capsules::alarm::AlarmDriver<'static, VirtualMuxAlarm<'static, tm4c129x::gpt::AlarmTimer>>,
+        capsules::alarm::AlarmDriver<
+            'static,
+            ExtendedAlarm<'static, VirtualMuxAlarm<'static, tm4c129x::gpt::AlarmTimer>>,
+        >,
         capsules::alarm::AlarmDriver::new(
-            virtual_alarm1,
+            extended_alarm,
             board_kernel.create_grant(&memory_allocation_capability)
         )
     );
-    virtual_alarm1.set_client(alarm);
+    virtual_alarm1.set_client(extended_alarm);
+    extended_alarm.set_client(alarm);
+    extended_alarm.start();
 
     // LEDs
     let led_pins = static_init!(
@@ -263,5 +289,13 @@ pub unsafe fn reset_handler() {
         FAULT_RESPONSE,
         &process_management_capability,
     );
-    board_kernel.kernel_loop(&tm4c1294, chip, Some(&tm4c1294.ipc), &main_loop_capability);
+    let scheduler =
+        kernel::scheduler::RoundRobinScheduler::new(kernel::scheduler::DEFAULT_TIMESLICE_US);
+    board_kernel.kernel_loop(
+        &tm4c1294,
+        chip,
+        Some(&tm4c1294.ipc),
+        &scheduler,
+        &main_loop_capability,
+    );
 }
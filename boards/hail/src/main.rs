@@ -10,16 +10,20 @@
 
 extern crate capsules;
 #[allow(unused_imports)]
-#[macro_use(create_capability, debug, debug_gpio, static_init)]
+#[macro_use(create_capability, debug, debug_gpio, static_init, storage_volume)]
 extern crate kernel;
 extern crate cortexm4;
 extern crate sam4l;
 
+use core::cell::Cell;
+
+use capsules::extended_alarm::ExtendedAlarm;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_i2c::{I2CDevice, MuxI2C};
 use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
 use capsules::virtual_uart::{UartDevice, UartMux};
 use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
 use kernel::hil;
 use kernel::hil::entropy::Entropy32;
 use kernel::hil::rng::Rng;
@@ -69,7 +73,7 @@ struct Hail {
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
-        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        ExtendedAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     >,
     ambient_light: &'static capsules::ambient_light::AmbientLight<'static>,
     temp: &'static capsules::temperature::TemperatureSensor<'static>,
@@ -87,6 +91,11 @@ struct Hail {
     ipc: kernel::ipc::IPC,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     dac: &'static capsules::dac::Dac<'static>,
+    crash_log: &'static capsules::crash_log::CrashLog<
+        'static,
+        sam4l::flashcalw::FLASHCALW,
+        sam4l::chip::Sam4l,
+    >,
 }
 
 /// Mapping of integer syscalls to objects that implement syscalls.
@@ -116,6 +125,8 @@ impl Platform for Hail {
 
             capsules::dac::DRIVER_NUM => f(Some(self.dac)),
 
+            capsules::crash_log::DRIVER_NUM => f(Some(self.crash_log)),
+
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -225,15 +236,31 @@ pub unsafe fn reset_handler() {
     // Initialize USART0 for Uart
     sam4l::usart::USART0.set_mode(sam4l::usart::UsartMode::Uart);
 
+    // Deferred calls for capsules, which the UART mux uses to start
+    // transmissions.
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 1], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    board_kernel.set_dynamic_deferred_call(dynamic_deferred_caller, &main_loop_capability);
+
     // Create a shared UART channel for the console and for kernel debug.
     let uart_mux = static_init!(
         UartMux<'static>,
         UartMux::new(
             &sam4l::usart::USART0,
             &mut capsules::virtual_uart::RX_BUF,
-            115200
+            115200,
+            dynamic_deferred_caller
         )
     );
+    uart_mux.initialize_callback_handle(
+        dynamic_deferred_caller
+            .register(uart_mux)
+            .expect("no deferred call slot available for uart mux"),
+    );
     hil::uart::UART::set_client(&sam4l::usart::USART0, uart_mux);
 
     // Create a UartDevice for the console.
@@ -346,14 +373,23 @@ pub unsafe fn reset_handler() {
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
         VirtualMuxAlarm::new(mux_alarm)
     );
+    let extended_alarm = static_init!(
+        ExtendedAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        ExtendedAlarm::new(virtual_alarm1, 32)
+    );
     let alarm = static_init!(
-        capsules::alarm::AlarmDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::alarm::AlarmDriver<
+            'static,
+            ExtendedAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        >,
         capsules::alarm::AlarmDriver::new(
-            virtual_alarm1,
+            extended_alarm,
             board_kernel.create_grant(&memory_allocation_capability)
         )
     );
-    virtual_alarm1.set_client(alarm);
+    virtual_alarm1.set_client(extended_alarm);
+    extended_alarm.set_client(alarm);
+    extended_alarm.start();
 
     // FXOS8700CQ accelerometer, device address 0x1e
     let fxos8700_i2c = static_init!(I2CDevice, I2CDevice::new(sensors_i2c, 0x1e));
@@ -518,14 +554,62 @@ pub unsafe fn reset_handler() {
         capsules::dac::Dac::new(&mut sam4l::dac::DAC)
     );
 
+    // Keep a record of the last kernel crash in a flash page in the kernel's
+    // storage region. The volume is 1 kB, so it holds a whole 512 byte page
+    // even if the linker does not place it on a page boundary.
+    storage_volume!(CRASH_LOG_VOLUME, 1);
+    pub static mut CRASH_LOG_PAGE: sam4l::flashcalw::Sam4lPage =
+        sam4l::flashcalw::Sam4lPage::new();
+    let crash_log_page_number = (&CRASH_LOG_VOLUME as *const [u8; 1024] as usize + 511) / 512;
+    sam4l::flashcalw::FLASH_CONTROLLER.configure();
+    let crash_log = static_init!(
+        capsules::crash_log::CrashLog<'static, sam4l::flashcalw::FLASHCALW, sam4l::chip::Sam4l>,
+        capsules::crash_log::CrashLog::new(
+            &sam4l::flashcalw::FLASH_CONTROLLER,
+            chip,
+            crash_log_page_number,
+            &mut CRASH_LOG_PAGE,
+            board_kernel.create_grant(&memory_allocation_capability)
+        )
+    );
+    hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, crash_log);
+    kernel::debug::set_crash_handler(crash_log);
+
+    // Restart apps that fault and ask for `RestartWithBackoff` in their TBF
+    // header after their delay.
+    //
+    // Create a dummy object that provides the `ProcessManagementCapability` to
+    // the capsules that restart processes.
+    struct ProcessMgmtCap;
+    unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+    let restart_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let restarts = static_init!(
+        [Cell<Option<capsules::process_restart_timer::PendingRestart>>; NUM_PROCS],
+        Default::default()
+    );
+    let restart_timer = static_init!(
+        capsules::process_restart_timer::ProcessRestartTimer<
+            'static,
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            ProcessMgmtCap,
+        >,
+        capsules::process_restart_timer::ProcessRestartTimer::new(
+            board_kernel,
+            restart_alarm,
+            restarts,
+            ProcessMgmtCap
+        )
+    );
+    restart_alarm.set_client(restart_timer);
+    board_kernel.set_restart_timer(restart_timer, &process_management_capability);
+
     // // DEBUG Restart All Apps
     // //
     // // Uncomment to enable a button press to restart all apps.
     // //
-    // // Create a dummy object that provides the `ProcessManagementCapability` to
-    // // the `debug_process_restart` capsule.
-    // struct ProcessMgmtCap;
-    // unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
     // let debug_process_restart = static_init!(
     //     capsules::debug_process_restart::DebugProcessRestart<
     //         'static,
@@ -557,9 +641,11 @@ pub unsafe fn reset_handler() {
         ipc: kernel::ipc::IPC::new(board_kernel, &memory_allocation_capability),
         crc: crc,
         dac: dac,
+        crash_log: crash_log,
     };
 
     hail.console.initialize();
+    hail.crash_log.load();
 
     // Create virtual device for kernel debug.
     let debugger_uart = static_init!(UartDevice, UartDevice::new(uart_mux, false));
@@ -596,6 +682,16 @@ pub unsafe fn reset_handler() {
         static _sapps: u8;
     }
 
+    // Only start apps whose SHA-256 credentials match. This catches apps that
+    // were damaged or changed after they were built, but not apps from an
+    // untrusted source, as anyone can hash an app. `tools/tbf_sha256.py` adds
+    // the credentials to an app.
+    let app_verifier = static_init!(
+        capsules::sha256_verifier::Sha256Verifier,
+        capsules::sha256_verifier::Sha256Verifier::new()
+    );
+    board_kernel.set_app_verifier(app_verifier, &process_management_capability);
+
     kernel::procs::load_processes(
         board_kernel,
         &cortexm4::syscall::SysCall::new(),
@@ -606,5 +702,13 @@ pub unsafe fn reset_handler() {
         FAULT_RESPONSE,
         &process_management_capability,
     );
-    board_kernel.kernel_loop(&hail, chip, Some(&hail.ipc), &main_loop_capability);
+    let scheduler =
+        kernel::scheduler::RoundRobinScheduler::new(kernel::scheduler::DEFAULT_TIMESLICE_US);
+    board_kernel.kernel_loop(
+        &hail,
+        chip,
+        Some(&hail.ipc),
+        &scheduler,
+        &main_loop_capability,
+    );
 }
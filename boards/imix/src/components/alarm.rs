@@ -15,6 +15,7 @@
 #![allow(dead_code)] // Components are intended to be conditionally included
 
 use capsules::alarm::AlarmDriver;
+use capsules::extended_alarm::ExtendedAlarm;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use kernel;
 use kernel::capabilities;
@@ -39,7 +40,10 @@ impl AlarmDriverComponent {
 }
 
 impl Component for AlarmDriverComponent {
-    type Output = &'static AlarmDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+    type Output = &'static AlarmDriver<
+        'static,
+        ExtendedAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    >;
 
     unsafe fn finalize(&mut self) -> Self::Output {
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
@@ -48,12 +52,18 @@ impl Component for AlarmDriverComponent {
             VirtualMuxAlarm<'static, sam4l::ast::Ast>,
             VirtualMuxAlarm::new(self.alarm_mux)
         );
+        let extended_alarm = static_init!(
+            ExtendedAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            ExtendedAlarm::new(virtual_alarm1, 32)
+        );
         let alarm = static_init!(
-            AlarmDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
-            AlarmDriver::new(virtual_alarm1, self.board_kernel.create_grant(&grant_cap))
+            AlarmDriver<'static, ExtendedAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>>,
+            AlarmDriver::new(extended_alarm, self.board_kernel.create_grant(&grant_cap))
         );
 
-        virtual_alarm1.set_client(alarm);
+        virtual_alarm1.set_client(extended_alarm);
+        extended_alarm.set_client(alarm);
+        extended_alarm.start();
         alarm
     }
 }
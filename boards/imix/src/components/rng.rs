@@ -2,12 +2,13 @@
 //!
 //! This provides one Component, RngComponent, which implements a
 //! userspace syscall interface to the RNG peripheral (TRNG) on the
-//! SAM4L.
+//! SAM4L, through a device of an RNG mux that shares the TRNG with the
+//! kernel.
 //!
 //! Usage
 //! -----
 //! ```rust
-//! let rng = RngComponent::new(board_kernel).finalize();
+//! let rng = RngComponent::new(board_kernel, rng_mux).finalize();
 //! ```
 
 // Author: Hudson Ayers <hayers@cs.stanford.edu>
@@ -16,21 +17,25 @@
 #![allow(dead_code)] // Components are intended to be conditionally included
 
 use capsules::rng;
+use capsules::virtual_rng::{MuxRng, VirtualRngDevice};
 use kernel;
 use kernel::capabilities;
 use kernel::component::Component;
-use kernel::hil::entropy::Entropy32;
 use kernel::hil::rng::Rng;
-use sam4l;
 
 pub struct RngComponent {
     board_kernel: &'static kernel::Kernel,
+    rng_mux: &'static MuxRng<'static>,
 }
 
 impl RngComponent {
-    pub fn new(board_kernel: &'static kernel::Kernel) -> RngComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        rng_mux: &'static MuxRng<'static>,
+    ) -> RngComponent {
         RngComponent {
             board_kernel: board_kernel,
+            rng_mux: rng_mux,
         }
     }
 }
@@ -41,19 +46,15 @@ impl Component for RngComponent {
     unsafe fn finalize(&mut self) -> Self::Output {
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
 
-        let entropy_to_random = static_init!(
-            rng::Entropy32ToRandom<'static>,
-            rng::Entropy32ToRandom::new(&sam4l::trng::TRNG)
+        let rng_device = static_init!(
+            VirtualRngDevice<'static>,
+            VirtualRngDevice::new(self.rng_mux)
         );
         let rng = static_init!(
             rng::RngDriver<'static>,
-            rng::RngDriver::new(
-                entropy_to_random,
-                self.board_kernel.create_grant(&grant_cap)
-            )
+            rng::RngDriver::new(rng_device, self.board_kernel.create_grant(&grant_cap))
         );
-        sam4l::trng::TRNG.set_client(entropy_to_random);
-        entropy_to_random.set_client(rng);
+        rng_device.set_client(rng);
 
         rng
     }
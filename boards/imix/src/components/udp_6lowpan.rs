@@ -1,16 +1,27 @@
 //! Component to initialize the udp/6lowpan interface on imix board.
 //!
-//! This provides one Component, UDPComponent, which implements a
-//! userspace syscall interface to a full udp stack on top of 6lowpan
+//! This provides one Component, UDPComponent, which implements
+//! userspace syscall interfaces to full udp and tcp stacks on top of 6lowpan.
+//! Processes bind ports through the socket table of a UDP mux (`MuxUDP`).
+//! The component also sets up ICMPv6, which answers echo requests, and
+//! 6LoWPAN neighbor discovery (`SixlowpanND`), which finds a router, fills
+//! the 6LoWPAN context table and writes the addresses of the node to the
+//! interface list. TCP takes the secret for its initial sequence numbers from
+//! a device of the RNG mux.
 //!
 //! Usage
 //! -----
 //! ```rust
-//! let udp_driver = UDPComponent::new(mux_mac,
-//!                                    DEFAULT_CTX_PREFIX_LEN,
-//!                                    DEFAULT_CTX_PREFIX,
-//!                                    DST_MAC_ADDR,
-//!                                    &LOCAL_IP_IFACES).finalize();
+//! let (udp_driver, tcp_driver) = UDPComponent::new(board_kernel,
+//!                                                  mux_mac,
+//!                                                  DEFAULT_CTX_PREFIX_LEN,
+//!                                                  DEFAULT_CTX_PREFIX,
+//!                                                  DST_MAC_ADDR,
+//!                                                  SRC_MAC_ADDR,
+//!                                                  EUI64,
+//!                                                  local_ip_ifaces,
+//!                                                  mux_alarm,
+//!                                                  rng_mux).finalize();
 //! ```
 
 // Author: Hudson Ayers <hayers@stanford.edu>
@@ -20,35 +31,64 @@
 
 use capsules;
 use capsules::ieee802154::device::MacDevice;
+use capsules::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::icmpv6::icmpv6_recv::{ICMP6RecvStruct, ICMP6RecvSubscriber};
+use capsules::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
 use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
 use capsules::net::ipv6::ipv6_recv::IP6Receiver;
-use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::sixlowpan::sixlowpan_nd::SixlowpanND;
 use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::net::tcp::tcp::TCPHeader;
 use capsules::net::udp::udp::UDPHeader;
+use capsules::net::udp::udp_mux::{MuxUDP, UDPSocket};
 use capsules::net::udp::udp_recv::UDPReceiver;
 use capsules::net::udp::udp_send::{UDPSendStruct, UDPSender};
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_rng::{MuxRng, VirtualRngDevice};
 
+use core::cell::Cell;
 use kernel;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::hil::radio;
+use kernel::hil::rng::Rng;
 use sam4l;
 
 const PAYLOAD_LEN: usize = 200; //The max size UDP message that can be sent by userland apps
+const NUM_APP_SOCKETS: usize = 4; //The max number of userland apps that can bind a port
+const SOCKET_QUEUE_LEN: usize = 400; //Enough for a few packets per socket
+const ICMP_PAYLOAD_LEN: usize = 100; //The max size of echo request data
+const ND_PAYLOAD_LEN: usize = 48; //The max size of ND messages after their ICMPv6 header
+const TCP_SEGMENT_LEN: usize = 192; //The max size of the data in a TCP segment
 
 // The UDP stack requires several packet buffers:
 //
 //   1. RF233_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
 //   2. SIXLOWPAN_RX_BUF: Buffer to hold full IP packets after they are decompressed by 6LoWPAN
 //   3. UDP_DGRAM: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd
+//   4. SOCKET_QUEUES: The receive queues of the sockets used by userland apps
+//   5. ICMP_RF233_BUF, ICMP_DGRAM: The same as 1. and 3., for the IP6_Sender used by ICMPv6
+//   6. TCP_RF233_BUF, TCP_DGRAM: The same as 1. and 3., for the IP6_Sender used by TCP
+//   7. TCP_SEGMENT_BUF: Holds the data of a TCP segment while it is handed to the IP6_Sender
+//   8. ND_RF233_BUF, ND_DGRAM: The same as 1. and 3., for the IP6_Sender used by neighbor
+//      discovery
 
 const UDP_HDR_SIZE: usize = 8;
 static mut RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
 static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
 static mut UDP_DGRAM: [u8; PAYLOAD_LEN - UDP_HDR_SIZE] = [0; PAYLOAD_LEN - UDP_HDR_SIZE];
+static mut SOCKET_QUEUES: [[u8; SOCKET_QUEUE_LEN]; NUM_APP_SOCKETS] =
+    [[0; SOCKET_QUEUE_LEN]; NUM_APP_SOCKETS];
+static mut ICMP_RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut ICMP_DGRAM: [u8; ICMP_PAYLOAD_LEN] = [0; ICMP_PAYLOAD_LEN];
+static mut TCP_RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut TCP_DGRAM: [u8; TCP_SEGMENT_LEN] = [0; TCP_SEGMENT_LEN];
+static mut TCP_SEGMENT_BUF: [u8; TCP_SEGMENT_LEN] = [0; TCP_SEGMENT_LEN];
+static mut ND_RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut ND_DGRAM: [u8; ND_PAYLOAD_LEN] = [0; ND_PAYLOAD_LEN];
 
 pub struct UDPComponent {
     board_kernel: &'static kernel::Kernel,
@@ -57,8 +97,10 @@ pub struct UDPComponent {
     ctx_pfix: [u8; 16],
     dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
-    interface_list: &'static [IPAddr],
+    eui64: [u8; 8],
+    interface_list: &'static [Cell<IPAddr>],
     alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    rng_mux: &'static MuxRng<'static>,
 }
 
 impl UDPComponent {
@@ -69,8 +111,10 @@ impl UDPComponent {
         ctx_pfix: [u8; 16],
         dst_mac_addr: MacAddress,
         src_mac_addr: MacAddress,
-        interface_list: &'static [IPAddr],
+        eui64: [u8; 8],
+        interface_list: &'static [Cell<IPAddr>],
         alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        rng_mux: &'static MuxRng<'static>,
     ) -> UDPComponent {
         UDPComponent {
             board_kernel: board_kernel,
@@ -79,14 +123,23 @@ impl UDPComponent {
             ctx_pfix: ctx_pfix,
             dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
+            eui64: eui64,
             interface_list: interface_list,
             alarm_mux: alarm,
+            rng_mux: rng_mux,
         }
     }
 }
 
 impl Component for UDPComponent {
-    type Output = &'static capsules::net::udp::UDPDriver<'static>;
+    type Output = (
+        &'static capsules::net::udp::UDPDriver<'static>,
+        &'static capsules::net::tcp::TCPDriver<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+            VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        >,
+    );
 
     unsafe fn finalize(&mut self) -> Self::Output {
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
@@ -105,15 +158,15 @@ impl Component for UDPComponent {
             sixlowpan_state::Sixlowpan<
                 'static,
                 sam4l::ast::Ast<'static>,
-                sixlowpan_compression::Context,
+                sixlowpan_compression::ContextTable,
             >,
             sixlowpan_state::Sixlowpan::new(
-                sixlowpan_compression::Context {
+                sixlowpan_compression::ContextTable::new(sixlowpan_compression::Context {
                     prefix: self.ctx_pfix,
                     prefix_len: self.ctx_pfix_len,
                     id: 0,
                     compress: false,
-                },
+                }),
                 &sam4l::ast::AST
             )
         );
@@ -153,7 +206,7 @@ impl Component for UDPComponent {
 
         // Initially, set src IP of the sender to be the first IP in the Interface
         // list. Userland apps can change this if they so choose.
-        ip_send.set_addr(self.interface_list[0]);
+        ip_send.set_addr(self.interface_list[0].get());
         udp_mac.set_transmit_client(ip_send);
 
         let udp_send = static_init!(
@@ -175,20 +228,227 @@ impl Component for UDPComponent {
         sixlowpan_state.set_rx_client(ip_receive);
 
         let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
-        ip_receive.set_client(udp_recv);
+        ip_receive.set_udp_client(udp_recv);
+
+        // ICMPv6 has its own IP6_Sender, as each sender can only have a single
+        // client and send one packet at a time.
+        let icmp_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let icmp_mac = static_init!(
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(icmp_mac);
+
+        let icmp_pyld: IPPayload = IPPayload {
+            header: TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type128)),
+            payload: &mut ICMP_DGRAM,
+        };
+        let icmp_dg = static_init!(IP6Packet<'static>, IP6Packet::new(icmp_pyld));
+
+        let icmp_ip_send = static_init!(
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            IP6SendStruct::new(
+                icmp_dg,
+                icmp_virtual_alarm,
+                &mut ICMP_RF233_BUF,
+                sixlowpan_state::TxState::new(sixlowpan_state),
+                icmp_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr
+            )
+        );
+        icmp_virtual_alarm.set_client(icmp_ip_send);
+        icmp_ip_send.set_addr(self.interface_list[0].get());
+        icmp_mac.set_transmit_client(icmp_ip_send);
+
+        let icmp_send = static_init!(
+            ICMP6SendStruct<
+                'static,
+                IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            >,
+            ICMP6SendStruct::new(icmp_ip_send)
+        );
+        icmp_ip_send.set_client(icmp_send);
+        let icmp_recv = static_init!(ICMP6RecvStruct<'static>, ICMP6RecvStruct::new(icmp_send));
+        icmp_send.set_client(icmp_recv);
+        ip_receive.set_icmp_client(icmp_recv);
+
+        // TCP has its own IP6_Sender as well.
+        let tcp_ip_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let tcp_mac = static_init!(
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(tcp_mac);
+
+        let tcp_pyld: IPPayload = IPPayload {
+            header: TransportHeader::TCP(TCPHeader::new()),
+            payload: &mut TCP_DGRAM,
+        };
+        let tcp_dg = static_init!(IP6Packet<'static>, IP6Packet::new(tcp_pyld));
+
+        let tcp_ip_send = static_init!(
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            IP6SendStruct::new(
+                tcp_dg,
+                tcp_ip_virtual_alarm,
+                &mut TCP_RF233_BUF,
+                sixlowpan_state::TxState::new(sixlowpan_state),
+                tcp_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr
+            )
+        );
+        tcp_ip_virtual_alarm.set_client(tcp_ip_send);
+        tcp_ip_send.set_addr(self.interface_list[0].get());
+        tcp_mac.set_transmit_client(tcp_ip_send);
+
+        let tcp_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let tcp_driver = static_init!(
+            capsules::net::tcp::TCPDriver<
+                'static,
+                IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+                VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            >,
+            capsules::net::tcp::TCPDriver::new(
+                tcp_ip_send,
+                tcp_virtual_alarm,
+                &mut TCP_SEGMENT_BUF,
+                self.board_kernel.create_grant(&grant_cap),
+                self.interface_list
+            )
+        );
+        tcp_ip_send.set_client(tcp_driver);
+        ip_receive.set_tcp_client(tcp_driver);
+        tcp_virtual_alarm.set_client(tcp_driver);
+        let tcp_rng = static_init!(
+            VirtualRngDevice<'static>,
+            VirtualRngDevice::new(self.rng_mux)
+        );
+        tcp_rng.set_client(tcp_driver);
+        tcp_rng.get();
+
+        // Neighbor discovery has its own IP6_Sender too, so that it gets the
+        // send_done callbacks of its messages, and its messages are not
+        // dropped while an echo reply is being sent.
+        let nd_ip_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let nd_mac = static_init!(
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(nd_mac);
+
+        let nd_pyld: IPPayload = IPPayload {
+            header: TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type133)),
+            payload: &mut ND_DGRAM,
+        };
+        let nd_dg = static_init!(IP6Packet<'static>, IP6Packet::new(nd_pyld));
+
+        let nd_ip_send = static_init!(
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            IP6SendStruct::new(
+                nd_dg,
+                nd_ip_virtual_alarm,
+                &mut ND_RF233_BUF,
+                sixlowpan_state::TxState::new(sixlowpan_state),
+                nd_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr
+            )
+        );
+        nd_ip_virtual_alarm.set_client(nd_ip_send);
+        nd_ip_send.set_addr(self.interface_list[0].get());
+        nd_mac.set_transmit_client(nd_ip_send);
+
+        let nd_icmp_send = static_init!(
+            ICMP6SendStruct<
+                'static,
+                IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            >,
+            ICMP6SendStruct::new(nd_ip_send)
+        );
+        nd_ip_send.set_client(nd_icmp_send);
+
+        // Neighbor discovery sets the gateway and source address of every
+        // IP6_Sender once it has found a router and registered an address.
+        let ip_senders = static_init!(
+            [&'static IP6Sender<'static>; 4],
+            [ip_send, icmp_ip_send, tcp_ip_send, nd_ip_send]
+        );
+        let nd_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let nd = static_init!(
+            SixlowpanND<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            SixlowpanND::new(
+                nd_virtual_alarm,
+                nd_icmp_send,
+                ip_senders,
+                &sixlowpan.ctx_store,
+                self.interface_list,
+                self.src_mac_addr,
+                self.eui64
+            )
+        );
+        nd_virtual_alarm.set_client(nd);
+        nd_icmp_send.set_client(nd);
+        let ra_subscriber = static_init!(
+            ICMP6RecvSubscriber<'static>,
+            ICMP6RecvSubscriber::new(ICMP6Type::Type134)
+        );
+        ra_subscriber.set_client(nd);
+        icmp_recv.add_subscriber(ra_subscriber);
+        let na_subscriber = static_init!(
+            ICMP6RecvSubscriber<'static>,
+            ICMP6RecvSubscriber::new(ICMP6Type::Type136)
+        );
+        na_subscriber.set_client(nd);
+        icmp_recv.add_subscriber(na_subscriber);
+
+        let udp_mux = static_init!(
+            MuxUDP<'static>,
+            MuxUDP::new(udp_send, self.interface_list)
+        );
+        udp_send.set_client(udp_mux);
+        udp_recv.set_client(udp_mux);
+
+        let app_sockets = static_init!(
+            [UDPSocket<'static>; NUM_APP_SOCKETS],
+            [
+                UDPSocket::new(udp_mux, &mut SOCKET_QUEUES[0]),
+                UDPSocket::new(udp_mux, &mut SOCKET_QUEUES[1]),
+                UDPSocket::new(udp_mux, &mut SOCKET_QUEUES[2]),
+                UDPSocket::new(udp_mux, &mut SOCKET_QUEUES[3])
+            ]
+        );
 
         let udp_driver = static_init!(
             capsules::net::udp::UDPDriver<'static>,
             capsules::net::udp::UDPDriver::new(
-                udp_send,
-                udp_recv,
+                app_sockets,
                 self.board_kernel.create_grant(&grant_cap),
                 self.interface_list,
                 PAYLOAD_LEN
             )
         );
-        udp_send.set_client(udp_driver);
-        udp_recv.set_client(udp_driver);
-        udp_driver
+        for socket in app_sockets.iter() {
+            udp_mux.add_socket(socket);
+            socket.set_client(udp_driver);
+        }
+        nd.start();
+        (udp_driver, tcp_driver)
     }
 }
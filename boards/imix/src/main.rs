@@ -19,20 +19,27 @@ extern crate sam4l;
 
 mod components;
 use capsules::alarm::AlarmDriver;
+use capsules::extended_alarm::ExtendedAlarm;
 use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_i2c::MuxI2C;
+use capsules::virtual_rng::MuxRng;
 use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
 use capsules::virtual_uart::{UartDevice, UartMux};
+use core::cell::Cell;
 use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
 use kernel::component::Component;
 use kernel::hil;
+use kernel::hil::entropy::Entropy32;
 use kernel::hil::radio;
 #[allow(unused_imports)]
 use kernel::hil::radio::{RadioConfig, RadioData};
+use kernel::hil::rng::Rng;
 use kernel::hil::spi::SpiMaster;
 use kernel::hil::Controller;
+use kernel::log::{Format, Level, Logger};
 use kernel::Chip;
 
 use components::adc::AdcComponent;
@@ -97,11 +104,14 @@ const RADIO_CHANNEL: u8 = 26;
 const SRC_MAC: u16 = 0xf00f;
 const DST_MAC_ADDR: MacAddress = MacAddress::Short(0x802);
 const SRC_MAC_ADDR: MacAddress = MacAddress::Short(SRC_MAC);
+// EUI-64 that identifies the node in its 6LoWPAN address registrations
+const EUI64: [u8; 8] = [0x02, 0x00, 0x00, 0xff, 0xfe, 0x00, 0xf0, 0x0f];
 const DEFAULT_CTX_PREFIX_LEN: u8 = 8; //Length of context for 6LoWPAN compression
 const DEFAULT_CTX_PREFIX: [u8; 16] = [0x0 as u8; 16]; //Context for 6LoWPAN Compression
 const PAN_ID: u16 = 0xABCD;
 
-static LOCAL_IP_IFACES: [IPAddr; 2] = [
+// Initial addresses of the network interfaces
+const LOCAL_IP_IFACES: [IPAddr; 2] = [
     IPAddr([
         0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
         0x0f,
@@ -128,7 +138,10 @@ pub static mut STACK_MEMORY: [u8; 0x2000] = [0; 0x2000];
 struct Imix {
     console: &'static capsules::console::Console<'static, UartDevice<'static>>,
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
-    alarm: &'static AlarmDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    alarm: &'static AlarmDriver<
+        'static,
+        ExtendedAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    >,
     temp: &'static capsules::temperature::TemperatureSensor<'static>,
     humidity: &'static capsules::humidity::HumiditySensor<'static>,
     ambient_light: &'static capsules::ambient_light::AmbientLight<'static>,
@@ -145,6 +158,14 @@ struct Imix {
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    tcp_driver: &'static capsules::net::tcp::TCPDriver<
+        'static,
+        capsules::net::ipv6::ipv6_send::IP6SendStruct<
+            'static,
+            VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        >,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     usb_driver: &'static capsules::usb_user::UsbSyscallDriver<
         'static,
@@ -192,6 +213,7 @@ impl kernel::Platform for Imix {
             capsules::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.radio_driver)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::tcp::DRIVER_NUM => f(Some(self.tcp_driver)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
@@ -307,6 +329,16 @@ pub unsafe fn reset_handler() {
 
     let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
 
+    // Deferred calls for capsules, which the UART mux uses to start
+    // transmissions.
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 1], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    board_kernel.set_dynamic_deferred_call(dynamic_deferred_caller, &main_cap);
+
     // # CONSOLE
     // Create a shared UART channel for the console and for kernel debug.
     sam4l::usart::USART3.set_mode(sam4l::usart::UsartMode::Uart);
@@ -315,13 +347,32 @@ pub unsafe fn reset_handler() {
         UartMux::new(
             &sam4l::usart::USART3,
             &mut capsules::virtual_uart::RX_BUF,
-            115200
+            115200,
+            dynamic_deferred_caller
         )
     );
+    uart_mux.initialize_callback_handle(
+        dynamic_deferred_caller
+            .register(uart_mux)
+            .expect("no deferred call slot available for uart mux"),
+    );
     hil::uart::UART::set_client(&sam4l::usart::USART3, uart_mux);
 
     let console = ConsoleComponent::new(board_kernel, uart_mux, 115200).finalize();
 
+    // # LOGGING
+    // The radio stack only logs warnings and errors, so that it does not
+    // flood the debug UART.
+    static LOG_FILTERS: [(&str, Option<Level>); 2] = [
+        ("capsules::ieee802154", Some(Level::Warn)),
+        ("capsules::net", Some(Level::Warn)),
+    ];
+    let logger = static_init!(
+        Logger,
+        Logger::new(Some(Level::Info), &LOG_FILTERS, Format::Text)
+    );
+    kernel::log::set_logger(logger);
+
     // Allow processes to communicate over BLE through the nRF51822
     let nrf_serialization =
         Nrf51822Component::new(&sam4l::usart::USART2, &sam4l::gpio::PB[07]).finalize();
@@ -374,7 +425,17 @@ pub unsafe fn reset_handler() {
     let button = ButtonComponent::new(board_kernel).finalize();
     let crc = CrcComponent::new(board_kernel).finalize();
     let analog_comparator = AcComponent::new().finalize();
-    let rng = RngComponent::new(board_kernel).finalize();
+
+    // # RNG
+    // The TRNG is shared by the RNG syscall driver and TCP.
+    let entropy_to_random = static_init!(
+        capsules::rng::Entropy32ToRandom<'static>,
+        capsules::rng::Entropy32ToRandom::new(&sam4l::trng::TRNG)
+    );
+    sam4l::trng::TRNG.set_client(entropy_to_random);
+    let rng_mux = static_init!(MuxRng<'static>, MuxRng::new(entropy_to_random));
+    entropy_to_random.set_client(rng_mux);
+    let rng = RngComponent::new(board_kernel, rng_mux).finalize();
 
     // Can this initialize be pushed earlier, or into component? -pal
     rf233.initialize(&mut RF233_BUF, &mut RF233_REG_WRITE, &mut RF233_REG_READ);
@@ -384,15 +445,24 @@ pub unsafe fn reset_handler() {
     let usb_driver = UsbComponent::new(board_kernel).finalize();
     let nonvolatile_storage = NonvolatileStorageComponent::new(board_kernel).finalize();
 
-    let udp_driver = UDPComponent::new(
+    let local_ip_ifaces = static_init!(
+        [Cell<IPAddr>; 2],
+        [
+            Cell::new(LOCAL_IP_IFACES[0]),
+            Cell::new(LOCAL_IP_IFACES[1])
+        ]
+    );
+    let (udp_driver, tcp_driver) = UDPComponent::new(
         board_kernel,
         mux_mac,
         DEFAULT_CTX_PREFIX_LEN,
         DEFAULT_CTX_PREFIX,
         DST_MAC_ADDR,
         SRC_MAC_ADDR,
-        &LOCAL_IP_IFACES,
+        EUI64,
+        local_ip_ifaces,
         mux_alarm,
+        rng_mux,
     ).finalize();
 
     let imix = Imix {
@@ -413,6 +483,7 @@ pub unsafe fn reset_handler() {
         ninedof,
         radio_driver,
         udp_driver,
+        tcp_driver,
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage: nonvolatile_storage,
@@ -450,5 +521,7 @@ pub unsafe fn reset_handler() {
         &process_mgmt_cap,
     );
 
-    board_kernel.kernel_loop(&imix, chip, Some(&imix.ipc), &main_cap);
+    let scheduler =
+        kernel::scheduler::RoundRobinScheduler::new(kernel::scheduler::DEFAULT_TIMESLICE_US);
+    board_kernel.kernel_loop(&imix, chip, Some(&imix.ipc), &scheduler, &main_cap);
 }
@@ -16,6 +16,7 @@ use capsules::virtual_uart::{UartDevice, UartMux};
 use cc26x2::aon;
 use cc26x2::prcm;
 use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
 use kernel::hil;
 use kernel::hil::entropy::Entropy32;
 use kernel::hil::i2c::I2CMaster;
@@ -53,7 +54,10 @@ pub struct Platform {
     button: &'static capsules::button::Button<'static, cc26x2::gpio::GPIOPin>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
-        capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26x2::rtc::Rtc>,
+        capsules::extended_alarm::ExtendedAlarm<
+            'static,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26x2::rtc::Rtc>,
+        >,
     >,
     rng: &'static capsules::rng::RngDriver<'static>,
     i2c_master: &'static capsules::i2c_master::I2CMasterDriver<cc26x2::i2c::I2CMaster<'static>>,
@@ -172,15 +176,31 @@ pub unsafe fn reset_handler() {
 
     // UART
 
+    // Deferred calls for capsules, which the UART mux uses to start
+    // transmissions.
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 1], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    board_kernel.set_dynamic_deferred_call(dynamic_deferred_caller, &main_loop_capability);
+
     // Create a shared UART channel for the console and for kernel debug.
     let uart_mux = static_init!(
         UartMux<'static>,
         UartMux::new(
             &cc26x2::uart::UART0,
             &mut capsules::virtual_uart::RX_BUF,
-            115200
+            115200,
+            dynamic_deferred_caller
         )
     );
+    uart_mux.initialize_callback_handle(
+        dynamic_deferred_caller
+            .register(uart_mux)
+            .expect("no deferred call slot available for uart mux"),
+    );
     hil::uart::UART::set_client(&cc26x2::uart::UART0, uart_mux);
 
     // Create a UartDevice for the console.
@@ -265,17 +285,29 @@ pub unsafe fn reset_handler() {
         capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26x2::rtc::Rtc>,
         capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
     );
+    let extended_alarm = static_init!(
+        capsules::extended_alarm::ExtendedAlarm<
+            'static,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26x2::rtc::Rtc>,
+        >,
+        capsules::extended_alarm::ExtendedAlarm::new(virtual_alarm1, 32)
+    );
     let alarm = static_init!(
         capsules::alarm::AlarmDriver<
             'static,
-            capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26x2::rtc::Rtc>,
+            capsules::extended_alarm::ExtendedAlarm<
+                'static,
+                capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26x2::rtc::Rtc>,
+            >,
         >,
         capsules::alarm::AlarmDriver::new(
-            virtual_alarm1,
+            extended_alarm,
             board_kernel.create_grant(&memory_allocation_capability)
         )
     );
-    virtual_alarm1.set_client(alarm);
+    virtual_alarm1.set_client(extended_alarm);
+    extended_alarm.set_client(alarm);
+    extended_alarm.start();
 
     let entropy_to_random = static_init!(
         capsules::rng::Entropy32ToRandom<'static>,
@@ -321,5 +353,13 @@ pub unsafe fn reset_handler() {
         &process_management_capability,
     );
 
-    board_kernel.kernel_loop(&launchxl, chip, Some(&ipc), &main_loop_capability);
+    let scheduler =
+        kernel::scheduler::RoundRobinScheduler::new(kernel::scheduler::DEFAULT_TIMESLICE_US);
+    board_kernel.kernel_loop(
+        &launchxl,
+        chip,
+        Some(&ipc),
+        &scheduler,
+        &main_loop_capability,
+    );
 }
@@ -62,9 +62,11 @@ extern crate nrf51;
 extern crate nrf5x;
 
 use capsules::alarm::AlarmDriver;
+use capsules::extended_alarm::ExtendedAlarm;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_uart::{UartDevice, UartMux};
 use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
 use kernel::hil;
 use kernel::hil::entropy::Entropy32;
 use kernel::hil::rng::Rng;
@@ -121,7 +123,7 @@ pub struct Platform {
     gpio: &'static capsules::gpio::GPIO<'static, nrf5x::gpio::GPIOPin>,
     led: &'static capsules::led::LED<'static, nrf5x::gpio::GPIOPin>,
     temp: &'static capsules::temperature::TemperatureSensor<'static>,
-    alarm: &'static AlarmDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
+    alarm: &'static AlarmDriver<'static, ExtendedAlarm<'static, VirtualMuxAlarm<'static, Rtc>>>,
     rng: &'static capsules::rng::RngDriver<'static>,
 }
 
@@ -259,15 +261,31 @@ pub unsafe fn reset_handler() {
         Pinmux::new(8),  /*. rts */
     );
 
+    // Deferred calls for capsules, which the UART mux uses to start
+    // transmissions.
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 1], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    board_kernel.set_dynamic_deferred_call(dynamic_deferred_caller, &main_loop_capability);
+
     // Create a shared UART channel for the console and for kernel debug.
     let uart_mux = static_init!(
         UartMux<'static>,
         UartMux::new(
             &nrf51::uart::UART0,
             &mut capsules::virtual_uart::RX_BUF,
-            115200
+            115200,
+            dynamic_deferred_caller
         )
     );
+    uart_mux.initialize_callback_handle(
+        dynamic_deferred_caller
+            .register(uart_mux)
+            .expect("no deferred call slot available for uart mux"),
+    );
     hil::uart::UART::set_client(&nrf51::uart::UART0, uart_mux);
 
     // Create a UartDevice for the console.
@@ -315,14 +333,21 @@ pub unsafe fn reset_handler() {
         VirtualMuxAlarm<'static, Rtc>,
         VirtualMuxAlarm::new(mux_alarm)
     );
+    // The RTC counter is 24 bits wide.
+    let extended_alarm = static_init!(
+        ExtendedAlarm<'static, VirtualMuxAlarm<'static, Rtc>>,
+        ExtendedAlarm::new(virtual_alarm1, 24)
+    );
     let alarm = static_init!(
-        AlarmDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
+        AlarmDriver<'static, ExtendedAlarm<'static, VirtualMuxAlarm<'static, Rtc>>>,
         AlarmDriver::new(
-            virtual_alarm1,
+            extended_alarm,
             board_kernel.create_grant(&memory_allocation_capability)
         )
     );
-    virtual_alarm1.set_client(alarm);
+    virtual_alarm1.set_client(extended_alarm);
+    extended_alarm.set_client(alarm);
+    extended_alarm.start();
 
     let ble_radio_virtual_alarm = static_init!(
         VirtualMuxAlarm<'static, Rtc>,
@@ -421,6 +446,8 @@ pub unsafe fn reset_handler() {
         &process_management_capability,
     );
 
+    let scheduler =
+        kernel::scheduler::RoundRobinScheduler::new(kernel::scheduler::DEFAULT_TIMESLICE_US);
     board_kernel.kernel_loop(
         &platform,
         chip,
@@ -428,6 +455,7 @@ pub unsafe fn reset_handler() {
             board_kernel,
             &memory_allocation_capability,
         )),
+        &scheduler,
         &main_loop_capability,
     );
 }
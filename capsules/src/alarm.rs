@@ -1,7 +1,34 @@
 //! Provides userspace applications with a alarm API.
+//!
+//! The driver keeps time with an `Alarm64`, so alarms do not need to be
+//! compared across wraps of the counter. Processes can use the lower 32 bits
+//! of the time, as before, or the full 64 bits.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! let extended_alarm = static_init!(
+//!     capsules::extended_alarm::ExtendedAlarm<
+//!         'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     >,
+//!     capsules::extended_alarm::ExtendedAlarm::new(virtual_alarm, 32)
+//! );
+//! virtual_alarm.set_client(extended_alarm);
+//! extended_alarm.start();
+//! let alarm = static_init!(
+//!     capsules::alarm::AlarmDriver<
+//!         'static,
+//!         capsules::extended_alarm::ExtendedAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     >,
+//!     capsules::alarm::AlarmDriver::new(extended_alarm, board_kernel.create_grant(&grant_cap))
+//! );
+//! extended_alarm.set_client(alarm);
+//! ```
 
 use core::cell::Cell;
-use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::hil::time::{self, Alarm64, Frequency};
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
 
 /// Syscall driver number.
@@ -10,13 +37,15 @@ pub const DRIVER_NUM: usize = 0x00000000;
 #[derive(Copy, Clone, Debug)]
 enum Expiration {
     Disabled,
-    Abs(u32),
+    Abs(u64),
 }
 
 #[derive(Copy, Clone)]
 pub struct AlarmData {
     expiration: Expiration,
     callback: Option<Callback>,
+    /// Upper 32 bits of the time read with command `5`.
+    time_high: u32,
 }
 
 impl Default for AlarmData {
@@ -24,53 +53,60 @@ impl Default for AlarmData {
         AlarmData {
             expiration: Expiration::Disabled,
             callback: None,
+            time_high: 0,
         }
     }
 }
 
-pub struct AlarmDriver<'a, A: Alarm> {
+pub struct AlarmDriver<'a, A: Alarm64> {
     alarm: &'a A,
     num_armed: Cell<usize>,
     app_alarm: Grant<AlarmData>,
-    prev: Cell<u32>,
 }
 
-impl<A: Alarm> AlarmDriver<'a, A> {
+impl<A: Alarm64> AlarmDriver<'a, A> {
     pub const fn new(alarm: &'a A, grant: Grant<AlarmData>) -> AlarmDriver<'a, A> {
         AlarmDriver {
             alarm: alarm,
             num_armed: Cell::new(0),
             app_alarm: grant,
-            prev: Cell::new(0),
         }
     }
 
-    fn reset_active_alarm(&self, now: u32) -> Option<u32> {
-        self.prev.set(now);
-        let mut next_alarm = u32::max_value();
-        let mut next_dist = u32::max_value();
+    /// Set the alarm to the earliest expiration of any process, or disable it
+    /// if there is none.
+    fn reset_active_alarm(&self) {
+        let mut next_alarm = None;
         for alarm in self.app_alarm.iter() {
-            alarm.enter(|alarm, _| match alarm.expiration {
-                Expiration::Abs(exp) => {
-                    let t_dist = exp.wrapping_sub(now);
-                    if next_dist > t_dist {
-                        next_alarm = exp;
-                        next_dist = t_dist;
+            alarm.enter(|alarm, _| {
+                if let Expiration::Abs(exp) = alarm.expiration {
+                    if next_alarm.map_or(true, |next| exp < next) {
+                        next_alarm = Some(exp);
                     }
                 }
-                Expiration::Disabled => {}
             });
         }
-        if next_alarm != u32::max_value() {
-            self.alarm.set_alarm(next_alarm);
-            Some(next_alarm)
+        match next_alarm {
+            Some(next) => self.alarm.set_alarm64(next),
+            None => self.alarm.disable(),
+        }
+    }
+
+    /// Extend a 32-bit time from a process to the 64-bit time nearest to
+    /// `now` with the same lower 32 bits. Times up to half the range of the
+    /// 32-bit counter behind `now` have already passed, so the alarm fires
+    /// right away instead of after the counter wraps.
+    fn extend_time(&self, time: u32, now: u64) -> u64 {
+        let diff = time.wrapping_sub(now as u32) as i32;
+        if diff >= 0 {
+            now + diff as u64
         } else {
-            None
+            now.saturating_sub(diff.wrapping_neg() as u32 as u64)
         }
     }
 }
 
-impl<A: Alarm> Driver for AlarmDriver<'a, A> {
+impl<A: Alarm64> Driver for AlarmDriver<'a, A> {
     /// Subscribe to alarm expiration
     ///
     /// ### `_subscribe_num`
@@ -98,7 +134,12 @@ impl<A: Alarm> Driver for AlarmDriver<'a, A> {
     /// - `2`: Read the the current clock value
     /// - `3`: Stop the alarm if it is outstanding
     /// - `4`: Set an alarm to fire at a given clock value `time`.
-    fn command(&self, cmd_type: usize, data: usize, _: usize, caller_id: AppId) -> ReturnCode {
+    /// - `5`: Read the lower 32 bits of the 64-bit clock value, and save the
+    ///   upper 32 bits for command `6`.
+    /// - `6`: Read the upper 32 bits saved by command `5`.
+    /// - `7`: Set an alarm to fire at a given 64-bit clock value, with the
+    ///   lower 32 bits in `data` and the upper 32 bits in `data2`.
+    fn command(&self, cmd_type: usize, data: usize, data2: usize, caller_id: AppId) -> ReturnCode {
         // Returns the error code to return to the user and whether we need to
         // reset which is the next active alarm. We only _don't_ reset if we're
         // disabling the underlying alarm anyway, if the underlying alarm is
@@ -106,7 +147,7 @@ impl<A: Alarm> Driver for AlarmDriver<'a, A> {
         // (i.e. no change to the alarms).
         self.app_alarm
             .enter(caller_id, |td, _alloc| {
-                let now = self.alarm.now();
+                let now = self.alarm.now64();
                 let (return_code, reset) = match cmd_type {
                     0 /* check if present */ => (ReturnCode::SuccessWithValue { value: 1 }, false),
                     1 /* Get clock frequency */ => {
@@ -114,7 +155,7 @@ impl<A: Alarm> Driver for AlarmDriver<'a, A> {
                         (ReturnCode::SuccessWithValue { value: freq }, false)
                     },
                     2 /* capture time */ => {
-                        (ReturnCode::SuccessWithValue { value: now as usize },
+                        (ReturnCode::SuccessWithValue { value: now as u32 as usize },
                          false)
                     },
                     3 /* Stop */ => {
@@ -124,7 +165,7 @@ impl<A: Alarm> Driver for AlarmDriver<'a, A> {
                                 // Request to stop when already stopped
                                 (ReturnCode::EALREADY, false)
                             },
-                            Expiration::Abs(exp) if exp != alarm_id => {
+                            Expiration::Abs(exp) if exp as u32 != alarm_id => {
                                 // Request to stop invalid alarm id
                                 (ReturnCode::EINVAL, false)
                             },
@@ -142,50 +183,53 @@ impl<A: Alarm> Driver for AlarmDriver<'a, A> {
                         if let Expiration::Disabled = td.expiration {
                             self.num_armed.set(self.num_armed.get() + 1);
                         }
-                        td.expiration = Expiration::Abs(time as u32);
+                        td.expiration = Expiration::Abs(self.extend_time(time as u32, now));
                         (ReturnCode::SuccessWithValue { value: time }, true)
                     },
+                    5 /* capture 64-bit time */ => {
+                        td.time_high = (now >> 32) as u32;
+                        (ReturnCode::SuccessWithValue { value: now as u32 as usize },
+                         false)
+                    },
+                    6 /* upper half of captured time */ => {
+                        (ReturnCode::SuccessWithValue { value: td.time_high as usize },
+                         false)
+                    },
+                    7 /* Set absolute 64-bit expiration */ => {
+                        let time = (data2 as u32 as u64) << 32 | data as u32 as u64;
+                        if let Expiration::Disabled = td.expiration {
+                            self.num_armed.set(self.num_armed.get() + 1);
+                        }
+                        td.expiration = Expiration::Abs(time);
+                        (ReturnCode::SuccessWithValue { value: data }, true)
+                    },
                     _ => (ReturnCode::ENOSUPPORT, false)
                 };
                 if reset {
-                    self.reset_active_alarm(now);
+                    self.reset_active_alarm();
                 }
                 return_code
             }).unwrap_or_else(|err| err.into())
     }
 }
 
-fn has_expired(alarm: u32, now: u32, prev: u32) -> bool {
-    now.wrapping_sub(prev) >= alarm.wrapping_sub(prev)
-}
-
-impl<A: Alarm> time::Client for AlarmDriver<'a, A> {
+impl<A: Alarm64> time::Client for AlarmDriver<'a, A> {
     fn fired(&self) {
-        let now = self.alarm.now();
+        let now = self.alarm.now64();
         self.app_alarm.each(|alarm| {
             if let Expiration::Abs(exp) = alarm.expiration {
-                let expired = has_expired(exp, now, self.prev.get());
-                if expired {
+                if exp <= now {
                     alarm.expiration = Expiration::Disabled;
                     self.num_armed.set(self.num_armed.get() - 1);
-                    alarm
-                        .callback
-                        .map(|mut cb| cb.schedule(now as usize, exp as usize, 0));
+                    alarm.callback.map(|mut cb| {
+                        cb.schedule(now as u32 as usize, exp as u32 as usize, (now >> 32) as usize)
+                    });
                 }
             }
         });
 
-        // If there are armed alarms left, reset the underlying alarm to the
-        // nearest interval.  Otherwise, disable the underlying alarm.
-        if self.num_armed.get() == 0 {
-            self.alarm.disable();
-        } else if let Some(next_alarm) = self.reset_active_alarm(now) {
-            let new_now = self.alarm.now();
-            if has_expired(next_alarm, new_now, now) {
-                self.fired();
-            }
-        } else {
-            self.alarm.disable();
-        }
+        // Set the alarm to the nearest remaining expiration, if any. The
+        // alarm fires right away if it has already passed.
+        self.reset_active_alarm();
     }
 }
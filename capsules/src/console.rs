@@ -30,6 +30,10 @@
 //! command(CONSOLE_DRIVER_NUM, 1, len_to_write_in_bytes)
 //! ```
 //!
+//! A buffer in flash, such as a constant string, can be written without
+//! copying it to memory first by sharing it with `allow_readonly` instead of
+//! `allow`.
+//!
 //! When the buffer has been written successfully, the buffer is released from
 //! the driver. Successive writes must call `allow` each time a buffer is to be
 //! written.
@@ -37,15 +41,39 @@
 use core::cmp;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::uart::{self, Client, UART};
-use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReadOnlyAppSlice, ReturnCode, Shared};
 
 /// Syscall driver number.
 pub const DRIVER_NUM: usize = 0x00000001;
 
+/// A buffer to write, shared with either `allow` or `allow_readonly`.
+enum WriteBuffer {
+    ReadWrite(AppSlice<Shared, u8>),
+    ReadOnly(ReadOnlyAppSlice<u8>),
+}
+
+impl WriteBuffer {
+    fn len(&self) -> usize {
+        match *self {
+            WriteBuffer::ReadWrite(ref slice) => slice.len(),
+            WriteBuffer::ReadOnly(ref slice) => slice.len(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for WriteBuffer {
+    fn as_ref(&self) -> &[u8] {
+        match *self {
+            WriteBuffer::ReadWrite(ref slice) => slice.as_ref(),
+            WriteBuffer::ReadOnly(ref slice) => slice.as_ref(),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct App {
     write_callback: Option<Callback>,
-    write_buffer: Option<AppSlice<Shared, u8>>,
+    write_buffer: Option<WriteBuffer>,
     write_len: usize,
     write_remaining: usize, // How many bytes didn't fit in the buffer and still need to be printed.
     pending_write: bool,
@@ -126,7 +154,7 @@ impl<U: UART> Console<'a, U> {
 
     /// Internal helper function for sending data for an existing transaction.
     /// Cannot fail. If can't send now, it will schedule for sending later.
-    fn send(&self, app_id: AppId, app: &mut App, slice: AppSlice<Shared, u8>) {
+    fn send(&self, app_id: AppId, app: &mut App, slice: WriteBuffer) {
         if self.tx_in_progress.is_none() {
             self.tx_in_progress.set(app_id);
             self.tx_buffer.take().map(|buffer| {
@@ -209,7 +237,7 @@ impl<U: UART> Driver for Console<'a, U> {
             1 => self
                 .apps
                 .enter(appid, |app, _| {
-                    app.write_buffer = slice;
+                    app.write_buffer = slice.map(WriteBuffer::ReadWrite);
                     ReturnCode::SUCCESS
                 }).unwrap_or_else(|err| err.into()),
             2 => self
@@ -222,6 +250,28 @@ impl<U: UART> Driver for Console<'a, U> {
         }
     }
 
+    /// Setup buffers that the driver only reads.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `1`: Write buffer, which may be in flash
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<ReadOnlyAppSlice<u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.write_buffer = slice.map(WriteBuffer::ReadOnly);
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
     /// Setup callbacks.
     ///
     /// ### `subscribe_num`
@@ -0,0 +1,292 @@
+//! Keeps a record of the last kernel crash in a reserved flash page.
+//!
+//! When the kernel panics, `kernel::debug::panic()` hands a
+//! `kernel::debug::CrashRecord` to this capsule, which writes it to the flash
+//! page. Since interrupts are not serviced during a panic, the capsule services
+//! the chip's pending interrupts itself until the write completes.
+//!
+//! After the board reboots, `load()` reads the page back. If it holds a record,
+//! the record is printed on the debug console and processes can read it with
+//! the syscall interface below. The record stays in flash until a process
+//! clears it or the kernel crashes again.
+//!
+//! A crash that happens while the capsule is loading or clearing the record is
+//! not saved, because the page buffer is in use.
+//!
+//! Usage
+//! -----
+//!
+//! The page should be reserved for the crash log, for example in the kernel's
+//! storage region as Hail does:
+//!
+//! ```
+//! storage_volume!(CRASH_LOG_VOLUME, 1);
+//! pub static mut CRASH_LOG_PAGE: sam4l::flashcalw::Sam4lPage = sam4l::flashcalw::Sam4lPage::new();
+//!
+//! // The first whole page in the volume.
+//! let page_number = (&CRASH_LOG_VOLUME as *const [u8; 1024] as usize + 511) / 512;
+//! sam4l::flashcalw::FLASH_CONTROLLER.configure();
+//! let crash_log = static_init!(
+//!     capsules::crash_log::CrashLog<'static, sam4l::flashcalw::FLASHCALW, sam4l::chip::Sam4l>,
+//!     capsules::crash_log::CrashLog::new(
+//!         &sam4l::flashcalw::FLASH_CONTROLLER,
+//!         chip,
+//!         page_number,
+//!         &mut CRASH_LOG_PAGE,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, crash_log);
+//! kernel::debug::set_crash_handler(crash_log);
+//! crash_log.load();
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Stability: 0 - Initial
+//!
+//! ### Allow
+//!
+//! - `0`: Buffer that command `1` copies the record into.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Called when clearing the record completes, with a `ReturnCode`.
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Copy the encoded record into the allowed buffer. Returns the length
+//!   of the record, which is 0 if there is none. See
+//!   `kernel::debug::CrashRecord::encode()` for the encoding.
+//! - `2`: Clear the record.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::debug::{CrashHandler, CrashRecord};
+use kernel::hil;
+use kernel::{AppId, AppSlice, Callback, Chip, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x50003;
+
+/// How many times to check for the flash write to finish during a panic
+/// before giving up.
+const PANIC_WRITE_POLLS: usize = 1_000_000;
+
+/// Erased flash reads as all ones.
+const ERASED: u8 = 0xff;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum State {
+    Idle,
+    Loading,
+    Clearing,
+    Saving,
+}
+
+pub struct CrashLog<'a, F: hil::flash::Flash + 'static, C: Chip + 'static> {
+    flash: &'a F,
+    chip: &'a C,
+    page_number: usize,
+    /// Holds the contents of the crash log page while the capsule is idle.
+    buffer: TakeCell<'static, F::Page>,
+    /// Length of the record at the start of `buffer`, or 0 if there is none.
+    record_len: Cell<usize>,
+    state: Cell<State>,
+    apps: Grant<App>,
+    clearing_app: OptionalCell<AppId>,
+}
+
+impl<F: hil::flash::Flash, C: Chip> CrashLog<'a, F, C> {
+    pub fn new(
+        flash: &'a F,
+        chip: &'a C,
+        page_number: usize,
+        buffer: &'static mut F::Page,
+        grant: Grant<App>,
+    ) -> CrashLog<'a, F, C> {
+        CrashLog {
+            flash: flash,
+            chip: chip,
+            page_number: page_number,
+            buffer: TakeCell::new(buffer),
+            record_len: Cell::new(0),
+            state: Cell::new(State::Idle),
+            apps: grant,
+            clearing_app: OptionalCell::empty(),
+        }
+    }
+
+    /// Read the record from flash. Call once when the board starts.
+    pub fn load(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
+            let ret = self.flash.read_page(self.page_number, buffer);
+            if ret == ReturnCode::SUCCESS {
+                self.state.set(State::Loading);
+            }
+            ret
+        })
+    }
+
+    fn clear(&self, appid: AppId) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
+            for byte in buffer.as_mut().iter_mut() {
+                *byte = ERASED;
+            }
+            self.record_len.set(0);
+            let ret = self.flash.write_page(self.page_number, buffer);
+            if ret == ReturnCode::SUCCESS {
+                self.state.set(State::Clearing);
+                self.clearing_app.set(appid);
+            }
+            ret
+        })
+    }
+
+    /// Copy the record into the buffer of `appid`, and return its length.
+    fn read_record(&self, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                let record_len = self.record_len.get();
+                app.buffer.as_mut().map_or(ReturnCode::EINVAL, |app_buffer| {
+                    self.buffer.map_or(ReturnCode::EBUSY, |buffer| {
+                        let len = cmp::min(record_len, app_buffer.len());
+                        app_buffer.as_mut()[..len].copy_from_slice(&buffer.as_mut()[..len]);
+                        ReturnCode::SuccessWithValue { value: record_len }
+                    })
+                })
+            }).unwrap_or_else(|err| err.into())
+    }
+}
+
+impl<F: hil::flash::Flash, C: Chip> CrashHandler for CrashLog<'a, F, C> {
+    fn save(&self, record: &CrashRecord) {
+        self.buffer.take().map(|buffer| {
+            for byte in buffer.as_mut().iter_mut() {
+                *byte = ERASED;
+            }
+            record.encode(buffer.as_mut());
+            if self.flash.write_page(self.page_number, buffer) != ReturnCode::SUCCESS {
+                return;
+            }
+            self.state.set(State::Saving);
+            for _ in 0..PANIC_WRITE_POLLS {
+                if self.state.get() != State::Saving {
+                    break;
+                }
+                if self.chip.has_pending_interrupts() {
+                    self.chip.service_pending_interrupts();
+                }
+            }
+        });
+    }
+}
+
+impl<F: hil::flash::Flash, C: Chip> hil::flash::Client<F> for CrashLog<'a, F, C> {
+    fn read_complete(&self, buffer: &'static mut F::Page, error: hil::flash::Error) {
+        self.state.set(State::Idle);
+        if error == hil::flash::Error::CommandComplete {
+            if let Some(record) = CrashRecord::decode(buffer.as_mut()) {
+                self.record_len.set(record.encoded_len());
+                debug!("---| Last crash |---\r\n{}", record);
+            }
+        }
+        self.buffer.replace(buffer);
+    }
+
+    fn write_complete(&self, buffer: &'static mut F::Page, error: hil::flash::Error) {
+        let state = self.state.replace(State::Idle);
+        self.buffer.replace(buffer);
+        if state == State::Clearing {
+            let ret = if error == hil::flash::Error::CommandComplete {
+                ReturnCode::SUCCESS
+            } else {
+                ReturnCode::FAIL
+            };
+            self.clearing_app.take().map(|appid| {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.callback.map(|mut cb| cb.schedule(ret.into(), 0, 0));
+                });
+            });
+        }
+    }
+
+    fn erase_complete(&self, _error: hil::flash::Error) {}
+}
+
+impl<F: hil::flash::Flash, C: Chip> Driver for CrashLog<'a, F, C> {
+    /// Setup the buffer to read the record into.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Set the buffer for command `1`.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Set the callback for when clearing the record completes.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Read or clear the crash record.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Copy the record into the allowed buffer and return its length.
+    /// - `2`: Clear the record.
+    fn command(&self, command_num: usize, _arg1: usize, _arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.read_record(appid),
+            2 => self.clear(appid),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Extend a 32-bit or narrower `Alarm` to a 64-bit `Alarm64`.
+//!
+//! The capsule counts how often the underlying alarm wraps around. To see
+//! every wrap, it keeps the underlying alarm armed at most a quarter of a wrap
+//! in the future, even when its client has no alarm set. For a 32-bit counter
+//! at 32 kHz that is one extra interrupt every 9 hours, for a 24-bit counter
+//! like the nRF5x RTC one every 2 minutes.
+//!
+//! The width of the counter is passed to `new()`, since the `Alarm` HIL
+//! always uses `u32` ticks.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! let extended_alarm = static_init!(
+//!     capsules::extended_alarm::ExtendedAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::extended_alarm::ExtendedAlarm::new(virtual_alarm, 32)
+//! );
+//! virtual_alarm.set_client(extended_alarm);
+//! extended_alarm.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Alarm64, Time};
+
+pub struct ExtendedAlarm<'a, A: Alarm + 'a> {
+    alarm: &'a A,
+    /// Width of the underlying counter in bits.
+    bits: u32,
+    /// Number of times the underlying counter wrapped, which is the time
+    /// above the lower `bits` bits.
+    high: Cell<u64>,
+    /// The underlying counter the last time it was read.
+    last_low: Cell<u32>,
+    /// The alarm the client set, if it is armed.
+    when: Cell<Option<u64>>,
+    client: OptionalCell<&'a time::Client>,
+}
+
+impl<A: Alarm> ExtendedAlarm<'a, A> {
+    /// `bits` is the width of the counter of `alarm`, at most 32.
+    pub fn new(alarm: &'a A, bits: u32) -> ExtendedAlarm<'a, A> {
+        ExtendedAlarm {
+            alarm: alarm,
+            bits: bits,
+            high: Cell::new(0),
+            last_low: Cell::new(0),
+            when: Cell::new(None),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a time::Client) {
+        self.client.set(client);
+    }
+
+    /// Start counting wraps. Time starts at the current value of the
+    /// underlying counter.
+    pub fn start(&self) {
+        self.last_low.set(self.low());
+        self.arm();
+    }
+
+    /// Read the underlying counter, without any bits above its width.
+    fn low(&self) -> u32 {
+        (self.alarm.now() as u64 & self.mask()) as u32
+    }
+
+    fn mask(&self) -> u64 {
+        (1 << self.bits) - 1
+    }
+
+    /// How far ahead the underlying alarm is set when the client has no alarm
+    /// due sooner. A quarter of a wrap leaves time to handle the alarm before
+    /// the counter passes the last value read.
+    fn check_interval(&self) -> u64 {
+        1 << (self.bits - 2)
+    }
+
+    /// Set the underlying alarm to the client's alarm, or to the next check if
+    /// that is sooner.
+    fn arm(&self) {
+        let now = self.now64();
+        let next_check = now + self.check_interval();
+        let next = self.when.get().map_or(next_check, |when| {
+            if when <= now {
+                now + 1
+            } else if when < next_check {
+                when
+            } else {
+                next_check
+            }
+        });
+        self.alarm.set_alarm((next & self.mask()) as u32);
+    }
+
+    /// Returns whether the client's alarm is due.
+    fn expired(&self) -> bool {
+        self.when.get().map_or(false, |when| when <= self.now64())
+    }
+}
+
+impl<A: Alarm> Time for ExtendedAlarm<'a, A> {
+    type Frequency = A::Frequency;
+
+    /// Disable the client's alarm. The underlying alarm stays armed to keep
+    /// counting wraps.
+    fn disable(&self) {
+        self.when.set(None);
+    }
+
+    fn is_armed(&self) -> bool {
+        self.when.get().is_some()
+    }
+}
+
+impl<A: Alarm> Alarm64 for ExtendedAlarm<'a, A> {
+    fn now64(&self) -> u64 {
+        let low = self.low();
+        if low < self.last_low.get() {
+            self.high.set(self.high.get() + 1);
+        }
+        self.last_low.set(low);
+        self.high.get() << self.bits | low as u64
+    }
+
+    fn set_alarm64(&self, tics: u64) {
+        self.when.set(Some(tics));
+        self.arm();
+    }
+
+    fn get_alarm64(&self) -> u64 {
+        self.when.get().unwrap_or(0)
+    }
+}
+
+impl<A: Alarm> time::Client for ExtendedAlarm<'a, A> {
+    fn fired(&self) {
+        // The client may set a new alarm that is already due, so keep going
+        // until the alarm is in the future.
+        while self.expired() {
+            self.when.set(None);
+            self.client.map(|client| client.fired());
+        }
+        self.arm();
+        if self.expired() {
+            self.fired();
+        }
+    }
+}
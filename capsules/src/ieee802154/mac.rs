@@ -174,13 +174,15 @@ impl<R: radio::Radio> radio::RxClient for AwakeMac<'a, R> {
         }
 
         if addr_match {
-            debug!("[AwakeMAC] Rcvd a 15.4 frame addressed to this device");
+            log_trace!("received a frame addressed to this device");
             self.rx_client.map(move |c| {
                 c.receive(buf, frame_len, crc_valid, result);
             });
         } else {
-            debug!("[AwakeMAC] Received a packet, but not addressed to us");
-            debug!("radio addr is: {:?}", self.radio.get_address());
+            log_trace!(
+                "received a frame not addressed to this device ({:#x})",
+                self.radio.get_address()
+            );
             self.radio.set_receive_buffer(buf);
         }
     }
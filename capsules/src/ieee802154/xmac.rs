@@ -81,7 +81,7 @@ use ieee802154::mac::Mac;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::radio;
 use kernel::hil::rng::{self, Rng};
-use kernel::hil::time::{self, Alarm, Frequency, Time};
+use kernel::hil::time::{self, Alarm, Time};
 use kernel::ReturnCode;
 use net::ieee802154::{FrameType, FrameVersion, Header, MacAddress, PanID};
 
@@ -208,11 +208,8 @@ impl<R: radio::Radio, A: Alarm> XMac<'a, R, A> {
     // Sets the timer to fire a set number of milliseconds in the future based
     // on the current tick value.
     fn set_timer_ms<T: Time>(&self, ms: u32) {
-        self.alarm.set_alarm(
-            self.alarm
-                .now()
-                .wrapping_add(((ms as f32 / 1000.0) * <T::Frequency>::frequency() as f32) as u32),
-        );
+        let ticks = time::ms_to_ticks::<T::Frequency>(ms as u64) as u32;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
     }
 
     fn transmit_preamble(&self) {
@@ -325,10 +322,9 @@ impl<R: radio::Radio, A: Alarm> rng::Client for XMac<'a, R, A> {
                     // asynchronous, we account for the time spent waiting for
                     // the callback and randomly determine the remaining time
                     // spent backing off.
-                    let time_remaining_ms =
-                        (((self.alarm.get_alarm().wrapping_sub(self.alarm.now())) as f32
-                            / <A::Frequency>::frequency() as f32)
-                            * 1000.0) as u32;
+                    let time_remaining_ms = time::ticks_to_ms::<A::Frequency>(
+                        self.alarm.get_alarm().wrapping_sub(self.alarm.now()) as u64,
+                    ) as u32;
                     self.set_timer_ms::<A>(random % time_remaining_ms);
                 }
                 rng::Continue::Done
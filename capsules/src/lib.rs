@@ -5,7 +5,7 @@
 #![no_std]
 
 #[allow(unused_imports)]
-#[macro_use(debug)]
+#[macro_use(debug, log, log_debug, log_error, log_trace, log_warn)]
 extern crate kernel;
 #[macro_use]
 extern crate enum_primitive;
@@ -24,9 +24,11 @@ pub mod app_flash_driver;
 pub mod ble_advertising_driver;
 pub mod button;
 pub mod console;
+pub mod crash_log;
 pub mod crc;
 pub mod dac;
 pub mod debug_process_restart;
+pub mod extended_alarm;
 pub mod fm25cl;
 pub mod fxos8700cq;
 pub mod gpio;
@@ -47,11 +49,13 @@ pub mod nonvolatile_storage_driver;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod pca9544a;
+pub mod process_restart_timer;
 pub mod rf233;
 pub mod rf233_const;
 pub mod rng;
 pub mod sdcard;
 pub mod segger_rtt;
+pub mod sha256_verifier;
 pub mod si7021;
 pub mod spi;
 pub mod temperature;
@@ -63,5 +67,6 @@ pub mod usbc_client;
 pub mod virtual_alarm;
 pub mod virtual_flash;
 pub mod virtual_i2c;
+pub mod virtual_rng;
 pub mod virtual_spi;
 pub mod virtual_uart;
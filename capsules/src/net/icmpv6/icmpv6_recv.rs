@@ -0,0 +1,179 @@
+//! This file contains the definition and implementation of the ICMPv6
+//! receive path. The [ICMP6RecvStruct](struct.ICMP6RecvStruct.html) is a
+//! client of an `IP6Receiver`, and handles every ICMPv6 message addressed to
+//! this node:
+//!
+//! - Echo requests are answered with an echo reply automatically, using an
+//!   `ICMP6Sender`. A request that arrives while the previous reply is still
+//!   being sent is dropped, as the sender can only send one packet at a time.
+//!   A request whose data does not fit in the packet buffer of the sender is
+//!   dropped as well.
+//! - Destination unreachable and parameter problem errors are logged. The
+//!   body of these messages is the start of the packet that caused the error,
+//!   which can be decoded with `IP6Header::decode`.
+//! - Every message is passed to the
+//!   [ICMP6RecvSubscriber](struct.ICMP6RecvSubscriber.html)s for its type, so
+//!   other capsules can handle specific ICMPv6 types.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! // `ip_send` is an `IP6Sender` that is only used for ICMPv6.
+//! let icmp_send = static_init!(
+//!     ICMP6SendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>>,
+//!     ICMP6SendStruct::new(ip_send)
+//! );
+//! ip_send.set_client(icmp_send);
+//! let icmp_recv = static_init!(ICMP6RecvStruct<'static>, ICMP6RecvStruct::new(icmp_send));
+//! icmp_send.set_client(icmp_recv);
+//! ip_receive.set_icmp_client(icmp_recv);
+//!
+//! // Another capsule that wants to see echo replies.
+//! let echo_replies = static_init!(
+//!     ICMP6RecvSubscriber<'static>,
+//!     ICMP6RecvSubscriber::new(ICMP6Type::Type129)
+//! );
+//! echo_replies.set_client(ping_capsule);
+//! icmp_recv.add_subscriber(echo_replies);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::ReturnCode;
+use net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use net::icmpv6::icmpv6_send::{ICMP6SendClient, ICMP6Sender};
+use net::ipv6::ip_utils::ip6_nh;
+use net::ipv6::ipv6::IP6Header;
+use net::ipv6::ipv6_recv::IP6RecvClient;
+
+/// A trait for a client of an `ICMP6RecvSubscriber`.
+pub trait ICMP6RecvClient {
+    /// Called when an ICMPv6 message of the subscribed type is received.
+    ///
+    /// # Arguments
+    ///
+    /// `ip_header` - The IPv6 header of the packet containing the message
+    /// `icmp_header` - The ICMPv6 header of the message
+    /// `payload` - The body of the message, following the ICMPv6 header
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]);
+}
+
+/// A subscription to one type of ICMPv6 message. Each subscriber is added to
+/// an `ICMP6RecvStruct` with `add_subscriber`, and can have a single client.
+pub struct ICMP6RecvSubscriber<'a> {
+    icmp_type: ICMP6Type,
+    client: OptionalCell<&'a ICMP6RecvClient>,
+    next: ListLink<'a, ICMP6RecvSubscriber<'a>>,
+}
+
+impl ICMP6RecvSubscriber<'a> {
+    pub fn new(icmp_type: ICMP6Type) -> ICMP6RecvSubscriber<'a> {
+        ICMP6RecvSubscriber {
+            icmp_type: icmp_type,
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a ICMP6RecvClient) {
+        self.client.set(client);
+    }
+
+    pub fn get_type(&self) -> ICMP6Type {
+        self.icmp_type
+    }
+}
+
+impl ListNode<'a, ICMP6RecvSubscriber<'a>> for ICMP6RecvSubscriber<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, ICMP6RecvSubscriber<'a>> {
+        &self.next
+    }
+}
+
+/// Receives ICMPv6 messages, answers echo requests and dispatches each
+/// message to the subscribers for its type.
+pub struct ICMP6RecvStruct<'a> {
+    icmp_sender: &'a ICMP6Sender<'a>,
+    subscribers: List<'a, ICMP6RecvSubscriber<'a>>,
+    sending: Cell<bool>,
+}
+
+impl ICMP6RecvStruct<'a> {
+    pub fn new(icmp_sender: &'a ICMP6Sender<'a>) -> ICMP6RecvStruct<'a> {
+        ICMP6RecvStruct {
+            icmp_sender: icmp_sender,
+            subscribers: List::new(),
+            sending: Cell::new(false),
+        }
+    }
+
+    pub fn add_subscriber(&self, subscriber: &'a ICMP6RecvSubscriber<'a>) {
+        self.subscribers.push_head(subscriber);
+    }
+
+    /// Answers an echo request with an echo reply that carries the same
+    /// identifier, sequence number and data. The request is dropped if the
+    /// sender is busy or its packet buffer is too small for the data, in
+    /// which case `send` returns `ESIZE`.
+    fn send_echo_reply(&self, ip_header: &IP6Header, id: u16, seqno: u16, payload: &[u8]) {
+        let src_addr = ip_header.get_src_addr();
+        if self.sending.get() || src_addr.is_multicast() || src_addr.is_unspecified() {
+            return;
+        }
+        let mut reply = ICMP6Header::new(ICMP6Type::Type129);
+        reply.set_options(ICMP6HeaderOptions::Type129 {
+            id: id,
+            seqno: seqno,
+        });
+        if self.icmp_sender.send(src_addr, reply, payload) == ReturnCode::SUCCESS {
+            self.sending.set(true);
+        }
+    }
+}
+
+impl IP6RecvClient for ICMP6RecvStruct<'a> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::ICMP {
+            return;
+        }
+        let (offset, icmp_header) = match ICMP6Header::decode(payload).done() {
+            Some(result) => result,
+            None => return,
+        };
+        let body = &payload[offset..];
+
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => {
+                self.send_echo_reply(&ip_header, id, seqno, body);
+            }
+            ICMP6HeaderOptions::Type1 { .. } => {
+                log_warn!("destination unreachable, code {}", icmp_header.get_code());
+            }
+            ICMP6HeaderOptions::Type4 { pointer } => {
+                log_warn!(
+                    "parameter problem, code {} at offset {}",
+                    icmp_header.get_code(),
+                    pointer
+                );
+            }
+            _ => {}
+        }
+
+        let icmp_type = icmp_header.get_type();
+        for subscriber in self.subscribers.iter() {
+            if subscriber.icmp_type == icmp_type {
+                subscriber
+                    .client
+                    .map(|client| client.receive(ip_header, icmp_header, body));
+            }
+        }
+    }
+}
+
+impl ICMP6SendClient for ICMP6RecvStruct<'a> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.sending.set(false);
+    }
+}
@@ -38,14 +38,16 @@ pub trait ICMP6Sender<'a> {
     ///
     /// `dest` - The destination IP address
     /// `icmp_header` - The ICMPv6 header to be sent
-    /// `buf` - The byte array containing the ICMPv6 payload
+    /// `buf` - The byte array containing the ICMPv6 payload, which is copied
+    /// before this function returns
     ///
     /// # Return Value
     ///
     /// This function returns a code reporting either success or any
-    /// synchronous errors. Note that any asynchronous errors are returned
-    /// via the callback.
-    fn send(&self, dest: IPAddr, icmp_header: ICMP6Header, buf: &'a [u8]) -> ReturnCode;
+    /// synchronous errors, such as `ESIZE` if the payload does not fit in the
+    /// packet buffer of the IP sender. Note that any asynchronous errors are
+    /// returned via the callback.
+    fn send(&self, dest: IPAddr, icmp_header: ICMP6Header, buf: &[u8]) -> ReturnCode;
 }
 
 /// A struct that implements the `ICMP6Sender` trait.
@@ -68,7 +70,7 @@ impl<T: IP6Sender<'a>> ICMP6Sender<'a> for ICMP6SendStruct<'a, T> {
         self.client.set(client);
     }
 
-    fn send(&self, dest: IPAddr, mut icmp_header: ICMP6Header, buf: &'a [u8]) -> ReturnCode {
+    fn send(&self, dest: IPAddr, mut icmp_header: ICMP6Header, buf: &[u8]) -> ReturnCode {
         let total_len = buf.len() + icmp_header.get_hdr_size();
         icmp_header.set_len(total_len as u16);
         let transport_header = TransportHeader::ICMP(icmp_header);
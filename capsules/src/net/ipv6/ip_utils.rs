@@ -165,14 +165,27 @@ pub fn compute_icmp_checksum(
 
     // add options
     match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
-            sum += unused >> 16; // upper 16 bits
-            sum += unused & 0xffff; // lower 16 bits
+        ICMP6HeaderOptions::Type1 { unused: word }
+        | ICMP6HeaderOptions::Type3 { unused: word }
+        | ICMP6HeaderOptions::Type4 { pointer: word }
+        | ICMP6HeaderOptions::Type133 { unused: word }
+        | ICMP6HeaderOptions::Type135 { unused: word }
+        | ICMP6HeaderOptions::Type136 { flags: word } => {
+            sum += word >> 16; // upper 16 bits
+            sum += word & 0xffff; // lower 16 bits
         }
         ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
             sum += id as u32;
             sum += seqno as u32;
         }
+        ICMP6HeaderOptions::Type134 {
+            hop_limit,
+            flags,
+            router_lifetime,
+        } => {
+            sum += (hop_limit as u32) << 8 | flags as u32;
+            sum += router_lifetime as u32;
+        }
     }
 
     // add icmp payload
@@ -183,7 +196,7 @@ pub fn compute_icmp_checksum(
     while sum > 0xffff {
         let sum_upper = sum >> 16;
         let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = sum_upper + sum_lower;
     }
 
     sum = !sum;
@@ -192,6 +205,28 @@ pub fn compute_icmp_checksum(
     sum as u16
 }
 
+/// Computes the checksum of a TCP segment. To set the checksum of a segment,
+/// pass its header with the checksum field set to 0. To check the checksum of
+/// a received segment, pass its header as it was received: the result is 0 if
+/// the checksum is correct.
+///
+/// # Arguments
+///
+/// `ip6_header` - The IPv6 header of the packet carrying the segment. Its
+/// payload length must be the length of the segment.
+/// `tcp_header` - The encoded TCP header, including options
+/// `payload` - The segment payload
+pub fn compute_tcp_checksum(ip6_header: &IP6Header, tcp_header: &[u8], payload: &[u8]) -> u16 {
+    let mut sum = compute_ipv6_ph_sum(ip6_header);
+    sum += compute_sum(tcp_header, tcp_header.len() as u16);
+    sum += compute_sum(payload, payload.len() as u16);
+
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
+    !sum as u16
+}
+
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
     let mut sum: u32 = 0;
 
@@ -209,7 +244,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
@@ -221,7 +256,12 @@ pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut i: usize = 0;
     while i < (len as usize) {
         let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
+        // An odd length is padded with a zero byte
+        let lsb = if i + 1 < len as usize {
+            buf[i + 1] as u32
+        } else {
+            0
+        };
         sum += msb + lsb;
         i += 2;
     }
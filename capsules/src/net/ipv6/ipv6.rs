@@ -66,15 +66,18 @@
 
 use kernel::ReturnCode;
 use net::icmpv6::icmpv6::ICMP6Header;
-use net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh, IPAddr};
+use net::ipv6::ip_utils::{compute_icmp_checksum, compute_tcp_checksum, compute_udp_checksum};
+use net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use net::stream::SResult;
 use net::stream::{decode_bytes, decode_u16, decode_u8};
 use net::stream::{encode_bytes, encode_u16, encode_u8};
-use net::tcp::TCPHeader;
+use net::tcp::tcp::{TCPHeader, TCP_HDR_LEN};
 use net::udp::udp::UDPHeader;
 
 pub const UDP_HDR_LEN: usize = 8;
 pub const ICMP_HDR_LEN: usize = 8;
+/// Largest TCP header that `TCPHeader` encodes.
+const TCP_MAX_HDR_LEN: usize = TCP_HDR_LEN + 4;
 
 /// This is the struct definition for an IPv6 header. It contains (in order)
 /// the same fields as a normal IPv6 header.
@@ -261,6 +264,9 @@ impl IP6Header {
     pub fn check_transport_checksum(&self, buf: &[u8]) -> ReturnCode {
         match self.next_header {
             ip6_nh::UDP => {
+                if buf.len() < UDP_HDR_LEN {
+                    return ReturnCode::FAIL; //Truncated header
+                }
                 let mut udp_header: [u8; UDP_HDR_LEN] = [0; UDP_HDR_LEN];
                 udp_header.copy_from_slice(&buf[..UDP_HDR_LEN]);
                 let checksum = match UDPHeader::decode(&udp_header).done() {
@@ -278,13 +284,23 @@ impl IP6Header {
                 ReturnCode::SUCCESS
             }
             ip6_nh::ICMP => {
-                // Untested (10/5/18)
-                let mut icmp_header: [u8; ICMP_HDR_LEN] = [0; ICMP_HDR_LEN];
-                icmp_header.copy_from_slice(&buf[..ICMP_HDR_LEN]);
-                let checksum = match ICMP6Header::decode(&icmp_header).done() {
-                    Some((_offset, mut hdr)) => {
-                        hdr.set_len(buf.len() as u16);
-                        u16::from_be(compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..]))
+                // The ICMPv6 checksum computation skips the checksum field, so
+                // the result must match the received checksum.
+                let valid = match ICMP6Header::decode(buf).done() {
+                    Some((offset, hdr)) => {
+                        compute_icmp_checksum(&self, &hdr, &buf[offset..]) == hdr.get_cksum()
+                    }
+                    None => false,
+                };
+                if !valid {
+                    return ReturnCode::FAIL; //Incorrect cksum
+                }
+                ReturnCode::SUCCESS
+            }
+            ip6_nh::TCP => {
+                let checksum = match TCPHeader::decode(buf).done() {
+                    Some((offset, _hdr)) => {
+                        compute_tcp_checksum(&self, &buf[..offset], &buf[offset..])
                     }
                     None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
                 };
@@ -342,10 +358,15 @@ impl IPPayload<'a> {
     ///
     /// `(u8, u16)` - Returns a tuple of the `ip6_nh` type of the
     /// `transport_header` and the total length of the `IPPayload`
-    /// (when serialized)
-    pub fn set_payload(&mut self, transport_header: TransportHeader, payload: &[u8]) -> (u8, u16) {
+    /// (when serialized), or `ESIZE` if the payload does not fit in the
+    /// payload buffer
+    pub fn set_payload(
+        &mut self,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> Result<(u8, u16), ReturnCode> {
         if self.payload.len() < payload.len() {
-            // TODO: Error
+            return Err(ReturnCode::ESIZE);
         }
         self.payload[..payload.len()].copy_from_slice(&payload);
         match transport_header {
@@ -353,15 +374,20 @@ impl IPPayload<'a> {
                 let length = (payload.len() + udp_header.get_hdr_size()) as u16;
                 udp_header.set_len(length);
                 self.header = transport_header;
-                (ip6_nh::UDP, length)
+                Ok((ip6_nh::UDP, length))
             }
             TransportHeader::ICMP(mut icmp_header) => {
                 let length = (payload.len() + icmp_header.get_hdr_size()) as u16;
                 icmp_header.set_len(length);
-                self.header = transport_header;
-                (ip6_nh::ICMP, length)
+                self.header = TransportHeader::ICMP(icmp_header);
+                Ok((ip6_nh::ICMP, length))
+            }
+            TransportHeader::TCP(mut tcp_header) => {
+                let length = (payload.len() + tcp_header.get_hdr_size()) as u16;
+                tcp_header.set_len(length);
+                self.header = TransportHeader::TCP(tcp_header);
+                Ok((ip6_nh::TCP, length))
             }
-            _ => (ip6_nh::NO_NEXT, payload.len() as u16),
         }
     }
 
@@ -380,9 +406,7 @@ impl IPPayload<'a> {
         let (offset, _) = match self.header {
             TransportHeader::UDP(udp_header) => udp_header.encode(buf, offset).done().unwrap(),
             TransportHeader::ICMP(icmp_header) => icmp_header.encode(buf, offset).done().unwrap(),
-            _ => {
-                unimplemented!();
-            }
+            TransportHeader::TCP(tcp_header) => tcp_header.encode(buf, offset).done().unwrap(),
         };
         let payload_length = self.get_payload_length();
         let offset = enc_consume!(buf, offset; encode_bytes, &self.payload[..payload_length]);
@@ -397,8 +421,8 @@ impl IPPayload<'a> {
             TransportHeader::ICMP(icmp_header) => {
                 icmp_header.get_len() as usize - icmp_header.get_hdr_size()
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(tcp_header) => {
+                tcp_header.get_len() as usize - tcp_header.get_hdr_size()
             }
         }
     }
@@ -446,7 +470,7 @@ impl IP6Packet<'a> {
         let transport_hdr_size = match self.payload.header {
             TransportHeader::UDP(udp_hdr) => udp_hdr.get_hdr_size(),
             TransportHeader::ICMP(icmp_header) => icmp_header.get_hdr_size(),
-            _ => unimplemented!(),
+            TransportHeader::TCP(tcp_header) => tcp_header.get_hdr_size(),
         };
         40 + transport_hdr_size
     }
@@ -472,8 +496,18 @@ impl IP6Packet<'a> {
                 let cksum = compute_icmp_checksum(&self.header, &icmp_header, self.payload.payload);
                 icmp_header.set_cksum(cksum);
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(ref mut tcp_header) => {
+                tcp_header.set_cksum(0);
+                let mut header = [0; TCP_MAX_HDR_LEN];
+                let hdr_size = tcp_header.get_hdr_size();
+                let payload_len = tcp_header.get_len() as usize - hdr_size;
+                tcp_header.encode(&mut header, 0);
+                let cksum = compute_tcp_checksum(
+                    &self.header,
+                    &header[..hdr_size],
+                    &self.payload.payload[..payload_len],
+                );
+                tcp_header.set_cksum(cksum);
             }
         }
     }
@@ -492,10 +526,24 @@ impl IP6Packet<'a> {
     /// `transport_header` - The `TransportHeader` to be set as the next header
     /// `payload` - The transport payload to be copied into the `IPPayload`
     /// transport payload
-    pub fn set_payload(&mut self, transport_header: TransportHeader, payload: &[u8]) {
-        let (next_header, payload_len) = self.payload.set_payload(transport_header, payload);
-        self.header.set_next_header(next_header);
-        self.header.set_payload_len(payload_len);
+    ///
+    /// # Return Value
+    ///
+    /// Returns `ESIZE` if the payload does not fit in the payload buffer, in
+    /// which case the packet is left unchanged.
+    pub fn set_payload(
+        &mut self,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        match self.payload.set_payload(transport_header, payload) {
+            Ok((next_header, payload_len)) => {
+                self.header.set_next_header(next_header);
+                self.header.set_payload_len(payload_len);
+                ReturnCode::SUCCESS
+            }
+            Err(err) => err,
+        }
     }
 
     // TODO: Do we need a decode equivalent? I don't think so, but we might
@@ -1,5 +1,7 @@
+use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::ReturnCode;
+use net::ipv6::ip_utils::ip6_nh;
 use net::ipv6::ipv6::IP6Header;
 use net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 
@@ -20,10 +22,13 @@ use net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 - The udp_mac MacUser has a single receive client, which is the `sixlowpan_state` struct
 - `sixlowpan_state` has a single rx_client, which in our case is a single struct that
   implements the `ip_receive ` trait.
-- the `ip_receive` implementing struct (`IP6RecvStruct`) has a single client, which is
-  udp_recv, a `UDPReceive` struct.
-- The UDPReceive struct is a field of the UDPDriver, which ultimately passes the
-  packets up to userland.
+- The `ip_receive` implementing struct (`IP6RecvStruct`) skips any extension
+  headers and passes each packet to the client for its transport protocol:
+  a UDP, TCP or ICMPv6 client, or a raw client for every other protocol.
+- The UDP client is udp_recv, a `UDPReceive` struct.
+- The client of the UDPReceive struct is the UDP mux (`MuxUDP`), which queues
+  each packet on the socket bound to its port. The UDPDriver holds the sockets
+  of userland apps, and ultimately passes the packets up to userland.
 */
 
 pub trait IP6RecvClient {
@@ -36,50 +41,232 @@ pub trait IP6RecvClient {
 /// The receiver receives IP packets destined for any local address.
 /// The receiver should drop any packets with destination addresses
 /// that are not among the local addresses of this device.
+///
+/// Packets are passed to a client based on their transport protocol. The
+/// header passed to the client describes the packet as if it had no
+/// extension headers: its next header is the transport protocol, and its
+/// payload length is the length of the transport packet.
 pub trait IP6Receiver<'a> {
-    fn set_client(&self, client: &'a IP6RecvClient);
+    /// Sets the client for UDP packets.
+    fn set_udp_client(&self, client: &'a IP6RecvClient);
+
+    /// Sets the client for TCP packets.
+    fn set_tcp_client(&self, client: &'a IP6RecvClient);
+
+    /// Sets the client for ICMPv6 packets.
+    fn set_icmp_client(&self, client: &'a IP6RecvClient);
+
+    /// Sets the client for packets whose transport protocol does not have a
+    /// client of its own, including protocols the stack does not implement.
+    /// The receiver does not verify the checksum of these packets.
+    fn set_raw_client(&self, client: &'a IP6RecvClient);
 }
 
+/// The reasons for which the receiver drops a packet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    /// The 6LoWPAN layer failed to receive the packet.
+    RxError = 0,
+    /// The IPv6 header or an extension header is truncated or invalid.
+    Malformed = 1,
+    /// The transport checksum is incorrect.
+    Checksum = 2,
+    /// The packet has an extension header or option that this node must
+    /// process but does not support, such as a fragment header or a routing
+    /// header that has segments left.
+    Unsupported = 3,
+    /// No client is set for the transport protocol of the packet.
+    NoClient = 4,
+}
+
+const DROP_REASON_COUNT: usize = 5;
+
 pub struct IP6RecvStruct<'a> {
-    client: OptionalCell<&'a IP6RecvClient>,
+    udp_client: OptionalCell<&'a IP6RecvClient>,
+    tcp_client: OptionalCell<&'a IP6RecvClient>,
+    icmp_client: OptionalCell<&'a IP6RecvClient>,
+    raw_client: OptionalCell<&'a IP6RecvClient>,
+    drops: [Cell<u32>; DROP_REASON_COUNT],
 }
 
 impl<'a> IP6Receiver<'a> for IP6RecvStruct<'a> {
-    fn set_client(&self, client: &'a IP6RecvClient) {
-        self.client.set(client);
+    fn set_udp_client(&self, client: &'a IP6RecvClient) {
+        self.udp_client.set(client);
+    }
+
+    fn set_tcp_client(&self, client: &'a IP6RecvClient) {
+        self.tcp_client.set(client);
+    }
+
+    fn set_icmp_client(&self, client: &'a IP6RecvClient) {
+        self.icmp_client.set(client);
+    }
+
+    fn set_raw_client(&self, client: &'a IP6RecvClient) {
+        self.raw_client.set(client);
     }
 }
 
 impl<'a> IP6RecvStruct<'a> {
     pub fn new() -> IP6RecvStruct<'a> {
         IP6RecvStruct {
-            client: OptionalCell::empty(),
+            udp_client: OptionalCell::empty(),
+            tcp_client: OptionalCell::empty(),
+            icmp_client: OptionalCell::empty(),
+            raw_client: OptionalCell::empty(),
+            drops: [
+                Cell::new(0),
+                Cell::new(0),
+                Cell::new(0),
+                Cell::new(0),
+                Cell::new(0),
+            ],
+        }
+    }
+
+    /// Returns the number of packets dropped for `reason` since boot or the
+    /// last call to `reset_drop_counts`.
+    pub fn get_drop_count(&self, reason: DropReason) -> u32 {
+        self.drops[reason as usize].get()
+    }
+
+    pub fn reset_drop_counts(&self) {
+        for count in self.drops.iter() {
+            count.set(0);
         }
     }
+
+    fn drop_packet(&self, reason: DropReason) {
+        let count = &self.drops[reason as usize];
+        count.set(count.get().wrapping_add(1));
+    }
+
+    /// Skips the extension headers at the start of `buf`, which holds the
+    /// payload of a packet with the given header. Returns the next header of
+    /// the last extension header, which is the transport protocol, and the
+    /// offset of the transport packet in `buf`.
+    ///
+    /// Hop-by-hop and destination options are skipped if every option in
+    /// them may be skipped by a node that does not recognize it, and a
+    /// routing header is skipped if no segments are left (RFC 8200). This
+    /// node does not forward packets or reassemble IPv6 fragments, so any
+    /// other extension header causes the packet to be dropped.
+    fn skip_extension_headers(
+        &self,
+        header: &IP6Header,
+        buf: &[u8],
+    ) -> Result<(u8, usize), DropReason> {
+        let mut next_header = header.get_next_header();
+        let mut offset = 0;
+        loop {
+            match next_header {
+                ip6_nh::HOP_OPTS | ip6_nh::DST_OPTS | ip6_nh::ROUTING => {}
+                ip6_nh::FRAGMENT => return Err(DropReason::Unsupported),
+                _ => return Ok((next_header, offset)),
+            }
+            // Every extension header starts with its next header and its
+            // length in 8-octet units, not counting the first 8 octets.
+            if buf.len() < offset + 8 {
+                return Err(DropReason::Malformed);
+            }
+            let ext_len = (buf[offset + 1] as usize + 1) * 8;
+            if buf.len() < offset + ext_len {
+                return Err(DropReason::Malformed);
+            }
+            let ext = &buf[offset..offset + ext_len];
+            match next_header {
+                // Hop-by-hop options may only follow the IPv6 header
+                ip6_nh::HOP_OPTS if offset != 0 => return Err(DropReason::Malformed),
+                ip6_nh::HOP_OPTS | ip6_nh::DST_OPTS => Self::check_options(&ext[2..])?,
+                // The third and fourth octets are the routing type and the
+                // number of segments left
+                _ => {
+                    if ext[3] != 0 {
+                        return Err(DropReason::Unsupported);
+                    }
+                }
+            }
+            next_header = ext[0];
+            offset += ext_len;
+        }
+    }
+
+    /// Checks the options of a hop-by-hop or destination options header.
+    /// None of the options are supported, so the packet must be dropped if
+    /// any option other than padding asks for it to be discarded when the
+    /// option is not recognized.
+    fn check_options(options: &[u8]) -> Result<(), DropReason> {
+        const PAD1: u8 = 0;
+        const PADN: u8 = 1;
+        let mut offset = 0;
+        while offset < options.len() {
+            let option_type = options[offset];
+            if option_type == PAD1 {
+                offset += 1;
+                continue;
+            }
+            if offset + 2 > options.len() {
+                return Err(DropReason::Malformed);
+            }
+            // The two highest bits of the type are the action to take if the
+            // option is not recognized, and 0 means skipping the option.
+            if option_type != PADN && option_type >> 6 != 0 {
+                return Err(DropReason::Unsupported);
+            }
+            offset += 2 + options[offset + 1] as usize;
+        }
+        if offset > options.len() {
+            return Err(DropReason::Malformed);
+        }
+        Ok(())
+    }
 }
 
 impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
     fn receive(&self, buf: &[u8], len: usize, result: ReturnCode) {
-        // TODO: Drop here?
         if len > buf.len() || result != ReturnCode::SUCCESS {
+            self.drop_packet(DropReason::RxError);
             return;
         }
-        match IP6Header::decode(buf).done() {
-            Some((offset, ip6_header)) => {
-                let checksum_result = ip6_header.check_transport_checksum(&buf[offset..len]);
-                if checksum_result == ReturnCode::FAIL {
-                    debug!("dropped!: {:?}", checksum_result);
-                    return; //Dropped.
-                }
-                // Note: Protocols for which checksum verification is not implemented (TCP, etc.)
-                // are automatically assumed as fine, rather than dropped
-
-                self.client
-                    .map(|client| client.receive(ip6_header, &buf[offset..len]));
+        let (offset, mut ip6_header) = match IP6Header::decode(&buf[..len]).done() {
+            Some(decoded) => decoded,
+            None => {
+                self.drop_packet(DropReason::Malformed);
+                return;
+            }
+        };
+        let ext_headers = self.skip_extension_headers(&ip6_header, &buf[offset..len]);
+        let (next_header, ext_len) = match ext_headers {
+            Ok(result) => result,
+            Err(reason) => {
+                self.drop_packet(reason);
+                return;
             }
+        };
+        let payload = &buf[offset + ext_len..len];
+        ip6_header.set_next_header(next_header);
+        ip6_header.set_payload_len(payload.len() as u16);
+
+        let client = match next_header {
+            ip6_nh::UDP => self.udp_client.map(|client| *client),
+            ip6_nh::TCP => self.tcp_client.map(|client| *client),
+            ip6_nh::ICMP => self.icmp_client.map(|client| *client),
+            _ => None,
+        };
+        let client = match client.or_else(|| self.raw_client.map(|client| *client)) {
+            Some(client) => client,
             None => {
-                // TODO: Report the error somewhere...
+                self.drop_packet(DropReason::NoClient);
+                return;
             }
+        };
+
+        // Note: Protocols for which checksum verification is not implemented
+        // are automatically assumed as fine, rather than dropped
+        if ip6_header.check_transport_checksum(payload) == ReturnCode::FAIL {
+            self.drop_packet(DropReason::Checksum);
+            return;
         }
+        client.receive(ip6_header, payload);
     }
 }
@@ -24,8 +24,12 @@ use kernel::ReturnCode;
 use net::ieee802154::MacAddress;
 use net::ipv6::ip_utils::IPAddr;
 use net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use net::sixlowpan::sixlowpan_compression::compute_mac;
 use net::sixlowpan::sixlowpan_state::TxState;
 
+/// The 802.15.4 short broadcast address, used for multicast packets.
+const BROADCAST_SHORT_ADDR: u16 = 0xffff;
+
 /// This trait must be implemented by upper layers in order to receive
 /// the `send_done` callback when a transmission has completed. The upper
 /// layer must then call `IP6Sender.set_client` in order to receive this
@@ -56,7 +60,8 @@ pub trait IP6Sender<'a> {
     fn set_addr(&self, src_addr: IPAddr);
 
     /// This method sets the gateway/next hop MAC address for this `IP6Sender`
-    /// instance.
+    /// instance. Packets to multicast and link-local destinations do not go
+    /// through the gateway.
     ///
     /// # Arguments
     /// `gateway` - MAC address to send the constructed packet to
@@ -76,6 +81,9 @@ pub trait IP6Sender<'a> {
     /// `dst` - IPv6 address to send the packet to
     /// `transport_header` - The `TransportHeader` for the packet being sent
     /// `payload` - The transport payload for the packet being sent
+    ///
+    /// Returns EBUSY if the previous packet has not been sent yet, and ESIZE
+    /// if the payload does not fit in the packet buffer of the sender.
     fn send_to(&self, dst: IPAddr, transport_header: TransportHeader, payload: &[u8])
         -> ReturnCode;
 }
@@ -93,9 +101,9 @@ pub struct IP6SendStruct<'a, A: time::Alarm> {
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
     client: OptionalCell<&'a IP6SendClient>,
+    busy: Cell<bool>,
 }
 
 impl<A: time::Alarm> IP6Sender<'a> for IP6SendStruct<'a, A> {
@@ -122,19 +130,31 @@ impl<A: time::Alarm> IP6Sender<'a> for IP6SendStruct<'a, A> {
         transport_header: TransportHeader,
         payload: &[u8],
     ) -> ReturnCode {
+        if self.busy.get() {
+            return ReturnCode::EBUSY;
+        }
         self.sixlowpan.init(
             self.src_mac_addr,
-            self.dst_mac_addr,
+            self.next_hop(dst),
             self.radio.get_pan(),
             None,
         );
-        self.init_packet(dst, transport_header, payload);
+        let ret = self.init_packet(dst, transport_header, payload);
+        if ret != ReturnCode::SUCCESS {
+            return ret;
+        }
+        self.busy.set(true);
         let ret = self.send_next_fragment();
+        if ret != ReturnCode::SUCCESS {
+            self.busy.set(false);
+        }
         ret
     }
 }
 
 impl<A: time::Alarm> IP6SendStruct<'a, A> {
+    /// `dst_mac_addr` is the initial gateway, which can be changed with
+    /// `set_gateway`.
     pub fn new(
         ip6_packet: &'static mut IP6Packet<'static>,
         alarm: &'a A,
@@ -152,20 +172,43 @@ impl<A: time::Alarm> IP6SendStruct<'a, A> {
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
             client: OptionalCell::empty(),
+            busy: Cell::new(false),
+        }
+    }
+
+    /// Returns the MAC address of the next hop towards `dst`. Multicast
+    /// packets are broadcast. Link-local addresses in 6LoWPAN are formed from
+    /// the MAC address of the interface (RFC 6775), so packets to them are
+    /// sent to the MAC address in their interface identifier. All other
+    /// packets are sent to the gateway.
+    fn next_hop(&self, dst: IPAddr) -> MacAddress {
+        if dst.is_multicast() {
+            MacAddress::Short(BROADCAST_SHORT_ADDR)
+        } else if dst.is_unicast_link_local() {
+            compute_mac(&dst.0[8..16])
+        } else {
+            self.gateway.get()
         }
     }
 
-    fn init_packet(&self, dst_addr: IPAddr, transport_header: TransportHeader, payload: &[u8]) {
-        self.ip6_packet.map(|ip6_packet| {
+    fn init_packet(
+        &self,
+        dst_addr: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        self.ip6_packet.map_or(ReturnCode::EBUSY, |ip6_packet| {
             ip6_packet.header = IP6Header::default();
             ip6_packet.header.src_addr = self.src_addr.get();
             ip6_packet.header.dst_addr = dst_addr;
-            ip6_packet.set_payload(transport_header, payload);
-            ip6_packet.set_transport_checksum();
-        });
+            let ret = ip6_packet.set_payload(transport_header, payload);
+            if ret == ReturnCode::SUCCESS {
+                ip6_packet.set_transport_checksum();
+            }
+            ret
+        })
     }
 
     // Returns EBUSY if the tx_buf is not there
@@ -214,6 +257,7 @@ impl<A: time::Alarm> IP6SendStruct<'a, A> {
     }
 
     fn send_completed(&self, result: ReturnCode) {
+        self.busy.set(false);
         self.client.map(move |client| client.send_done(result));
     }
 }
@@ -230,7 +274,7 @@ impl<A: time::Alarm> time::Client for IP6SendStruct<'a, A> {
 impl<A: time::Alarm> TxClient for IP6SendStruct<'a, A> {
     fn send_done(&self, tx_buf: &'static mut [u8], acked: bool, result: ReturnCode) {
         self.tx_buf.replace(tx_buf);
-        debug!("Send result: {:?}, acked: {}", result, acked);
+        log_debug!("send result: {}, acked: {}", isize::from(result), acked);
         // Below code adds delay between fragments. Despite some efforts
         // to fix this bug, I find that without it the receiving imix cannot
         // receive more than 2 fragments in a single packet without hanging
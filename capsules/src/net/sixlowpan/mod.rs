@@ -1,2 +1,3 @@
 pub mod sixlowpan_compression;
+pub mod sixlowpan_nd;
 pub mod sixlowpan_state;
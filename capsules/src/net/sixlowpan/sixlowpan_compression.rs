@@ -1,7 +1,9 @@
 /// Implements the 6LoWPAN specification for sending IPv6 datagrams over
 /// 802.15.4 packets efficiently, as detailed in RFC 6282.
+use core::cell::Cell;
 use core::mem;
 use core::result::Result;
+use kernel::ReturnCode;
 use net::ieee802154::MacAddress;
 use net::ipv6::ip_utils::{compute_udp_checksum, ip6_nh, IPAddr};
 use net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
@@ -113,7 +115,7 @@ pub fn compute_iid(mac_addr: &MacAddress) -> [u8; 8] {
         &MacAddress::Short(short_addr) => {
             // IID is 0000:00ff:fe00:XXXX, where XXXX is 16-bit MAC
             let mut iid: [u8; 8] = iphc::MAC_BASE;
-            iid[6] = (short_addr >> 1) as u8;
+            iid[6] = (short_addr >> 8) as u8;
             iid[7] = (short_addr & 0xff) as u8;
             iid
         }
@@ -126,6 +128,19 @@ pub fn compute_iid(mac_addr: &MacAddress) -> [u8; 8] {
     }
 }
 
+/// Computes the MAC address that an Interface Identifier was derived from
+/// by `compute_iid`.
+pub fn compute_mac(iid: &[u8]) -> MacAddress {
+    if iid[0..6] == iphc::MAC_BASE[0..6] {
+        MacAddress::Short((iid[6] as u16) << 8 | (iid[7] as u16))
+    } else {
+        let mut long_addr: [u8; 8] = [0; 8];
+        long_addr.copy_from_slice(&iid[0..8]);
+        long_addr[0] ^= iphc::MAC_UL;
+        MacAddress::Long(long_addr)
+    }
+}
+
 impl ContextStore for Context {
     fn get_context_from_addr(&self, ip_addr: IPAddr) -> Option<Context> {
         if util::matches_prefix(&ip_addr.0, &self.prefix, self.prefix_len) {
@@ -152,6 +167,89 @@ impl ContextStore for Context {
     }
 }
 
+/// Number of contexts a `ContextTable` can hold.
+pub const CONTEXT_TABLE_LEN: usize = 4;
+
+/// A `ContextStore` whose contexts can be changed at runtime, for example by
+/// the 6LoWPAN Context Options that routers advertise. Context 0 is set when
+/// the table is created, and can be updated but not removed.
+pub struct ContextTable {
+    contexts: [Cell<Option<Context>>; CONTEXT_TABLE_LEN],
+}
+
+impl ContextTable {
+    pub fn new(context_0: Context) -> ContextTable {
+        ContextTable {
+            contexts: [
+                Cell::new(Some(Context {
+                    id: 0,
+                    ..context_0
+                })),
+                Cell::new(None),
+                Cell::new(None),
+                Cell::new(None),
+            ],
+        }
+    }
+
+    /// Adds a context, or replaces the context with the same id. Returns
+    /// ENOMEM if the table is full.
+    pub fn update(&self, context: Context) -> ReturnCode {
+        let slot = self
+            .contexts
+            .iter()
+            .find(|slot| slot.get().map_or(false, |ctx| ctx.id == context.id))
+            .or_else(|| self.contexts.iter().find(|slot| slot.get().is_none()));
+        match slot {
+            Some(slot) => {
+                slot.set(Some(context));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    /// Removes the context with the given id, unless it is context 0.
+    pub fn remove(&self, ctx_id: u8) {
+        if ctx_id == 0 {
+            return;
+        }
+        for slot in self.contexts.iter() {
+            if slot.get().map_or(false, |ctx| ctx.id == ctx_id) {
+                slot.set(None);
+            }
+        }
+    }
+}
+
+impl ContextStore for ContextTable {
+    fn get_context_from_addr(&self, ip_addr: IPAddr) -> Option<Context> {
+        // Use the longest matching prefix
+        self.contexts
+            .iter()
+            .filter_map(|slot| slot.get())
+            .filter(|ctx| util::matches_prefix(&ip_addr.0, &ctx.prefix, ctx.prefix_len))
+            .max_by_key(|ctx| ctx.prefix_len)
+    }
+
+    fn get_context_from_id(&self, ctx_id: u8) -> Option<Context> {
+        self.contexts
+            .iter()
+            .filter_map(|slot| slot.get())
+            .find(|ctx| ctx.id == ctx_id)
+    }
+
+    fn get_context_from_prefix(&self, prefix: &[u8], prefix_len: u8) -> Option<Context> {
+        self.contexts
+            .iter()
+            .filter_map(|slot| slot.get())
+            .find(|ctx| {
+                ctx.prefix_len == prefix_len
+                    && util::matches_prefix(prefix, &ctx.prefix, prefix_len)
+            })
+    }
+}
+
 pub fn is_lowpan(packet: &[u8]) -> bool {
     (packet[0] & iphc::DISPATCH[0]) == iphc::DISPATCH[0]
 }
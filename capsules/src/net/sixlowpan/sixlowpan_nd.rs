@@ -0,0 +1,569 @@
+//! Implements the host side of Neighbor Discovery for 6LoWPAN networks, as
+//! detailed in RFC 6775.
+//!
+//! In 6LoWPAN-ND, hosts do not resolve the addresses of their neighbors with
+//! multicast solicitations. Instead, a host finds a router, forms its
+//! addresses from the prefixes the router advertises, and registers them with
+//! the router, which then forwards all traffic that is not link-local:
+//!
+//! 1. The host forms its link-local address from its MAC address with
+//!    `compute_iid`, and sends Router Solicitations to all routers.
+//! 2. The Router Advertisement gives the MAC address of the router, which is
+//!    used as the gateway of the `IP6Sender`s, the prefixes from which the
+//!    host forms its global address (SLAAC), and the 6LoWPAN contexts, which
+//!    are stored in the `ContextTable` used for header compression.
+//! 3. The host registers its global address by sending a Neighbor
+//!    Solicitation with an Address Registration Option to the router, and
+//!    starts using the address once a Neighbor Advertisement confirms it.
+//! 4. Before the registration, router, prefix or any context expires, the
+//!    host solicits a new Router Advertisement and registers again.
+//!
+//! The addresses of the host are written to the interface list shared with
+//! the UDP and TCP drivers: the link-local address to the first entry, and
+//! the global address to the second, once it is registered. The global
+//! address is also set as the source address of the `IP6Sender`s when the
+//! host starts registering it, as the Neighbor Solicitation must be sent from
+//! the address being registered.
+//!
+//! This implementation only keeps track of a single router and a single
+//! global address, and prefixes are only used if they are 64 bits long. It
+//! does not answer Neighbor Solicitations, as routers rely on the
+//! registrations instead, and does not support multihop registration
+//! (duplicate address requests to a border router).
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! // `icmp_recv` is set up as in `icmpv6_recv.rs`, `nd_icmp_send` is an
+//! // `ICMP6SendStruct` on an `IP6Sender` of its own, and `sixlowpan` is a
+//! // `Sixlowpan` that uses a `ContextTable`.
+//! let nd = static_init!(
+//!     SixlowpanND<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     SixlowpanND::new(
+//!         nd_virtual_alarm,
+//!         nd_icmp_send,
+//!         &IP_SENDERS,
+//!         &sixlowpan.ctx_store,
+//!         interface_list,
+//!         SRC_MAC_ADDR,
+//!         eui64
+//!     )
+//! );
+//! nd_virtual_alarm.set_client(nd);
+//! nd_icmp_send.set_client(nd);
+//! let ra_subscriber = static_init!(
+//!     ICMP6RecvSubscriber<'static>,
+//!     ICMP6RecvSubscriber::new(ICMP6Type::Type134)
+//! );
+//! ra_subscriber.set_client(nd);
+//! icmp_recv.add_subscriber(ra_subscriber);
+//! let na_subscriber = static_init!(
+//!     ICMP6RecvSubscriber<'static>,
+//!     ICMP6RecvSubscriber::new(ICMP6Type::Type136)
+//! );
+//! na_subscriber.set_client(nd);
+//! icmp_recv.add_subscriber(na_subscriber);
+//! nd.start();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::hil::time;
+use kernel::ReturnCode;
+use net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use net::icmpv6::icmpv6_send::{ICMP6SendClient, ICMP6Sender};
+use net::ieee802154::MacAddress;
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::IP6Header;
+use net::ipv6::ipv6_send::IP6Sender;
+use net::sixlowpan::sixlowpan_compression::{compute_iid, compute_mac, Context, ContextTable};
+use net::util::{slice_to_u16, slice_to_u32, u16_to_slice};
+
+/// Neighbor Discovery option types (RFC 4861, RFC 6775)
+mod nd_option {
+    pub const SLLAO: u8 = 1;
+    pub const PIO: u8 = 3;
+    pub const ARO: u8 = 33;
+    pub const SIXCO: u8 = 34;
+
+    pub const PIO_LEN: usize = 32;
+    pub const PIO_AUTONOMOUS: u8 = 0x40;
+    pub const ARO_LEN: usize = 16;
+    pub const SIXCO_COMPRESS: u8 = 0x10;
+    pub const SIXCO_CID_MASK: u8 = 0x0f;
+}
+
+/// Status values of the Address Registration Option. Any other status, such
+/// as `2` (Neighbor Cache full), means the router could not register us.
+mod aro_status {
+    pub const SUCCESS: u8 = 0;
+    pub const DUPLICATE: u8 = 1;
+}
+
+// Protocol constants, from RFC 4861 and RFC 6775
+const RTR_SOLICITATION_INTERVAL_S: u32 = 10;
+const MAX_RTR_SOLICITATIONS: u32 = 3;
+const MAX_RTR_SOLICITATION_INTERVAL_S: u32 = 3600;
+const RETRANS_TIMER_S: u32 = 1;
+const MAX_UNICAST_SOLICIT: u32 = 3;
+const ND_HOP_LIMIT: u8 = 255;
+
+/// Lifetime of the registrations of the host, in minutes.
+const REGISTRATION_LIFETIME_MIN: u16 = 60;
+
+/// Longest interval the alarm is set for. Longer timeouts are counted down
+/// in steps of this length, so that they do not overflow the alarm.
+const MAX_ALARM_STEP_S: u32 = 60;
+
+/// Length of the body of a Router Advertisement before its options.
+const RA_BODY_LEN: usize = 8;
+
+/// Length of the longest message body the host sends: a Neighbor
+/// Solicitation with an ARO and a SLLAO for a long MAC address.
+const MAX_BODY_LEN: usize = 48;
+
+const ALL_ROUTERS: IPAddr = IPAddr([
+    0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02,
+]);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Looking for a router
+    Soliciting,
+    /// Registering the global address with the router
+    Registering,
+    /// Registered, or no address to register
+    Registered,
+    /// The global address is used by another host
+    Failed,
+}
+
+pub struct SixlowpanND<'a, A: time::Alarm> {
+    alarm: &'a A,
+    icmp_sender: &'a ICMP6Sender<'a>,
+    ip_senders: &'a [&'a IP6Sender<'a>],
+    ctx_store: &'a ContextTable,
+    interface_list: &'a [Cell<IPAddr>],
+    mac_addr: MacAddress,
+    eui64: [u8; 8],
+
+    state: Cell<State>,
+    /// Messages sent in the current state
+    attempts: Cell<u32>,
+    /// Link-local address of the router
+    router: Cell<Option<IPAddr>>,
+    global_addr: Cell<Option<IPAddr>>,
+    /// Shortest of the lifetimes learned from the last Router Advertisement
+    lifetime_s: Cell<u32>,
+    /// Seconds left until the timeout of the current state
+    timer_s: Cell<u32>,
+    /// Whether a message is being sent
+    sending: Cell<bool>,
+    /// Whether to send the message of the current state once the message
+    /// being sent is done
+    pending: Cell<bool>,
+}
+
+impl<A: time::Alarm> SixlowpanND<'a, A> {
+    /// # Arguments
+    ///
+    /// * `icmp_sender` - Sends the Neighbor Discovery messages. Its client
+    /// must be this `SixlowpanND`, so it should not be shared with other
+    /// senders of ICMPv6 messages.
+    ///
+    /// * `ip_senders` - The senders whose gateway and source address are set
+    /// to the router and the address of the host, including the one that
+    /// `icmp_sender` sends with
+    ///
+    /// * `ctx_store` - The context table that is updated with the contexts
+    /// advertised by the router
+    ///
+    /// * `interface_list` - The addresses of the host, which must hold at
+    /// least two entries
+    ///
+    /// * `mac_addr` - The MAC address of the host, from which its addresses
+    /// are formed
+    ///
+    /// * `eui64` - The EUI-64 of the host, which identifies it in its
+    /// registrations
+    pub fn new(
+        alarm: &'a A,
+        icmp_sender: &'a ICMP6Sender<'a>,
+        ip_senders: &'a [&'a IP6Sender<'a>],
+        ctx_store: &'a ContextTable,
+        interface_list: &'a [Cell<IPAddr>],
+        mac_addr: MacAddress,
+        eui64: [u8; 8],
+    ) -> SixlowpanND<'a, A> {
+        SixlowpanND {
+            alarm: alarm,
+            icmp_sender: icmp_sender,
+            ip_senders: ip_senders,
+            ctx_store: ctx_store,
+            interface_list: interface_list,
+            mac_addr: mac_addr,
+            eui64: eui64,
+            state: Cell::new(State::Idle),
+            attempts: Cell::new(0),
+            router: Cell::new(None),
+            global_addr: Cell::new(None),
+            lifetime_s: Cell::new(0),
+            timer_s: Cell::new(0),
+            sending: Cell::new(false),
+            pending: Cell::new(false),
+        }
+    }
+
+    /// Forms the link-local address of the host, and starts looking for a
+    /// router.
+    pub fn start(&self) {
+        let mut link_local = IPAddr::new();
+        link_local.0[0] = 0xfe;
+        link_local.0[1] = 0x80;
+        link_local.0[8..16].copy_from_slice(&compute_iid(&self.mac_addr));
+        self.interface_list[0].set(link_local);
+        self.set_src_addr(link_local);
+        self.solicit();
+    }
+
+    fn set_src_addr(&self, addr: IPAddr) {
+        for sender in self.ip_senders.iter() {
+            sender.set_addr(addr);
+        }
+    }
+
+    fn solicit(&self) {
+        self.state.set(State::Soliciting);
+        self.attempts.set(0);
+        self.send_message();
+        self.set_timer(RTR_SOLICITATION_INTERVAL_S);
+    }
+
+    fn register(&self) {
+        self.state.set(State::Registering);
+        self.attempts.set(0);
+        self.global_addr.get().map(|addr| self.set_src_addr(addr));
+        self.send_message();
+        self.set_timer(RETRANS_TIMER_S);
+    }
+
+    /// Writes a Source Link-Layer Address Option to `buf`, and returns its
+    /// length.
+    fn encode_sllao(&self, buf: &mut [u8]) -> usize {
+        let len = match self.mac_addr {
+            MacAddress::Short(addr) => {
+                u16_to_slice(addr, &mut buf[2..4]);
+                8
+            }
+            MacAddress::Long(addr) => {
+                buf[2..10].copy_from_slice(&addr);
+                16
+            }
+        };
+        buf[0] = nd_option::SLLAO;
+        buf[1] = (len / 8) as u8;
+        len
+    }
+
+    /// Sends the message of the current state: a Router Solicitation while
+    /// looking for a router, or a Neighbor Solicitation while registering.
+    /// If a message is still being sent, the message is sent once that one
+    /// is done.
+    fn send_message(&self) {
+        if self.sending.get() {
+            self.pending.set(true);
+            return;
+        }
+        self.pending.set(false);
+        let result = match self.state.get() {
+            State::Soliciting => self.send_rs(),
+            State::Registering => self.send_ns(),
+            _ => return,
+        };
+        match result {
+            ReturnCode::SUCCESS => self.sending.set(true),
+            // Another client of the sender is sending, try again when our
+            // next message is done or the timer runs out.
+            ReturnCode::EBUSY => self.pending.set(true),
+            _ => log_warn!("failed to send ND message: {}", isize::from(result)),
+        }
+    }
+
+    fn send_rs(&self) -> ReturnCode {
+        let mut body = [0; MAX_BODY_LEN];
+        let len = self.encode_sllao(&mut body);
+        let header = ICMP6Header::new(ICMP6Type::Type133);
+        self.icmp_sender.send(ALL_ROUTERS, header, &body[..len])
+    }
+
+    /// Sends a Neighbor Solicitation that registers the global address with
+    /// the router.
+    fn send_ns(&self) -> ReturnCode {
+        let (router, addr) = match (self.router.get(), self.global_addr.get()) {
+            (Some(router), Some(addr)) => (router, addr),
+            _ => return ReturnCode::EINVAL,
+        };
+        let mut body = [0; MAX_BODY_LEN];
+        body[0..16].copy_from_slice(&addr.0);
+        {
+            let aro = &mut body[16..16 + nd_option::ARO_LEN];
+            aro[0] = nd_option::ARO;
+            aro[1] = (nd_option::ARO_LEN / 8) as u8;
+            u16_to_slice(REGISTRATION_LIFETIME_MIN, &mut aro[6..8]);
+            aro[8..16].copy_from_slice(&self.eui64);
+        }
+        let len = 16 + nd_option::ARO_LEN;
+        let len = len + self.encode_sllao(&mut body[len..]);
+        let header = ICMP6Header::new(ICMP6Type::Type135);
+        self.icmp_sender.send(router, header, &body[..len])
+    }
+
+    fn receive_ra(&self, ip_header: &IP6Header, router_lifetime: u16, body: &[u8]) {
+        let src_addr = ip_header.get_src_addr();
+        if body.len() < RA_BODY_LEN || !src_addr.is_unicast_link_local() {
+            return;
+        }
+        let mut router_mac = compute_mac(&src_addr.0[8..16]);
+        let mut prefix_addr = None;
+        let mut lifetime_s = router_lifetime as u32;
+        let options = &body[RA_BODY_LEN..];
+        let mut off = 0;
+        while off + 2 <= options.len() {
+            let len = options[off + 1] as usize * 8;
+            if len == 0 || off + len > options.len() {
+                return;
+            }
+            let option = &options[off..off + len];
+            match option[0] {
+                nd_option::SLLAO if len == 8 => {
+                    router_mac = MacAddress::Short(slice_to_u16(&option[2..4]));
+                }
+                nd_option::SLLAO => {
+                    let mut addr = [0; 8];
+                    addr.copy_from_slice(&option[2..10]);
+                    router_mac = MacAddress::Long(addr);
+                }
+                nd_option::PIO if len == nd_option::PIO_LEN => {
+                    let valid_s = slice_to_u32(&option[4..8]);
+                    if option[2] == 64 && option[3] & nd_option::PIO_AUTONOMOUS != 0 && valid_s > 0
+                    {
+                        let mut addr = IPAddr::new();
+                        addr.0[0..8].copy_from_slice(&option[16..24]);
+                        addr.0[8..16].copy_from_slice(&compute_iid(&self.mac_addr));
+                        prefix_addr = Some(addr);
+                        lifetime_s = cmp::min(lifetime_s, valid_s);
+                    }
+                }
+                nd_option::SIXCO if len >= 16 => {
+                    let id = option[3] & nd_option::SIXCO_CID_MASK;
+                    let valid_min = slice_to_u16(&option[6..8]);
+                    if valid_min == 0 {
+                        self.ctx_store.remove(id);
+                    } else {
+                        // The prefix field is padded to 8 or 16 bytes, and
+                        // prefixes are never longer than 16 bytes.
+                        let prefix_bytes = cmp::min(len - 8, 16);
+                        let prefix_len = cmp::min(option[2], (prefix_bytes * 8) as u8);
+                        let mut prefix = [0; 16];
+                        prefix[..prefix_bytes].copy_from_slice(&option[8..8 + prefix_bytes]);
+                        let stored = self.ctx_store.update(Context {
+                            prefix: prefix,
+                            prefix_len: prefix_len,
+                            id: id,
+                            compress: option[3] & nd_option::SIXCO_COMPRESS != 0,
+                        });
+                        // Only contexts that fit in the table need to be
+                        // refreshed before they expire.
+                        if stored == ReturnCode::SUCCESS {
+                            lifetime_s = cmp::min(lifetime_s, valid_min as u32 * 60);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            off += len;
+        }
+
+        // A router lifetime of 0 means that the router is not a default
+        // router, so only its contexts are used.
+        if router_lifetime == 0 {
+            return;
+        }
+        self.router.set(Some(src_addr));
+        for sender in self.ip_senders.iter() {
+            sender.set_gateway(router_mac);
+        }
+        self.lifetime_s.set(lifetime_s);
+
+        let prefix_changed = prefix_addr.map_or(false, |addr| Some(addr) != self.global_addr.get());
+        if prefix_addr.is_some() {
+            self.global_addr.set(prefix_addr);
+        }
+        match self.state.get() {
+            State::Soliciting | State::Registered if prefix_changed => self.register(),
+            State::Soliciting => {
+                if self.global_addr.get().is_some() {
+                    self.register();
+                } else {
+                    self.state.set(State::Registered);
+                    self.set_timer(self.refresh_interval(lifetime_s));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_na(&self, body: &[u8]) {
+        let addr = match self.global_addr.get() {
+            Some(addr) => addr,
+            None => return,
+        };
+        if self.state.get() != State::Registering || body.len() < 16 || body[0..16] != addr.0 {
+            return;
+        }
+        let options = &body[16..];
+        let mut off = 0;
+        while off + 2 <= options.len() {
+            let len = options[off + 1] as usize * 8;
+            if len == 0 || off + len > options.len() {
+                return;
+            }
+            let option = &options[off..off + len];
+            if option[0] == nd_option::ARO && len == nd_option::ARO_LEN {
+                self.registration_done(option[2], slice_to_u16(&option[6..8]));
+                return;
+            }
+            off += len;
+        }
+    }
+
+    fn registration_done(&self, status: u8, lifetime_min: u16) {
+        match status {
+            aro_status::SUCCESS => {
+                self.global_addr.get().map(|addr| self.interface_list[1].set(addr));
+                self.state.set(State::Registered);
+                let lifetime_s = cmp::min(self.lifetime_s.get(), lifetime_min as u32 * 60);
+                self.set_timer(self.refresh_interval(lifetime_s));
+            }
+            aro_status::DUPLICATE => {
+                // The address is formed from our MAC address, so there is no
+                // other address to try.
+                log_error!("global address is used by another host");
+                self.state.set(State::Failed);
+                self.forget_global_addr();
+            }
+            _ => {
+                // The router's cache is full, or it sent a status we do not
+                // know. Try again later, possibly with another router.
+                self.solicit();
+                self.attempts.set(MAX_RTR_SOLICITATIONS);
+            }
+        }
+    }
+
+    fn forget_global_addr(&self) {
+        self.global_addr.set(None);
+        self.interface_list[1].set(IPAddr::new());
+        self.set_src_addr(self.interface_list[0].get());
+    }
+
+    /// Refresh lifetimes well before they expire.
+    fn refresh_interval(&self, lifetime_s: u32) -> u32 {
+        cmp::max(lifetime_s - lifetime_s / 4, RTR_SOLICITATION_INTERVAL_S)
+    }
+
+    fn set_timer(&self, seconds: u32) {
+        self.timer_s.set(seconds);
+        self.set_alarm();
+    }
+
+    fn set_alarm(&self) {
+        let step_s = cmp::min(self.timer_s.get(), MAX_ALARM_STEP_S);
+        let ticks = time::ms_to_ticks::<A::Frequency>(step_s as u64 * 1000) as u32;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+    }
+
+    fn timeout(&self) {
+        let attempts = self.attempts.get() + 1;
+        self.attempts.set(attempts);
+        match self.state.get() {
+            State::Soliciting => {
+                self.send_message();
+                // After the initial solicitations, back off exponentially
+                let interval = if attempts < MAX_RTR_SOLICITATIONS {
+                    RTR_SOLICITATION_INTERVAL_S
+                } else {
+                    let shift = cmp::min(attempts - MAX_RTR_SOLICITATIONS + 1, 9);
+                    cmp::min(
+                        RTR_SOLICITATION_INTERVAL_S << shift,
+                        MAX_RTR_SOLICITATION_INTERVAL_S,
+                    )
+                };
+                self.set_timer(interval);
+            }
+            State::Registering => {
+                if attempts < MAX_UNICAST_SOLICIT {
+                    self.send_message();
+                    self.set_timer(RETRANS_TIMER_S);
+                } else {
+                    // The router does not answer, look for another one.
+                    self.router.set(None);
+                    if self.interface_list[1].get().is_unspecified() {
+                        self.set_src_addr(self.interface_list[0].get());
+                    }
+                    self.solicit();
+                }
+            }
+            State::Registered => self.solicit(),
+            State::Idle | State::Failed => {}
+        }
+    }
+}
+
+impl<A: time::Alarm> time::Client for SixlowpanND<'a, A> {
+    fn fired(&self) {
+        let step_s = cmp::min(self.timer_s.get(), MAX_ALARM_STEP_S);
+        self.timer_s.set(self.timer_s.get() - step_s);
+        if self.timer_s.get() > 0 {
+            self.set_alarm();
+        } else {
+            self.timeout();
+        }
+    }
+}
+
+impl<A: time::Alarm> ICMP6SendClient for SixlowpanND<'a, A> {
+    fn send_done(&self, result: ReturnCode) {
+        self.sending.set(false);
+        if result != ReturnCode::SUCCESS {
+            // The message is sent again when the timer runs out.
+            log_warn!("ND message not sent: {}", isize::from(result));
+        }
+        if self.pending.get() {
+            self.send_message();
+        }
+    }
+}
+
+impl<A: time::Alarm> ICMP6RecvClient for SixlowpanND<'a, A> {
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        // Neighbor Discovery messages must not have been forwarded
+        if ip_header.get_hop_limit() != ND_HOP_LIMIT || icmp_header.get_code() != 0 {
+            return;
+        }
+        match self.state.get() {
+            State::Idle | State::Failed => return,
+            _ => {}
+        }
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type134 {
+                router_lifetime, ..
+            } => self.receive_ra(&ip_header, router_lifetime, payload),
+            ICMP6HeaderOptions::Type136 { .. } => self.receive_na(payload),
+            _ => {}
+        }
+    }
+}
@@ -681,9 +681,10 @@ impl RxState<'a> {
 
     // Checks if a given RxState is free or expired (and thus, can be freed).
     // This function implements the reassembly timeout for 6LoWPAN lazily.
-    fn is_busy(&self, frequency: u32, current_time: u32) -> bool {
-        let expired = current_time >= (self.start_time.get() + FRAG_TIMEOUT * frequency);
-        if expired {
+    fn is_busy<F: Frequency>(&self, current_time: u32) -> bool {
+        let timeout = time::ms_to_ticks::<F>(FRAG_TIMEOUT as u64 * 1000);
+        let elapsed = current_time.wrapping_sub(self.start_time.get()) as u64;
+        if self.busy.get() && elapsed >= timeout {
             self.end_receive(None, ReturnCode::FAIL);
         }
         self.busy.get()
@@ -906,7 +907,7 @@ impl<A: time::Alarm, C: ContextStore> Sixlowpan<'a, A, C> {
         let rx_state = self
             .rx_states
             .iter()
-            .find(|state| !state.is_busy(self.clock.now(), A::Frequency::frequency()));
+            .find(|state| !state.is_busy::<A::Frequency>(self.clock.now()));
         rx_state
             .map(|state| {
                 state.start_receive(
@@ -977,7 +978,7 @@ impl<A: time::Alarm, C: ContextStore> Sixlowpan<'a, A, C> {
             rx_state = self
                 .rx_states
                 .iter()
-                .find(|state| !state.is_busy(self.clock.now(), A::Frequency::frequency()));
+                .find(|state| !state.is_busy::<A::Frequency>(self.clock.now()));
             // Initialize new state
             rx_state.map(|state| {
                 state.start_receive(
@@ -0,0 +1,748 @@
+//! TCP userspace interface.
+//!
+//! Implements a userspace interface for TCP connections. Each process can
+//! have one connection at a time, which it either opens to a remote endpoint
+//! or accepts on a local port. The kernel does not buffer the data of a
+//! connection. Instead, data that was sent stays in the write buffer of the
+//! process until the remote end acknowledges it, and received data is
+//! appended to the read buffer of the process, whose free space is the window
+//! advertised to the remote end.
+//!
+//! Initial sequence numbers are chosen as in RFC 6528: the clock plus a hash
+//! of the endpoints of the connection and a secret, so that they cannot be
+//! guessed from the sequence numbers of other connections. The driver takes
+//! the secret from an `Rng` it is the client of, and the board must call
+//! `get()` on that `Rng` once at boot.
+//!
+//! The driver sends segments with its own `IP6Sender`, and receives them as
+//! a client of an `IP6Receiver`. Segments are copied into a kernel buffer
+//! before they are handed to the sender, so the size of that buffer is the
+//! largest segment the driver sends or accepts. It must not be larger than the
+//! payload buffer of the sender's `IP6Packet`.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! static mut TCP_SEGMENT_BUF: [u8; 192] = [0; 192];
+//!
+//! // `ip_send` is set up the same way as for the UDP driver, with its own
+//! // `MacUser` and `IP6Packet`.
+//! let tcp_driver = static_init!(
+//!     capsules::net::tcp::TCPDriver<
+//!         'static,
+//!         IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     >,
+//!     capsules::net::tcp::TCPDriver::new(
+//!         ip_send,
+//!         tcp_virtual_alarm,
+//!         &mut TCP_SEGMENT_BUF,
+//!         board_kernel.create_grant(&grant_cap),
+//!         &LOCAL_IP_IFACES
+//!     )
+//! );
+//! ip_send.set_client(tcp_driver);
+//! ip_receive.set_tcp_client(tcp_driver);
+//! tcp_virtual_alarm.set_client(tcp_driver);
+//! tcp_rng.set_client(tcp_driver);
+//! tcp_rng.get();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::TakeCell;
+use kernel::hil::{rng, time};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use net::ipv6::ipv6::{IP6Header, TransportHeader};
+use net::ipv6::ipv6_recv::IP6RecvClient;
+use net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use net::stream::encode_u16;
+use net::stream::SResult;
+use net::tcp::tcp::TCPHeader;
+use net::tcp::tcp_state::{self, TCPConnection, TCPEndpoint, TCPState};
+use sha256_verifier::Sha256;
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x30003;
+
+/// Length of an endpoint in the config buffer: the address followed by the
+/// port in network byte order.
+const ENDPOINT_LEN: usize = 18;
+
+/// First port that is handed out when a process connects from port 0.
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+#[derive(Default)]
+pub struct App {
+    event_callback: Option<Callback>,
+    rx_callback: Option<Callback>,
+    tx_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<Shared, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+    connection: TCPConnection,
+    /// Bytes of received data at the start of the read buffer.
+    rx_len: usize,
+    /// Length of the write in progress.
+    tx_len: usize,
+    /// Bytes of the write in progress that were acknowledged.
+    tx_acked: usize,
+    /// When the timer of the connection expires, in alarm ticks.
+    timer: Option<u32>,
+}
+
+impl App {
+    /// Returns the free space in the read buffer.
+    fn rcv_wnd(&self) -> usize {
+        self.app_read
+            .as_ref()
+            .map_or(0, |buf| buf.len().saturating_sub(self.rx_len))
+    }
+}
+
+pub struct TCPDriver<'a, T: IP6Sender<'a> + 'a, A: time::Alarm + 'a> {
+    sender: &'a T,
+    alarm: &'a A,
+
+    /// Grant of apps that use this driver.
+    apps: Grant<App>,
+
+    /// Holds the payload of a segment while it is handed to the sender.
+    tx_buf: TakeCell<'static, [u8]>,
+    max_segment_size: usize,
+    /// Whether the sender is busy with a segment.
+    sending: Cell<bool>,
+    /// Reset for a segment that does not belong to a connection, with the
+    /// local and the remote address.
+    pending_reset: Cell<Option<(IPAddr, IPAddr, TCPHeader)>>,
+
+    /// List of IP Addresses of the interfaces on the device
+    interface_list: &'static [Cell<IPAddr>],
+    next_ephemeral_port: Cell<u16>,
+
+    /// Secret for initial sequence numbers, from the `Rng`.
+    iss_secret: Cell<[u32; 4]>,
+    /// Number of words of `iss_secret` that the `Rng` filled in.
+    iss_secret_len: Cell<usize>,
+}
+
+impl<T: IP6Sender<'a>, A: time::Alarm> TCPDriver<'a, T, A> {
+    pub fn new(
+        sender: &'a T,
+        alarm: &'a A,
+        tx_buf: &'static mut [u8],
+        grant: Grant<App>,
+        interface_list: &'static [Cell<IPAddr>],
+    ) -> TCPDriver<'a, T, A> {
+        TCPDriver {
+            sender: sender,
+            alarm: alarm,
+            apps: grant,
+            max_segment_size: cmp::min(tx_buf.len(), 0xffff),
+            tx_buf: TakeCell::new(tx_buf),
+            sending: Cell::new(false),
+            pending_reset: Cell::new(None),
+            interface_list: interface_list,
+            next_ephemeral_port: Cell::new(EPHEMERAL_PORT_START),
+            iss_secret: Cell::new([0; 4]),
+            iss_secret_len: Cell::new(0),
+        }
+    }
+
+    /// Returns the initial sequence number for a connection from `local` to
+    /// `remote`, as in RFC 6528.
+    fn initial_seq_num(&self, local: &TCPEndpoint, remote: &TCPEndpoint) -> u32 {
+        let mut sha = Sha256::new();
+        for word in self.iss_secret.get().iter() {
+            sha.update(&[(word >> 24) as u8, (word >> 16) as u8, (word >> 8) as u8, *word as u8]);
+        }
+        for endpoint in [local, remote].iter() {
+            sha.update(&endpoint.addr.0);
+            sha.update(&[(endpoint.port >> 8) as u8, endpoint.port as u8]);
+        }
+        let hash = sha.finish();
+        let offset = (hash[0] as u32) << 24
+            | (hash[1] as u32) << 16
+            | (hash[2] as u32) << 8
+            | hash[3] as u32;
+        self.alarm.now().wrapping_add(offset)
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Returns the app whose connection `matches`.
+    fn find_app<F>(&self, matches: F) -> Option<AppId>
+    where
+        F: Fn(&TCPConnection) -> bool,
+    {
+        let mut found = None;
+        for app in self.apps.iter() {
+            found = app.enter(|app, _| {
+                if matches(&app.connection) {
+                    Some(app.appid())
+                } else {
+                    None
+                }
+            });
+            if found.is_some() {
+                break;
+            }
+        }
+        found
+    }
+
+    fn is_local(&self, addr: &IPAddr) -> bool {
+        !addr.is_unspecified() && self.interface_list.iter().any(|iface| iface.get() == *addr)
+    }
+
+    fn port_in_use(&self, port: u16) -> bool {
+        self.find_app(|connection| {
+            !connection.is_closed() && connection.get_local().port == port
+        }).is_some()
+    }
+
+    /// Returns an unused port from the ephemeral range.
+    fn ephemeral_port(&self) -> u16 {
+        loop {
+            let port = self.next_ephemeral_port.get();
+            self.next_ephemeral_port
+                .set(port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START));
+            if !self.port_in_use(port) {
+                return port;
+            }
+        }
+    }
+
+    /// Reads endpoint number `index` from the config buffer of `appid`.
+    fn read_endpoint(&self, appid: AppId, index: usize) -> Option<TCPEndpoint> {
+        self.apps
+            .enter(appid, |app, _| {
+                app.app_cfg.as_ref().and_then(|cfg| {
+                    let cfg = cfg.as_ref();
+                    if cfg.len() < (index + 1) * ENDPOINT_LEN {
+                        return None;
+                    }
+                    let buf = &cfg[index * ENDPOINT_LEN..(index + 1) * ENDPOINT_LEN];
+                    let mut addr = IPAddr::new();
+                    addr.0.copy_from_slice(&buf[..16]);
+                    Some(TCPEndpoint {
+                        addr: addr,
+                        port: (buf[16] as u16) << 8 | buf[17] as u16,
+                    })
+                })
+            }).unwrap_or(None)
+    }
+
+    /// Writes the local and remote endpoint of the connection of `app` to its
+    /// config buffer.
+    fn write_endpoints(&self, app: &mut App) -> ReturnCode {
+        let endpoints = [app.connection.get_local(), app.connection.get_remote()];
+        app.app_cfg.as_mut().map_or(ReturnCode::EINVAL, |cfg| {
+            if cfg.len() < 2 * ENDPOINT_LEN {
+                return ReturnCode::EINVAL;
+            }
+            for (i, endpoint) in endpoints.iter().enumerate() {
+                let buf = &mut cfg.as_mut()[i * ENDPOINT_LEN..(i + 1) * ENDPOINT_LEN];
+                buf[..16].copy_from_slice(&endpoint.addr.0);
+                encode_port(&mut buf[16..], endpoint.port);
+            }
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn connect(&self, appid: AppId) -> ReturnCode {
+        let (local, remote) = match (self.read_endpoint(appid, 0), self.read_endpoint(appid, 1)) {
+            (Some(local), Some(remote)) => (local, remote),
+            _ => return ReturnCode::EINVAL,
+        };
+        if !self.is_local(&local.addr) || remote.addr.is_unspecified() || remote.port == 0 {
+            return ReturnCode::EINVAL;
+        }
+        let local = if local.port == 0 {
+            TCPEndpoint {
+                addr: local.addr,
+                port: self.ephemeral_port(),
+            }
+        } else if self.port_in_use(local.port) {
+            return ReturnCode::EBUSY;
+        } else {
+            local
+        };
+        let iss = self.initial_seq_num(&local, &remote);
+        self.open(appid, |connection| {
+            connection.connect(local, remote, iss, self.max_segment_size)
+        })
+    }
+
+    fn listen(&self, appid: AppId) -> ReturnCode {
+        let local = match self.read_endpoint(appid, 0) {
+            Some(local) => local,
+            None => return ReturnCode::EINVAL,
+        };
+        if !self.is_local(&local.addr) || local.port == 0 {
+            return ReturnCode::EINVAL;
+        }
+        if self.port_in_use(local.port) {
+            return ReturnCode::EBUSY;
+        }
+        // The initial sequence number is chosen again once the remote end is
+        // known.
+        let iss = self.initial_seq_num(&local, &TCPEndpoint::default());
+        self.open(appid, |connection| {
+            connection.listen(local, iss, self.max_segment_size)
+        })
+    }
+
+    /// Replace the connection of `appid`, if it is closed, with a new one
+    /// that `open` sets up.
+    fn open<F>(&self, appid: AppId, open: F) -> ReturnCode
+    where
+        F: FnOnce(&mut TCPConnection),
+    {
+        let ret = self.do_with_app(appid, |app| {
+            if !app.connection.is_closed() {
+                return ReturnCode::EBUSY;
+            }
+            open(&mut app.connection);
+            app.rx_len = 0;
+            app.tx_len = 0;
+            app.tx_acked = 0;
+            app.timer = None;
+            ReturnCode::SUCCESS
+        });
+        self.do_output();
+        ret
+    }
+
+    /// Start, restart or stop the timer of the connection of `app`. The
+    /// alarm is set by `arm_alarm`.
+    fn update_timer(&self, app: &mut App, restart: bool) {
+        if !app.connection.timer_needed() {
+            app.timer = None;
+        } else if restart || app.timer.is_none() {
+            let ms = app.connection.timeout_ms() as u64;
+            let ticks = time::ms_to_ticks::<A::Frequency>(ms) as u32;
+            app.timer = Some(self.alarm.now().wrapping_add(ticks));
+        }
+    }
+
+    /// Set the alarm for the timer that expires first.
+    fn arm_alarm(&self) {
+        let now = self.alarm.now();
+        let mut next: Option<u32> = None;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                app.timer.map(|timer| {
+                    // Timers that already expired have a negative remainder.
+                    let remaining = cmp::max(timer.wrapping_sub(now) as i32, 1) as u32;
+                    next = Some(next.map_or(remaining, |next| cmp::min(next, remaining)));
+                });
+            });
+        }
+        match next {
+            Some(remaining) => self.alarm.set_alarm(now.wrapping_add(remaining)),
+            None => self.alarm.disable(),
+        }
+    }
+
+    /// Send segments until the sender is busy or there is nothing to send.
+    fn do_output(&self) {
+        // The sender can finish a segment before `send_to` returns, which
+        // calls this function again while `tx_buf` is in use.
+        if self.tx_buf.is_none() {
+            return;
+        }
+        while !self.sending.get() {
+            let segment = self
+                .pending_reset
+                .take()
+                .map(|(src, dst, header)| (src, dst, header, 0))
+                .or_else(|| self.next_app_segment());
+            match segment {
+                Some((src, dst, header, len)) => {
+                    self.sending.set(true);
+                    self.sender.set_addr(src);
+                    let result = self.tx_buf.map_or(ReturnCode::ENOMEM, |buf| {
+                        self.sender
+                            .send_to(dst, TransportHeader::TCP(header), &buf[..len])
+                    });
+                    if result != ReturnCode::SUCCESS {
+                        // The segment is lost, and will be retransmitted if
+                        // it needs to be.
+                        self.sending.set(false);
+                    }
+                }
+                None => break,
+            }
+        }
+        self.arm_alarm();
+    }
+
+    /// Returns the next segment that an app wants to send, with its payload
+    /// copied to `tx_buf`, as (local address, remote address, header,
+    /// payload length).
+    ///
+    /// Notably, an app that keeps sending can starve apps with a later ID.
+    fn next_app_segment(&self) -> Option<(IPAddr, IPAddr, TCPHeader, usize)> {
+        let mut segment = None;
+        for app in self.apps.iter() {
+            segment = app.enter(|app, _| {
+                let rcv_wnd = app.rcv_wnd();
+                app.connection.next_segment(rcv_wnd).map(|seg| {
+                    let start = app.tx_acked + seg.data_offset;
+                    let end = start + seg.data_len;
+                    let len = app.app_write.as_ref().map_or(0, |write| {
+                        if end > write.len() {
+                            // The app replaced its write buffer with a shorter
+                            // one, so its data can't be sent.
+                            return 0;
+                        }
+                        self.tx_buf.map_or(0, |buf| {
+                            buf[..seg.data_len].copy_from_slice(&write.as_ref()[start..end]);
+                            seg.data_len
+                        })
+                    });
+                    self.update_timer(app, false);
+                    let connection = &app.connection;
+                    (
+                        connection.get_local().addr,
+                        connection.get_remote().addr,
+                        seg.header,
+                        len,
+                    )
+                })
+            });
+            if segment.is_some() {
+                break;
+            }
+        }
+        segment
+    }
+
+    /// Handle a segment for the connection of `app`.
+    fn receive_segment(
+        &self,
+        app: &mut App,
+        ip_header: &IP6Header,
+        header: &TCPHeader,
+        data: &[u8],
+    ) {
+        let old_state = app.connection.get_state();
+        let rcv_wnd = app.rcv_wnd();
+        let received =
+            app.connection
+                .receive(ip_header.get_src_addr(), header, data.len(), rcv_wnd);
+        if old_state == TCPState::Listen && app.connection.get_state() == TCPState::SynReceived {
+            let (local, remote) = (app.connection.get_local(), app.connection.get_remote());
+            app.connection.set_iss(self.initial_seq_num(&local, &remote));
+        }
+
+        if received.data_len > 0 {
+            let start = received.data_offset;
+            let end = start + received.data_len;
+            let rx_len = app.rx_len;
+            app.app_read.as_mut().map(|read| {
+                read.as_mut()[rx_len..rx_len + end - start].copy_from_slice(&data[start..end]);
+            });
+            app.rx_len += received.data_len;
+            let rx_len = app.rx_len;
+            app.rx_callback.map(|mut cb| cb.schedule(rx_len, 0, 0));
+        }
+        if received.acked > 0 {
+            app.tx_acked += received.acked;
+            if app.tx_acked == app.tx_len {
+                let tx_len = app.tx_len;
+                app.tx_callback.map(|mut cb| cb.schedule(tx_len, 0, 0));
+            }
+        }
+        if received.events != 0 {
+            app.event_callback
+                .map(|mut cb| cb.schedule(received.events, 0, 0));
+        }
+        if received.reset {
+            self.queue_reset(ip_header, header, data.len());
+        }
+        let restart = received.acked > 0 || app.connection.get_state() != old_state;
+        self.update_timer(app, restart);
+    }
+
+    /// Answer a segment with a reset. Resets are not retransmitted, so if one
+    /// is already waiting to be sent, this one is dropped.
+    fn queue_reset(&self, ip_header: &IP6Header, header: &TCPHeader, data_len: usize) {
+        if self.pending_reset.get().is_none() {
+            self.pending_reset.set(
+                tcp_state::reset_reply(header, data_len)
+                    .map(|reset| (ip_header.get_dst_addr(), ip_header.get_src_addr(), reset)),
+            );
+        }
+    }
+}
+
+fn encode_port(buf: &mut [u8], port: u16) -> SResult<usize> {
+    let off = enc_consume!(buf, 0; encode_u16, port);
+    stream_done!(off, off);
+}
+
+impl<T: IP6Sender<'a>, A: time::Alarm> Driver for TCPDriver<'a, T, A> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Read buffer. Received data is appended to it. The free space in
+    ///        this buffer is the window advertised to the remote end, so the
+    ///        buffer must be allowed before connecting or listening. Received
+    ///        data that does not fit in a new, shorter buffer is dropped.
+    /// - `1`: Write buffer. Contains the data to send.
+    /// - `2`: Config buffer. Contains endpoints (16 byte IPv6 address followed
+    ///        by a 2 byte port in network byte order) for some commands.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 => self.do_with_app(appid, |app| {
+                match allow_num {
+                    0 => {
+                        let len = slice.as_ref().map_or(0, |read| read.len());
+                        app.rx_len = cmp::min(app.rx_len, len);
+                        app.app_read = slice;
+                    }
+                    1 => app.app_write = slice,
+                    2 => app.app_cfg = slice,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Connection events. The first argument is a bit mask of
+    ///        `tcp_state::tcp_event` values.
+    /// - `1`: Data was received. The first argument is the number of bytes of
+    ///        received data in the read buffer.
+    /// - `2`: All data of a write was acknowledged. The first argument is the
+    ///        length of the write.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 | 1 | 2 => self.do_with_app(app_id, |app| {
+                match subscribe_num {
+                    0 => app.event_callback = callback,
+                    1 => app.rx_callback = callback,
+                    2 => app.tx_callback = callback,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// TCP control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Connect from the local endpoint in the first half of the config
+    ///        buffer to the remote endpoint in the second half. A local port of
+    ///        0 picks an unused port. Returns EINVAL if the endpoints can't be
+    ///        parsed or the local address is not an interface address, and
+    ///        EBUSY if the app has an open connection or the local port is in
+    ///        use. The event callback reports when the connection is open.
+    /// - `2`: Listen on the local endpoint at the start of the config buffer,
+    ///        and accept the first connection to it. Returns the same errors as
+    ///        `1`. Command `7` gives the remote endpoint once the event
+    ///        callback reports the connection.
+    /// - `3`: Send the first `arg1` bytes of the write buffer. Returns ERESERVE
+    ///        if the connection is not open or is closing, EBUSY if a previous
+    ///        write was not acknowledged yet, and EINVAL if the write buffer is
+    ///        shorter than `arg1`.
+    /// - `4`: Drop the first `arg1` bytes of received data from the read
+    ///        buffer, moving the rest of the data to the start of the buffer.
+    ///        This opens the window for the remote end to send more.
+    /// - `5`: Close the connection once all data is sent. The event callback
+    ///        reports when the remote end closes as well.
+    /// - `6`: Abort the connection, sending a reset to the remote end.
+    /// - `7`: Write the local and remote endpoint of the connection to the
+    ///        config buffer.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        let ret = match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.connect(appid),
+            2 => self.listen(appid),
+            3 => self.do_with_app(appid, |app| {
+                if !app.connection.can_send() {
+                    return ReturnCode::ERESERVE;
+                }
+                if app.connection.get_queued() > 0 {
+                    return ReturnCode::EBUSY;
+                }
+                if arg1 == 0 || app.app_write.as_ref().map_or(0, |write| write.len()) < arg1 {
+                    return ReturnCode::EINVAL;
+                }
+                app.tx_len = arg1;
+                app.tx_acked = 0;
+                app.connection.send(arg1);
+                ReturnCode::SUCCESS
+            }),
+            4 => self.do_with_app(appid, |app| {
+                if arg1 > app.rx_len {
+                    return ReturnCode::EINVAL;
+                }
+                let rx_len = app.rx_len;
+                app.app_read.as_mut().map(|read| {
+                    let read = read.as_mut();
+                    for i in arg1..cmp::min(rx_len, read.len()) {
+                        read[i - arg1] = read[i];
+                    }
+                });
+                app.rx_len -= arg1;
+                app.connection.window_opened();
+                ReturnCode::SUCCESS
+            }),
+            5 => self.do_with_app(appid, |app| {
+                app.connection.close();
+                self.update_timer(app, false);
+                ReturnCode::SUCCESS
+            }),
+            6 => self.do_with_app(appid, |app| {
+                let connection = &mut app.connection;
+                let local = connection.get_local().addr;
+                let remote = connection.get_remote().addr;
+                connection.abort().map(|reset| {
+                    self.pending_reset.set(Some((local, remote, reset)));
+                });
+                app.timer = None;
+                ReturnCode::SUCCESS
+            }),
+            7 => self.do_with_app(appid, |app| self.write_endpoints(app)),
+            _ => return ReturnCode::ENOSUPPORT,
+        };
+        self.do_output();
+        ret
+    }
+}
+
+impl<T: IP6Sender<'a>, A: time::Alarm> IP6SendClient for TCPDriver<'a, T, A> {
+    fn send_done(&self, _result: ReturnCode) {
+        // Lost segments are retransmitted when the timer of their connection
+        // expires, so the result does not matter.
+        self.sending.set(false);
+        self.do_output();
+    }
+}
+
+impl<T: IP6Sender<'a>, A: time::Alarm> IP6RecvClient for TCPDriver<'a, T, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::TCP {
+            return;
+        }
+        let (offset, header) = match TCPHeader::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let data = &payload[offset..];
+        let local = TCPEndpoint {
+            addr: ip_header.get_dst_addr(),
+            port: header.get_dst_port(),
+        };
+        let remote = TCPEndpoint {
+            addr: ip_header.get_src_addr(),
+            port: header.get_src_port(),
+        };
+
+        // A connection takes precedence over a listening app.
+        let appid = self
+            .find_app(|connection| {
+                let state = connection.get_state();
+                state != TCPState::Closed
+                    && state != TCPState::Listen
+                    && connection.get_local() == local
+                    && connection.get_remote() == remote
+            }).or_else(|| {
+                self.find_app(|connection| {
+                    connection.get_state() == TCPState::Listen && connection.get_local() == local
+                })
+            });
+        match appid {
+            Some(appid) => {
+                let _ = self.apps.enter(appid, |app, _| {
+                    self.receive_segment(app, &ip_header, &header, data)
+                });
+            }
+            None => self.queue_reset(&ip_header, &header, data.len()),
+        }
+        self.do_output();
+    }
+}
+
+impl<T: IP6Sender<'a>, A: time::Alarm> rng::Client for TCPDriver<'a, T, A> {
+    fn randomness_available(
+        &self,
+        randomness: &mut Iterator<Item = u32>,
+        _error: ReturnCode,
+    ) -> rng::Continue {
+        let mut secret = self.iss_secret.get();
+        let mut len = self.iss_secret_len.get();
+        while len < secret.len() {
+            match randomness.next() {
+                Some(random) => {
+                    secret[len] = random;
+                    len += 1;
+                }
+                None => break,
+            }
+        }
+        self.iss_secret.set(secret);
+        self.iss_secret_len.set(len);
+        if len < secret.len() {
+            rng::Continue::More
+        } else {
+            rng::Continue::Done
+        }
+    }
+}
+
+impl<T: IP6Sender<'a>, A: time::Alarm> time::Client for TCPDriver<'a, T, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                let expired = app
+                    .timer
+                    .map_or(false, |timer| now.wrapping_sub(timer) as i32 >= 0);
+                if expired {
+                    app.timer = None;
+                    let events = app.connection.timeout();
+                    if events != 0 {
+                        app.event_callback.map(|mut cb| cb.schedule(events, 0, 0));
+                    }
+                    self.update_timer(app, true);
+                }
+            });
+        }
+        self.do_output();
+    }
+}
@@ -0,0 +1,640 @@
+//! This file contains the TCP connection state machine from RFC 793. A
+//! [TCPConnection](struct.TCPConnection.html) keeps the state of a single
+//! connection: it decides which segment to send next, and what to do with
+//! received segments. It does not hold any data. Instead, its user keeps the
+//! send and receive buffers, tells the connection how many bytes are queued
+//! for sending and how much room there is for received data, and copies data
+//! in and out of the buffers as the connection says. Offsets into the send
+//! buffer count from the oldest unacknowledged byte.
+//!
+//! The user is also responsible for the timer of the connection. Whenever
+//! `TCPConnection::timer_needed` is true, the user must call
+//! `TCPConnection::timeout` once `TCPConnection::timeout_ms` milliseconds have
+//! passed without the remote end acknowledging new data.
+
+// Simplifications compared to RFC 793
+// -----------------------------------
+// - Segments that arrive out of order are dropped, and the remote end is sent
+//   a duplicate acknowledgment so that it retransmits them.
+// - Retransmission is go-back-N with a fixed initial timeout that doubles on
+//   every retry. There is no round-trip time estimation and no congestion
+//   control, which is acceptable for the low-rate links this stack runs on.
+// - A listening connection accepts one remote end and becomes that
+//   connection. Other connection requests are refused until the user listens
+//   again.
+// - TIME-WAIT lasts `TIME_WAIT_MS`, which is much shorter than the 4 minutes
+//   RFC 793 asks for, to free the connection sooner.
+// - The urgent pointer and all options except MSS are ignored.
+
+use core::cmp;
+use net::ipv6::ip_utils::IPAddr;
+use net::tcp::tcp::{tcp_flags, TCPHeader};
+
+/// How many times a segment is retransmitted before giving up on the
+/// connection.
+pub const MAX_RETRIES: u8 = 6;
+
+/// Retransmission timeout before the first retry.
+const INITIAL_RTO_MS: u32 = 1000;
+
+const TIME_WAIT_MS: u32 = 10000;
+
+/// MSS to use if the remote end does not send the MSS option: the minimum
+/// IPv6 MTU minus the IPv6 and TCP headers.
+const DEFAULT_MSS: usize = 1220;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TCPState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// Events that are reported to the user of a connection, as a bit mask.
+pub mod tcp_event {
+    /// The three-way handshake completed.
+    pub const CONNECTED: usize = 0x01;
+    /// The remote end closed its side. No more data will arrive.
+    pub const REMOTE_CLOSED: usize = 0x02;
+    /// Both ends closed the connection.
+    pub const CLOSED: usize = 0x04;
+    /// The remote end reset or refused the connection.
+    pub const RESET: usize = 0x08;
+    /// The remote end stopped acknowledging segments.
+    pub const TIMED_OUT: usize = 0x10;
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TCPEndpoint {
+    pub addr: IPAddr,
+    pub port: u16,
+}
+
+impl Default for TCPEndpoint {
+    fn default() -> TCPEndpoint {
+        TCPEndpoint {
+            addr: IPAddr::new(),
+            port: 0,
+        }
+    }
+}
+
+/// A segment that the connection wants to send.
+pub struct Segment {
+    pub header: TCPHeader,
+    /// Offset of the payload in the send buffer.
+    pub data_offset: usize,
+    pub data_len: usize,
+}
+
+/// What to do with a received segment.
+#[derive(Default)]
+pub struct Received {
+    /// Offset of the part of the segment payload to append to the receive
+    /// buffer.
+    pub data_offset: usize,
+    /// Length of the part of the segment payload to append to the receive
+    /// buffer.
+    pub data_len: usize,
+    /// Number of bytes at the start of the send buffer that the remote end
+    /// acknowledged, and that can be dropped.
+    pub acked: usize,
+    /// `tcp_event` bits to report.
+    pub events: usize,
+    /// Whether to answer the segment with `reset_reply`.
+    pub reset: bool,
+}
+
+// Comparisons of sequence numbers, which wrap around.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+/// Returns the reset that answers a segment which does not belong to a
+/// connection, or `None` if the segment is a reset itself.
+pub fn reset_reply(header: &TCPHeader, data_len: usize) -> Option<TCPHeader> {
+    if header.has_flags(tcp_flags::RST) {
+        return None;
+    }
+    let mut reset = TCPHeader::new();
+    reset.set_src_port(header.get_dst_port());
+    reset.set_dst_port(header.get_src_port());
+    if header.has_flags(tcp_flags::ACK) {
+        reset.set_seq_num(header.get_ack_num());
+        reset.set_flags(tcp_flags::RST);
+    } else {
+        let mut seg_len = data_len as u32;
+        if header.has_flags(tcp_flags::SYN) {
+            seg_len += 1;
+        }
+        if header.has_flags(tcp_flags::FIN) {
+            seg_len += 1;
+        }
+        reset.set_ack_num(header.get_seq_num().wrapping_add(seg_len));
+        reset.set_flags(tcp_flags::RST | tcp_flags::ACK);
+    }
+    Some(reset)
+}
+
+pub struct TCPConnection {
+    state: TCPState,
+    local: TCPEndpoint,
+    remote: TCPEndpoint,
+    /// Largest segment this end sends or wants to receive.
+    max_mss: usize,
+    /// Largest segment to send to the remote end.
+    snd_mss: usize,
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: usize,
+    rcv_nxt: u32,
+    /// Bytes in the send buffer, starting at `snd_una`.
+    queued: usize,
+    /// Whether the user closed the connection. A FIN follows the queued data.
+    closing: bool,
+    ack_pending: bool,
+    /// Whether to send one byte into a closed send window.
+    probe: bool,
+    retries: u8,
+}
+
+impl Default for TCPConnection {
+    fn default() -> TCPConnection {
+        TCPConnection {
+            state: TCPState::Closed,
+            local: TCPEndpoint::default(),
+            remote: TCPEndpoint::default(),
+            max_mss: 0,
+            snd_mss: 0,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            queued: 0,
+            closing: false,
+            ack_pending: false,
+            probe: false,
+            retries: 0,
+        }
+    }
+}
+
+impl TCPConnection {
+    pub fn new() -> TCPConnection {
+        TCPConnection::default()
+    }
+
+    pub fn get_state(&self) -> TCPState {
+        self.state
+    }
+
+    pub fn get_local(&self) -> TCPEndpoint {
+        self.local
+    }
+
+    pub fn get_remote(&self) -> TCPEndpoint {
+        self.remote
+    }
+
+    /// Returns whether the connection can be reused. A connection in
+    /// TIME-WAIT can be, at the risk of old segments arriving on the new one.
+    pub fn is_closed(&self) -> bool {
+        self.state == TCPState::Closed || self.state == TCPState::TimeWait
+    }
+
+    /// Returns whether data can be queued for sending.
+    pub fn can_send(&self) -> bool {
+        (self.state == TCPState::Established || self.state == TCPState::CloseWait) && !self.closing
+    }
+
+    /// Returns the number of queued bytes that were not acknowledged yet.
+    pub fn get_queued(&self) -> usize {
+        self.queued
+    }
+
+    /// Open a connection to `remote`.
+    ///
+    /// # Arguments
+    ///
+    /// `local` - Local address and port of the connection
+    /// `remote` - Address and port to connect to
+    /// `iss` - Initial sequence number
+    /// `mss` - Largest segment that this end can send and receive
+    pub fn connect(&mut self, local: TCPEndpoint, remote: TCPEndpoint, iss: u32, mss: usize) {
+        self.reset(local, iss, mss);
+        self.remote = remote;
+        self.state = TCPState::SynSent;
+    }
+
+    /// Wait for a connection on `local`. The arguments are the same as for
+    /// `connect`.
+    pub fn listen(&mut self, local: TCPEndpoint, iss: u32, mss: usize) {
+        self.reset(local, iss, mss);
+        self.state = TCPState::Listen;
+    }
+
+    /// Replace the initial sequence number of a connection that did not send
+    /// its SYN yet. A listening connection only learns its remote end from the
+    /// SYN it receives, so its user can choose the initial sequence number for
+    /// that remote end once `receive` moved the connection from LISTEN to
+    /// SYN-RECEIVED.
+    pub fn set_iss(&mut self, iss: u32) {
+        self.iss = iss;
+        self.snd_una = iss;
+        self.snd_nxt = iss;
+    }
+
+    fn reset(&mut self, local: TCPEndpoint, iss: u32, mss: usize) {
+        *self = TCPConnection::default();
+        self.local = local;
+        self.max_mss = mss;
+        self.snd_mss = mss;
+        self.iss = iss;
+        self.snd_una = iss;
+        self.snd_nxt = iss;
+    }
+
+    /// Queue `len` more bytes of the send buffer for sending.
+    pub fn send(&mut self, len: usize) {
+        self.queued += len;
+    }
+
+    /// Close this end of the connection once the queued data is sent.
+    pub fn close(&mut self) {
+        match self.state {
+            TCPState::Listen | TCPState::SynSent => self.state = TCPState::Closed,
+            TCPState::SynReceived | TCPState::Established | TCPState::CloseWait => {
+                self.closing = true
+            }
+            _ => {}
+        }
+    }
+
+    /// Close the connection immediately. Returns the reset to send to the
+    /// remote end, if it has to be told.
+    pub fn abort(&mut self) -> Option<TCPHeader> {
+        let reset = match self.state {
+            TCPState::SynReceived
+            | TCPState::Established
+            | TCPState::FinWait1
+            | TCPState::FinWait2
+            | TCPState::CloseWait => {
+                let mut header = self.header(tcp_flags::RST);
+                header.set_seq_num(self.snd_nxt);
+                Some(header)
+            }
+            _ => None,
+        };
+        self.state = TCPState::Closed;
+        reset
+    }
+
+    /// Tell the connection that room was made in the receive buffer, so that
+    /// it sends the new window to the remote end.
+    pub fn window_opened(&mut self) {
+        if self.is_synchronized() {
+            self.ack_pending = true;
+        }
+    }
+
+    /// Returns whether the user needs to run the timer of the connection.
+    pub fn timer_needed(&self) -> bool {
+        match self.state {
+            TCPState::Closed | TCPState::Listen | TCPState::FinWait2 => false,
+            TCPState::TimeWait => true,
+            _ => self.snd_nxt != self.snd_una || self.queued > 0,
+        }
+    }
+
+    /// Returns how long the timer of the connection runs.
+    pub fn timeout_ms(&self) -> u32 {
+        if self.state == TCPState::TimeWait {
+            TIME_WAIT_MS
+        } else {
+            INITIAL_RTO_MS << self.retries
+        }
+    }
+
+    /// Called when the timer of the connection expires. Returns `tcp_event`
+    /// bits to report.
+    pub fn timeout(&mut self) -> usize {
+        match self.state {
+            TCPState::Closed | TCPState::Listen => 0,
+            TCPState::TimeWait => {
+                // CLOSED was reported when entering TIME-WAIT.
+                self.state = TCPState::Closed;
+                0
+            }
+            _ => {
+                if self.is_synchronized() && self.snd_wnd == 0 {
+                    // The remote end is alive, but has no room for data.
+                    self.probe = true;
+                } else {
+                    self.retries += 1;
+                    if self.retries > MAX_RETRIES {
+                        self.state = TCPState::Closed;
+                        return tcp_event::TIMED_OUT;
+                    }
+                }
+                // Go back and resend everything that is not acknowledged.
+                self.snd_nxt = self.snd_una;
+                0
+            }
+        }
+    }
+
+    fn is_synchronized(&self) -> bool {
+        match self.state {
+            TCPState::Closed | TCPState::Listen | TCPState::SynSent | TCPState::SynReceived => {
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn header(&self, flags: u16) -> TCPHeader {
+        let mut header = TCPHeader::new();
+        header.set_src_port(self.local.port);
+        header.set_dst_port(self.remote.port);
+        header.set_seq_num(self.snd_nxt);
+        if flags & tcp_flags::ACK != 0 {
+            header.set_ack_num(self.rcv_nxt);
+        }
+        header.set_flags(flags);
+        header
+    }
+
+    fn set_snd_mss(&mut self, mss: Option<u16>) {
+        self.snd_mss = cmp::min(mss.map_or(DEFAULT_MSS, |mss| mss as usize), self.max_mss);
+    }
+
+    /// Returns the next segment to send, if there is one. `rcv_wnd` is the
+    /// room in the receive buffer.
+    pub fn next_segment(&mut self, rcv_wnd: usize) -> Option<Segment> {
+        let mut segment = None;
+        match self.state {
+            TCPState::SynSent | TCPState::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let flags = if self.state == TCPState::SynSent {
+                        tcp_flags::SYN
+                    } else {
+                        tcp_flags::SYN | tcp_flags::ACK
+                    };
+                    let mut header = self.header(flags);
+                    header.set_mss(Some(cmp::min(self.max_mss, 0xffff) as u16));
+                    self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                    segment = Some((header, 0, 0));
+                }
+            }
+            TCPState::Established
+            | TCPState::CloseWait
+            | TCPState::FinWait1
+            | TCPState::Closing
+            | TCPState::LastAck => {
+                let sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+                if sent < self.queued {
+                    let mut usable = self.snd_wnd.saturating_sub(sent);
+                    if usable == 0 && self.probe {
+                        usable = 1;
+                    }
+                    let len = cmp::min(cmp::min(self.queued - sent, usable), self.snd_mss);
+                    if len > 0 {
+                        let header = self.header(tcp_flags::ACK | tcp_flags::PSH);
+                        self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+                        self.probe = false;
+                        segment = Some((header, sent, len));
+                    }
+                } else if sent == self.queued && self.closing {
+                    let header = self.header(tcp_flags::FIN | tcp_flags::ACK);
+                    self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                    if self.state == TCPState::Established {
+                        self.state = TCPState::FinWait1;
+                    } else if self.state == TCPState::CloseWait {
+                        self.state = TCPState::LastAck;
+                    }
+                    segment = Some((header, 0, 0));
+                }
+            }
+            _ => {}
+        }
+        // In SYN-RECEIVED, an acknowledgment answers the SYN-ACK of a
+        // simultaneous open, which completes the handshake at the other end.
+        let can_ack = self.is_synchronized() || self.state == TCPState::SynReceived;
+        if segment.is_none() && self.ack_pending && can_ack {
+            segment = Some((self.header(tcp_flags::ACK), 0, 0));
+        }
+
+        segment.map(|(mut header, data_offset, data_len)| {
+            header.set_window(cmp::min(rcv_wnd, 0xffff) as u16);
+            if header.has_flags(tcp_flags::ACK) {
+                self.ack_pending = false;
+            }
+            Segment {
+                header: header,
+                data_offset: data_offset,
+                data_len: data_len,
+            }
+        })
+    }
+
+    /// Handle a segment from `src_addr` that was sent to this connection.
+    ///
+    /// # Arguments
+    ///
+    /// `src_addr` - Address the segment was sent from
+    /// `header` - Header of the segment
+    /// `data_len` - Length of the segment payload
+    /// `rcv_wnd` - Room in the receive buffer
+    pub fn receive(
+        &mut self,
+        src_addr: IPAddr,
+        header: &TCPHeader,
+        data_len: usize,
+        rcv_wnd: usize,
+    ) -> Received {
+        let mut received = Received::default();
+        let seq = header.get_seq_num();
+        let ack = header.get_ack_num();
+        let is_ack = header.has_flags(tcp_flags::ACK);
+        let is_rst = header.has_flags(tcp_flags::RST);
+        let is_syn = header.has_flags(tcp_flags::SYN);
+        let is_fin = header.has_flags(tcp_flags::FIN);
+
+        match self.state {
+            TCPState::Closed => {
+                received.reset = true;
+                return received;
+            }
+            TCPState::Listen => {
+                if is_ack {
+                    received.reset = !is_rst;
+                } else if is_syn && !is_rst {
+                    self.remote = TCPEndpoint {
+                        addr: src_addr,
+                        port: header.get_src_port(),
+                    };
+                    self.rcv_nxt = seq.wrapping_add(1);
+                    self.snd_wnd = header.get_window() as usize;
+                    self.set_snd_mss(header.get_mss());
+                    self.state = TCPState::SynReceived;
+                }
+                return received;
+            }
+            TCPState::SynSent => {
+                let ack_ok = is_ack && ack == self.snd_nxt;
+                if is_ack && !ack_ok {
+                    received.reset = !is_rst;
+                } else if is_rst {
+                    if ack_ok {
+                        self.state = TCPState::Closed;
+                        received.events = tcp_event::RESET;
+                    }
+                } else if is_syn {
+                    self.rcv_nxt = seq.wrapping_add(1);
+                    self.snd_wnd = header.get_window() as usize;
+                    self.set_snd_mss(header.get_mss());
+                    if ack_ok {
+                        self.snd_una = ack;
+                        self.ack_pending = true;
+                        self.state = TCPState::Established;
+                        received.events = tcp_event::CONNECTED;
+                    } else {
+                        // Both ends opened at the same time, so answer with
+                        // a SYN-ACK.
+                        self.snd_nxt = self.iss;
+                        self.state = TCPState::SynReceived;
+                    }
+                }
+                return received;
+            }
+            _ => {}
+        }
+
+        // Only accept segments that start at the next expected byte, or that
+        // overlap it.
+        let seg_len = data_len as u32 + if is_fin { 1 } else { 0 };
+        let acceptable = if seg_len == 0 {
+            seq == self.rcv_nxt
+        } else {
+            seq_le(seq, self.rcv_nxt) && seq_lt(self.rcv_nxt, seq.wrapping_add(seg_len))
+        };
+        if !acceptable {
+            if !is_rst {
+                self.ack_pending = true;
+            }
+            return received;
+        }
+        let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+
+        if is_rst {
+            if seq == self.rcv_nxt {
+                if self.state != TCPState::TimeWait {
+                    received.events = tcp_event::RESET;
+                }
+                self.state = TCPState::Closed;
+            }
+            return received;
+        }
+        if is_syn {
+            received.reset = true;
+            received.events = tcp_event::RESET;
+            self.state = TCPState::Closed;
+            return received;
+        }
+        if !is_ack {
+            return received;
+        }
+
+        if self.state == TCPState::SynReceived {
+            if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+                self.snd_una = ack;
+                self.retries = 0;
+                self.state = TCPState::Established;
+                received.events |= tcp_event::CONNECTED;
+            } else {
+                received.reset = true;
+                return received;
+            }
+        } else if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+            let advance = ack.wrapping_sub(self.snd_una) as usize;
+            let acked = cmp::min(advance, self.queued);
+            self.queued -= acked;
+            self.snd_una = ack;
+            self.retries = 0;
+            received.acked = acked;
+            if advance > acked {
+                // Our FIN was acknowledged.
+                match self.state {
+                    TCPState::FinWait1 => self.state = TCPState::FinWait2,
+                    TCPState::Closing => {
+                        self.state = TCPState::TimeWait;
+                        received.events |= tcp_event::CLOSED;
+                    }
+                    TCPState::LastAck => {
+                        self.state = TCPState::Closed;
+                        received.events |= tcp_event::CLOSED;
+                        return received;
+                    }
+                    _ => {}
+                }
+            }
+        } else if seq_lt(self.snd_nxt, ack) {
+            // Acknowledges data that was not sent yet.
+            self.ack_pending = true;
+            return received;
+        }
+        if ack == self.snd_una {
+            self.snd_wnd = header.get_window() as usize;
+        }
+
+        let mut all_data = true;
+        if data_len > skip {
+            self.ack_pending = true;
+            match self.state {
+                TCPState::Established | TCPState::FinWait1 | TCPState::FinWait2 => {
+                    let len = cmp::min(data_len - skip, rcv_wnd);
+                    all_data = len == data_len - skip;
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32);
+                    received.data_offset = skip;
+                    received.data_len = len;
+                }
+                _ => {}
+            }
+        }
+
+        if is_fin && all_data {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.ack_pending = true;
+            match self.state {
+                TCPState::Established => {
+                    self.state = TCPState::CloseWait;
+                    received.events |= tcp_event::REMOTE_CLOSED;
+                }
+                TCPState::FinWait1 => {
+                    self.state = TCPState::Closing;
+                    received.events |= tcp_event::REMOTE_CLOSED;
+                }
+                TCPState::FinWait2 => {
+                    self.state = TCPState::TimeWait;
+                    received.events |= tcp_event::REMOTE_CLOSED | tcp_event::CLOSED;
+                }
+                _ => {}
+            }
+        }
+        received
+    }
+}
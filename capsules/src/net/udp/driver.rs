@@ -3,18 +3,22 @@
 //! Implements a userspace interface for sending and receiving UDP messages.
 //! Processes use this driver to send UDP packets from a common interface
 //! and bind to UDP ports for receiving packets.
-//! Also exposes a list of interface addresses to the application (currently
-//! hard-coded).
+//! Each process that binds a port is given a `UDPSocket` from a pool of
+//! sockets, so processes share the socket table of the `MuxUDP` with kernel
+//! capsules. Packets received on the port wait in the queue of the socket
+//! until the process provides a read buffer for them.
+//! Also exposes a list of interface addresses to the application. The
+//! addresses can change at runtime, for example when they are configured by
+//! Neighbor Discovery, and entries without an address are unspecified (::).
 
 use core::cell::Cell;
-use core::{cmp, mem};
+use core::{cmp, mem, ptr};
 use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
 use net::ipv6::ip_utils::IPAddr;
 use net::stream::encode_u16;
 use net::stream::encode_u8;
 use net::stream::SResult;
-use net::udp::udp_recv::{UDPReceiver, UDPRecvClient};
-use net::udp::udp_send::{UDPSendClient, UDPSender};
+use net::udp::udp_mux::{UDPSocket, UDPSocketClient};
 
 /// Syscall number
 pub const DRIVER_NUM: usize = 0x30002;
@@ -63,16 +67,19 @@ pub struct App {
     app_cfg: Option<AppSlice<Shared, u8>>,
     app_rx_cfg: Option<AppSlice<Shared, u8>>,
     pending_tx: Option<[UDPEndpoint; 2]>,
-    bound_port: Option<UDPEndpoint>,
+    /// Index of the socket of this app in the socket pool
+    socket: Option<usize>,
+    /// Whether the read buffer can take the next received packet
+    rx_ready: bool,
+    /// Whether the app asked to receive one packet per read buffer with
+    /// command 5
+    rx_one_per_buffer: bool,
 }
 
 #[allow(dead_code)]
 pub struct UDPDriver<'a> {
-    /// UDP sender
-    sender: &'a UDPSender<'a>,
-
-    /// UDP receiver
-    receiver: &'a UDPReceiver<'a>,
+    /// Sockets that are given to apps when they bind a port
+    sockets: &'a [UDPSocket<'a>],
 
     /// Grant of apps that use this radio driver.
     apps: Grant<App>,
@@ -80,7 +87,7 @@ pub struct UDPDriver<'a> {
     current_app: Cell<Option<AppId>>,
 
     /// List of IP Addresses of the interfaces on the device
-    interface_list: &'static [IPAddr],
+    interface_list: &'static [Cell<IPAddr>],
 
     /// Maximum length payload that an app can transmit via this driver
     max_tx_pyld_len: usize,
@@ -88,15 +95,13 @@ pub struct UDPDriver<'a> {
 
 impl<'a> UDPDriver<'a> {
     pub fn new(
-        sender: &'a UDPSender<'a>,
-        receiver: &'a UDPReceiver<'a>,
+        sockets: &'a [UDPSocket<'a>],
         grant: Grant<App>,
-        interface_list: &'static [IPAddr],
+        interface_list: &'static [Cell<IPAddr>],
         max_tx_pyld_len: usize,
     ) -> UDPDriver<'a> {
         UDPDriver {
-            sender: sender,
-            receiver: receiver,
+            sockets: sockets,
             apps: grant,
             current_app: Cell::new(None),
             interface_list: interface_list,
@@ -245,18 +250,27 @@ impl<'a> UDPDriver<'a> {
             };
             let dst_addr = addr_ports[1].addr;
             let dst_port = addr_ports[1].port;
-            let src_port = addr_ports[0].port;
+            let socket = match app.socket {
+                Some(index) => &self.sockets[index],
+                None => return ReturnCode::ERESERVE,
+            };
 
             // Send UDP payload. Payload will be copied into IP6Packet in kernel mem.
             let result = app
                 .app_write
                 .as_ref()
                 .map_or(ReturnCode::ENOMEM, |payload| {
-                    self.sender
-                        .send_to(dst_addr, dst_port, src_port, payload.as_ref())
+                    socket.send_to(dst_addr, dst_port, payload.as_ref())
                 });
-            if result == ReturnCode::SUCCESS {
-                self.current_app.set(Some(appid));
+            match result {
+                ReturnCode::SUCCESS => self.current_app.set(Some(appid)),
+                ReturnCode::EBUSY => {
+                    // A kernel capsule is sending, so keep the packet queued
+                    // until the socket is told that it can send again.
+                    app.pending_tx = Some(addr_ports);
+                    return ReturnCode::SUCCESS;
+                }
+                _ => {}
             }
             result
         })
@@ -296,8 +310,8 @@ impl<'a> UDPDriver<'a> {
     #[inline]
     fn parse_ip_port_pair(&self, buf: &[u8]) -> Option<UDPEndpoint> {
         if buf.len() != mem::size_of::<UDPEndpoint>() {
-            debug!(
-                "[parse] len is {:?}, not {:?} as expected",
+            log_warn!(
+                "endpoint length is {}, not {} as expected",
                 buf.len(),
                 mem::size_of::<UDPEndpoint>()
             );
@@ -316,12 +330,93 @@ impl<'a> UDPDriver<'a> {
     }
 }
 
+impl<'a> UDPDriver<'a> {
+    /// Returns the index of `socket` in the socket pool.
+    fn socket_index(&self, socket: &UDPSocket<'a>) -> Option<usize> {
+        self.sockets.iter().position(|s| ptr::eq(s, socket))
+    }
+
+    /// Whether an app holds the socket at `index` in the socket pool.
+    fn socket_in_use(&self, index: usize) -> bool {
+        self.apps
+            .iter()
+            .any(|app| app.enter(|app, _| app.socket == Some(index)))
+    }
+
+    /// Releases the sockets of apps that no longer exist, and returns the
+    /// index of a socket that no app holds.
+    fn get_free_socket(&self) -> Option<usize> {
+        let mut free_socket = None;
+        for index in 0..self.sockets.len() {
+            if !self.socket_in_use(index) {
+                self.sockets[index].unbind();
+                free_socket = free_socket.or(Some(index));
+            }
+        }
+        free_socket
+    }
+
+    /// Copies received packets from the queue of the socket of `app` into its
+    /// read buffer, if the app is ready for them, and tells the app about the
+    /// last one. Packets that do not fit in the read buffer are dropped.
+    ///
+    /// By default the read buffer stays allowed and always holds the most
+    /// recent packet. Apps that asked for one packet per read buffer get the
+    /// oldest packet, and the others wait in the queue until the buffer is
+    /// allowed again.
+    fn deliver_packet(&self, app: &mut App) {
+        let socket = match app.socket {
+            Some(index) => &self.sockets[index],
+            None => return,
+        };
+        if !app.rx_ready || app.rx_callback.is_none() {
+            return;
+        }
+        let mut app_read = app.app_read.take();
+        app_read.as_mut().map(|rbuf| {
+            let rbuf = rbuf.as_mut();
+            let mut received = None;
+            while let Some(len) = socket.next_packet_len() {
+                if len > rbuf.len() {
+                    socket.receive(&mut []);
+                    continue;
+                }
+                received = socket.receive(rbuf);
+                if app.rx_one_per_buffer {
+                    break;
+                }
+            }
+            received.map(|info| {
+                // Write address of sender into rx_cfg so it can be read by client
+                let sender_addr = UDPEndpoint {
+                    addr: info.src_addr,
+                    port: info.src_port,
+                };
+                app.app_rx_cfg.as_mut().map(|cfg| {
+                    if cfg.len() == 2 * mem::size_of::<UDPEndpoint>() {
+                        sender_addr.encode(cfg.as_mut(), 0);
+                    }
+                });
+                if app.rx_one_per_buffer {
+                    app.rx_ready = false;
+                }
+                app.rx_callback.map(|mut cb| cb.schedule(info.len, 0, 0));
+            });
+        });
+        app.app_read = app_read;
+    }
+}
+
 impl<'a> Driver for UDPDriver<'a> {
     /// Setup buffers to read/write from.
     ///
     /// ### `allow_num`
     ///
-    /// - `0`: Read buffer. Will contain the received payload.
+    /// - `0`: Read buffer. Will contain the received payload. Received
+    ///        packets wait in the queue of the socket of the app until a
+    ///        read buffer is allowed. Each packet overwrites the previous
+    ///        one, unless the app asked for one packet per read buffer with
+    ///        command `5`.
     /// - `1`: Write buffer. Contains the UDP payload to be transmitted.
     /// - `2`: Config buffer. Used to contain miscellaneous data associated with
     ///        some commands, namely source/destination addresses and ports.
@@ -337,7 +432,11 @@ impl<'a> Driver for UDPDriver<'a> {
         match allow_num {
             0 | 1 | 2 | 3 => self.do_with_app(appid, |app| {
                 match allow_num {
-                    0 => app.app_read = slice,
+                    0 => {
+                        app.rx_ready = slice.is_some();
+                        app.app_read = slice;
+                        self.deliver_packet(app);
+                    }
                     1 => app.app_write = slice,
                     2 => app.app_cfg = slice,
                     3 => app.app_rx_cfg = slice,
@@ -368,8 +467,9 @@ impl<'a> Driver for UDPDriver<'a> {
     ) -> ReturnCode {
         match subscribe_num {
             0 => self.do_with_app(app_id, |app| {
-                if app.bound_port.is_some() {
+                if app.socket.is_some() {
                     app.rx_callback = callback;
+                    self.deliver_packet(app);
                     ReturnCode::SUCCESS
                 } else {
                     ReturnCode::ERESERVE
@@ -414,21 +514,27 @@ impl<'a> Driver for UDPDriver<'a> {
     ///        an app with a lower app id can send constantly and starve an app with a
     ///        later ID.
     /// - `3`: Bind to the address in rx_cfg. Returns SUCCESS if that addr/port combo is free,
-    ///        returns EINVAL if the address requested is not a local interface, or if the port
-    ///        requested is 0. Returns EBUSY if that port is already bound to by another app.
+    ///        returns EINVAL if the address requested is not a local interface. Returns EBUSY
+    ///        if that port is already bound to by another app or by a kernel capsule, and
+    ///        ENOMEM if every socket for apps is in use. Ports of apps that were restarted
+    ///        or terminated are free again. Binding to the unspecified address
+    ///        (::) binds the port on every local interface. If the port requested is 0, a
+    ///        free ephemeral port is chosen and returned with SuccessWithValue.
     ///        This command should be called after allow() is called on the rx_cfg buffer, and
     ///        before subscribe() is used to set up the recv callback. Additionally, apps can only
     ///        send on ports after they have bound to said port. If this command is called
-    ///        and the address in rx_cfg is 0::0 : 0, this command will reset the option
-    ///        containing the bound port to None and set the rx callback to None. Notably,
-    ///        the current implementation of this only allows for each app to bind to a single
-    ///        port at a time, as such an implementation conserves memory (and is similar
-    ///        to the approach applied by TinyOS and Riot). Further, there is
-    ///        currently no mechanism for anything in the kernel to bind to ports, and there
-    ///        is no distinction between ephemeral ports and reserved ports.
+    ///        and the address in rx_cfg is 0::0 : 0, this command will release the socket
+    ///        of the app, discarding any packets queued for it, and set the rx callback to
+    ///        None. Notably, the current implementation of this only allows for each app to
+    ///        bind to a single port at a time, as such an implementation conserves memory (and
+    ///        is similar to the approach applied by TinyOS and Riot).
     /// - `4`: Returns the maximum payload that can be transmitted by apps using this driver.
     ///        This represents the size of the payload buffer in the kernel. Apps can use this
     ///        syscall to ensure they do not attempt to send too-large messages.
+    /// - `5`: If `arg1` is 1, each read buffer receives a single packet, and the following
+    ///        packets wait in the queue of the socket until the read buffer is allowed
+    ///        again. If `arg1` is 0, the read buffer receives every packet, which is the
+    ///        default.
 
     fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
         match command_num {
@@ -441,7 +547,7 @@ impl<'a> Driver for UDPDriver<'a> {
                 let iface_size = mem::size_of::<IPAddr>();
                 for i in 0..n_ifaces_to_copy {
                     cfg[i * iface_size..(i + 1) * iface_size]
-                        .copy_from_slice(&self.interface_list[i].0);
+                        .copy_from_slice(&self.interface_list[i].get().0);
                 }
                 // Returns total number of interfaces
                 ReturnCode::SuccessWithValue {
@@ -456,10 +562,14 @@ impl<'a> Driver for UDPDriver<'a> {
                         // Cannot support more than one pending tx per process.
                         return ReturnCode::EBUSY;
                     }
-                    if app.bound_port.is_none() {
+                    let binding = app
+                        .socket
+                        .and_then(|index| self.sockets[index].get_binding());
+                    let (bound_addr, bound_port) = match binding {
+                        Some(binding) => binding,
                         // Currently, apps need to bind to a port before they can send from said port
-                        return ReturnCode::ERESERVE;
-                    }
+                        None => return ReturnCode::ERESERVE,
+                    };
                     let next_tx = app.app_cfg.as_ref().and_then(|cfg| {
                         if cfg.len() != 2 * mem::size_of::<UDPEndpoint>() {
                             return None;
@@ -469,7 +579,9 @@ impl<'a> Driver for UDPDriver<'a> {
                             self.parse_ip_port_pair(&cfg.as_ref()[mem::size_of::<UDPEndpoint>()..]),
                             self.parse_ip_port_pair(&cfg.as_ref()[..mem::size_of::<UDPEndpoint>()]),
                         ) {
-                            if Some(src.clone()) == app.bound_port {
+                            if src.port == bound_port
+                                && (src.addr == bound_addr || bound_addr.is_unspecified())
+                            {
                                 Some([src, dst])
                             } else {
                                 None
@@ -488,75 +600,75 @@ impl<'a> Driver for UDPDriver<'a> {
             3 => {
                 self.do_with_app(appid, |app| {
                     // Move UDPEndpoint into udp.rs?
-                    let mut requested_addr_opt = app.app_rx_cfg.as_ref().and_then(|cfg| {
+                    let requested_addr_opt = app.app_rx_cfg.as_ref().and_then(|cfg| {
                         if cfg.len() != 2 * mem::size_of::<UDPEndpoint>() {
                             None
-                        } else if let Some(local_iface) =
-                            self.parse_ip_port_pair(&cfg.as_ref()[mem::size_of::<UDPEndpoint>()..])
-                        {
-                            Some(local_iface)
                         } else {
-                            None
+                            self.parse_ip_port_pair(&cfg.as_ref()[mem::size_of::<UDPEndpoint>()..])
                         }
                     });
-                    if requested_addr_opt.is_none() {
-                        return ReturnCode::EINVAL;
+                    let requested_addr = match requested_addr_opt {
+                        Some(requested_addr) => requested_addr,
+                        None => return ReturnCode::EINVAL,
+                    };
+                    // If zero address, close any already bound socket
+                    if requested_addr.is_zero() {
+                        app.socket.take().map(|index| self.sockets[index].unbind());
+                        app.rx_callback = None;
+                        return ReturnCode::SUCCESS;
                     }
-                    if requested_addr_opt.is_some() {
-                        let requested_addr = requested_addr_opt.unwrap();
-                        // If zero address, close any already bound socket
-                        if requested_addr.is_zero() {
-                            app.rx_callback = None;
-                            app.bound_port = None;
-                            return ReturnCode::SUCCESS;
-                        }
-                        // Check that requested addr is a local interface
-                        let mut requested_is_local = false;
-                        for i in 0..self.interface_list.len() {
-                            if requested_addr.addr == self.interface_list[i] {
-                                requested_is_local = true;
-                            }
-                        }
-                        if !requested_is_local {
-                            return ReturnCode::EINVAL;
-                        }
-                        let mut addr_already_bound = false;
-                        for app in self.apps.iter() {
-                            app.enter(|other_app, _| {
-                                if other_app.bound_port.is_some() {
-                                    let other_addr_opt = other_app.bound_port.clone();
-                                    let other_addr = other_addr_opt.unwrap();
-                                    if other_addr.port == requested_addr.port {
-                                        if other_addr.addr == requested_addr.addr {
-                                            addr_already_bound = true;
-                                        }
-                                    }
-                                }
-                            });
-                        }
-                        if addr_already_bound {
-                            return ReturnCode::EBUSY;
-                        } else {
-                            requested_addr_opt = Some(requested_addr);
-                            // If this point is reached, the requested addr is free and valid
-                            app.bound_port = requested_addr_opt;
-                            return ReturnCode::SUCCESS;
+                    // The sockets of apps that were restarted or terminated
+                    // still hold their ports, so release them before binding,
+                    // also when the app already has a socket. Rebinding keeps
+                    // the socket of the app, and its queue.
+                    let free_socket = self.get_free_socket();
+                    let index = match app.socket.or(free_socket) {
+                        Some(index) => index,
+                        None => return ReturnCode::ENOMEM,
+                    };
+                    // The socket table checks that the address is local and
+                    // that nothing else is bound to the port.
+                    let result = self.sockets[index].bind(requested_addr.addr, requested_addr.port);
+                    match result {
+                        ReturnCode::SUCCESS | ReturnCode::SuccessWithValue { .. } => {
+                            app.socket = Some(index);
                         }
-                    } else {
-                        return ReturnCode::EINVAL;
+                        _ => {}
                     }
+                    result
                 })
             }
             4 => ReturnCode::SuccessWithValue {
                 value: self.max_tx_pyld_len,
             },
+            5 => self.do_with_app(appid, |app| match arg1 {
+                0 | 1 => {
+                    app.rx_one_per_buffer = arg1 == 1;
+                    if !app.rx_one_per_buffer {
+                        // The read buffer takes every packet again
+                        app.rx_ready = app.app_read.is_some();
+                        self.deliver_packet(app);
+                    }
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::EINVAL,
+            }),
             _ => ReturnCode::ENOSUPPORT,
         }
     }
 }
 
-impl<'a> UDPSendClient for UDPDriver<'a> {
-    fn send_done(&self, result: ReturnCode) {
+impl<'a> UDPSocketClient<'a> for UDPDriver<'a> {
+    fn packet_received(&self, socket: &'a UDPSocket<'a>) {
+        let index = self.socket_index(socket);
+        self.apps.each(|app| {
+            if index.is_some() && app.socket == index {
+                self.deliver_packet(app);
+            }
+        });
+    }
+
+    fn send_done(&self, _socket: &'a UDPSocket<'a>, result: ReturnCode) {
         self.current_app.get().map(|appid| {
             let _ = self.apps.enter(appid, |app, _| {
                 app.tx_callback
@@ -566,54 +678,8 @@ impl<'a> UDPSendClient for UDPDriver<'a> {
         self.current_app.set(None);
         self.do_next_tx_queued();
     }
-}
 
-impl<'a> UDPRecvClient for UDPDriver<'a> {
-    fn receive(
-        &self,
-        src_addr: IPAddr,
-        dst_addr: IPAddr,
-        src_port: u16,
-        dst_port: u16,
-        payload: &[u8],
-    ) {
-        self.apps.each(|app| {
-            if app.bound_port.is_some() {
-                let appid = app.appid();
-                self.do_with_app(app.appid(), |app| {
-                    let mut for_me = false;
-                    app.bound_port.as_ref().map(|requested_addr| {
-                        if requested_addr.addr == dst_addr && requested_addr.port == dst_port {
-                            for_me = true;
-                        }
-                    });
-                    if for_me {
-                        let mut app_read = app.app_read.take();
-                        app_read.as_mut().map(|rbuf| {
-                            let rbuf = rbuf.as_mut();
-                            let len = payload.len();
-                            if rbuf.len() >= len {
-                                // silently ignore packets that don't fit?
-                                rbuf[..len].copy_from_slice(&payload[..len]);
-
-                                // Write address of sender into rx_cfg so it can be read by client
-                                let sender_addr = UDPEndpoint {
-                                    addr: src_addr,
-                                    port: src_port,
-                                };
-                                let cfg_len = 2 * mem::size_of::<UDPEndpoint>();
-                                self.do_with_rx_cfg_mut(appid, cfg_len, |cfg| {
-                                    sender_addr.encode(cfg, 0);
-                                    ReturnCode::SUCCESS
-                                });
-                                app.rx_callback.map(|mut cb| cb.schedule(len, 0, 0));
-                            }
-                        });
-                        app.app_read = app_read;
-                    }
-                    ReturnCode::SUCCESS
-                });
-            }
-        });
+    fn send_ready(&self, _socket: &'a UDPSocket<'a>) {
+        self.do_next_tx_queued();
     }
 }
@@ -1,5 +1,6 @@
 pub mod driver;
 pub mod udp;
+pub mod udp_mux;
 pub mod udp_recv;
 pub mod udp_send;
 
@@ -0,0 +1,433 @@
+//! Multiplexed access to the UDP layer.
+//!
+//! `MuxUDP` lets several users share a single `UDPSender` and `UDPReceiver`.
+//! Each user, whether a kernel capsule or the userspace `UDPDriver` acting
+//! for a process, sends and receives through a `UDPSocket`. The sockets
+//! registered with the mux form a shared socket table:
+//!
+//! - A socket is bound to a local address and port before it can send or
+//!   receive. Binding fails with `EBUSY` if another socket is bound to the
+//!   same port on the same address, and the unspecified address (::) binds
+//!   the port on every local address.
+//! - Binding to port 0 picks a free port from the ephemeral range
+//!   (49152-65535, RFC 6335), which is returned by `bind`.
+//! - Every received packet is added to the receive queue of the socket bound
+//!   to its destination, and the client of the socket is told that a packet
+//!   is waiting. A socket bound to the exact destination address takes
+//!   precedence over one bound to the unspecified address. Packets for ports
+//!   without a socket, or that do not fit in the queue, are dropped.
+//! - Packets are sent from the address their socket is bound to, or from the
+//!   first local address if the socket is bound to the unspecified address.
+//! - The mux sends one packet at a time. A send while another is in progress
+//!   fails with `EBUSY`, and the client of the socket is told once the mux is
+//!   ready to send again.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! let udp_mux = static_init!(
+//!     capsules::net::udp::udp_mux::MuxUDP<'static>,
+//!     capsules::net::udp::udp_mux::MuxUDP::new(udp_send, local_ip_ifaces)
+//! );
+//! udp_send.set_client(udp_mux);
+//! udp_recv.set_client(udp_mux);
+//!
+//! // Each user of the mux creates a socket with its own receive queue.
+//! let socket = static_init!(
+//!     capsules::net::udp::udp_mux::UDPSocket<'static>,
+//!     capsules::net::udp::udp_mux::UDPSocket::new(udp_mux, &mut RX_QUEUE)
+//! );
+//! udp_mux.add_socket(socket);
+//! socket.set_client(capsule);
+//! socket.bind(IPAddr::new(), 5683);
+//! ```
+
+use core::cell::Cell;
+use core::{cmp, ptr};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::{List, ListLink, ListNode};
+use kernel::ReturnCode;
+use net::ipv6::ip_utils::IPAddr;
+use net::udp::udp_recv::UDPRecvClient;
+use net::udp::udp_send::{UDPSendClient, UDPSender};
+
+/// The first port of the ephemeral port range (RFC 6335).
+pub const EPHEMERAL_PORT_MIN: u16 = 49152;
+
+// Each packet in a receive queue is stored as the source address and port,
+// the destination address and port, and the payload length, followed by the
+// payload.
+const QUEUE_ENTRY_HDR_LEN: usize = 16 + 2 + 16 + 2 + 2;
+
+/// The addresses and length of a packet taken from the receive queue of a
+/// `UDPSocket`.
+#[derive(Copy, Clone, Debug)]
+pub struct UDPRecvInfo {
+    pub src_addr: IPAddr,
+    pub src_port: u16,
+    pub dst_addr: IPAddr,
+    pub dst_port: u16,
+    /// Length of the payload, which can be larger than the buffer it was
+    /// copied into.
+    pub len: usize,
+}
+
+/// The client of a `UDPSocket`.
+pub trait UDPSocketClient<'a> {
+    /// Called when a packet has been added to the receive queue of `socket`.
+    /// The packet can be read with `UDPSocket::receive`.
+    fn packet_received(&self, socket: &'a UDPSocket<'a>);
+
+    /// Called when a packet sent from `socket` has been sent.
+    fn send_done(&self, socket: &'a UDPSocket<'a>, result: ReturnCode);
+
+    /// Called when the mux has finished sending a packet, if a send from
+    /// `socket` failed with `EBUSY` in the meantime.
+    fn send_ready(&self, socket: &'a UDPSocket<'a>);
+}
+
+/// A socket in the socket table of a `MuxUDP`.
+pub struct UDPSocket<'a> {
+    mux: &'a MuxUDP<'a>,
+    binding: Cell<Option<(IPAddr, u16)>>,
+    client: OptionalCell<&'a UDPSocketClient<'a>>,
+    rx_queue: TakeCell<'a, [u8]>,
+    rx_queue_len: Cell<usize>,
+    rx_dropped: Cell<u32>,
+    tx_waiting: Cell<bool>,
+    next: ListLink<'a, UDPSocket<'a>>,
+}
+
+impl ListNode<'a, UDPSocket<'a>> for UDPSocket<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, UDPSocket<'a>> {
+        &self.next
+    }
+}
+
+impl UDPSocket<'a> {
+    /// Creates a socket that queues received packets in `rx_queue`. The
+    /// socket must be added to the mux with `MuxUDP::add_socket`.
+    pub fn new(mux: &'a MuxUDP<'a>, rx_queue: &'a mut [u8]) -> UDPSocket<'a> {
+        UDPSocket {
+            mux: mux,
+            binding: Cell::new(None),
+            client: OptionalCell::empty(),
+            rx_queue: TakeCell::new(rx_queue),
+            rx_queue_len: Cell::new(0),
+            rx_dropped: Cell::new(0),
+            tx_waiting: Cell::new(false),
+            next: ListLink::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a UDPSocketClient<'a>) {
+        self.client.set(client);
+    }
+
+    /// Binds the socket to `addr` and `port`, replacing any previous binding.
+    /// `addr` must be a local address or the unspecified address, which
+    /// binds the port on every local address. If `port` is 0, a free
+    /// ephemeral port is chosen.
+    ///
+    /// Returns `SUCCESS`, or `SuccessWithValue` with the chosen port if
+    /// `port` is 0. Returns `EINVAL` if `addr` is not a local address,
+    /// `EBUSY` if the port is already bound on `addr`, or if no ephemeral
+    /// port is free.
+    pub fn bind(&'a self, addr: IPAddr, port: u16) -> ReturnCode {
+        self.mux.bind(self, addr, port)
+    }
+
+    /// Releases the port of the socket and discards its queued packets.
+    pub fn unbind(&self) {
+        self.binding.set(None);
+        self.rx_queue_len.set(0);
+    }
+
+    /// Returns the local address and port the socket is bound to.
+    pub fn get_binding(&self) -> Option<(IPAddr, u16)> {
+        self.binding.get()
+    }
+
+    pub fn is_bound(&self) -> bool {
+        self.binding.get().is_some()
+    }
+
+    /// Sends `buf` from the port of the socket to `dest` and `dst_port`.
+    /// Returns `ERESERVE` if the socket is not bound, and `EBUSY` if the mux
+    /// is sending another packet.
+    pub fn send_to(&'a self, dest: IPAddr, dst_port: u16, buf: &[u8]) -> ReturnCode {
+        self.mux.send_from(self, dest, dst_port, buf)
+    }
+
+    /// Takes the oldest packet from the receive queue and copies as much of
+    /// its payload as fits into `buf`. Returns `None` if the queue is empty.
+    pub fn receive(&self, buf: &mut [u8]) -> Option<UDPRecvInfo> {
+        if self.rx_queue_len.get() == 0 {
+            return None;
+        }
+        self.rx_queue.map(|queue| {
+            let mut info = UDPRecvInfo {
+                src_addr: IPAddr::new(),
+                src_port: 0,
+                dst_addr: IPAddr::new(),
+                dst_port: 0,
+                len: 0,
+            };
+            info.src_addr.0.copy_from_slice(&queue[0..16]);
+            info.src_port = read_u16(&queue[16..18]);
+            info.dst_addr.0.copy_from_slice(&queue[18..34]);
+            info.dst_port = read_u16(&queue[34..36]);
+            info.len = read_u16(&queue[36..38]) as usize;
+
+            let copy_len = cmp::min(info.len, buf.len());
+            buf[..copy_len]
+                .copy_from_slice(&queue[QUEUE_ENTRY_HDR_LEN..QUEUE_ENTRY_HDR_LEN + copy_len]);
+
+            // Move the remaining packets to the front of the queue
+            let entry_len = QUEUE_ENTRY_HDR_LEN + info.len;
+            let queue_len = self.rx_queue_len.get();
+            for i in entry_len..queue_len {
+                queue[i - entry_len] = queue[i];
+            }
+            self.rx_queue_len.set(queue_len - entry_len);
+            info
+        })
+    }
+
+    /// Returns the payload length of the oldest packet in the receive queue,
+    /// or `None` if the queue is empty.
+    pub fn next_packet_len(&self) -> Option<usize> {
+        if self.rx_queue_len.get() == 0 {
+            return None;
+        }
+        self.rx_queue.map(|queue| read_u16(&queue[36..38]) as usize)
+    }
+
+    /// Returns whether there are packets in the receive queue.
+    pub fn has_packets(&self) -> bool {
+        self.rx_queue_len.get() > 0
+    }
+
+    /// Returns the number of packets dropped because the receive queue was
+    /// full.
+    pub fn get_dropped(&self) -> u32 {
+        self.rx_dropped.get()
+    }
+
+    /// Whether the socket accepts packets sent to `addr` and `port`.
+    fn matches(&self, addr: IPAddr, port: u16, exact: bool) -> bool {
+        self.binding.get().map_or(false, |(bound_addr, bound_port)| {
+            bound_port == port
+                && if exact {
+                    bound_addr == addr
+                } else {
+                    bound_addr.is_unspecified()
+                }
+        })
+    }
+
+    /// Adds a packet to the receive queue. Returns false if it does not fit.
+    fn enqueue(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+    ) -> bool {
+        self.rx_queue
+            .map(|queue| {
+                let start = self.rx_queue_len.get();
+                let end = start + QUEUE_ENTRY_HDR_LEN + payload.len();
+                if end > queue.len() {
+                    return false;
+                }
+                let entry = &mut queue[start..end];
+                entry[0..16].copy_from_slice(&src_addr.0);
+                write_u16(&mut entry[16..18], src_port);
+                entry[18..34].copy_from_slice(&dst_addr.0);
+                write_u16(&mut entry[34..36], dst_port);
+                write_u16(&mut entry[36..38], payload.len() as u16);
+                entry[QUEUE_ENTRY_HDR_LEN..].copy_from_slice(payload);
+                self.rx_queue_len.set(end);
+                true
+            }).unwrap_or(false)
+    }
+}
+
+fn read_u16(buf: &[u8]) -> u16 {
+    (buf[0] as u16) << 8 | (buf[1] as u16)
+}
+
+fn write_u16(buf: &mut [u8], value: u16) {
+    buf[0] = (value >> 8) as u8;
+    buf[1] = value as u8;
+}
+
+/// Shares a `UDPSender` and a `UDPReceiver` between the sockets in its
+/// socket table.
+pub struct MuxUDP<'a> {
+    sender: &'a UDPSender<'a>,
+    interface_list: &'a [Cell<IPAddr>],
+    sockets: List<'a, UDPSocket<'a>>,
+    inflight: OptionalCell<&'a UDPSocket<'a>>,
+    next_ephemeral_port: Cell<u16>,
+}
+
+impl MuxUDP<'a> {
+    pub fn new(sender: &'a UDPSender<'a>, interface_list: &'a [Cell<IPAddr>]) -> MuxUDP<'a> {
+        MuxUDP {
+            sender: sender,
+            interface_list: interface_list,
+            sockets: List::new(),
+            inflight: OptionalCell::empty(),
+            next_ephemeral_port: Cell::new(EPHEMERAL_PORT_MIN),
+        }
+    }
+
+    /// Adds a socket to the socket table. Each socket should only be added
+    /// once.
+    pub fn add_socket(&self, socket: &'a UDPSocket<'a>) {
+        self.sockets.push_head(socket);
+    }
+
+    fn is_local(&self, addr: IPAddr) -> bool {
+        self.interface_list
+            .iter()
+            .any(|iface| !addr.is_unspecified() && iface.get() == addr)
+    }
+
+    /// Whether a socket other than `socket` is bound to `port` on `addr`.
+    /// The unspecified address conflicts with every address.
+    fn is_bound(&self, socket: &UDPSocket<'a>, addr: IPAddr, port: u16) -> bool {
+        self.sockets.iter().any(|other| {
+            !ptr::eq(other, socket)
+                && other.binding.get().map_or(false, |(other_addr, other_port)| {
+                    other_port == port
+                        && (other_addr == addr
+                            || other_addr.is_unspecified()
+                            || addr.is_unspecified())
+                })
+        })
+    }
+
+    /// Finds a free port in the ephemeral range, starting after the last
+    /// port that was handed out.
+    fn find_ephemeral_port(&self, socket: &UDPSocket<'a>, addr: IPAddr) -> Option<u16> {
+        let range = (0xffff - EPHEMERAL_PORT_MIN) as usize + 1;
+        for _ in 0..range {
+            let port = self.next_ephemeral_port.get();
+            self.next_ephemeral_port.set(if port == 0xffff {
+                EPHEMERAL_PORT_MIN
+            } else {
+                port + 1
+            });
+            if !self.is_bound(socket, addr, port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    fn bind(&self, socket: &'a UDPSocket<'a>, addr: IPAddr, port: u16) -> ReturnCode {
+        if !addr.is_unspecified() && !self.is_local(addr) {
+            return ReturnCode::EINVAL;
+        }
+        if port == 0 {
+            match self.find_ephemeral_port(socket, addr) {
+                Some(port) => {
+                    socket.binding.set(Some((addr, port)));
+                    ReturnCode::SuccessWithValue {
+                        value: port as usize,
+                    }
+                }
+                None => ReturnCode::EBUSY,
+            }
+        } else if self.is_bound(socket, addr, port) {
+            ReturnCode::EBUSY
+        } else {
+            socket.binding.set(Some((addr, port)));
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn send_from(
+        &self,
+        socket: &'a UDPSocket<'a>,
+        dest: IPAddr,
+        dst_port: u16,
+        buf: &[u8],
+    ) -> ReturnCode {
+        let (bound_addr, src_port) = match socket.binding.get() {
+            Some(binding) => binding,
+            None => return ReturnCode::ERESERVE,
+        };
+        if self.inflight.is_some() {
+            socket.tx_waiting.set(true);
+            return ReturnCode::EBUSY;
+        }
+        // The first address of the interface is the default source address
+        // of the stack, so sockets bound to every address send from it.
+        let src_addr = if bound_addr.is_unspecified() {
+            self.interface_list
+                .first()
+                .map_or(bound_addr, |iface| iface.get())
+        } else {
+            bound_addr
+        };
+        self.sender.set_addr(src_addr);
+        let result = self.sender.send_to(dest, dst_port, src_port, buf);
+        if result == ReturnCode::SUCCESS {
+            self.inflight.set(socket);
+        }
+        result
+    }
+}
+
+impl UDPSendClient for MuxUDP<'a> {
+    fn send_done(&self, result: ReturnCode) {
+        self.inflight.take().map(|socket| {
+            socket
+                .client
+                .map(|client| client.send_done(socket, result));
+        });
+        for socket in self.sockets.iter() {
+            if self.inflight.is_some() {
+                break;
+            }
+            if socket.tx_waiting.get() {
+                socket.tx_waiting.set(false);
+                socket.client.map(|client| client.send_ready(socket));
+            }
+        }
+    }
+}
+
+impl UDPRecvClient for MuxUDP<'a> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+    ) {
+        let socket = self
+            .sockets
+            .iter()
+            .find(|socket| socket.matches(dst_addr, dst_port, true))
+            .or_else(|| {
+                self.sockets
+                    .iter()
+                    .find(|socket| socket.matches(dst_addr, dst_port, false))
+            });
+        socket.map(|socket| {
+            if socket.enqueue(src_addr, dst_addr, src_port, dst_port, payload) {
+                socket.client.map(|client| client.packet_received(socket));
+            } else {
+                socket.rx_dropped.set(socket.rx_dropped.get().wrapping_add(1));
+            }
+        });
+    }
+}
@@ -4,11 +4,10 @@ use net::ipv6::ipv6::IP6Header;
 use net::ipv6::ipv6_recv::IP6RecvClient;
 use net::udp::udp::UDPHeader;
 
-/// The UDP driver implements this client interface trait to receive
+/// The UDP mux (`MuxUDP`) implements this client interface trait to receive
 /// packets passed up the network stack to the UDPReceiver, and then
-/// distributes them to userland applications from there.
-/// Kernel apps can also instantiate structs that implement this trait
-/// in order to receive UDP packets
+/// distributes them to the sockets bound to their ports, which belong to
+/// userland applications or kernel capsules.
 pub trait UDPRecvClient {
     fn receive(
         &self,
@@ -45,8 +44,7 @@ impl<'a> IP6RecvClient for UDPReceiver<'a> {
             Some((offset, udp_header)) => {
                 let len = udp_header.get_len() as usize;
                 if len > payload.len() {
-                    // TODO: ERROR
-                    debug!("[UDP_RECV] Error: UDP length too long");
+                    log_warn!("UDP length {} is longer than the packet", len);
                     return;
                 }
                 self.client.map(|client| {
@@ -55,7 +53,7 @@ impl<'a> IP6RecvClient for UDPReceiver<'a> {
                         ip_header.get_dst_addr(),
                         udp_header.get_src_port(),
                         udp_header.get_dst_port(),
-                        &payload[offset..],
+                        &payload[offset..len],
                     );
                 });
             }
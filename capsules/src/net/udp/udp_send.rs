@@ -32,6 +32,13 @@ pub trait UDPSender<'a> {
     /// for the `UDPSender` instance
     fn set_client(&self, client: &'a UDPSendClient);
 
+    /// This function sets the source address of the packets sent by this
+    /// `UDPSender` instance.
+    ///
+    /// # Arguments
+    /// `src_addr` - IPv6 address to send packets from
+    fn set_addr(&self, src_addr: IPAddr);
+
     /// This function constructs a `UDPHeader` and sends the payload to the
     /// provided destination IP address over the provided source and
     /// destination ports.
@@ -76,6 +83,10 @@ impl<T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
         self.client.set(client);
     }
 
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.ip_send_struct.set_addr(src_addr);
+    }
+
     fn send_to(&self, dest: IPAddr, dst_port: u16, src_port: u16, buf: &[u8]) -> ReturnCode {
         let mut udp_header = UDPHeader::new();
         udp_header.set_dst_port(dst_port);
@@ -54,6 +54,10 @@ pub fn slice_to_u16(buf: &[u8]) -> u16 {
     ((buf[0] as u16) << 8) | (buf[1] as u16)
 }
 
+pub fn slice_to_u32(buf: &[u8]) -> u32 {
+    ((slice_to_u16(&buf[0..2]) as u32) << 16) | (slice_to_u16(&buf[2..4]) as u32)
+}
+
 pub fn u16_to_slice(short: u16, slice: &mut [u8]) {
     slice[0] = (short >> 8) as u8;
     slice[1] = (short & 0xff) as u8;
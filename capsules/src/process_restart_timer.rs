@@ -0,0 +1,187 @@
+//! Restart faulted processes after a delay.
+//!
+//! This capsule implements `kernel::procs::RestartTimer` on top of an alarm,
+//! which the kernel needs for processes that use the
+//! `FaultResponse::RestartWithBackoff` fault response. When such a process
+//! faults the kernel leaves it in the fault state and asks this capsule to
+//! restart it later. A pending restart is dropped if the process is
+//! terminated, restarted or resumed in the meantime, or has otherwise left
+//! the fault state by the time it is due.
+//!
+//! The pending restarts are kept in a table with one entry per process slot,
+//! rather than in the grant of the faulted process, so that they can always
+//! be recorded. Delays that do not fit in the range of the alarm are counted
+//! down in several steps.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! struct ProcessMgmtCap;
+//! unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+//! let restart_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let restarts = static_init!(
+//!     [Cell<Option<capsules::process_restart_timer::PendingRestart>>; NUM_PROCS],
+//!     Default::default()
+//! );
+//! let restart_timer = static_init!(
+//!     capsules::process_restart_timer::ProcessRestartTimer<
+//!         'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!         ProcessMgmtCap,
+//!     >,
+//!     capsules::process_restart_timer::ProcessRestartTimer::new(
+//!         board_kernel,
+//!         restart_alarm,
+//!         restarts,
+//!         ProcessMgmtCap
+//!     )
+//! );
+//! restart_alarm.set_client(restart_timer);
+//! board_kernel.set_restart_timer(restart_timer, &process_mgmt_cap);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::introspection::Introspection;
+use kernel::procs::{RestartTimer, State};
+use kernel::{AppId, Kernel};
+
+/// Longest step the alarm is set for, in alarm ticks. Keeping it to half the
+/// range of the alarm makes sure the elapsed time between two updates of the
+/// table does not wrap.
+const MAX_ALARM_STEP: u32 = 1 << 31;
+
+/// A process that is waiting to be restarted.
+#[derive(Copy, Clone)]
+pub struct PendingRestart {
+    app: AppId,
+    /// Alarm tick at which `remaining` was last updated.
+    reference: u32,
+    /// Alarm ticks after `reference` at which the process should be
+    /// restarted.
+    remaining: u64,
+}
+
+pub struct ProcessRestartTimer<'a, A: Alarm + 'a, C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    introspection: Introspection,
+    alarm: &'a A,
+    /// Pending restarts, indexed by the index of the `AppId` of the process.
+    restarts: &'a [Cell<Option<PendingRestart>>],
+    capability: C,
+}
+
+impl<A: Alarm, C: ProcessManagementCapability> ProcessRestartTimer<'a, A, C> {
+    /// `restarts` must have an entry for every process slot of the board.
+    pub fn new(
+        kernel: &'static Kernel,
+        alarm: &'a A,
+        restarts: &'a [Cell<Option<PendingRestart>>],
+        capability: C,
+    ) -> ProcessRestartTimer<'a, A, C> {
+        ProcessRestartTimer {
+            kernel: kernel,
+            introspection: Introspection::new(kernel),
+            alarm: alarm,
+            restarts: restarts,
+            capability: capability,
+        }
+    }
+
+    /// Count the time that passed since the last update of all pending
+    /// restarts.
+    fn update_remaining(&self, now: u32) {
+        for restart in self.restarts.iter() {
+            restart.set(restart.get().map(|mut pending| {
+                let elapsed = now.wrapping_sub(pending.reference);
+                pending.remaining = pending.remaining.saturating_sub(elapsed as u64);
+                pending.reference = now;
+                pending
+            }));
+        }
+    }
+
+    /// Set the alarm for the nearest pending restart, or disable it if there
+    /// are none.
+    fn reset_active_alarm(&self, now: u32) {
+        let next = self
+            .restarts
+            .iter()
+            .filter_map(|restart| restart.get())
+            .map(|pending| cmp::min(pending.remaining, MAX_ALARM_STEP as u64) as u32)
+            .min();
+        match next {
+            Some(remaining) => self.alarm.set_alarm(now.wrapping_add(cmp::max(remaining, 1))),
+            None => self.alarm.disable(),
+        }
+    }
+}
+
+impl<A: Alarm, C: ProcessManagementCapability> RestartTimer for ProcessRestartTimer<'a, A, C> {
+    fn restart_after(&self, app: AppId, delay_ms: u32) {
+        let now = self.alarm.now();
+        let freq = <A::Frequency>::frequency() as u64;
+        let delay_tics = delay_ms as u64 * freq / 1000;
+
+        match self.restarts.get(app.idx()) {
+            Some(restart) => {
+                self.update_remaining(now);
+                restart.set(Some(PendingRestart {
+                    app: app,
+                    reference: now,
+                    remaining: delay_tics,
+                }));
+                self.reset_active_alarm(now);
+            }
+            // The table is too short for this process, so the best we can do
+            // is restart it right away.
+            None => {
+                self.kernel.restart_process(app, &self.capability);
+            }
+        }
+    }
+
+    fn cancel(&self, app: AppId) {
+        let pending = self
+            .restarts
+            .get(app.idx())
+            .map_or(false, |restart| restart.take().is_some());
+        if pending {
+            let now = self.alarm.now();
+            self.update_remaining(now);
+            self.reset_active_alarm(now);
+        }
+    }
+}
+
+impl<A: Alarm, C: ProcessManagementCapability> time::Client for ProcessRestartTimer<'a, A, C> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        self.update_remaining(now);
+
+        for restart in self.restarts.iter() {
+            match restart.get() {
+                Some(pending) if pending.remaining == 0 => {
+                    restart.set(None);
+                    // Something else may have brought the process back
+                    // already.
+                    let state = self
+                        .introspection
+                        .process_state(pending.app, &self.capability);
+                    if state == Some(State::Fault) {
+                        self.kernel.restart_process(pending.app, &self.capability);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.reset_active_alarm(now);
+    }
+}
@@ -0,0 +1,187 @@
+//! Verify apps with SHA-256 hashes before they are loaded.
+//!
+//! `Sha256Verifier` implements `kernel::procs::AppVerifier` for apps whose
+//! credentials are a SHA-256 hash (format `1` of the TBF credentials element).
+//! It hashes the signed regions of the app in software and accepts the app if
+//! the hash matches the one in its header. Apps with other credentials are
+//! rejected.
+//!
+//! A hash only shows that the app was not corrupted or changed after it was
+//! built, not who built it, so this is meant for boards that want to catch
+//! damaged images rather than untrusted ones.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let verifier = static_init!(
+//!     capsules::sha256_verifier::Sha256Verifier,
+//!     capsules::sha256_verifier::Sha256Verifier::new()
+//! );
+//! board_kernel.set_app_verifier(verifier, &process_mgmt_cap);
+//! kernel::procs::load_processes(...);
+//! ```
+
+use kernel::procs::{AppCredentials, AppVerifier};
+
+/// Length of a SHA-256 hash in bytes.
+pub const HASH_LEN: usize = 32;
+
+const BLOCK_LEN: usize = 64;
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Software implementation of SHA-256 (FIPS 180-4).
+pub struct Sha256 {
+    state: [u32; 8],
+    /// Data that does not fill a block yet.
+    block: [u8; BLOCK_LEN],
+    block_len: usize,
+    /// Length of all data hashed so far, in bytes.
+    total_len: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256 {
+            state: INITIAL_STATE,
+            block: [0; BLOCK_LEN],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Add `data` to the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let len = ::core::cmp::min(BLOCK_LEN - self.block_len, data.len());
+            self.block[self.block_len..self.block_len + len].copy_from_slice(&data[..len]);
+            self.block_len += len;
+            data = &data[len..];
+            if self.block_len == BLOCK_LEN {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Pad the data and return the hash.
+    pub fn finish(mut self) -> [u8; HASH_LEN] {
+        let bit_len = self.total_len * 8;
+        self.block[self.block_len] = 0x80;
+        self.block_len += 1;
+        if self.block_len > BLOCK_LEN - 8 {
+            for byte in self.block[self.block_len..].iter_mut() {
+                *byte = 0;
+            }
+            self.compress();
+            self.block_len = 0;
+        }
+        for byte in self.block[self.block_len..BLOCK_LEN - 8].iter_mut() {
+            *byte = 0;
+        }
+        for i in 0..8 {
+            self.block[BLOCK_LEN - 8 + i] = (bit_len >> (56 - 8 * i)) as u8;
+        }
+        self.compress();
+
+        let mut hash = [0; HASH_LEN];
+        for (i, word) in self.state.iter().enumerate() {
+            for j in 0..4 {
+                hash[4 * i + j] = (word >> (24 - 8 * j)) as u8;
+            }
+        }
+        hash
+    }
+
+    /// Process the full block in `self.block`.
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = (self.block[4 * i] as u32) << 24
+                | (self.block[4 * i + 1] as u32) << 16
+                | (self.block[4 * i + 2] as u32) << 8
+                | self.block[4 * i + 3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut v = self.state;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v[7] = v[6];
+            v[6] = v[5];
+            v[5] = v[4];
+            v[4] = v[3].wrapping_add(t1);
+            v[3] = v[2];
+            v[2] = v[1];
+            v[1] = v[0];
+            v[0] = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip(v.iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+/// Accepts apps whose SHA-256 credentials match the hash of their signed
+/// regions.
+pub struct Sha256Verifier;
+
+impl Sha256Verifier {
+    pub fn new() -> Sha256Verifier {
+        Sha256Verifier
+    }
+}
+
+impl AppVerifier for Sha256Verifier {
+    fn verify(&self, credentials: AppCredentials, signed_regions: &[&'static [u8]]) -> bool {
+        let expected = match credentials {
+            AppCredentials::Sha256(hash) => hash,
+            _ => return false,
+        };
+        let mut sha = Sha256::new();
+        for region in signed_regions.iter() {
+            sha.update(region);
+        }
+        let hash = sha.finish();
+
+        // Compare every byte, so the time taken does not depend on where the
+        // hashes differ.
+        expected.len() == HASH_LEN
+            && hash
+                .iter()
+                .zip(expected.iter())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
@@ -1,5 +1,11 @@
 //! Virtualize the Alarm interface to enable multiple users of an underlying
 //! alarm hardware peripheral.
+//!
+//! The mux stays on the 32-bit `Alarm` interface on purpose, as that is what
+//! the hardware alarms implement. It compares alarms relative to the time it
+//! last set the underlying alarm, so it handles wraps of the counter itself.
+//! Users that need 64-bit time put an `ExtendedAlarm` on top of a
+//! `VirtualMuxAlarm`.
 
 use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
@@ -0,0 +1,126 @@
+//! Virtualize a random number generator.
+//!
+//! `MuxRng` shares one `Rng` between several users, each of which gets a
+//! `VirtualRngDevice` that is an `Rng` of its own. The underlying `Rng` runs
+//! while any device wants randomness, and the numbers it delivers are handed
+//! to the waiting devices in turn, so no two devices get the same numbers.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! let rng_mux = static_init!(MuxRng<'static>, MuxRng::new(entropy_to_random));
+//! entropy_to_random.set_client(rng_mux);
+//!
+//! let rng_device = static_init!(VirtualRngDevice<'static>, VirtualRngDevice::new(rng_mux));
+//! let rng_driver = static_init!(
+//!     capsules::rng::RngDriver<'static>,
+//!     capsules::rng::RngDriver::new(rng_device, board_kernel.create_grant(&grant_cap))
+//! );
+//! rng_device.set_client(rng_driver);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::rng::{self, Rng};
+use kernel::ReturnCode;
+
+pub struct MuxRng<'a> {
+    rng: &'a Rng<'a>,
+    devices: List<'a, VirtualRngDevice<'a>>,
+}
+
+impl MuxRng<'a> {
+    pub const fn new(rng: &'a Rng<'a>) -> MuxRng<'a> {
+        MuxRng {
+            rng: rng,
+            devices: List::new(),
+        }
+    }
+
+    fn any_requested(&self) -> bool {
+        self.devices.iter().any(|device| device.requested.get())
+    }
+}
+
+impl rng::Client for MuxRng<'a> {
+    fn randomness_available(
+        &self,
+        randomness: &mut Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> rng::Continue {
+        for device in self.devices.iter().filter(|device| device.requested.get()) {
+            let done = device.client.map_or(true, |client| {
+                client.randomness_available(randomness, error) == rng::Continue::Done
+            });
+            if done {
+                device.requested.set(false);
+            }
+        }
+        if self.any_requested() {
+            rng::Continue::More
+        } else {
+            rng::Continue::Done
+        }
+    }
+}
+
+pub struct VirtualRngDevice<'a> {
+    mux: &'a MuxRng<'a>,
+    /// Whether the client is waiting for randomness.
+    requested: Cell<bool>,
+    next: ListLink<'a, VirtualRngDevice<'a>>,
+    client: OptionalCell<&'a rng::Client>,
+}
+
+impl ListNode<'a, VirtualRngDevice<'a>> for VirtualRngDevice<'a> {
+    fn next(&self) -> &'a ListLink<VirtualRngDevice<'a>> {
+        &self.next
+    }
+}
+
+impl VirtualRngDevice<'a> {
+    pub const fn new(mux: &'a MuxRng<'a>) -> VirtualRngDevice<'a> {
+        VirtualRngDevice {
+            mux: mux,
+            requested: Cell::new(false),
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl Rng<'a> for VirtualRngDevice<'a> {
+    fn get(&self) -> ReturnCode {
+        if self.requested.get() {
+            return ReturnCode::SUCCESS;
+        }
+        // If another device is waiting, the underlying `Rng` is running
+        // already.
+        let running = self.mux.any_requested();
+        self.requested.set(true);
+        if running {
+            return ReturnCode::SUCCESS;
+        }
+        let result = self.mux.rng.get();
+        if result != ReturnCode::SUCCESS {
+            self.requested.set(false);
+        }
+        result
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        self.requested.set(false);
+        if self.mux.any_requested() {
+            ReturnCode::SUCCESS
+        } else {
+            self.mux.rng.cancel()
+        }
+    }
+
+    fn set_client(&'a self, client: &'a rng::Client) {
+        self.mux.devices.push_head(self);
+        self.client.set(client);
+    }
+}
@@ -10,6 +10,11 @@
 //! `UartMux` provides shared access to a single UART bus for multiple users.
 //! `UartDevice` provides access for a single client.
 //!
+//! A transmission that a client asks for is started from a dynamic deferred
+//! call, after the request returns. If the UART fails to start it and calls
+//! back right away, the callback then does not reach the client while it is
+//! still in its call to `transmit()`.
+//!
 //! Usage
 //! -----
 //!
@@ -17,7 +22,17 @@
 //! // Create a shared UART channel for the console and for kernel debug.
 //! let uart_mux = static_init!(
 //!     UartMux<'static>,
-//!     UartMux::new(&sam4l::usart::USART0, &mut capsules::virtual_uart::RX_BUF)
+//!     UartMux::new(
+//!         &sam4l::usart::USART0,
+//!         &mut capsules::virtual_uart::RX_BUF,
+//!         115200,
+//!         dynamic_deferred_caller
+//!     )
+//! );
+//! uart_mux.initialize_callback_handle(
+//!     dynamic_deferred_caller
+//!         .register(uart_mux)
+//!         .expect("no deferred call slot available for the UART mux"),
 //! );
 //! hil::uart::UART::set_client(&sam4l::usart::USART0, uart_mux);
 
@@ -41,6 +56,9 @@ use core::cell::Cell;
 use core::cmp;
 
 use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
 use kernel::common::{List, ListLink, ListNode};
 use kernel::hil;
 use kernel::hil::uart;
@@ -56,6 +74,8 @@ pub struct UartMux<'a> {
     inflight: OptionalCell<&'a UartDevice<'a>>,
     buffer: TakeCell<'static, [u8]>,
     completing_read: Cell<bool>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
 }
 
 impl<'a> hil::uart::Client for UartMux<'a> {
@@ -140,8 +160,19 @@ impl<'a> hil::uart::Client for UartMux<'a> {
     }
 }
 
+impl<'a> DynamicDeferredCallClient for UartMux<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        self.do_next_op();
+    }
+}
+
 impl<'a> UartMux<'a> {
-    pub fn new(uart: &'a hil::uart::UART, buffer: &'static mut [u8], speed: u32) -> UartMux<'a> {
+    pub fn new(
+        uart: &'a hil::uart::UART,
+        buffer: &'static mut [u8],
+        speed: u32,
+        deferred_caller: &'a DynamicDeferredCall,
+    ) -> UartMux<'a> {
         UartMux {
             uart: uart,
             speed: speed,
@@ -149,9 +180,17 @@ impl<'a> UartMux<'a> {
             inflight: OptionalCell::empty(),
             buffer: TakeCell::new(buffer),
             completing_read: Cell::new(false),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
         }
     }
 
+    /// Set the handle the mux got when it registered with its
+    /// `DynamicDeferredCall`. Until it is set, transmissions start right away.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.set(handle);
+    }
+
     pub fn initialize(&self) {
         self.uart.configure(uart::UARTParameters {
             baud_rate: self.speed,
@@ -161,6 +200,18 @@ impl<'a> UartMux<'a> {
         });
     }
 
+    /// Start the next transmission from a deferred call, after the current
+    /// call into the mux returns. Without a handle the transmission starts
+    /// right away.
+    fn do_next_op_async(&self) {
+        self.handle.map_or_else(
+            || self.do_next_op(),
+            |handle| {
+                self.deferred_caller.set(*handle);
+            },
+        );
+    }
+
     fn do_next_op(&self) {
         if self.inflight.is_none() {
             let mnode = self.devices.iter().find(|node| node.operation.is_some());
@@ -295,7 +346,7 @@ impl<'a> hil::uart::UART for UartDevice<'a> {
     fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
         self.tx_buffer.replace(tx_data);
         self.operation.set(Operation::Transmit { len: tx_len });
-        self.mux.do_next_op();
+        self.mux.do_next_op_async();
     }
 
     /// Receive data until buffer is full.
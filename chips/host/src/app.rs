@@ -0,0 +1,238 @@
+//! Simulated processes and the flash image they are loaded from.
+//!
+//! A simulated process is a closure. Whenever the kernel runs the process, the
+//! closure is called with what happened since it last ran (an `Event`) and
+//! returns what the process does next (an `Action`), usually a system call.
+//! The closure keeps whatever state it needs between calls.
+
+use std::mem;
+
+use kernel::procs::FunctionCall;
+
+/// What happened to a process since it last ran.
+#[derive(Copy, Clone, Debug)]
+pub enum Event {
+    /// The process starts (or restarts) at its entry point. The arguments are
+    /// the ones the kernel passes to `_start`: the start of the app's code,
+    /// the start and length of its memory, and its initial heap break.
+    Start {
+        text_start: usize,
+        mem_start: usize,
+        mem_len: usize,
+        app_heap_break: usize,
+    },
+
+    /// The kernel called a callback the process subscribed. `pc` is the
+    /// callback pointer that was passed to `subscribe`.
+    Callback {
+        pc: usize,
+        arguments: [usize; 4],
+    },
+
+    /// The most recent system call returned this value.
+    Return(isize),
+
+    /// The process continues after it was preempted or after it spun.
+    Resume,
+}
+
+/// What a process does next.
+#[derive(Copy, Clone, Debug)]
+pub enum Action {
+    Yield,
+    Subscribe {
+        driver_number: usize,
+        subdriver_number: usize,
+        callback_ptr: usize,
+        appdata: usize,
+    },
+    Command {
+        driver_number: usize,
+        subdriver_number: usize,
+        arg0: usize,
+        arg1: usize,
+    },
+    Allow {
+        driver_number: usize,
+        subdriver_number: usize,
+        allow_address: *mut u8,
+        allow_size: usize,
+    },
+    Memop {
+        operand: usize,
+        arg0: usize,
+    },
+    AllowReadOnly {
+        driver_number: usize,
+        subdriver_number: usize,
+        allow_address: *const u8,
+        allow_size: usize,
+    },
+    /// Compute for the given number of microseconds without making a system
+    /// call. This is how simulated time passes, and how a process uses up its
+    /// timeslice.
+    Spin(u32),
+    /// Trigger a fault, e.g. as if the process had accessed invalid memory.
+    Fault,
+}
+
+/// A process to be loaded by the simulation.
+pub struct SimApp {
+    name: String,
+    minimum_ram_size: u32,
+    tlvs: Vec<(u16, Vec<u8>)>,
+    rodata: Vec<u8>,
+    crate process: Box<FnMut(Event) -> Action>,
+}
+
+impl SimApp {
+    pub fn new<F: 'static + FnMut(Event) -> Action>(name: &str, process: F) -> SimApp {
+        SimApp {
+            name: name.to_string(),
+            minimum_ram_size: 0,
+            tlvs: Vec::new(),
+            rodata: Vec::new(),
+            process: Box::new(process),
+        }
+    }
+
+    /// Set the `minimum_ram_size` of the app's TBF header.
+    pub fn minimum_ram_size(mut self, size: u32) -> SimApp {
+        self.minimum_ram_size = size;
+        self
+    }
+
+    /// Add a TBF header element with the given type and value, e.g. to give
+    /// the app a priority or restrict the drivers it may use.
+    pub fn tlv(mut self, tipe: u16, value: &[u8]) -> SimApp {
+        self.tlvs.push((tipe, value.to_vec()));
+        self
+    }
+
+    /// Put constant data in the app's flash, like the read-only data of a
+    /// real app. It follows the four bytes of code, at `text_start + 4`.
+    pub fn rodata(mut self, data: &[u8]) -> SimApp {
+        self.rodata = data.to_vec();
+        self
+    }
+
+    /// Size of the TBF header of this app.
+    crate fn header_size(&self) -> usize {
+        let tbf = self.tbf();
+        tbf[2] as usize | (tbf[3] as usize) << 8
+    }
+
+    /// Build the TBF image of this app. The image consists of the header,
+    /// four bytes of "code", the first of which is the entry point, and the
+    /// read-only data.
+    crate fn tbf(&self) -> Vec<u8> {
+        let mut tlvs = Vec::new();
+        let mut main = Vec::new();
+        // The entry point is the first byte after the header. It has the Thumb
+        // bit set, like entry points on hardware.
+        push_u32(&mut main, 1); // init_fn_offset
+        push_u32(&mut main, 0); // protected_size
+        push_u32(&mut main, self.minimum_ram_size);
+        push_tlv(&mut tlvs, 1, &main);
+        push_tlv(&mut tlvs, 3, self.name.as_bytes());
+        for &(tipe, ref value) in self.tlvs.iter() {
+            push_tlv(&mut tlvs, tipe, value);
+        }
+
+        let header_size = 16 + tlvs.len();
+        // The next app starts on a word boundary.
+        let total_size = (header_size + 4 + self.rodata.len() + 3) / 4 * 4;
+
+        let mut image = Vec::new();
+        push_u16(&mut image, 2);
+        push_u16(&mut image, header_size as u16);
+        push_u32(&mut image, total_size as u32);
+        push_u32(&mut image, 1); // enabled
+        push_u32(&mut image, 0); // checksum, filled in below
+        image.extend_from_slice(&tlvs);
+        image.extend_from_slice(&[0; 4]);
+        image.extend_from_slice(&self.rodata);
+        image.resize(total_size, 0);
+
+        let checksum = (0..header_size / 4)
+            .filter(|&i| i != 3)
+            .fold(0, |checksum, i| checksum ^ read_u32(&image, i * 4));
+        write_u32(&mut image, 12, checksum);
+        image
+    }
+}
+
+/// Lay out the TBF images of `apps` back to back, the way they would be in
+/// flash. Returns the flash and the offset of each app in it.
+crate fn build_flash(apps: &[SimApp]) -> (&'static [u8], Vec<usize>) {
+    let mut image = Vec::new();
+    let mut offsets = Vec::new();
+    for app in apps.iter() {
+        offsets.push(image.len());
+        image.extend_from_slice(&app.tbf());
+    }
+    // An empty header marks the end of the apps.
+    image.extend_from_slice(&[0; 16]);
+
+    // The kernel reads the header as words, so the flash must be aligned.
+    let words = (image.len() + mem::size_of::<usize>() - 1) / mem::size_of::<usize>();
+    let flash = Box::leak(vec![0usize; words].into_boxed_slice());
+    let flash = unsafe {
+        ::std::slice::from_raw_parts_mut(
+            flash.as_mut_ptr() as *mut u8,
+            words * mem::size_of::<usize>(),
+        )
+    };
+    flash[..image.len()].copy_from_slice(&image);
+    (flash, offsets)
+}
+
+crate fn event_for_call(call: FunctionCall, entry_point: usize) -> Event {
+    if call.pc == entry_point {
+        Event::Start {
+            text_start: call.argument0,
+            mem_start: call.argument1,
+            mem_len: call.argument2,
+            app_heap_break: call.argument3,
+        }
+    } else {
+        Event::Callback {
+            pc: call.pc,
+            arguments: [
+                call.argument0,
+                call.argument1,
+                call.argument2,
+                call.argument3,
+            ],
+        }
+    }
+}
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.push(value as u8);
+    buf.push((value >> 8) as u8);
+}
+
+fn push_u32(buf: &mut Vec<u8>, value: u32) {
+    push_u16(buf, value as u16);
+    push_u16(buf, (value >> 16) as u16);
+}
+
+fn push_tlv(buf: &mut Vec<u8>, tipe: u16, value: &[u8]) {
+    push_u16(buf, tipe);
+    push_u16(buf, value.len() as u16);
+    buf.extend_from_slice(value);
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    (0..4).fold(0, |value, i| value | (buf[offset + i] as u32) << (8 * i))
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        buf[offset + i] = (value >> (8 * i)) as u8;
+    }
+}
@@ -0,0 +1,84 @@
+//! Simulated chip.
+//!
+//! Peripherals of the simulated chip are whatever the test needs: a test
+//! raises an "interrupt" by queueing a closure with `raise_interrupt()`, and
+//! the kernel runs it the next time it services interrupts.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel;
+use mpu;
+use systick;
+
+pub struct SimChip {
+    mpu: mpu::MPU,
+    systick: systick::SysTick,
+    interrupts: RefCell<VecDeque<Box<FnMut()>>>,
+    /// How many times the kernel went to sleep.
+    sleep_count: Cell<usize>,
+}
+
+impl SimChip {
+    pub fn new() -> SimChip {
+        SimChip {
+            mpu: mpu::MPU::new(),
+            systick: systick::SysTick::new(),
+            interrupts: RefCell::new(VecDeque::new()),
+            sleep_count: Cell::new(0),
+        }
+    }
+
+    /// Queue an interrupt handler. The kernel runs queued handlers in order
+    /// when it services interrupts, and preempts processes to do so.
+    pub fn raise_interrupt<F: 'static + FnMut()>(&self, handler: F) {
+        self.interrupts.borrow_mut().push_back(Box::new(handler));
+    }
+
+    /// How many times the kernel went to sleep because it had no work to do.
+    pub fn sleep_count(&self) -> usize {
+        self.sleep_count.get()
+    }
+}
+
+impl kernel::Chip for SimChip {
+    type MPU = mpu::MPU;
+    type SysTick = systick::SysTick;
+
+    fn mpu(&self) -> &mpu::MPU {
+        &self.mpu
+    }
+
+    fn systick(&self) -> &systick::SysTick {
+        &self.systick
+    }
+
+    fn service_pending_interrupts(&self) {
+        // Handlers may raise further interrupts, so do not hold the borrow
+        // while running one.
+        loop {
+            let next = self.interrupts.borrow_mut().pop_front();
+            match next {
+                Some(mut handler) => handler(),
+                None => break,
+            }
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        !self.interrupts.borrow().is_empty()
+    }
+
+    fn sleep(&self) {
+        // There is nothing to wait for: if no interrupt is pending nothing
+        // can happen until the test raises one.
+        self.sleep_count.set(self.sleep_count.get() + 1);
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        f()
+    }
+}
@@ -0,0 +1,275 @@
+//! Simulated chip for running the Tock kernel on a development machine.
+//!
+//! This crate provides implementations of `Chip`, `MPU`, `SysTick` and
+//! `UserspaceKernelBoundary` that run on the host, so that the kernel main
+//! loop, processes, grants, IPC and capsules can be exercised with
+//! `cargo test`. Processes are host closures: see `SimApp`, `Event` and
+//! `Action`.
+//!
+//! A test sets up a kernel and loads its apps with `boot()`. Since the test
+//! needs the kernel to return control at some point, it drives the main loop
+//! with `run_until_idle()` instead of calling `Kernel::kernel_loop()`. Tests
+//! of loading apps at runtime use `boot_with_loader()` and `SysCall::add_app()`.
+//!
+//! ```
+//! extern crate host;
+//! extern crate kernel;
+//!
+//! use std::cell::Cell;
+//! use std::rc::Rc;
+//!
+//! use host::{Action, Event, SimApp, SimPlatform};
+//! use kernel::capabilities::MainLoopCapability;
+//! use kernel::{AppId, Driver, ReturnCode};
+//!
+//! struct Cap;
+//! unsafe impl MainLoopCapability for Cap {}
+//!
+//! struct Echo;
+//! impl Driver for Echo {
+//!     fn command(&self, _: usize, arg0: usize, _: usize, _: AppId) -> ReturnCode {
+//!         ReturnCode::SuccessWithValue { value: arg0 }
+//!     }
+//! }
+//!
+//! let result = Rc::new(Cell::new(0));
+//! let app_result = result.clone();
+//! let app = SimApp::new("echo", move |event| match event {
+//!     Event::Start { .. } => Action::Command {
+//!         driver_number: 0x42,
+//!         subdriver_number: 1,
+//!         arg0: 7,
+//!         arg1: 0,
+//!     },
+//!     Event::Return(value) => {
+//!         app_result.set(value);
+//!         Action::Yield
+//!     }
+//!     _ => Action::Yield,
+//! });
+//!
+//! let (kernel, chip, _) = host::boot(vec![app]);
+//! let mut platform = SimPlatform::new();
+//! platform.add_driver(0x42, host::leak(Echo));
+//!
+//! let scheduler = kernel::scheduler::RoundRobinScheduler::new(10000);
+//! assert!(host::run_until_idle(kernel, &platform, chip, None, &scheduler, 10, &Cap));
+//! assert_eq!(result.get(), 7);
+//! ```
+
+#![feature(crate_visibility_modifier)]
+#![crate_name = "host"]
+#![crate_type = "rlib"]
+
+extern crate kernel;
+
+mod app;
+mod chip;
+mod platform;
+
+pub mod mpu;
+pub mod syscall;
+pub mod systick;
+
+pub use app::{Action, Event, SimApp};
+pub use chip::SimChip;
+pub use platform::SimPlatform;
+pub use syscall::SysCall;
+
+use std::cell::Cell;
+use std::mem;
+
+use kernel::capabilities::{MainLoopCapability, ProcessManagementCapability};
+use kernel::procs::{DynamicProcessLoader, FaultResponse, ProcessType};
+use kernel::scheduler::{Scheduler, SchedulingDecision, StoppedExecutingReason};
+use kernel::{ipc, Chip, Kernel, Platform};
+
+/// Memory for each app that `boot()` loads.
+const APP_MEMORY_SIZE: usize = 16 * 1024;
+
+struct BootCap;
+unsafe impl ProcessManagementCapability for BootCap {}
+
+/// Give `value` a static lifetime, like `static_init!` does on a board.
+pub fn leak<T>(value: T) -> &'static mut T {
+    Box::leak(Box::new(value))
+}
+
+/// Allocate `size` bytes of zeroed memory for processes. The memory is word
+/// aligned, like app memory on a board.
+pub fn app_memory(size: usize) -> &'static mut [u8] {
+    let words = (size + mem::size_of::<usize>() - 1) / mem::size_of::<usize>();
+    let memory = Box::leak(vec![0usize; words].into_boxed_slice());
+    unsafe { ::std::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, size) }
+}
+
+/// Boot a kernel on a new `SimChip` with one process slot for each of `apps`,
+/// and load the apps. A process that faults panics the kernel.
+///
+/// Returns the kernel, the chip and the process array that the kernel loaded
+/// the apps into, like the `PROCESSES` static of a board.
+pub fn boot(
+    apps: Vec<SimApp>,
+) -> (
+    &'static Kernel,
+    &'static SimChip,
+    &'static [Option<&'static ProcessType>],
+) {
+    let (kernel, chip, procs, ()) = boot_with(apps, FaultResponse::Panic, |_| ());
+    (kernel, chip, procs)
+}
+
+/// Like `boot()`, but faulting processes are handled with `fault_response`,
+/// and `setup` runs after the kernel is created and before the apps are
+/// loaded. Grants have to be created, and things like the app verifier set,
+/// in `setup`. Also returns what `setup` returned.
+pub fn boot_with<T, F: FnOnce(&'static Kernel) -> T>(
+    apps: Vec<SimApp>,
+    fault_response: FaultResponse,
+    setup: F,
+) -> (
+    &'static Kernel,
+    &'static SimChip,
+    &'static [Option<&'static ProcessType>],
+    T,
+) {
+    let memory_size = apps.len() * APP_MEMORY_SIZE;
+    let (kernel, chip, _, procs, _, _, value) =
+        boot_processes(apps, 0, memory_size, fault_response, setup);
+    (kernel, chip, procs, value)
+}
+
+/// Like `boot()`, but with `free_slots` more process slots than apps, and
+/// `memory_size` bytes of memory for all processes. Also returns a
+/// `DynamicProcessLoader` for loading processes into the free slots and the
+/// memory the apps did not use, and the `SysCall` that `SysCall::add_app()`
+/// adds apps to at runtime.
+pub fn boot_with_loader(
+    apps: Vec<SimApp>,
+    free_slots: usize,
+    memory_size: usize,
+) -> (
+    &'static Kernel,
+    &'static SimChip,
+    &'static [Option<&'static ProcessType>],
+    &'static SysCall,
+    &'static DynamicProcessLoader<SysCall, mpu::MPU>,
+) {
+    let (kernel, chip, syscall, procs, processes, memory, ()) =
+        boot_processes(apps, free_slots, memory_size, FaultResponse::Panic, |_| ());
+    let loader: &'static DynamicProcessLoader<SysCall, mpu::MPU> =
+        leak(DynamicProcessLoader::new(
+            kernel,
+            syscall,
+            chip.mpu(),
+            processes,
+            memory,
+            FaultResponse::Panic,
+        ));
+    (kernel, chip, procs, syscall, loader)
+}
+
+/// Boot a kernel and load `apps` into a process array with `free_slots` more
+/// slots than apps. Returns, among others, the process array both for reading
+/// and for loading more processes into, and the memory that no process got.
+fn boot_processes<T, F: FnOnce(&'static Kernel) -> T>(
+    apps: Vec<SimApp>,
+    free_slots: usize,
+    memory_size: usize,
+    fault_response: FaultResponse,
+    setup: F,
+) -> (
+    &'static Kernel,
+    &'static SimChip,
+    &'static SysCall,
+    &'static [Option<&'static ProcessType>],
+    &'static mut [Option<&'static ProcessType>],
+    &'static mut [u8],
+    T,
+) {
+    let processes: &'static mut [Option<&'static ProcessType>] =
+        Box::leak(vec![None; apps.len() + free_slots].into_boxed_slice());
+    // The kernel and the caller read the array that `load_processes()` fills
+    // in, and a `DynamicProcessLoader` may fill in later.
+    let procs: &'static [Option<&'static ProcessType>] = unsafe { &*(processes as *const _) };
+    let loader_processes: &'static mut [Option<&'static ProcessType>] =
+        unsafe { &mut *(processes as *mut _) };
+    let memory = app_memory(memory_size);
+
+    let chip: &'static SimChip = leak(SimChip::new());
+    let syscall: &'static SysCall = leak(SysCall::new(chip, apps));
+    let kernel: &'static Kernel = leak(Kernel::new(procs));
+    let value = setup(kernel);
+    let memory = kernel::procs::load_processes(
+        kernel,
+        syscall,
+        chip.mpu(),
+        syscall.flash_start(),
+        memory,
+        processes,
+        fault_response,
+        &BootCap,
+    );
+    (
+        kernel,
+        chip,
+        syscall,
+        procs,
+        loader_processes,
+        memory,
+        value,
+    )
+}
+
+/// Run the kernel main loop until the kernel has no more work to do and no
+/// interrupt is pending, or until the scheduler has chosen a process to run
+/// `max_runs` times. Limiting how often processes run keeps a test from
+/// hanging on a process that never yields.
+///
+/// Returns `true` if the kernel became idle.
+pub fn run_until_idle<P: Platform, SC: Scheduler>(
+    kernel: &'static Kernel,
+    platform: &P,
+    chip: &SimChip,
+    ipc: Option<&ipc::IPC>,
+    scheduler: &SC,
+    max_runs: usize,
+    capability: &MainLoopCapability,
+) -> bool {
+    let scheduler = LimitedScheduler {
+        scheduler: scheduler,
+        remaining_runs: Cell::new(max_runs),
+    };
+    loop {
+        let sleep_count = chip.sleep_count();
+        kernel.kernel_loop_operation(platform, chip, ipc, &scheduler, capability);
+        if chip.sleep_count() != sleep_count && !chip.has_pending_interrupts() {
+            return true;
+        }
+        if scheduler.remaining_runs.get() == 0 {
+            return false;
+        }
+    }
+}
+
+/// Passes scheduling decisions through until `remaining_runs` is used up.
+struct LimitedScheduler<'a, SC: 'a + Scheduler> {
+    scheduler: &'a SC,
+    remaining_runs: Cell<usize>,
+}
+
+impl<'a, SC: Scheduler> Scheduler for LimitedScheduler<'a, SC> {
+    fn next(&self, kernel: &Kernel) -> Option<SchedulingDecision> {
+        if self.remaining_runs.get() == 0 {
+            return None;
+        }
+        self.scheduler.next(kernel).map(|decision| {
+            self.remaining_runs.set(self.remaining_runs.get() - 1);
+            decision
+        })
+    }
+
+    fn result(&self, reason: StoppedExecutingReason) {
+        self.scheduler.result(reason);
+    }
+}
@@ -0,0 +1,186 @@
+//! Simulated memory protection unit.
+//!
+//! The simulated MPU does not stop simulated processes from accessing memory,
+//! since they are host code. Instead it keeps track of the regions the kernel
+//! asks for, so tests can check what a process would be allowed to access.
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+
+use kernel::mpu::{self, Permissions, Region};
+
+/// A region together with the user mode permissions it was allocated with.
+#[derive(Copy, Clone)]
+pub struct SimRegion {
+    pub region: Region,
+    pub permissions: Permissions,
+}
+
+impl SimRegion {
+    fn contains(&self, start: usize, size: usize) -> bool {
+        let region_start = self.region.start_address() as usize;
+        start >= region_start && start + size <= region_start + self.region.size()
+    }
+}
+
+/// The regions of one process.
+#[derive(Clone, Default)]
+pub struct MpuConfig {
+    /// Regions allocated with `allocate_region()`, e.g. for flash.
+    regions: Vec<SimRegion>,
+    /// The app-owned part of process memory.
+    app_memory: Option<SimRegion>,
+    /// The stack guard, which user mode cannot access.
+    guard: Option<Region>,
+}
+
+impl MpuConfig {
+    /// Returns whether user mode may read `size` bytes at `start` under this
+    /// configuration.
+    pub fn accessible(&self, start: *const u8, size: usize) -> bool {
+        let start = start as usize;
+        let guarded = self.guard.map_or(false, |guard| {
+            let guard_start = guard.start_address() as usize;
+            start < guard_start + guard.size() && guard_start < start + size
+        });
+        !guarded
+            && (self.app_memory.map_or(false, |r| r.contains(start, size))
+                || self.regions.iter().any(|r| r.contains(start, size)))
+    }
+
+    pub fn guard(&self) -> Option<Region> {
+        self.guard
+    }
+}
+
+pub struct MPU {
+    enabled: Cell<bool>,
+    /// The configuration most recently passed to `configure_mpu()`.
+    config: RefCell<MpuConfig>,
+}
+
+impl MPU {
+    pub fn new() -> MPU {
+        MPU {
+            enabled: Cell::new(false),
+            config: RefCell::new(MpuConfig::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// Returns the configuration of the process that ran most recently.
+    pub fn current_config(&self) -> MpuConfig {
+        self.config.borrow().clone()
+    }
+}
+
+impl mpu::MPU for MPU {
+    type MpuConfig = MpuConfig;
+
+    fn enable_mpu(&self) {
+        self.enabled.set(true);
+    }
+
+    fn disable_mpu(&self) {
+        self.enabled.set(false);
+    }
+
+    fn number_total_regions(&self) -> usize {
+        8
+    }
+
+    fn allocate_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_region_size: usize,
+        permissions: Permissions,
+        config: &mut MpuConfig,
+    ) -> Option<Region> {
+        if min_region_size > unallocated_memory_size {
+            return None;
+        }
+        let region = Region::new(unallocated_memory_start, min_region_size);
+        config.regions.push(SimRegion {
+            region: region,
+            permissions: permissions,
+        });
+        Some(region)
+    }
+
+    fn allocate_app_memory_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_memory_size: usize,
+        initial_app_memory_size: usize,
+        initial_kernel_memory_size: usize,
+        permissions: Permissions,
+        config: &mut MpuConfig,
+    ) -> Option<(*const u8, usize)> {
+        if config.app_memory.is_some() {
+            return None;
+        }
+        // Like the Cortex-M MPU, round process memory up to a power of two.
+        // This also leaves room for grants.
+        let memory_size = cmp::max(
+            min_memory_size,
+            initial_app_memory_size + initial_kernel_memory_size,
+        ).next_power_of_two();
+        if memory_size > unallocated_memory_size {
+            return None;
+        }
+        config.app_memory = Some(SimRegion {
+            region: Region::new(unallocated_memory_start, initial_app_memory_size),
+            permissions: permissions,
+        });
+        Some((unallocated_memory_start, memory_size))
+    }
+
+    fn update_app_memory_region(
+        &self,
+        app_memory_break: *const u8,
+        kernel_memory_break: *const u8,
+        permissions: Permissions,
+        config: &mut MpuConfig,
+    ) -> Result<(), ()> {
+        if (app_memory_break as usize) > (kernel_memory_break as usize) {
+            return Err(());
+        }
+        let start = match config.app_memory {
+            Some(app_memory) => app_memory.region.start_address(),
+            None => return Err(()),
+        };
+        config.app_memory = Some(SimRegion {
+            region: Region::new(start, app_memory_break as usize - start as usize),
+            permissions: permissions,
+        });
+        Ok(())
+    }
+
+    fn allocate_guard_region(
+        &self,
+        guard_memory_start: *const u8,
+        guard_memory_size: usize,
+        min_guard_size: usize,
+        config: &mut MpuConfig,
+    ) -> Option<Region> {
+        if min_guard_size > guard_memory_size {
+            return None;
+        }
+        let guard = Region::new(guard_memory_start, min_guard_size);
+        config.guard = Some(guard);
+        Some(guard)
+    }
+
+    fn remove_guard_region(&self, config: &mut MpuConfig) {
+        config.guard = None;
+    }
+
+    fn configure_mpu(&self, config: &MpuConfig) {
+        *self.config.borrow_mut() = config.clone();
+    }
+}
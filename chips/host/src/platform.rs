@@ -0,0 +1,35 @@
+//! Simulated platform.
+
+use kernel::{Driver, Platform};
+
+/// A platform that maps driver numbers to the drivers a test registered.
+pub struct SimPlatform<'a> {
+    drivers: Vec<(usize, &'a Driver)>,
+}
+
+impl<'a> SimPlatform<'a> {
+    pub fn new() -> SimPlatform<'a> {
+        SimPlatform {
+            drivers: Vec::new(),
+        }
+    }
+
+    /// Make `driver` available to processes as driver number `driver_num`.
+    pub fn add_driver(&mut self, driver_num: usize, driver: &'a Driver) {
+        self.drivers.push((driver_num, driver));
+    }
+}
+
+impl<'a> Platform for SimPlatform<'a> {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&Driver>) -> R,
+    {
+        let driver = self
+            .drivers
+            .iter()
+            .find(|&&(num, _)| num == driver_num)
+            .map(|&(_, driver)| driver);
+        f(driver)
+    }
+}
@@ -0,0 +1,365 @@
+//! Implementation of the `UserspaceKernelBoundary` for simulated processes.
+//!
+//! Like on real hardware, the state of a stopped process is kept in a frame on
+//! its stack. A frame records either a function the kernel wants the process
+//! to call, a system call the process made, or that the process was
+//! preempted. When the kernel switches to a process the frame is popped and
+//! turned into the `Event` the process closure is called with, and the
+//! `Action` the closure returns is pushed as the next frame.
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::mem;
+use std::ptr::{read_volatile, write_volatile};
+use std::slice;
+
+use app::{self, Action, Event, SimApp};
+use chip::SimChip;
+use kernel;
+use kernel::procs::{FunctionCall, FunctionCallSource};
+use kernel::syscall::{ContextSwitchReason, Syscall};
+use kernel::Chip;
+
+/// Size of a stack frame in words.
+const FRAME_WORDS: isize = 8;
+const FRAME_BYTES: usize = FRAME_WORDS as usize * mem::size_of::<usize>();
+
+/// Kinds of stack frames. The kind is the first word of a frame.
+const FRAME_FUNCTION_CALL: usize = 0x5ca1;
+const FRAME_SYSCALL: usize = 0x5ca2;
+const FRAME_PREEMPTED: usize = 0x5ca3;
+
+// Layout of a frame. For a function call frame, the arguments are stored in
+// the register words and the return value word is unused. For a preempted
+// frame, the first register word holds the time left of the `Action::Spin`
+// that was preempted.
+const FRAME_KIND: isize = 0;
+const FRAME_PC_OR_SVC: isize = 1;
+const FRAME_R0: isize = 2;
+const FRAME_RETURN_VALUE: isize = 6;
+
+/// This holds all of the state that the kernel must keep for a simulated
+/// process when it is not executing.
+#[derive(Copy, Clone, Default)]
+pub struct SimStoredState {
+    /// Index of the `SimApp` this process runs, once it is known.
+    app: Option<usize>,
+}
+
+struct LoadedApp {
+    app: SimApp,
+    entry_point: usize,
+}
+
+pub struct SysCall {
+    chip: &'static SimChip,
+    apps: RefCell<Vec<LoadedApp>>,
+    flash: &'static [u8],
+}
+
+impl SysCall {
+    /// Create the boundary for the given apps. Their TBF images are placed in
+    /// a simulated flash, which is what the board passes to
+    /// `load_processes()`.
+    pub fn new(chip: &'static SimChip, apps: Vec<SimApp>) -> SysCall {
+        let (flash, offsets) = app::build_flash(&apps);
+        let apps = apps
+            .into_iter()
+            .zip(offsets.into_iter())
+            .map(|(app, offset)| {
+                let flash_start = flash.as_ptr() as usize + offset;
+                // The entry point immediately follows the header, and has the
+                // Thumb bit set.
+                let entry_point = flash_start + app.header_size() + 1;
+                LoadedApp {
+                    app: app,
+                    entry_point: entry_point,
+                }
+            })
+            .collect();
+        SysCall {
+            chip: chip,
+            apps: RefCell::new(apps),
+            flash: flash,
+        }
+    }
+
+    /// Start of the simulated flash that holds the apps.
+    pub fn flash_start(&self) -> *const u8 {
+        self.flash.as_ptr()
+    }
+
+    /// Write the TBF image of `app` to new flash, as if it had been installed
+    /// while the kernel is running. A `DynamicProcessLoader` can then load it
+    /// from the returned flash.
+    pub fn add_app(&self, app: SimApp) -> &'static [u8] {
+        let (flash, _) = app::build_flash(slice::from_ref(&app));
+        let entry_point = flash.as_ptr() as usize + app.header_size() + 1;
+        self.apps.borrow_mut().push(LoadedApp {
+            app: app,
+            entry_point: entry_point,
+        });
+        flash
+    }
+
+    /// Find the app a process runs from the entry point the kernel started it
+    /// at.
+    fn find_app(&self, pc: usize) -> Option<usize> {
+        self.apps
+            .borrow()
+            .iter()
+            .position(|loaded| loaded.entry_point == pc)
+    }
+
+    unsafe fn push_frame(
+        &self,
+        stack_pointer: *const usize,
+        kind: usize,
+        words: [usize; 5],
+    ) -> *mut usize {
+        let frame = (stack_pointer as *mut usize).offset(-FRAME_WORDS);
+        write_volatile(frame.offset(FRAME_KIND), kind);
+        for (i, word) in words.iter().enumerate() {
+            write_volatile(frame.offset(FRAME_PC_OR_SVC + i as isize), *word);
+        }
+        write_volatile(frame.offset(FRAME_RETURN_VALUE), 0);
+        frame
+    }
+}
+
+impl kernel::syscall::UserspaceKernelBoundary for SysCall {
+    type StoredState = SimStoredState;
+
+    unsafe fn get_syscall(&self, stack_pointer: *const usize) -> Option<Syscall> {
+        if read_volatile(stack_pointer.offset(FRAME_KIND)) != FRAME_SYSCALL {
+            return None;
+        }
+        let r0 = read_volatile(stack_pointer.offset(FRAME_R0));
+        let r1 = read_volatile(stack_pointer.offset(FRAME_R0 + 1));
+        let r2 = read_volatile(stack_pointer.offset(FRAME_R0 + 2));
+        let r3 = read_volatile(stack_pointer.offset(FRAME_R0 + 3));
+        match read_volatile(stack_pointer.offset(FRAME_PC_OR_SVC)) {
+            0 => Some(Syscall::YIELD),
+            1 => Some(Syscall::SUBSCRIBE {
+                driver_number: r0,
+                subdriver_number: r1,
+                callback_ptr: r2 as *mut (),
+                appdata: r3,
+            }),
+            2 => Some(Syscall::COMMAND {
+                driver_number: r0,
+                subdriver_number: r1,
+                arg0: r2,
+                arg1: r3,
+            }),
+            3 => Some(Syscall::ALLOW {
+                driver_number: r0,
+                subdriver_number: r1,
+                allow_address: r2 as *mut u8,
+                allow_size: r3,
+            }),
+            4 => Some(Syscall::MEMOP {
+                operand: r0,
+                arg0: r1,
+            }),
+            5 => Some(Syscall::ALLOW_READONLY {
+                driver_number: r0,
+                subdriver_number: r1,
+                allow_address: r2 as *const u8,
+                allow_size: r3,
+            }),
+            _ => None,
+        }
+    }
+
+    unsafe fn set_syscall_return_value(&self, stack_pointer: *const usize, return_value: isize) {
+        let sp = stack_pointer as *mut usize;
+        write_volatile(sp.offset(FRAME_RETURN_VALUE), return_value as usize);
+    }
+
+    unsafe fn pop_syscall_stack_frame(
+        &self,
+        stack_pointer: *const usize,
+        _state: &mut SimStoredState,
+    ) -> *mut usize {
+        (stack_pointer as *mut usize).offset(FRAME_WORDS)
+    }
+
+    unsafe fn push_function_call(
+        &self,
+        stack_pointer: *const usize,
+        remaining_stack_memory: usize,
+        callback: FunctionCall,
+        _state: &SimStoredState,
+    ) -> Result<*mut usize, *mut usize> {
+        if remaining_stack_memory < FRAME_BYTES {
+            return Err((stack_pointer as *mut usize).offset(-FRAME_WORDS));
+        }
+        Ok(self.push_frame(
+            stack_pointer,
+            FRAME_FUNCTION_CALL,
+            [
+                callback.pc,
+                callback.argument0,
+                callback.argument1,
+                callback.argument2,
+                callback.argument3,
+            ],
+        ))
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        stack_pointer: *const usize,
+        state: &mut SimStoredState,
+    ) -> (*mut usize, ContextSwitchReason) {
+        // Pop the frame on top of the stack and turn it into an event.
+        let frame = stack_pointer;
+        let stack_pointer = (stack_pointer as *mut usize).offset(FRAME_WORDS);
+        let word = |i| read_volatile(frame.offset(i));
+        let mut pending_spin = 0;
+        let mut event = match word(FRAME_KIND) {
+            FRAME_FUNCTION_CALL => {
+                // Where the call came from is not kept on the stack.
+                let call = FunctionCall {
+                    source: FunctionCallSource::Kernel,
+                    pc: word(FRAME_PC_OR_SVC),
+                    argument0: word(FRAME_R0),
+                    argument1: word(FRAME_R0 + 1),
+                    argument2: word(FRAME_R0 + 2),
+                    argument3: word(FRAME_R0 + 3),
+                };
+                if state.app.is_none() {
+                    state.app = self.find_app(call.pc);
+                }
+                match state.app {
+                    Some(idx) => app::event_for_call(call, self.apps.borrow()[idx].entry_point),
+                    None => return (frame as *mut usize, ContextSwitchReason::Fault),
+                }
+            }
+            FRAME_SYSCALL => Event::Return(word(FRAME_RETURN_VALUE) as isize),
+            FRAME_PREEMPTED => {
+                pending_spin = word(FRAME_R0) as u32;
+                Event::Resume
+            }
+            _ => return (frame as *mut usize, ContextSwitchReason::Fault),
+        };
+        let idx = match state.app {
+            Some(idx) => idx,
+            None => return (frame as *mut usize, ContextSwitchReason::Fault),
+        };
+
+        // Run the process until it makes a system call or is stopped.
+        loop {
+            let action = {
+                let mut apps = self.apps.borrow_mut();
+                let process = &mut apps[idx].app.process;
+                if pending_spin > 0 {
+                    // Finish the spin before the process continues.
+                    Action::Spin(mem::replace(&mut pending_spin, 0))
+                } else {
+                    process(event)
+                }
+            };
+            let (svc, registers) = match action {
+                Action::Yield => (0, [0; 4]),
+                Action::Subscribe {
+                    driver_number,
+                    subdriver_number,
+                    callback_ptr,
+                    appdata,
+                } => (1, [driver_number, subdriver_number, callback_ptr, appdata]),
+                Action::Command {
+                    driver_number,
+                    subdriver_number,
+                    arg0,
+                    arg1,
+                } => (2, [driver_number, subdriver_number, arg0, arg1]),
+                Action::Allow {
+                    driver_number,
+                    subdriver_number,
+                    allow_address,
+                    allow_size,
+                } => (
+                    3,
+                    [
+                        driver_number,
+                        subdriver_number,
+                        allow_address as usize,
+                        allow_size,
+                    ],
+                ),
+                Action::Memop { operand, arg0 } => (4, [operand, arg0, 0, 0]),
+                Action::AllowReadOnly {
+                    driver_number,
+                    subdriver_number,
+                    allow_address,
+                    allow_size,
+                } => (
+                    5,
+                    [
+                        driver_number,
+                        subdriver_number,
+                        allow_address as usize,
+                        allow_size,
+                    ],
+                ),
+                Action::Spin(us) => {
+                    let left = self.chip.systick().advance(us);
+                    let reason = if self.chip.systick().take_interrupt() {
+                        ContextSwitchReason::TimesliceExpired
+                    } else if self.chip.has_pending_interrupts() {
+                        ContextSwitchReason::Interrupted
+                    } else {
+                        event = Event::Resume;
+                        continue;
+                    };
+                    let sp = self.push_frame(
+                        stack_pointer,
+                        FRAME_PREEMPTED,
+                        [0, left as usize, 0, 0, 0],
+                    );
+                    return (sp, reason);
+                }
+                Action::Fault => return (stack_pointer, ContextSwitchReason::Fault),
+            };
+            let sp = self.push_frame(
+                stack_pointer,
+                FRAME_SYSCALL,
+                [svc, registers[0], registers[1], registers[2], registers[3]],
+            );
+            return (sp, ContextSwitchReason::SyscallFired);
+        }
+    }
+
+    unsafe fn fault_fmt(&self, writer: &mut Write) {
+        let _ = writer.write_fmt(format_args!("\r\n---| Simulated process fault |---\r\n"));
+    }
+
+    unsafe fn fault_registers(
+        &self,
+        _stack_pointer: *const usize,
+        _state: &SimStoredState,
+    ) -> kernel::debug::FaultRegisters {
+        // Simulated processes have no registers to report.
+        kernel::debug::FaultRegisters::default()
+    }
+
+    unsafe fn process_detail_fmt(
+        &self,
+        stack_pointer: *const usize,
+        state: &SimStoredState,
+        writer: &mut Write,
+    ) {
+        let kind = match read_volatile(stack_pointer.offset(FRAME_KIND)) {
+            FRAME_FUNCTION_CALL => "function call",
+            FRAME_SYSCALL => "system call",
+            FRAME_PREEMPTED => "preempted",
+            _ => "invalid",
+        };
+        let _ = writer.write_fmt(format_args!(
+            "\r\n Simulated app: {:?}   Top frame: {}\r\n",
+            state.app, kind
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+//! Simulated system tick timer.
+//!
+//! Time only passes in the simulation when a simulated process spins (see
+//! `Action::Spin`), so the timer expires exactly when a process has spun for
+//! longer than its timeslice.
+
+use std::cell::Cell;
+
+use kernel;
+
+pub struct SysTick {
+    /// Microseconds left until the timer expires.
+    value_us: Cell<u32>,
+    /// Value the timer reloads with when it expires.
+    reload_us: Cell<u32>,
+    enabled: Cell<bool>,
+    interrupt_enabled: Cell<bool>,
+    overflowed: Cell<bool>,
+    /// Set when the timer expired with its interrupt enabled. Cleared by
+    /// `take_interrupt()`.
+    interrupt_pending: Cell<bool>,
+    /// Total simulated time that has passed, in microseconds.
+    elapsed_us: Cell<u64>,
+}
+
+impl SysTick {
+    pub fn new() -> SysTick {
+        SysTick {
+            value_us: Cell::new(0),
+            reload_us: Cell::new(0),
+            enabled: Cell::new(false),
+            interrupt_enabled: Cell::new(false),
+            overflowed: Cell::new(false),
+            interrupt_pending: Cell::new(false),
+            elapsed_us: Cell::new(0),
+        }
+    }
+
+    /// Let up to `us` microseconds of simulated time pass. If the timer
+    /// expires with its interrupt enabled, time stops there, as the interrupt
+    /// preempts the process. Returns how much of `us` is left.
+    pub fn advance(&self, us: u32) -> u32 {
+        let value = self.value_us.get();
+        if !self.enabled.get() || us < value {
+            if self.enabled.get() {
+                self.value_us.set(value - us);
+            }
+            self.elapsed_us.set(self.elapsed_us.get() + us as u64);
+            return 0;
+        }
+
+        // Like the hardware timer, reload and keep counting.
+        let reload = self.reload_us.get();
+        self.overflowed.set(true);
+        if self.interrupt_enabled.get() {
+            self.value_us.set(reload);
+            self.interrupt_pending.set(true);
+            self.elapsed_us.set(self.elapsed_us.get() + value as u64);
+            us - value
+        } else {
+            let past = us - value;
+            self.value_us
+                .set(if reload == 0 { 0 } else { reload - past % reload });
+            self.elapsed_us.set(self.elapsed_us.get() + us as u64);
+            0
+        }
+    }
+
+    /// Returns whether the timer interrupt fired since the last call.
+    pub fn take_interrupt(&self) -> bool {
+        self.interrupt_pending.replace(false)
+    }
+
+    /// Total simulated time that has passed, in microseconds.
+    pub fn elapsed_us(&self) -> u64 {
+        self.elapsed_us.get()
+    }
+}
+
+impl kernel::SysTick for SysTick {
+    fn set_timer(&self, us: u32) {
+        self.reload_us.set(us);
+        self.value_us.set(us);
+        self.overflowed.set(false);
+    }
+
+    fn greater_than(&self, us: u32) -> bool {
+        self.value_us.get() > us
+    }
+
+    fn overflowed(&self) -> bool {
+        self.overflowed.get()
+    }
+
+    fn get_value(&self) -> u32 {
+        self.value_us.get()
+    }
+
+    fn reset(&self) {
+        self.value_us.set(0);
+        self.enabled.set(false);
+        self.interrupt_enabled.set(false);
+        self.overflowed.set(false);
+        self.interrupt_pending.set(false);
+    }
+
+    fn enable(&self, with_interrupt: bool) {
+        self.enabled.set(true);
+        self.interrupt_enabled.set(with_interrupt);
+    }
+}
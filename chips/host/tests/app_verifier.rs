@@ -0,0 +1,120 @@
+//! Tests that a board with the SHA-256 app verifier only loads apps whose
+//! credentials match their image.
+
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+use capsules::sha256_verifier::{Sha256, Sha256Verifier, HASH_LEN};
+use host::{Action, SimApp, SimChip};
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::procs::FaultResponse;
+
+struct Cap;
+unsafe impl ProcessManagementCapability for Cap {}
+
+/// TBF header element type of the credentials.
+const CREDENTIALS_TLV: u16 = 7;
+/// Credentials format of a SHA-256 hash.
+const FORMAT_SHA256: u32 = 1;
+
+fn read_u16(buf: &[u8], offset: usize) -> usize {
+    buf[offset] as usize | (buf[offset + 1] as usize) << 8
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> usize {
+    read_u16(buf, offset) | read_u16(buf, offset + 2) << 16
+}
+
+fn app(name: &str, hash: &[u8; HASH_LEN]) -> SimApp {
+    let mut credentials = vec![FORMAT_SHA256 as u8, 0, 0, 0];
+    credentials.extend_from_slice(hash);
+    SimApp::new(name, |_| Action::Yield).tlv(CREDENTIALS_TLV, &credentials)
+}
+
+/// Hash the signed regions of a TBF image: everything but the header checksum
+/// and the credential data.
+fn hash_image(flash: &[u8]) -> [u8; HASH_LEN] {
+    let header_size = read_u16(flash, 2);
+    let mut offset = 16;
+    while offset < header_size {
+        let tipe = read_u16(flash, offset) as u16;
+        let len = read_u16(flash, offset + 2);
+        if tipe == CREDENTIALS_TLV {
+            // The data follows the type, length and format fields.
+            let data_start = offset + 8;
+            let data_end = offset + 4 + len;
+            let mut sha = Sha256::new();
+            sha.update(&flash[..12]);
+            sha.update(&flash[16..data_start]);
+            sha.update(&flash[data_end..]);
+            return sha.finish();
+        }
+        offset += 4 + (len + 3) / 4 * 4;
+    }
+    panic!("image has no credentials");
+}
+
+/// Load `apps` on a board with the SHA-256 verifier, and return which process
+/// slots are filled.
+fn load_verified(apps: Vec<SimApp>) -> Vec<bool> {
+    let (_, _, procs, ()) = host::boot_with(apps, FaultResponse::Panic, |kernel| {
+        kernel.set_app_verifier(host::leak(Sha256Verifier::new()), &Cap)
+    });
+    procs.iter().map(|p| p.is_some()).collect()
+}
+
+/// Returns the hash of the image of an app named `name`. The credential data
+/// is not signed, so the hash does not depend on the hash in the image.
+fn hash_of(name: &str) -> [u8; HASH_LEN] {
+    let chip: &'static SimChip = host::leak(SimChip::new());
+    let syscall = host::SysCall::new(chip, vec![app(name, &[0; HASH_LEN])]);
+    let image = unsafe {
+        let total_size = read_u32(std::slice::from_raw_parts(syscall.flash_start(), 8), 4);
+        std::slice::from_raw_parts(syscall.flash_start(), total_size)
+    };
+    hash_image(image)
+}
+
+#[test]
+fn sha256_test_vectors() {
+    let mut sha = Sha256::new();
+    sha.update(b"abc");
+    assert_eq!(
+        sha.finish()[..],
+        [
+            0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+            0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61,
+            0xf2, 0x00, 0x15, 0xad,
+        ][..]
+    );
+
+    // Two blocks, fed in pieces that do not line up with the blocks.
+    let mut sha = Sha256::new();
+    sha.update(b"abcdbcdecdefdefgefghfghighijhi");
+    sha.update(b"jkijkljklmklmnlmnomnopnopq");
+    assert_eq!(
+        sha.finish()[..],
+        [
+            0x24, 0x8d, 0x6a, 0x61, 0xd2, 0x06, 0x38, 0xb8, 0xe5, 0xc0, 0x26, 0x93, 0x0c, 0x3e,
+            0x60, 0x39, 0xa3, 0x3c, 0xe4, 0x59, 0x64, 0xff, 0x21, 0x67, 0xf6, 0xec, 0xed, 0xd4,
+            0x19, 0xdb, 0x06, 0xc1,
+        ][..]
+    );
+}
+
+#[test]
+fn loads_app_with_matching_hash() {
+    let hash = hash_of("good");
+    assert_eq!(load_verified(vec![app("good", &hash)]), [true]);
+}
+
+#[test]
+fn rejects_tampered_app() {
+    // Same length of name, so only the contents of the image differ.
+    let hash = hash_of("good");
+    assert_eq!(
+        load_verified(vec![app("evil", &hash), app("good", &hash)]),
+        [false, true]
+    );
+}
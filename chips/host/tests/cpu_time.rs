@@ -0,0 +1,58 @@
+//! Tests that the kernel accounts for the time processes run, whether or not
+//! they are preempted, and only counts timeslice expirations that preempted
+//! the process.
+
+extern crate host;
+extern crate kernel;
+
+use host::{Action, Event, SimApp, SimPlatform};
+use kernel::capabilities::MainLoopCapability;
+use kernel::procs::ProcessType;
+use kernel::scheduler::{CooperativeScheduler, RoundRobinScheduler, Scheduler};
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+
+/// Run an app that spins for `spin_us` and then yields, and return its
+/// process.
+fn spin<SC: Scheduler>(spin_us: u32, scheduler: &SC) -> &'static ProcessType {
+    let mut spun = false;
+    let app = SimApp::new("spin", move |event| match event {
+        Event::Start { .. } if !spun => {
+            spun = true;
+            Action::Spin(spin_us)
+        }
+        _ => Action::Yield,
+    });
+
+    let (kernel, chip, procs) = host::boot(vec![app]);
+    let platform = SimPlatform::new();
+    assert!(host::run_until_idle(
+        kernel,
+        &platform,
+        chip,
+        None,
+        scheduler,
+        10,
+        &Cap
+    ));
+
+    procs[0].unwrap()
+}
+
+#[test]
+fn cooperative_process_running_longer_than_timer_period() {
+    let scheduler = CooperativeScheduler::new();
+    let process = spin(250000, &scheduler);
+    assert_eq!(process.debug_cpu_time_us(), 250000);
+    // The accounting timer expired twice, but never preempted the process.
+    assert_eq!(process.debug_timeslice_expiration_count(), 0);
+}
+
+#[test]
+fn preempted_process() {
+    let scheduler = RoundRobinScheduler::new(10000);
+    let process = spin(25000, &scheduler);
+    assert_eq!(process.debug_cpu_time_us(), 25000);
+    assert_eq!(process.debug_timeslice_expiration_count(), 2);
+}
@@ -0,0 +1,182 @@
+//! Tests that callbacks of the same subscription are coalesced when the
+//! callback queue of a process is full, that the process is told how many
+//! other callbacks were dropped, and that the function it is told with has to
+//! be in its flash.
+
+extern crate host;
+extern crate kernel;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use host::{Action, Event, SimApp, SimPlatform};
+use kernel::capabilities::{MainLoopCapability, MemoryAllocationCapability};
+use kernel::procs::FaultResponse;
+use kernel::scheduler::RoundRobinScheduler;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+unsafe impl MemoryAllocationCapability for Cap {}
+
+const BURST_DRIVER: usize = 0x90000;
+const SUBSCRIPTIONS: usize = 4;
+
+/// TBF header element that sets the length of the callback queue.
+const TBF_CALLBACK_QUEUE: u16 = 10;
+
+#[derive(Default)]
+struct Subscriptions {
+    callbacks: [Option<Callback>; SUBSCRIPTIONS],
+}
+
+/// Schedules bursts of callbacks.
+struct Burst {
+    apps: Grant<Subscriptions>,
+}
+
+impl Driver for Burst {
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| match app.callbacks.get_mut(subscribe_num) {
+                Some(slot) => {
+                    *slot = callback;
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::ENOSUPPORT,
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    /// Command 0 schedules `arg1` callbacks, the i-th one on subscription
+    /// `i % SUBSCRIPTIONS` with argument `i`.
+    fn command(&self, minor_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match minor_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    for i in 0..arg1 {
+                        app.callbacks[i % SUBSCRIPTIONS].map(|mut cb| cb.schedule(i, 0, 0));
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Seen {
+    Return(isize),
+    Callback { pc: usize, argument0: usize },
+}
+
+fn memop(operand: usize, arg0: usize) -> Action {
+    Action::Memop {
+        operand: operand,
+        arg0: arg0,
+    }
+}
+
+fn callback_pc(subscribe_num: usize) -> usize {
+    0x1000 + 0x100 * subscribe_num
+}
+
+#[test]
+fn full_queue_coalesces_and_reports_drops() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let handler = Rc::new(RefCell::new(0));
+    let app = {
+        let log = log.clone();
+        let handler = handler.clone();
+        let mut actions = VecDeque::new();
+        let mut callbacks = 0;
+        SimApp::new("burst", move |event| {
+            match event {
+                Event::Start {
+                    text_start,
+                    mem_start,
+                    ..
+                } => {
+                    *handler.borrow_mut() = text_start + 3;
+                    // A handler in RAM is rejected, one in flash is taken.
+                    actions.push_back(memop(14, mem_start + 1));
+                    actions.push_back(memop(14, text_start + 3));
+                    for i in 0..SUBSCRIPTIONS {
+                        actions.push_back(Action::Subscribe {
+                            driver_number: BURST_DRIVER,
+                            subdriver_number: i,
+                            callback_ptr: callback_pc(i),
+                            appdata: 0,
+                        });
+                    }
+                    actions.push_back(Action::Command {
+                        driver_number: BURST_DRIVER,
+                        subdriver_number: 0,
+                        arg0: 6,
+                        arg1: 0,
+                    });
+                }
+                Event::Return(value) => log.borrow_mut().push(Seen::Return(value)),
+                Event::Callback { pc, arguments } => {
+                    log.borrow_mut().push(Seen::Callback {
+                        pc: pc,
+                        argument0: arguments[0],
+                    });
+                    callbacks += 1;
+                    if callbacks == 3 {
+                        actions.push_back(memop(15, 0));
+                    }
+                }
+                Event::Resume => {}
+            }
+            actions.pop_front().unwrap_or(Action::Yield)
+        }).tlv(TBF_CALLBACK_QUEUE, &[2, 0, 0, 0])
+    };
+
+    let (kernel, chip, procs, burst) = host::boot_with(vec![app], FaultResponse::Panic, |kernel| {
+        &*host::leak(Burst {
+            apps: kernel.create_grant(&Cap),
+        })
+    });
+
+    let mut platform = SimPlatform::new();
+    platform.add_driver(BURST_DRIVER, burst);
+    let scheduler = RoundRobinScheduler::new(10000);
+    assert!(host::run_until_idle(
+        kernel,
+        &platform,
+        chip,
+        None,
+        &scheduler,
+        10,
+        &Cap
+    ));
+
+    // The queue holds two callbacks. The callbacks for subscriptions 2 and 3
+    // are dropped, and the later ones for subscriptions 0 and 1 replace the
+    // queued ones. The handler runs once there is room again.
+    let einval = isize::from(ReturnCode::EINVAL);
+    let mut expected = vec![Seen::Return(einval)];
+    expected.extend((0..SUBSCRIPTIONS + 2).map(|_| Seen::Return(0)));
+    expected.push(Seen::Callback {
+        pc: callback_pc(0),
+        argument0: 4,
+    });
+    expected.push(Seen::Callback {
+        pc: callback_pc(1),
+        argument0: 5,
+    });
+    expected.push(Seen::Callback {
+        pc: *handler.borrow(),
+        argument0: 2,
+    });
+    expected.push(Seen::Return(2));
+    assert_eq!(*log.borrow(), expected);
+    assert_eq!(procs[0].unwrap().debug_dropped_callback_count(), 2);
+}
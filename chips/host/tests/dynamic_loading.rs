@@ -0,0 +1,128 @@
+//! Tests loading an app with a `DynamicProcessLoader` after the kernel has
+//! started, and that apps are rejected when there is no free process slot,
+//! not enough memory, or the TBF header is cut off.
+
+extern crate host;
+extern crate kernel;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use host::{Action, Event, SimApp, SimChip, SimPlatform};
+use kernel::capabilities::{MainLoopCapability, ProcessManagementCapability};
+use kernel::scheduler::RoundRobinScheduler;
+use kernel::{Kernel, ReturnCode};
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+unsafe impl ProcessManagementCapability for Cap {}
+
+/// An app that writes its name to `started` when it starts, and then yields.
+fn app(name: &'static str, started: &Rc<RefCell<Vec<&'static str>>>) -> SimApp {
+    let started = started.clone();
+    SimApp::new(name, move |event| {
+        if let Event::Start { .. } = event {
+            started.borrow_mut().push(name);
+        }
+        Action::Yield
+    })
+}
+
+fn run(kernel: &'static Kernel, chip: &SimChip) {
+    let platform = SimPlatform::new();
+    let scheduler = RoundRobinScheduler::new(10000);
+    assert!(host::run_until_idle(
+        kernel,
+        &platform,
+        chip,
+        None,
+        &scheduler,
+        10,
+        &Cap
+    ));
+}
+
+#[test]
+fn loads_app_into_free_slot() {
+    let started = Rc::new(RefCell::new(Vec::new()));
+    let (kernel, chip, procs, syscall, loader) =
+        host::boot_with_loader(vec![app("boot", &started)], 1, 32 * 1024);
+    run(kernel, chip);
+    assert!(procs[1].is_none());
+
+    let flash = syscall.add_app(app("late", &started));
+    let appid = loader.load_process(flash, &Cap).ok().unwrap();
+    assert_eq!(appid.idx(), 1);
+    assert_eq!(procs[1].unwrap().get_process_name(), "late");
+    run(kernel, chip);
+    assert_eq!(*started.borrow(), vec!["boot", "late"]);
+
+    // The app cannot be loaded twice.
+    assert_eq!(
+        loader.load_process(flash, &Cap).err(),
+        Some(ReturnCode::EINVAL)
+    );
+}
+
+#[test]
+fn rejects_app_without_free_slot() {
+    let started = Rc::new(RefCell::new(Vec::new()));
+    let (kernel, chip, procs, syscall, loader) =
+        host::boot_with_loader(vec![app("boot", &started)], 0, 32 * 1024);
+
+    let flash = syscall.add_app(app("late", &started));
+    assert_eq!(
+        loader.load_process(flash, &Cap).err(),
+        Some(ReturnCode::ENOMEM)
+    );
+    assert_eq!(procs.len(), 1);
+    run(kernel, chip);
+    assert_eq!(*started.borrow(), vec!["boot"]);
+}
+
+#[test]
+fn rejects_app_without_enough_memory() {
+    let started = Rc::new(RefCell::new(Vec::new()));
+    let (kernel, chip, procs, syscall, loader) =
+        host::boot_with_loader(vec![app("boot", &started)], 1, 32 * 1024);
+
+    let flash = syscall.add_app(app("large", &started).minimum_ram_size(64 * 1024));
+    assert_eq!(
+        loader.load_process(flash, &Cap).err(),
+        Some(ReturnCode::ENOMEM)
+    );
+    assert!(procs[1].is_none());
+
+    // The rejected app did not use up the slot or the memory.
+    let flash = syscall.add_app(app("small", &started));
+    assert!(loader.load_process(flash, &Cap).is_ok());
+    run(kernel, chip);
+    assert_eq!(*started.borrow(), vec!["boot", "small"]);
+}
+
+#[test]
+fn rejects_truncated_tbf() {
+    let started = Rc::new(RefCell::new(Vec::new()));
+    let (_, _, procs, syscall, loader) = host::boot_with_loader(Vec::new(), 1, 16 * 1024);
+
+    let flash = syscall.add_app(app("cut", &started));
+    let header_size = flash[2] as usize | (flash[3] as usize) << 8;
+    let total_size = flash[4] as usize | (flash[5] as usize) << 8;
+    // Shorter than the base header, shorter than the header, and shorter than
+    // the app.
+    assert_eq!(
+        loader.load_process(&flash[..8], &Cap).err(),
+        Some(ReturnCode::EINVAL)
+    );
+    assert_eq!(
+        loader.load_process(&flash[..header_size - 4], &Cap).err(),
+        Some(ReturnCode::EINVAL)
+    );
+    assert_eq!(
+        loader.load_process(&flash[..total_size - 4], &Cap).err(),
+        Some(ReturnCode::ESIZE)
+    );
+    assert!(procs[0].is_none());
+
+    assert!(loader.load_process(&flash[..total_size], &Cap).is_ok());
+}
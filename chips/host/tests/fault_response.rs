@@ -0,0 +1,197 @@
+//! Tests what the kernel does with a process that faults, depending on the
+//! fault response: restarting it up to a limit, restarting it after a delay
+//! that doubles every time, or leaving it in the fault state.
+
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+extern crate mock;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use capsules::process_restart_timer::{PendingRestart, ProcessRestartTimer};
+use host::{Action, Event, SimApp, SimChip, SimPlatform};
+use kernel::capabilities::{MainLoopCapability, ProcessManagementCapability};
+use kernel::hil::time::{Alarm, Freq1KHz};
+use kernel::procs::{FaultResponse, ProcessType, State};
+use kernel::scheduler::RoundRobinScheduler;
+use kernel::Kernel;
+use mock::MockAlarm;
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+unsafe impl ProcessManagementCapability for Cap {}
+
+type RestartTimer = ProcessRestartTimer<'static, MockAlarm<Freq1KHz>, Cap>;
+
+/// An app that faults the first `faults` times it starts, and yields after
+/// that. It writes the time to `starts` whenever it starts.
+fn faulting_app(
+    faults: usize,
+    alarm: &'static MockAlarm<Freq1KHz>,
+    starts: &Rc<RefCell<Vec<u32>>>,
+) -> SimApp {
+    let starts = starts.clone();
+    SimApp::new("faulty", move |event| match event {
+        Event::Start { .. } => {
+            starts.borrow_mut().push(alarm.now());
+            if starts.borrow().len() <= faults {
+                Action::Fault
+            } else {
+                Action::Yield
+            }
+        }
+        _ => Action::Yield,
+    })
+}
+
+fn run(kernel: &'static Kernel, chip: &SimChip) {
+    let platform = SimPlatform::new();
+    let scheduler = RoundRobinScheduler::new(10000);
+    assert!(host::run_until_idle(
+        kernel,
+        &platform,
+        chip,
+        None,
+        &scheduler,
+        10,
+        &Cap
+    ));
+}
+
+/// Boot a kernel that restarts faulted processes with a restart timer on
+/// `alarm`, and load an app that faults `faults` times.
+fn boot_with_restart_timer(
+    fault_response: FaultResponse,
+    faults: usize,
+) -> (
+    &'static Kernel,
+    &'static SimChip,
+    &'static ProcessType,
+    &'static MockAlarm<Freq1KHz>,
+    Rc<RefCell<Vec<u32>>>,
+) {
+    let alarm: &'static MockAlarm<Freq1KHz> = mock::leak(MockAlarm::new());
+    let starts = Rc::new(RefCell::new(Vec::new()));
+    let app = faulting_app(faults, alarm, &starts);
+    let (kernel, chip, procs, ()) = host::boot_with(vec![app], fault_response, |kernel| {
+        let restarts: &'static [Cell<Option<PendingRestart>>] = mock::leak([Cell::new(None)]);
+        let restart_timer: &'static RestartTimer =
+            mock::leak(ProcessRestartTimer::new(kernel, alarm, restarts, Cap));
+        alarm.set_client(restart_timer);
+        kernel.set_restart_timer(restart_timer, &Cap);
+    });
+    (kernel, chip, procs[0].unwrap(), alarm, starts)
+}
+
+#[test]
+fn restart_with_limit_gives_up_after_max_restarts() {
+    let starts = Rc::new(RefCell::new(Vec::new()));
+    let alarm: &'static MockAlarm<Freq1KHz> = mock::leak(MockAlarm::new());
+    let app = faulting_app(usize::max_value(), alarm, &starts);
+    let fault_response = FaultResponse::RestartWithLimit { max_restarts: 2 };
+    let (kernel, chip, procs, ()) = host::boot_with(vec![app], fault_response, |_| ());
+    run(kernel, chip);
+
+    // The first start and two restarts.
+    assert_eq!(starts.borrow().len(), 3);
+    let process = procs[0].unwrap();
+    assert_eq!(process.debug_restart_count(), 2);
+    assert_eq!(process.get_state(), State::Fault);
+}
+
+#[test]
+fn stop_leaves_process_in_fault_state() {
+    let starts = Rc::new(RefCell::new(Vec::new()));
+    let alarm: &'static MockAlarm<Freq1KHz> = mock::leak(MockAlarm::new());
+    let app = faulting_app(usize::max_value(), alarm, &starts);
+    let (kernel, chip, procs, ()) = host::boot_with(vec![app], FaultResponse::Stop, |_| ());
+    run(kernel, chip);
+
+    assert_eq!(starts.borrow().len(), 1);
+    let process = procs[0].unwrap();
+    assert_eq!(process.debug_restart_count(), 0);
+    assert_eq!(process.get_state(), State::Fault);
+}
+
+#[test]
+fn restart_with_backoff_doubles_the_delay() {
+    let fault_response = FaultResponse::RestartWithBackoff {
+        max_restarts: 3,
+        initial_delay_ms: 10,
+    };
+    let (kernel, chip, process, alarm, starts) =
+        boot_with_restart_timer(fault_response, usize::max_value());
+
+    run(kernel, chip);
+    while alarm.advance_to_alarm() {
+        run(kernel, chip);
+    }
+
+    // The alarm ticks once a millisecond.
+    assert_eq!(*starts.borrow(), vec![0, 10, 30, 70]);
+    assert_eq!(process.debug_restart_count(), 3);
+    assert_eq!(process.get_state(), State::Fault);
+}
+
+#[test]
+fn restart_with_backoff_waits_in_fault_state() {
+    let fault_response = FaultResponse::RestartWithBackoff {
+        max_restarts: 3,
+        initial_delay_ms: 10,
+    };
+    let (kernel, chip, process, alarm, starts) = boot_with_restart_timer(fault_response, 1);
+
+    run(kernel, chip);
+    assert_eq!(process.get_state(), State::Fault);
+    alarm.advance(9);
+    run(kernel, chip);
+    assert_eq!(process.get_state(), State::Fault);
+    alarm.advance(1);
+    run(kernel, chip);
+    assert_eq!(*starts.borrow(), vec![0, 10]);
+    assert_eq!(process.get_state(), State::Yielded);
+}
+
+#[test]
+fn terminating_cancels_pending_restart() {
+    let fault_response = FaultResponse::RestartWithBackoff {
+        max_restarts: 3,
+        initial_delay_ms: 10,
+    };
+    let (kernel, chip, process, alarm, starts) = boot_with_restart_timer(fault_response, 1);
+
+    run(kernel, chip);
+    assert_eq!(
+        kernel.terminate_process(process.appid(), &Cap),
+        kernel::ReturnCode::SUCCESS
+    );
+    assert!(!alarm.advance_to_alarm());
+    alarm.advance(100);
+    run(kernel, chip);
+    assert_eq!(*starts.borrow(), vec![0]);
+    assert_eq!(process.get_state(), State::Terminated);
+}
+
+#[test]
+fn restarting_by_hand_cancels_pending_restart() {
+    let fault_response = FaultResponse::RestartWithBackoff {
+        max_restarts: 3,
+        initial_delay_ms: 10,
+    };
+    let (kernel, chip, process, alarm, starts) = boot_with_restart_timer(fault_response, 1);
+
+    run(kernel, chip);
+    alarm.advance(5);
+    kernel.restart_process(process.appid(), &Cap);
+    run(kernel, chip);
+    assert!(!alarm.advance_to_alarm());
+    alarm.advance(100);
+    run(kernel, chip);
+
+    // The process is only restarted once, and keeps running.
+    assert_eq!(*starts.borrow(), vec![0, 5]);
+    assert_eq!(process.debug_restart_count(), 1);
+    assert_eq!(process.get_state(), State::Yielded);
+}
@@ -0,0 +1,147 @@
+//! Tests that drivers get separate grant memory for each process, allocated
+//! from the memory of that process.
+
+extern crate host;
+extern crate kernel;
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use host::{Action, Event, SimApp, SimPlatform};
+use kernel::capabilities::{MainLoopCapability, MemoryAllocationCapability};
+use kernel::procs::FaultResponse;
+use kernel::scheduler::RoundRobinScheduler;
+use kernel::{AppId, Driver, Grant, ReturnCode};
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+unsafe impl MemoryAllocationCapability for Cap {}
+
+const COUNTER_DRIVER: usize = 0x90000;
+const LARGE_DRIVER: usize = 0x90001;
+
+#[derive(Default)]
+struct Count {
+    count: usize,
+}
+
+/// Counts how often each process called command 0.
+struct Counter {
+    apps: Grant<Count>,
+}
+
+impl Driver for Counter {
+    fn command(&self, minor_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match minor_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.count += 1;
+                    ReturnCode::SuccessWithValue { value: app.count }
+                }).unwrap_or(ReturnCode::ENOMEM),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+/// A grant that is larger than the memory of any process.
+#[derive(Default)]
+struct LargeState([[[u8; 32]; 32]; 32]);
+
+struct Large {
+    apps: Grant<LargeState>,
+}
+
+impl Driver for Large {
+    fn command(&self, _: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |_, _| ReturnCode::SUCCESS)
+            .unwrap_or(ReturnCode::ENOMEM)
+    }
+}
+
+fn command(driver_number: usize) -> Action {
+    Action::Command {
+        driver_number: driver_number,
+        subdriver_number: 0,
+        arg0: 0,
+        arg1: 0,
+    }
+}
+
+/// An app that calls the counter `times` times and then the large driver, and
+/// writes the return values to `log`.
+fn counting_app(name: &str, times: usize, log: &Rc<RefCell<Vec<isize>>>) -> SimApp {
+    let log = log.clone();
+    let mut calls = 0;
+    SimApp::new(name, move |event| {
+        if let Event::Return(value) = event {
+            log.borrow_mut().push(value);
+        }
+        match event {
+            Event::Start { .. } | Event::Return(_) if calls < times => {
+                calls += 1;
+                command(COUNTER_DRIVER)
+            }
+            Event::Return(_) if calls == times => {
+                calls += 1;
+                command(LARGE_DRIVER)
+            }
+            _ => Action::Yield,
+        }
+    })
+}
+
+#[test]
+fn each_process_has_its_own_grant() {
+    let first = Rc::new(RefCell::new(Vec::new()));
+    let second = Rc::new(RefCell::new(Vec::new()));
+    let apps = vec![
+        counting_app("first", 3, &first),
+        counting_app("second", 2, &second),
+    ];
+
+    let (kernel, chip, procs, (counter, large)) =
+        host::boot_with(apps, FaultResponse::Panic, |kernel| {
+            let counter: &'static Counter = host::leak(Counter {
+                apps: kernel.create_grant(&Cap),
+            });
+            let large: &'static Large = host::leak(Large {
+                apps: kernel.create_grant(&Cap),
+            });
+            (counter, large)
+        });
+    let breaks: Vec<usize> = procs
+        .iter()
+        .map(|p| p.unwrap().kernel_memory_break() as usize)
+        .collect();
+
+    let mut platform = SimPlatform::new();
+    platform.add_driver(COUNTER_DRIVER, counter);
+    platform.add_driver(LARGE_DRIVER, large);
+    let scheduler = RoundRobinScheduler::new(10000);
+    assert!(host::run_until_idle(
+        kernel,
+        &platform,
+        chip,
+        None,
+        &scheduler,
+        10,
+        &Cap
+    ));
+
+    let enomem = isize::from(ReturnCode::ENOMEM);
+    assert_eq!(*first.borrow(), vec![1, 2, 3, enomem]);
+    assert_eq!(*second.borrow(), vec![1, 2, enomem]);
+    for (p, &old_break) in procs.iter().zip(breaks.iter()) {
+        let p = p.unwrap();
+        // Only the counter grant fit, and it came out of process memory.
+        assert_eq!(p.debug_grant_bytes(0), mem::size_of::<Count>());
+        assert_eq!(p.debug_grant_bytes(1), 0);
+        assert!((p.kernel_memory_break() as usize) <= old_break - mem::size_of::<Count>());
+        let failure = p.grant_allocation_failure().unwrap();
+        assert_eq!(failure.driver_number, Some(LARGE_DRIVER));
+        assert_eq!(failure.size, mem::size_of::<LargeState>());
+    }
+}
@@ -0,0 +1,343 @@
+//! Tests a round trip through IPC, where a client looks up a service by name,
+//! sends it a message, gets a reply, and notifies the service, and that ids of
+//! services become stale when the service restarts.
+
+extern crate host;
+extern crate kernel;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::slice;
+
+use host::{Action, Event, SimApp, SimPlatform};
+use kernel::capabilities::{MainLoopCapability, MemoryAllocationCapability};
+use kernel::ipc::{self, IPC};
+use kernel::procs::FaultResponse;
+use kernel::scheduler::RoundRobinScheduler;
+use kernel::ReturnCode;
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+unsafe impl MemoryAllocationCapability for Cap {}
+
+// Subscribe and allow numbers of the IPC driver.
+const SERVICE_NOTIFY: usize = 0;
+const MESSAGE_RECEIVE: usize = 256;
+const MESSAGE_SEND: usize = 257;
+const SERVICE_REGISTER: usize = 258;
+
+// `client_or_svc` values of the IPC command.
+const NOTIFY_SERVICE: usize = 0;
+const SEND_MESSAGE: usize = 2;
+const SEND_REPLY: usize = 3;
+const MESSAGE_DONE: usize = 4;
+
+// Callback pointers the apps subscribe, so they can tell callbacks apart.
+const MESSAGE_CALLBACK: usize = 0x1000;
+const NOTIFY_CALLBACK: usize = 0x2000;
+
+// Where the apps keep their buffers, as offsets into their memory.
+const NAME: usize = 0;
+const RECEIVE: usize = 32;
+const SEND: usize = 64;
+const BUFFER_LEN: usize = 32;
+
+const TIMESLICE_US: u32 = 10000;
+
+fn allow(subdriver_number: usize, mem_start: usize, offset: usize, size: usize) -> Action {
+    Action::Allow {
+        driver_number: ipc::DRIVER_NUM,
+        subdriver_number: subdriver_number,
+        allow_address: (mem_start + offset) as *mut u8,
+        allow_size: size,
+    }
+}
+
+fn subscribe(subdriver_number: usize, callback_ptr: usize) -> Action {
+    Action::Subscribe {
+        driver_number: ipc::DRIVER_NUM,
+        subdriver_number: subdriver_number,
+        callback_ptr: callback_ptr,
+        appdata: 0,
+    }
+}
+
+fn command(target_id: usize, client_or_svc: usize, len: usize) -> Action {
+    Action::Command {
+        driver_number: ipc::DRIVER_NUM,
+        subdriver_number: target_id,
+        arg0: client_or_svc,
+        arg1: len,
+    }
+}
+
+fn buffer(mem_start: usize, offset: usize) -> &'static mut [u8] {
+    unsafe { slice::from_raw_parts_mut((mem_start + offset) as *mut u8, BUFFER_LEN) }
+}
+
+/// What the apps saw, in order.
+#[derive(Debug, PartialEq)]
+enum Seen {
+    Return(isize),
+    Message {
+        sender: usize,
+        payload: Vec<u8>,
+        reply: bool,
+    },
+    Notify {
+        client: usize,
+    },
+    Start,
+}
+
+/// A service registered as "echo" that replies to every message with the
+/// message reversed.
+fn echo_service(log: &Rc<RefCell<Vec<Seen>>>) -> SimApp {
+    let log = log.clone();
+    let mut mem_start = 0;
+    let mut next = VecDeque::new();
+    SimApp::new("server", move |event| {
+        match event {
+            Event::Start { mem_start: start, .. } => {
+                mem_start = start;
+                buffer(mem_start, NAME)[..4].copy_from_slice(b"echo");
+                next.push_back(allow(SERVICE_REGISTER, mem_start, NAME, 4));
+                next.push_back(allow(MESSAGE_RECEIVE, mem_start, RECEIVE, BUFFER_LEN));
+                next.push_back(allow(MESSAGE_SEND, mem_start, SEND, BUFFER_LEN));
+                next.push_back(subscribe(MESSAGE_RECEIVE, MESSAGE_CALLBACK));
+                next.push_back(subscribe(SERVICE_NOTIFY, NOTIFY_CALLBACK));
+            }
+            Event::Return(value) => log.borrow_mut().push(Seen::Return(value)),
+            Event::Callback {
+                pc: MESSAGE_CALLBACK,
+                arguments: [sender, len, reply, _],
+            } => {
+                let payload = buffer(mem_start, RECEIVE)[..len].to_vec();
+                for (i, &byte) in payload.iter().rev().enumerate() {
+                    buffer(mem_start, SEND)[i] = byte;
+                }
+                log.borrow_mut().push(Seen::Message {
+                    sender: sender,
+                    payload: payload,
+                    reply: reply != 0,
+                });
+                next.push_back(command(sender, SEND_REPLY, len));
+                next.push_back(command(0, MESSAGE_DONE, 0));
+            }
+            Event::Callback {
+                pc: NOTIFY_CALLBACK,
+                arguments: [client, _, _, _],
+            } => log.borrow_mut().push(Seen::Notify { client: client }),
+            _ => {}
+        }
+        next.pop_front().unwrap_or(Action::Yield)
+    })
+}
+
+/// A client that looks up "echo", sends it "ping", and notifies it once the
+/// reply arrived.
+fn client(log: &Rc<RefCell<Vec<Seen>>>) -> SimApp {
+    let log = log.clone();
+    let mut mem_start = 0;
+    let mut service_id = None;
+    let mut next = VecDeque::new();
+    SimApp::new("client", move |event| {
+        match event {
+            Event::Start { mem_start: start, .. } => {
+                mem_start = start;
+                buffer(mem_start, NAME)[..4].copy_from_slice(b"echo");
+                buffer(mem_start, SEND)[..4].copy_from_slice(b"ping");
+                next.push_back(allow(0, mem_start, NAME, 4));
+            }
+            Event::Return(value) => {
+                log.borrow_mut().push(Seen::Return(value));
+                if service_id.is_none() {
+                    // The lookup returned the id of the service.
+                    let id = value as usize;
+                    service_id = Some(id);
+                    next.push_back(allow(MESSAGE_RECEIVE, mem_start, RECEIVE, BUFFER_LEN));
+                    next.push_back(allow(MESSAGE_SEND, mem_start, SEND, BUFFER_LEN));
+                    next.push_back(subscribe(MESSAGE_RECEIVE, MESSAGE_CALLBACK));
+                    next.push_back(command(id, SEND_MESSAGE, 4));
+                }
+            }
+            Event::Callback {
+                pc: MESSAGE_CALLBACK,
+                arguments: [sender, len, reply, _],
+            } => {
+                log.borrow_mut().push(Seen::Message {
+                    sender: sender,
+                    payload: buffer(mem_start, RECEIVE)[..len].to_vec(),
+                    reply: reply != 0,
+                });
+                next.push_back(command(0, MESSAGE_DONE, 0));
+                next.push_back(command(service_id.unwrap(), NOTIFY_SERVICE, 0));
+            }
+            _ => {}
+        }
+        next.pop_front().unwrap_or(Action::Yield)
+    })
+}
+
+/// Load `apps` with IPC and run them until the kernel is idle. Faulting
+/// processes are handled with `fault_response`.
+fn run_apps(apps: Vec<SimApp>, fault_response: FaultResponse) {
+    let (kernel, chip, procs, ipc) =
+        host::boot_with(apps, fault_response, |kernel| IPC::new(kernel, &Cap));
+    assert!(procs.iter().all(|p| p.is_some()));
+    let mut platform = SimPlatform::new();
+    platform.add_driver(ipc::DRIVER_NUM, &ipc);
+    let scheduler = RoundRobinScheduler::new(TIMESLICE_US);
+    assert!(host::run_until_idle(
+        kernel,
+        &platform,
+        chip,
+        Some(&ipc),
+        &scheduler,
+        50,
+        &Cap
+    ));
+}
+
+#[test]
+fn message_round_trip() {
+    let service_log = Rc::new(RefCell::new(Vec::new()));
+    let client_log = Rc::new(RefCell::new(Vec::new()));
+    run_apps(
+        vec![echo_service(&service_log), client(&client_log)],
+        FaultResponse::Panic,
+    );
+
+    let service_id = 1;
+    let client_id = 2;
+    assert_eq!(
+        *service_log.borrow(),
+        vec![
+            Seen::Return(0),
+            Seen::Return(0),
+            Seen::Return(0),
+            Seen::Return(0),
+            Seen::Return(0),
+            Seen::Message {
+                sender: client_id,
+                payload: b"ping".to_vec(),
+                reply: false,
+            },
+            Seen::Return(0),
+            Seen::Return(0),
+            Seen::Notify { client: client_id },
+        ]
+    );
+    assert_eq!(
+        *client_log.borrow(),
+        vec![
+            Seen::Return(service_id as isize),
+            Seen::Return(0),
+            Seen::Return(0),
+            Seen::Return(0),
+            Seen::Return(0),
+            Seen::Message {
+                sender: service_id,
+                payload: b"gnip".to_vec(),
+                reply: true,
+            },
+            Seen::Return(0),
+            Seen::Return(0),
+        ]
+    );
+}
+
+/// A service that faults the first time it is notified.
+fn faulting_service(log: &Rc<RefCell<Vec<Seen>>>) -> SimApp {
+    let log = log.clone();
+    let mut faulted = false;
+    SimApp::new("restarts", move |event| match event {
+        Event::Start { .. } => {
+            log.borrow_mut().push(Seen::Start);
+            subscribe(SERVICE_NOTIFY, NOTIFY_CALLBACK)
+        }
+        Event::Callback {
+            pc: NOTIFY_CALLBACK,
+            arguments: [client, _, _, _],
+        } => {
+            log.borrow_mut().push(Seen::Notify { client: client });
+            if faulted {
+                Action::Yield
+            } else {
+                faulted = true;
+                Action::Fault
+            }
+        }
+        _ => Action::Yield,
+    })
+}
+
+/// A client that notifies the "restarts" service, lets it restart, and then
+/// notifies it again with the id from before the restart.
+fn stale_client(log: &Rc<RefCell<Vec<Seen>>>) -> SimApp {
+    let log = log.clone();
+    let mut mem_start = 0;
+    let mut service_id = 0;
+    let mut returns = 0;
+    SimApp::new("client", move |event| match event {
+        Event::Start { mem_start: start, .. } => {
+            mem_start = start;
+            buffer(mem_start, NAME)[..8].copy_from_slice(b"restarts");
+            allow(0, mem_start, NAME, 8)
+        }
+        Event::Return(value) => {
+            log.borrow_mut().push(Seen::Return(value));
+            returns += 1;
+            match returns {
+                1 | 4 => {
+                    service_id = value as usize;
+                    command(service_id, NOTIFY_SERVICE, 0)
+                }
+                // Give the service time to handle the notification.
+                2 => Action::Spin(TIMESLICE_US),
+                // The id is stale, so look the service up again.
+                3 => allow(0, mem_start, NAME, 8),
+                _ => Action::Yield,
+            }
+        }
+        Event::Resume => command(service_id, NOTIFY_SERVICE, 0),
+        _ => Action::Yield,
+    })
+}
+
+#[test]
+fn ids_become_stale_when_the_service_restarts() {
+    let service_log = Rc::new(RefCell::new(Vec::new()));
+    let client_log = Rc::new(RefCell::new(Vec::new()));
+    // Eight idle processes come first, so the service and the client are
+    // past the first eight process slots.
+    let mut apps: Vec<SimApp> = (0..8)
+        .map(|i| SimApp::new(&format!("idle{}", i), |_| Action::Yield))
+        .collect();
+    apps.push(faulting_service(&service_log));
+    apps.push(stale_client(&client_log));
+    run_apps(apps, FaultResponse::Restart);
+
+    let service_id = 9;
+    let client_id = 10;
+    assert_eq!(
+        *client_log.borrow(),
+        vec![
+            Seen::Return(service_id as isize),
+            Seen::Return(0),
+            Seen::Return(isize::from(ReturnCode::ECANCEL)),
+            Seen::Return(service_id as isize),
+            Seen::Return(0),
+        ]
+    );
+    assert_eq!(
+        *service_log.borrow(),
+        vec![
+            Seen::Start,
+            Seen::Notify { client: client_id },
+            Seen::Start,
+            Seen::Notify { client: client_id },
+        ]
+    );
+}
@@ -0,0 +1,242 @@
+//! Tests stopping, resuming, terminating and restarting a process: a stopped
+//! process keeps its callbacks without keeping the kernel awake, terminating
+//! a process reclaims its grants, and restarting it runs it from its entry
+//! point again.
+
+extern crate host;
+extern crate kernel;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+
+use host::{Action, Event, SimApp, SimChip, SimPlatform};
+use kernel::capabilities::{
+    MainLoopCapability, MemoryAllocationCapability, ProcessManagementCapability,
+};
+use kernel::procs::{FaultResponse, ProcessType, State};
+use kernel::scheduler::RoundRobinScheduler;
+use kernel::{AppId, Callback, Driver, Grant, Kernel, ReturnCode};
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+unsafe impl MemoryAllocationCapability for Cap {}
+unsafe impl ProcessManagementCapability for Cap {}
+
+const NOTIFY_DRIVER: usize = 0x90000;
+const CALLBACK_PC: usize = 0x1000;
+
+#[derive(Default)]
+struct Subscription {
+    callback: Option<Callback>,
+    commands: usize,
+}
+
+/// Counts the commands of each process, and calls it back when the test
+/// says so.
+struct Notify {
+    apps: Grant<Subscription>,
+}
+
+impl Notify {
+    /// Schedule `count` callbacks for `appid`, with arguments 0 to `count`.
+    fn notify(&self, appid: AppId, count: usize) {
+        self.apps
+            .enter(appid, |app, _| {
+                for i in 0..count {
+                    app.callback.map(|mut cb| cb.schedule(i, 0, 0));
+                }
+            }).unwrap();
+    }
+}
+
+impl Driver for Notify {
+    fn subscribe(
+        &self,
+        _subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                app.callback = callback;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    /// Command 0 returns how many commands the process has called.
+    fn command(&self, _: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                app.commands += 1;
+                ReturnCode::SuccessWithValue {
+                    value: app.commands,
+                }
+            }).unwrap_or_else(|err| err.into())
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Seen {
+    Start { mem_start: usize, app_heap_break: usize },
+    Return(isize),
+    Callback(usize),
+}
+
+/// An app that subscribes to the notify driver, calls its command, and then
+/// yields. It writes what it sees to the log.
+fn managed_app(log: &Rc<RefCell<Vec<Seen>>>) -> SimApp {
+    let log = log.clone();
+    let mut actions = VecDeque::new();
+    SimApp::new("managed", move |event| {
+        match event {
+            Event::Start {
+                mem_start,
+                app_heap_break,
+                ..
+            } => {
+                log.borrow_mut().push(Seen::Start {
+                    mem_start: mem_start,
+                    app_heap_break: app_heap_break,
+                });
+                actions.push_back(Action::Subscribe {
+                    driver_number: NOTIFY_DRIVER,
+                    subdriver_number: 0,
+                    callback_ptr: CALLBACK_PC,
+                    appdata: 0,
+                });
+                actions.push_back(Action::Command {
+                    driver_number: NOTIFY_DRIVER,
+                    subdriver_number: 0,
+                    arg0: 0,
+                    arg1: 0,
+                });
+            }
+            Event::Return(value) => log.borrow_mut().push(Seen::Return(value)),
+            Event::Callback { pc, arguments } => {
+                assert_eq!(pc, CALLBACK_PC);
+                log.borrow_mut().push(Seen::Callback(arguments[0]));
+            }
+            Event::Resume => {}
+        }
+        actions.pop_front().unwrap_or(Action::Yield)
+    })
+}
+
+/// Boot the managed app, and run it until it yields for good.
+fn boot_managed(
+    log: &Rc<RefCell<Vec<Seen>>>,
+) -> (
+    &'static Kernel,
+    &'static SimChip,
+    &'static ProcessType,
+    &'static Notify,
+) {
+    let (kernel, chip, procs, notify) =
+        host::boot_with(vec![managed_app(log)], FaultResponse::Panic, |kernel| {
+            &*host::leak(Notify {
+                apps: kernel.create_grant(&Cap),
+            })
+        });
+    assert!(run(kernel, chip, notify));
+    (kernel, chip, procs[0].unwrap(), notify)
+}
+
+fn run(kernel: &'static Kernel, chip: &SimChip, notify: &'static Notify) -> bool {
+    let mut platform = SimPlatform::new();
+    platform.add_driver(NOTIFY_DRIVER, notify);
+    let scheduler = RoundRobinScheduler::new(10000);
+    host::run_until_idle(kernel, &platform, chip, None, &scheduler, 10, &Cap)
+}
+
+/// Run one iteration of the main loop, and return whether the kernel went to
+/// sleep, which it only does when no process has work to do.
+fn slept(kernel: &'static Kernel, chip: &SimChip, notify: &'static Notify) -> bool {
+    let mut platform = SimPlatform::new();
+    platform.add_driver(NOTIFY_DRIVER, notify);
+    let scheduler = RoundRobinScheduler::new(10000);
+    let sleep_count = chip.sleep_count();
+    kernel.kernel_loop_operation(&platform, chip, None, &scheduler, &Cap);
+    chip.sleep_count() != sleep_count
+}
+
+#[test]
+fn stopped_process_keeps_its_callbacks() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let (kernel, chip, process, notify) = boot_managed(&log);
+    let appid = process.appid();
+    log.borrow_mut().clear();
+
+    notify.notify(appid, 2);
+    assert_eq!(kernel.stop_process(appid, &Cap), ReturnCode::SUCCESS);
+    assert_eq!(process.get_state(), State::StoppedYielded);
+    // The callbacks of a stopped process are not work for the kernel, so it
+    // sleeps instead of waiting for the process to run.
+    assert!(slept(kernel, chip, notify));
+    assert!(log.borrow().is_empty());
+
+    // More callbacks can be scheduled while the process is stopped.
+    notify.notify(appid, 1);
+    assert!(slept(kernel, chip, notify));
+    assert!(log.borrow().is_empty());
+
+    assert_eq!(kernel.resume_process(appid, &Cap), ReturnCode::SUCCESS);
+    assert_eq!(process.get_state(), State::Yielded);
+    assert!(run(kernel, chip, notify));
+    assert_eq!(
+        *log.borrow(),
+        vec![Seen::Callback(0), Seen::Callback(1), Seen::Callback(0)]
+    );
+    // All of the work was done, so the kernel sleeps again.
+    assert!(slept(kernel, chip, notify));
+}
+
+#[test]
+fn terminated_process_loses_its_grants_and_callbacks() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let (kernel, chip, process, notify) = boot_managed(&log);
+    let appid = process.appid();
+    assert!(notify.apps.grant(appid).is_some());
+    assert_eq!(process.debug_grant_bytes(0), mem::size_of::<Subscription>());
+    let grant_break = process.kernel_memory_break();
+    log.borrow_mut().clear();
+
+    notify.notify(appid, 2);
+    assert_eq!(kernel.terminate_process(appid, &Cap), ReturnCode::SUCCESS);
+    assert_eq!(process.get_state(), State::Terminated);
+    assert!(notify.apps.grant(appid).is_none());
+    assert_eq!(process.debug_grant_bytes(0), 0);
+    assert!(
+        process.kernel_memory_break() as usize
+            >= grant_break as usize + mem::size_of::<Subscription>()
+    );
+
+    // The callbacks were dropped, and the process does not run again.
+    assert!(slept(kernel, chip, notify));
+    assert!(run(kernel, chip, notify));
+    assert!(log.borrow().is_empty());
+}
+
+#[test]
+fn restarted_process_starts_over() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let (kernel, chip, process, notify) = boot_managed(&log);
+    let appid = process.appid();
+    let first_start = log.borrow_mut().remove(0);
+    assert_eq!(*log.borrow(), vec![Seen::Return(0), Seen::Return(1)]);
+    log.borrow_mut().clear();
+
+    // Restarting drops the pending callbacks and the grant, so the command
+    // count starts over.
+    notify.notify(appid, 2);
+    assert_eq!(kernel.restart_process(appid, &Cap), ReturnCode::SUCCESS);
+    assert!(notify.apps.grant(appid).is_none());
+    assert!(run(kernel, chip, notify));
+    assert_eq!(
+        *log.borrow(),
+        vec![first_start, Seen::Return(0), Seen::Return(1)]
+    );
+    assert_eq!(process.get_state(), State::Yielded);
+    assert_eq!(process.debug_restart_count(), 1);
+}
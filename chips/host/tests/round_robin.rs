@@ -0,0 +1,73 @@
+//! Tests that the round robin scheduler takes turns between processes that use
+//! up their timeslice.
+
+extern crate host;
+extern crate kernel;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use host::{Action, Event, SimApp, SimPlatform};
+use kernel::capabilities::MainLoopCapability;
+use kernel::scheduler::RoundRobinScheduler;
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+
+const TIMESLICE_US: u32 = 10000;
+
+/// An app that spins for a full timeslice `slices` times and then yields. It
+/// writes its name to `log` every time it runs.
+fn spinner(name: &'static str, slices: usize, log: &Rc<RefCell<Vec<&'static str>>>) -> SimApp {
+    let log = log.clone();
+    let mut remaining = slices;
+    SimApp::new(name, move |event| match event {
+        Event::Start { .. } | Event::Resume => {
+            log.borrow_mut().push(name);
+            if remaining > 0 {
+                remaining -= 1;
+                Action::Spin(TIMESLICE_US)
+            } else {
+                Action::Yield
+            }
+        }
+        _ => Action::Yield,
+    })
+}
+
+#[test]
+fn processes_take_turns() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let apps = vec![
+        spinner("a", 2, &log),
+        spinner("b", 1, &log),
+        spinner("c", 2, &log),
+    ];
+
+    let (kernel, chip, procs) = host::boot(apps);
+    let platform = SimPlatform::new();
+    let scheduler = RoundRobinScheduler::new(TIMESLICE_US);
+    assert!(host::run_until_idle(
+        kernel,
+        &platform,
+        chip,
+        None,
+        &scheduler,
+        20,
+        &Cap
+    ));
+
+    // Every process runs once per round until it yields. "b" yields in the
+    // second round, so the third round is only "a" and "c".
+    assert_eq!(
+        *log.borrow(),
+        vec!["a", "b", "c", "a", "b", "c", "a", "c"]
+    );
+    for p in procs.iter() {
+        let p = p.unwrap();
+        assert_eq!(
+            p.debug_timeslice_expiration_count() as u64,
+            p.debug_cpu_time_us() / TIMESLICE_US as u64
+        );
+    }
+}
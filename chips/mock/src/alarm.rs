@@ -0,0 +1,114 @@
+//! Mock alarm with virtual time.
+//!
+//! Time only moves when the test calls `advance()` or `advance_to_alarm()`.
+//! If the alarm expires while time moves, its client is called before the
+//! method returns.
+//!
+//! The counter is 32 bits wide unless the alarm is created with
+//! `with_width()`, e.g. to stand in for a 24-bit RTC.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Frequency, Time};
+
+pub struct MockAlarm<F: Frequency> {
+    /// Counter values are masked with this, like a narrower counter.
+    mask: u32,
+    now: Cell<u32>,
+    alarm: Cell<u32>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'static time::Client>,
+    _frequency: PhantomData<F>,
+}
+
+impl<F: Frequency> MockAlarm<F> {
+    pub fn new() -> MockAlarm<F> {
+        MockAlarm::with_width(32)
+    }
+
+    /// Create an alarm whose counter is `bits` bits wide. Only the lower
+    /// `bits` bits of the alarm that is set are compared with the counter.
+    pub fn with_width(bits: u32) -> MockAlarm<F> {
+        MockAlarm {
+            mask: ((1u64 << bits) - 1) as u32,
+            now: Cell::new(0),
+            alarm: Cell::new(0),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+            _frequency: PhantomData,
+        }
+    }
+
+    pub fn set_client(&self, client: &'static time::Client) {
+        self.client.set(client);
+    }
+
+    /// Set the current time without firing the alarm, e.g. to test how a
+    /// capsule handles the counter wrapping around.
+    pub fn set_now(&self, now: u32) {
+        self.now.set(now & self.mask);
+    }
+
+    /// Let `ticks` ticks pass. If the alarm expires on the way, time stops at
+    /// the alarm, the alarm is disarmed and the client is called, and then
+    /// the remaining ticks pass (possibly firing the alarm again if the
+    /// client set it).
+    pub fn advance(&self, ticks: u32) {
+        let mut remaining = ticks;
+        loop {
+            let until_alarm = self.until_alarm();
+            if !self.armed.get() || until_alarm > remaining {
+                self.now.set(self.now.get().wrapping_add(remaining) & self.mask);
+                return;
+            }
+            self.now.set(self.alarm.get());
+            remaining -= until_alarm;
+            self.armed.set(false);
+            self.client.map(|client| client.fired());
+        }
+    }
+
+    /// Move time forward to the alarm and fire it. Returns `false` and does
+    /// nothing if the alarm is not armed.
+    pub fn advance_to_alarm(&self) -> bool {
+        if !self.armed.get() {
+            return false;
+        }
+        let until_alarm = self.until_alarm();
+        self.advance(until_alarm);
+        true
+    }
+
+    fn until_alarm(&self) -> u32 {
+        self.alarm.get().wrapping_sub(self.now.get()) & self.mask
+    }
+}
+
+impl<F: Frequency> Time for MockAlarm<F> {
+    type Frequency = F;
+
+    fn disable(&self) {
+        self.armed.set(false);
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+}
+
+impl<F: Frequency> Alarm for MockAlarm<F> {
+    fn now(&self) -> u32 {
+        self.now.get()
+    }
+
+    fn set_alarm(&self, tics: u32) {
+        self.alarm.set(tics & self.mask);
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.alarm.get()
+    }
+}
@@ -0,0 +1,161 @@
+//! Mock flash with page-level fault injection.
+//!
+//! Operations complete when the test calls `handle_interrupt()`. Pages marked
+//! with `fail_page()` complete every operation with `Error::FlashError` and
+//! leave their contents unchanged.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::flash::{self, Flash};
+use kernel::ReturnCode;
+
+pub const PAGE_SIZE: usize = 512;
+
+/// Erased flash reads as all ones.
+const ERASED: u8 = 0xff;
+
+pub struct MockFlashPage(pub [u8; PAGE_SIZE]);
+
+impl Default for MockFlashPage {
+    fn default() -> MockFlashPage {
+        MockFlashPage([0; PAGE_SIZE])
+    }
+}
+
+impl AsMut<[u8]> for MockFlashPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Operation {
+    Read,
+    Write,
+    Erase,
+}
+
+pub struct MockFlash {
+    client: OptionalCell<&'static flash::Client<MockFlash>>,
+    pages: RefCell<Vec<[u8; PAGE_SIZE]>>,
+    failing_pages: RefCell<HashSet<usize>>,
+    /// The operation in progress and the page it is on.
+    operation: Cell<Option<(Operation, usize)>>,
+    buffer: TakeCell<'static, MockFlashPage>,
+}
+
+impl MockFlash {
+    /// Create a flash of `num_pages` erased pages.
+    pub fn new(num_pages: usize) -> MockFlash {
+        MockFlash {
+            client: OptionalCell::empty(),
+            pages: RefCell::new(vec![[ERASED; PAGE_SIZE]; num_pages]),
+            failing_pages: RefCell::new(HashSet::new()),
+            operation: Cell::new(None),
+            buffer: TakeCell::empty(),
+        }
+    }
+
+    /// Make every operation on `page_number` fail.
+    pub fn fail_page(&self, page_number: usize) {
+        self.failing_pages.borrow_mut().insert(page_number);
+    }
+
+    /// Make operations on `page_number` succeed again.
+    pub fn clear_fault(&self, page_number: usize) {
+        self.failing_pages.borrow_mut().remove(&page_number);
+    }
+
+    /// Returns a copy of the contents of a page.
+    pub fn page(&self, page_number: usize) -> [u8; PAGE_SIZE] {
+        self.pages.borrow()[page_number]
+    }
+
+    /// Set the contents of a page directly, without an operation.
+    pub fn set_page(&self, page_number: usize, data: &[u8; PAGE_SIZE]) {
+        self.pages.borrow_mut()[page_number] = *data;
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.operation.get().is_some()
+    }
+
+    /// Complete the operation in progress.
+    pub fn handle_interrupt(&self) {
+        let (operation, page_number) = match self.operation.take() {
+            Some(operation) => operation,
+            None => return,
+        };
+        let error = if self.failing_pages.borrow().contains(&page_number) {
+            flash::Error::FlashError
+        } else {
+            let mut pages = self.pages.borrow_mut();
+            match operation {
+                Operation::Read => {
+                    self.buffer.map(|buffer| buffer.0 = pages[page_number]);
+                }
+                Operation::Write => {
+                    self.buffer.map(|buffer| pages[page_number] = buffer.0);
+                }
+                Operation::Erase => pages[page_number] = [ERASED; PAGE_SIZE],
+            }
+            flash::Error::CommandComplete
+        };
+        self.client.map(move |client| match operation {
+            Operation::Read => {
+                self.buffer
+                    .take()
+                    .map(|buffer| client.read_complete(buffer, error));
+            }
+            Operation::Write => {
+                self.buffer
+                    .take()
+                    .map(|buffer| client.write_complete(buffer, error));
+            }
+            Operation::Erase => client.erase_complete(error),
+        });
+    }
+
+    fn start(&self, operation: Operation, page_number: usize) -> ReturnCode {
+        if self.is_busy() {
+            ReturnCode::EBUSY
+        } else if page_number >= self.pages.borrow().len() {
+            ReturnCode::EINVAL
+        } else {
+            self.operation.set(Some((operation, page_number)));
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<C: flash::Client<Self>> flash::HasClient<'static, C> for MockFlash {
+    fn set_client(&self, client: &'static C) {
+        self.client.set(client);
+    }
+}
+
+impl Flash for MockFlash {
+    type Page = MockFlashPage;
+
+    fn read_page(&self, page_number: usize, buf: &'static mut MockFlashPage) -> ReturnCode {
+        let result = self.start(Operation::Read, page_number);
+        if result == ReturnCode::SUCCESS {
+            self.buffer.replace(buf);
+        }
+        result
+    }
+
+    fn write_page(&self, page_number: usize, buf: &'static mut MockFlashPage) -> ReturnCode {
+        let result = self.start(Operation::Write, page_number);
+        if result == ReturnCode::SUCCESS {
+            self.buffer.replace(buf);
+        }
+        result
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        self.start(Operation::Erase, page_number)
+    }
+}
@@ -0,0 +1,122 @@
+//! Mock I2C master that records transactions.
+//!
+//! Each `write`, `read` or `write_read` is recorded as an `I2CTransaction`
+//! and completes when the test calls `handle_interrupt()`. Data for reads
+//! comes from responses queued with `push_response()`; reads without a
+//! queued response read zeros.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::i2c::{self, I2CMaster};
+
+/// A transaction the I2C master performed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct I2CTransaction {
+    pub addr: u8,
+    /// The bytes written.
+    pub write: Vec<u8>,
+    /// How many bytes were read.
+    pub read_len: usize,
+}
+
+pub struct MockI2C {
+    client: OptionalCell<&'static i2c::I2CHwMasterClient>,
+    enabled: Cell<bool>,
+    transactions: RefCell<Vec<I2CTransaction>>,
+    responses: RefCell<VecDeque<Vec<u8>>>,
+    /// Error to complete the next transaction with.
+    next_error: Cell<Option<i2c::Error>>,
+    buffer: TakeCell<'static, [u8]>,
+    read_len: Cell<usize>,
+}
+
+impl MockI2C {
+    pub fn new() -> MockI2C {
+        MockI2C {
+            client: OptionalCell::empty(),
+            enabled: Cell::new(false),
+            transactions: RefCell::new(Vec::new()),
+            responses: RefCell::new(VecDeque::new()),
+            next_error: Cell::new(None),
+            buffer: TakeCell::empty(),
+            read_len: Cell::new(0),
+        }
+    }
+
+    pub fn set_master_client(&self, client: &'static i2c::I2CHwMasterClient) {
+        self.client.set(client);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.buffer.is_some()
+    }
+
+    /// Returns and clears the transactions recorded so far.
+    pub fn take_transactions(&self) -> Vec<I2CTransaction> {
+        self.transactions.replace(Vec::new())
+    }
+
+    /// Queue the data the device returns for the next read.
+    pub fn push_response(&self, data: &[u8]) {
+        self.responses.borrow_mut().push_back(data.to_vec());
+    }
+
+    /// Complete the next transaction with `error`, e.g. `Error::AddressNak`.
+    pub fn inject_error(&self, error: i2c::Error) {
+        self.next_error.set(Some(error));
+    }
+
+    /// Complete the transaction in progress.
+    pub fn handle_interrupt(&self) {
+        self.buffer.take().map(|buffer| {
+            let error = self.next_error.take().unwrap_or(i2c::Error::CommandComplete);
+            if error == i2c::Error::CommandComplete && self.read_len.get() > 0 {
+                let response = self.responses.borrow_mut().pop_front().unwrap_or(Vec::new());
+                for (i, byte) in buffer[..self.read_len.get()].iter_mut().enumerate() {
+                    *byte = response.get(i).cloned().unwrap_or(0);
+                }
+            }
+            self.client
+                .map(move |client| client.command_complete(buffer, error));
+        });
+    }
+
+    fn start(&self, addr: u8, data: &'static mut [u8], write_len: usize, read_len: usize) {
+        let write_len = write_len.min(data.len());
+        self.transactions.borrow_mut().push(I2CTransaction {
+            addr: addr,
+            write: data[..write_len].to_vec(),
+            read_len: read_len.min(data.len()),
+        });
+        self.read_len.set(read_len.min(data.len()));
+        self.buffer.replace(data);
+    }
+}
+
+impl I2CMaster for MockI2C {
+    fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    fn disable(&self) {
+        self.enabled.set(false);
+    }
+
+    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        self.start(addr, data, write_len as usize, read_len as usize);
+    }
+
+    fn write(&self, addr: u8, data: &'static mut [u8], len: u8) {
+        self.start(addr, data, len as usize, 0);
+    }
+
+    fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8) {
+        self.start(addr, buffer, 0, len as usize);
+    }
+}
@@ -0,0 +1,84 @@
+//! Mock implementations of HIL traits for testing capsules on a host.
+//!
+//! The mocks stand in for chip peripherals. They record what a capsule asks
+//! of them, let the test script how the hardware responds, and complete
+//! operations only when the test says so: asynchronous operations complete
+//! when the test calls the mock's `handle_interrupt()`, like a peripheral
+//! finishing an operation and raising its interrupt, and alarms fire when the
+//! test moves virtual time forward.
+//!
+//! - `MockAlarm`: alarm with virtual time.
+//! - `MockUart`: UART that captures transmitted bytes and receives bytes
+//!   injected by the test.
+//! - `MockFlash`: flash whose pages can be made to fail.
+//! - `MockI2C` and `MockSpi`: masters that record transactions and return
+//!   scripted data.
+//!
+//! For example, testing that a virtual alarm fires at the right time:
+//!
+//! ```
+//! extern crate capsules;
+//! extern crate kernel;
+//! extern crate mock;
+//!
+//! use std::cell::Cell;
+//!
+//! use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+//! use kernel::hil::time::{self, Alarm, Freq32KHz};
+//! use mock::MockAlarm;
+//!
+//! struct Counter(Cell<usize>);
+//! impl time::Client for Counter {
+//!     fn fired(&self) {
+//!         self.0.set(self.0.get() + 1);
+//!     }
+//! }
+//!
+//! let alarm: &'static MockAlarm<Freq32KHz> = mock::leak(MockAlarm::new());
+//! let mux = mock::leak(MuxAlarm::new(alarm));
+//! alarm.set_client(mux);
+//!
+//! let virtual_alarm = mock::leak(VirtualMuxAlarm::new(mux));
+//! let counter = mock::leak(Counter(Cell::new(0)));
+//! virtual_alarm.set_client(counter);
+//!
+//! virtual_alarm.set_alarm(100);
+//! alarm.advance(99);
+//! assert_eq!(counter.0.get(), 0);
+//! alarm.advance(1);
+//! assert_eq!(counter.0.get(), 1);
+//! assert!(!alarm.advance_to_alarm());
+//! ```
+
+#![crate_name = "mock"]
+#![crate_type = "rlib"]
+
+extern crate kernel;
+
+pub mod alarm;
+pub mod flash;
+pub mod i2c;
+pub mod spi;
+pub mod uart;
+
+pub use alarm::MockAlarm;
+pub use flash::{MockFlash, MockFlashPage};
+pub use i2c::{I2CTransaction, MockI2C};
+pub use spi::{MockSpi, SpiTransaction};
+pub use uart::MockUart;
+
+use kernel::procs::ProcessType;
+use kernel::Kernel;
+
+/// Give `value` a static lifetime, like `static_init!` does on a board.
+pub fn leak<T>(value: T) -> &'static mut T {
+    Box::leak(Box::new(value))
+}
+
+/// Create a kernel without processes, for capsules that need the kernel for
+/// grants or deferred calls but are tested without apps. Tests with apps load
+/// them with `host::boot()` instead.
+pub fn boot() -> &'static Kernel {
+    let processes: &'static [Option<&'static ProcessType>] = leak([None]);
+    leak(Kernel::new(processes))
+}
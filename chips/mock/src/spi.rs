@@ -0,0 +1,186 @@
+//! Mock SPI master that records transactions.
+//!
+//! Each `read_write_bytes` is recorded as a `SpiTransaction` and completes
+//! when the test calls `handle_interrupt()`. The bytes read come from
+//! responses queued with `push_response()`; without a queued response the
+//! mock reads zeros. Single byte transfers complete immediately.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::spi::{self, ClockPhase, ClockPolarity, SpiMaster};
+use kernel::ReturnCode;
+
+/// A transfer the SPI master performed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SpiTransaction {
+    /// The chip select that was active.
+    pub chip_select: Option<u8>,
+    /// The bytes written.
+    pub write: Vec<u8>,
+    /// Whether the transfer had a read buffer.
+    pub read: bool,
+}
+
+pub struct MockSpi {
+    client: OptionalCell<&'static spi::SpiMasterClient>,
+    chip_select: Cell<Option<u8>>,
+    rate: Cell<u32>,
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+    held_low: Cell<bool>,
+    transactions: RefCell<Vec<SpiTransaction>>,
+    responses: RefCell<VecDeque<u8>>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+}
+
+impl MockSpi {
+    pub fn new() -> MockSpi {
+        MockSpi {
+            client: OptionalCell::empty(),
+            chip_select: Cell::new(None),
+            rate: Cell::new(0),
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+            held_low: Cell::new(false),
+            transactions: RefCell::new(Vec::new()),
+            responses: RefCell::new(VecDeque::new()),
+            write_buffer: TakeCell::empty(),
+            read_buffer: TakeCell::empty(),
+            len: Cell::new(0),
+        }
+    }
+
+    /// Whether the chip select is being held low between transfers.
+    pub fn is_held_low(&self) -> bool {
+        self.held_low.get()
+    }
+
+    /// Returns and clears the transactions recorded so far.
+    pub fn take_transactions(&self) -> Vec<SpiTransaction> {
+        self.transactions.replace(Vec::new())
+    }
+
+    /// Queue bytes for the device to send in the following transfers.
+    pub fn push_response(&self, data: &[u8]) {
+        self.responses.borrow_mut().extend(data.iter());
+    }
+
+    /// Complete the transfer in progress.
+    pub fn handle_interrupt(&self) {
+        self.write_buffer.take().map(|write_buffer| {
+            let len = self.len.get();
+            let mut read_buffer = self.read_buffer.take();
+            {
+                let mut responses = self.responses.borrow_mut();
+                for i in 0..len {
+                    let byte = responses.pop_front().unwrap_or(0);
+                    read_buffer.as_mut().map(|buffer| buffer[i] = byte);
+                }
+            }
+            self.client
+                .map(move |client| client.read_write_done(write_buffer, read_buffer, len));
+        });
+    }
+
+    fn next_response(&self) -> u8 {
+        self.responses.borrow_mut().pop_front().unwrap_or(0)
+    }
+
+    fn record(&self, write: &[u8], read: bool) {
+        self.transactions.borrow_mut().push(SpiTransaction {
+            chip_select: self.chip_select.get(),
+            write: write.to_vec(),
+            read: read,
+        });
+    }
+}
+
+impl SpiMaster for MockSpi {
+    type ChipSelect = u8;
+
+    fn set_client(&self, client: &'static spi::SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    fn init(&self) {}
+
+    fn is_busy(&self) -> bool {
+        self.write_buffer.is_some()
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        let len = read_buffer
+            .as_ref()
+            .map_or(len, |buffer| len.min(buffer.len()))
+            .min(write_buffer.len());
+        self.record(&write_buffer[..len], read_buffer.is_some());
+        self.len.set(len);
+        self.write_buffer.replace(write_buffer);
+        read_buffer.map(|buffer| self.read_buffer.replace(buffer));
+        ReturnCode::SUCCESS
+    }
+
+    fn write_byte(&self, val: u8) {
+        self.record(&[val], false);
+        self.next_response();
+    }
+
+    fn read_byte(&self) -> u8 {
+        self.record(&[0], true);
+        self.next_response()
+    }
+
+    fn read_write_byte(&self, val: u8) -> u8 {
+        self.record(&[val], true);
+        self.next_response()
+    }
+
+    fn specify_chip_select(&self, cs: u8) {
+        self.chip_select.set(Some(cs));
+    }
+
+    fn set_rate(&self, rate: u32) -> u32 {
+        self.rate.set(rate);
+        rate
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.rate.get()
+    }
+
+    fn set_clock(&self, polarity: ClockPolarity) {
+        self.polarity.set(polarity);
+    }
+
+    fn get_clock(&self) -> ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) {
+        self.phase.set(phase);
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.phase.get()
+    }
+
+    fn hold_low(&self) {
+        self.held_low.set(true);
+    }
+
+    fn release_low(&self) {
+        self.held_low.set(false);
+    }
+}
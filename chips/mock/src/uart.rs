@@ -0,0 +1,142 @@
+//! Mock UART that captures transmitted bytes and receives injected bytes.
+//!
+//! Transmissions and receptions complete when the test calls
+//! `handle_interrupt()`, like they would when a UART peripheral raises its
+//! interrupt.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart::{self, UARTParameters, UART};
+use kernel::ReturnCode;
+
+pub struct MockUart {
+    client: OptionalCell<&'static uart::Client>,
+    params: Cell<Option<UARTParameters>>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    /// Every byte transmitted so far.
+    tx_data: RefCell<Vec<u8>>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_aborted: Cell<bool>,
+    /// Bytes injected by the test that have not been received yet.
+    rx_data: RefCell<VecDeque<u8>>,
+    /// Error to complete the next transmission or reception with.
+    next_error: Cell<Option<uart::Error>>,
+}
+
+impl MockUart {
+    pub fn new() -> MockUart {
+        MockUart {
+            client: OptionalCell::empty(),
+            params: Cell::new(None),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_data: RefCell::new(Vec::new()),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_aborted: Cell::new(false),
+            rx_data: RefCell::new(VecDeque::new()),
+            next_error: Cell::new(None),
+        }
+    }
+
+    /// The parameters the UART was last configured with.
+    pub fn parameters(&self) -> Option<UARTParameters> {
+        self.params.get()
+    }
+
+    /// Returns and clears the bytes transmitted so far.
+    pub fn take_tx(&self) -> Vec<u8> {
+        self.tx_data.replace(Vec::new())
+    }
+
+    /// Make `data` arrive on the receive line.
+    pub fn inject_rx(&self, data: &[u8]) {
+        self.rx_data.borrow_mut().extend(data.iter());
+    }
+
+    /// Complete the next transmission or reception with `error`, e.g.
+    /// `uart::Error::ParityError`.
+    pub fn inject_error(&self, error: uart::Error) {
+        self.next_error.set(Some(error));
+    }
+
+    pub fn is_transmitting(&self) -> bool {
+        self.tx_buffer.is_some()
+    }
+
+    pub fn is_receiving(&self) -> bool {
+        self.rx_buffer.is_some()
+    }
+
+    /// Complete the pending transmission, and the pending reception if enough
+    /// bytes have been injected or it was aborted.
+    pub fn handle_interrupt(&self) {
+        self.tx_buffer.take().map(|buffer| {
+            let error = self.next_error.take().unwrap_or(uart::Error::CommandComplete);
+            if error == uart::Error::CommandComplete {
+                self.tx_data
+                    .borrow_mut()
+                    .extend_from_slice(&buffer[..self.tx_len.get()]);
+            }
+            self.client
+                .map(move |client| client.transmit_complete(buffer, error));
+        });
+
+        let available = self.rx_data.borrow().len();
+        if available < self.rx_len.get() && !self.rx_aborted.get() {
+            return;
+        }
+        self.rx_buffer.take().map(|buffer| {
+            let len = if self.rx_aborted.get() {
+                available.min(self.rx_len.get())
+            } else {
+                self.rx_len.get()
+            };
+            {
+                let mut rx_data = self.rx_data.borrow_mut();
+                for (byte, data) in buffer.iter_mut().zip(rx_data.drain(..len)) {
+                    *byte = data;
+                }
+            }
+            let error = if self.rx_aborted.replace(false) {
+                uart::Error::Aborted
+            } else {
+                self.next_error.take().unwrap_or(uart::Error::CommandComplete)
+            };
+            self.client
+                .map(move |client| client.receive_complete(buffer, len, error));
+        });
+    }
+}
+
+impl UART for MockUart {
+    fn set_client(&self, client: &'static uart::Client) {
+        self.client.set(client);
+    }
+
+    fn configure(&self, params: UARTParameters) -> ReturnCode {
+        self.params.set(Some(params));
+        ReturnCode::SUCCESS
+    }
+
+    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
+        self.tx_len.set(tx_len.min(tx_data.len()));
+        self.tx_buffer.replace(tx_data);
+    }
+
+    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        self.rx_len.set(rx_len.min(rx_buffer.len()));
+        self.rx_aborted.set(false);
+        self.rx_buffer.replace(rx_buffer);
+    }
+
+    fn abort_receive(&self) {
+        if self.rx_buffer.is_some() {
+            self.rx_aborted.set(true);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Tests the alarm syscall driver on top of a virtual alarm and the mock
+//! alarm, with processes run by the host simulation.
+
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+extern crate mock;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use capsules::alarm::AlarmDriver;
+use capsules::extended_alarm::ExtendedAlarm;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use host::{Action, Event, SimApp, SimPlatform};
+use kernel::capabilities::{MainLoopCapability, MemoryAllocationCapability};
+use kernel::hil::time::Freq32KHz;
+use kernel::procs::FaultResponse;
+use kernel::scheduler::RoundRobinScheduler;
+use mock::MockAlarm;
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+unsafe impl MemoryAllocationCapability for Cap {}
+
+type Alarm = ExtendedAlarm<'static, VirtualMuxAlarm<'static, MockAlarm<Freq32KHz>>>;
+
+const ALARM_CALLBACK: usize = 0x1000;
+
+fn alarm_command(command: usize, arg0: usize) -> Action {
+    Action::Command {
+        driver_number: capsules::alarm::DRIVER_NUM,
+        subdriver_number: command,
+        arg0: arg0,
+        arg1: 0,
+    }
+}
+
+/// An app that reads the time, sets an alarm `offset` ticks from it, and
+/// writes the `now` and `expiration` arguments of the alarm callback to `log`.
+fn alarm_app(name: &str, offset: i32, log: &Rc<RefCell<Vec<(usize, usize)>>>) -> SimApp {
+    let log = log.clone();
+    let mut step = 0;
+    SimApp::new(name, move |event| {
+        step += 1;
+        match (step, event) {
+            (1, Event::Start { .. }) => Action::Subscribe {
+                driver_number: capsules::alarm::DRIVER_NUM,
+                subdriver_number: 0,
+                callback_ptr: ALARM_CALLBACK,
+                appdata: 0,
+            },
+            (2, Event::Return(_)) => alarm_command(2, 0),
+            (3, Event::Return(now)) => {
+                alarm_command(4, (now as u32).wrapping_add(offset as u32) as usize)
+            }
+            (
+                _,
+                Event::Callback {
+                    pc: ALARM_CALLBACK,
+                    arguments: [now, expiration, _, _],
+                },
+            ) => {
+                log.borrow_mut().push((now, expiration));
+                Action::Yield
+            }
+            _ => Action::Yield,
+        }
+    })
+}
+
+#[test]
+fn alarms_fire_in_order() {
+    let later = Rc::new(RefCell::new(Vec::new()));
+    let past = Rc::new(RefCell::new(Vec::new()));
+    let apps = vec![alarm_app("later", 100, &later), alarm_app("past", -10, &past)];
+
+    let alarm: &'static MockAlarm<Freq32KHz> = mock::leak(MockAlarm::new());
+    alarm.set_now(1000);
+    let mux = mock::leak(MuxAlarm::new(alarm));
+    alarm.set_client(mux);
+    let virtual_alarm = mock::leak(VirtualMuxAlarm::new(mux));
+    let extended_alarm: &'static Alarm = mock::leak(ExtendedAlarm::new(virtual_alarm, 32));
+    virtual_alarm.set_client(extended_alarm);
+    extended_alarm.start();
+    let (kernel, chip, _, driver) = host::boot_with(apps, FaultResponse::Panic, |kernel| {
+        let driver: &'static AlarmDriver<'static, Alarm> = mock::leak(AlarmDriver::new(
+            extended_alarm,
+            kernel.create_grant(&Cap),
+        ));
+        extended_alarm.set_client(driver);
+        driver
+    });
+    let mut platform = SimPlatform::new();
+    platform.add_driver(capsules::alarm::DRIVER_NUM, driver);
+    let scheduler = RoundRobinScheduler::new(10000);
+    let run = || {
+        assert!(host::run_until_idle(
+            kernel,
+            &platform,
+            chip,
+            None,
+            &scheduler,
+            10,
+            &Cap
+        ))
+    };
+
+    run();
+    assert!(later.borrow().is_empty());
+    assert!(past.borrow().is_empty());
+
+    // An alarm set slightly in the past fires right away rather than after
+    // the counter wraps.
+    alarm.advance(1);
+    run();
+    assert_eq!(*past.borrow(), vec![(1001, 990)]);
+    assert!(later.borrow().is_empty());
+
+    alarm.advance(98);
+    run();
+    assert!(later.borrow().is_empty());
+
+    alarm.advance(1);
+    run();
+    assert_eq!(*later.borrow(), vec![(1100, 1100)]);
+    assert_eq!(past.borrow().len(), 1);
+}
@@ -0,0 +1,274 @@
+//! Tests the console syscall driver on the mock UART, with processes run by
+//! the host simulation.
+
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+extern crate mock;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::slice;
+
+use capsules::console::Console;
+use host::{Action, Event, SimApp, SimPlatform};
+use kernel::capabilities::{MainLoopCapability, MemoryAllocationCapability};
+use kernel::hil::uart::UART;
+use kernel::procs::FaultResponse;
+use kernel::scheduler::RoundRobinScheduler;
+use kernel::ReturnCode;
+use mock::MockUart;
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+unsafe impl MemoryAllocationCapability for Cap {}
+
+const WRITE_CALLBACK: usize = 0x1000;
+const READ_CALLBACK: usize = 0x2000;
+
+fn allow(subdriver_number: usize, address: usize, size: usize) -> Action {
+    Action::Allow {
+        driver_number: capsules::console::DRIVER_NUM,
+        subdriver_number: subdriver_number,
+        allow_address: address as *mut u8,
+        allow_size: size,
+    }
+}
+
+fn allow_readonly(subdriver_number: usize, address: usize, size: usize) -> Action {
+    Action::AllowReadOnly {
+        driver_number: capsules::console::DRIVER_NUM,
+        subdriver_number: subdriver_number,
+        allow_address: address as *const u8,
+        allow_size: size,
+    }
+}
+
+fn subscribe(subdriver_number: usize, callback_ptr: usize) -> Action {
+    Action::Subscribe {
+        driver_number: capsules::console::DRIVER_NUM,
+        subdriver_number: subdriver_number,
+        callback_ptr: callback_ptr,
+        appdata: 0,
+    }
+}
+
+fn command(subdriver_number: usize, len: usize) -> Action {
+    Action::Command {
+        driver_number: capsules::console::DRIVER_NUM,
+        subdriver_number: subdriver_number,
+        arg0: len,
+        arg1: 0,
+    }
+}
+
+/// An app that writes `data` and writes the arguments of the write callback
+/// to `log`.
+fn writer(name: &str, data: &'static [u8], log: &Rc<RefCell<Vec<usize>>>) -> SimApp {
+    let log = log.clone();
+    let mut next = VecDeque::new();
+    SimApp::new(name, move |event| {
+        match event {
+            Event::Start { mem_start, .. } => {
+                let buffer = unsafe { slice::from_raw_parts_mut(mem_start as *mut u8, 128) };
+                buffer[..data.len()].copy_from_slice(data);
+                next.push_back(allow(1, mem_start, data.len()));
+                next.push_back(subscribe(1, WRITE_CALLBACK));
+                next.push_back(command(1, data.len()));
+            }
+            Event::Callback {
+                pc: WRITE_CALLBACK,
+                arguments: [written, _, _, _],
+            } => log.borrow_mut().push(written),
+            _ => {}
+        }
+        next.pop_front().unwrap_or(Action::Yield)
+    })
+}
+
+/// An app that reads `len` bytes and writes the return code and the bytes it
+/// received to `log`.
+fn reader(len: usize, log: &Rc<RefCell<Vec<(usize, Vec<u8>)>>>) -> SimApp {
+    let log = log.clone();
+    let mut mem_start = 0;
+    let mut next = VecDeque::new();
+    SimApp::new("reader", move |event| {
+        match event {
+            Event::Start { mem_start: start, .. } => {
+                mem_start = start;
+                next.push_back(allow(2, mem_start, 16));
+                next.push_back(subscribe(2, READ_CALLBACK));
+                next.push_back(command(2, len));
+            }
+            Event::Callback {
+                pc: READ_CALLBACK,
+                arguments: [rc, len, _, _],
+            } => {
+                let buffer = unsafe { slice::from_raw_parts(mem_start as *const u8, len) };
+                log.borrow_mut().push((rc, buffer.to_vec()));
+            }
+            _ => {}
+        }
+        next.pop_front().unwrap_or(Action::Yield)
+    })
+}
+
+/// Boot a kernel with a console on a mock UART, and load `apps`.
+fn boot_console(
+    apps: Vec<SimApp>,
+) -> (
+    &'static kernel::Kernel,
+    &'static host::SimChip,
+    &'static MockUart,
+    &'static Console<'static, MockUart>,
+) {
+    let uart: &'static MockUart = mock::leak(MockUart::new());
+    let (kernel, chip, _, console) = host::boot_with(apps, FaultResponse::Panic, |kernel| {
+        let console: &'static Console<'static, MockUart> = mock::leak(Console::new(
+            uart,
+            115200,
+            mock::leak([0; 64]),
+            mock::leak([0; 64]),
+            kernel.create_grant(&Cap),
+        ));
+        uart.set_client(console);
+        console.initialize();
+        console
+    });
+    (kernel, chip, uart, console)
+}
+
+#[test]
+fn writes_and_reads() {
+    static LONG: &'static [u8] =
+        b"A message that is longer than the transmit buffer of the console, \
+          so it is sent in two parts.";
+    let long = Rc::new(RefCell::new(Vec::new()));
+    let short = Rc::new(RefCell::new(Vec::new()));
+    let read = Rc::new(RefCell::new(Vec::new()));
+    let apps = vec![
+        writer("long", LONG, &long),
+        writer("short", b"hi", &short),
+        reader(5, &read),
+    ];
+
+    let (kernel, chip, uart, console) = boot_console(apps);
+    assert_eq!(uart.parameters().unwrap().baud_rate, 115200);
+    let mut platform = SimPlatform::new();
+    platform.add_driver(capsules::console::DRIVER_NUM, console);
+    let scheduler = RoundRobinScheduler::new(10000);
+    let run = || {
+        assert!(host::run_until_idle(
+            kernel,
+            &platform,
+            chip,
+            None,
+            &scheduler,
+            10,
+            &Cap
+        ))
+    };
+
+    run();
+    assert!(uart.is_transmitting());
+    assert!(uart.is_receiving());
+    uart.inject_rx(b"hello, world");
+    let mut interrupts = 0;
+    while uart.is_transmitting() || uart.is_receiving() {
+        uart.handle_interrupt();
+        run();
+        interrupts += 1;
+    }
+
+    // The long message takes two transmissions, and the short one waits for
+    // it to finish.
+    assert_eq!(interrupts, 3);
+    let mut expected = LONG.to_vec();
+    expected.extend_from_slice(b"hi");
+    assert_eq!(uart.take_tx(), expected);
+    assert_eq!(*long.borrow(), vec![LONG.len()]);
+    assert_eq!(*short.borrow(), vec![2]);
+    assert_eq!(*read.borrow(), vec![(0, b"hello".to_vec())]);
+}
+
+#[test]
+fn writes_read_only_buffers_from_flash_and_memory() {
+    static FLASH_TEXT: &'static [u8] = b"from flash, ";
+    static RAM_TEXT: &'static [u8] = b"from memory";
+    static OUTSIDE: [u8; 4] = [0; 4];
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let app = {
+        let log = log.clone();
+        let mut next = VecDeque::new();
+        SimApp::new("readonly", move |event| {
+            match event {
+                Event::Start {
+                    text_start,
+                    mem_start,
+                    ..
+                } => {
+                    let buffer = unsafe { slice::from_raw_parts_mut(mem_start as *mut u8, 16) };
+                    buffer[..RAM_TEXT.len()].copy_from_slice(RAM_TEXT);
+                    next.push_back(subscribe(1, WRITE_CALLBACK));
+                    next.push_back(allow_readonly(1, text_start + 4, FLASH_TEXT.len()));
+                    next.push_back(command(1, FLASH_TEXT.len()));
+                    next.push_back(Action::Yield);
+                    next.push_back(allow_readonly(1, mem_start, RAM_TEXT.len()));
+                    next.push_back(command(1, RAM_TEXT.len()));
+                    next.push_back(Action::Yield);
+                    next.push_back(allow_readonly(1, OUTSIDE.as_ptr() as usize, OUTSIDE.len()));
+                }
+                Event::Return(value) => log.borrow_mut().push(value),
+                Event::Callback {
+                    pc: WRITE_CALLBACK,
+                    arguments: [written, _, _, _],
+                } => log.borrow_mut().push(written as isize),
+                _ => {}
+            }
+            next.pop_front().unwrap_or(Action::Yield)
+        }).rodata(FLASH_TEXT)
+    };
+
+    let (kernel, chip, uart, console) = boot_console(vec![app]);
+    let mut platform = SimPlatform::new();
+    platform.add_driver(capsules::console::DRIVER_NUM, console);
+    let scheduler = RoundRobinScheduler::new(10000);
+    let run = || {
+        assert!(host::run_until_idle(
+            kernel,
+            &platform,
+            chip,
+            None,
+            &scheduler,
+            10,
+            &Cap
+        ))
+    };
+
+    run();
+    while uart.is_transmitting() {
+        uart.handle_interrupt();
+        run();
+    }
+
+    let mut expected = FLASH_TEXT.to_vec();
+    expected.extend_from_slice(RAM_TEXT);
+    assert_eq!(uart.take_tx(), expected);
+    // Subscribe, allow and command return 0, and each write calls back with
+    // the number of bytes written. A buffer outside the process is refused.
+    let einval = isize::from(ReturnCode::EINVAL);
+    assert_eq!(
+        *log.borrow(),
+        vec![
+            0,
+            0,
+            0,
+            FLASH_TEXT.len() as isize,
+            0,
+            0,
+            RAM_TEXT.len() as isize,
+            einval,
+        ]
+    );
+}
@@ -0,0 +1,148 @@
+//! Tests encoding and decoding crash records, and saving a record to the mock
+//! flash with `CrashLog` and loading it back after a "reboot".
+
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+extern crate mock;
+
+use capsules::crash_log::CrashLog;
+use host::SimChip;
+use kernel::capabilities::MemoryAllocationCapability;
+use kernel::debug::{
+    self, CrashHandler, CrashKind, CrashRecord, DebugWriter, DebugWriterWrapper, FaultRegisters,
+    MAX_CRASH_RECORD_LEN,
+};
+use kernel::hil::flash::HasClient;
+use kernel::hil::uart::UART;
+use kernel::Chip;
+use mock::flash::PAGE_SIZE;
+use mock::{MockFlash, MockFlashPage, MockUart};
+
+struct Cap;
+unsafe impl MemoryAllocationCapability for Cap {}
+
+fn record() -> CrashRecord<'static> {
+    CrashRecord {
+        kind: CrashKind::ProcessFault,
+        registers: FaultRegisters {
+            pc: 0x0003_0f21,
+            lr: 0x0003_0e11,
+            cfsr: 0x0000_0082,
+            hfsr: 0x4000_0000,
+            mmfar: 0x2000_4000,
+            bfar: 0xe000_ed38,
+        },
+        kernel_version: "v1.2",
+        file: "kernel/src/process.rs",
+        line: 812,
+        process_name: "blink",
+        message: "Process blink had a fault",
+    }
+}
+
+#[test]
+fn record_round_trip() {
+    let mut buffer = [0xff; PAGE_SIZE];
+    let len = record().encode(&mut buffer);
+    assert_eq!(len, record().encoded_len());
+    assert_eq!(CrashRecord::decode(&buffer[..len]), Some(record()));
+    // The rest of the page does not matter.
+    assert_eq!(CrashRecord::decode(&buffer), Some(record()));
+}
+
+#[test]
+fn long_strings_are_truncated() {
+    let kernel_version = "v".repeat(40);
+    let file = "f".repeat(100);
+    // The 32 byte limit falls in the middle of the last "é" that would fit.
+    let process_name = format!("a{}", "é".repeat(20));
+    let message = "m".repeat(200);
+    let long = CrashRecord {
+        kernel_version: &kernel_version,
+        file: &file,
+        process_name: &process_name,
+        message: &message,
+        ..record()
+    };
+
+    let mut buffer = [0xff; PAGE_SIZE];
+    let len = long.encode(&mut buffer);
+    assert_eq!(len, MAX_CRASH_RECORD_LEN - 1);
+    let decoded = CrashRecord::decode(&buffer).unwrap();
+    assert_eq!(decoded.kernel_version, &kernel_version[..32]);
+    assert_eq!(decoded.file, &file[..64]);
+    assert_eq!(decoded.process_name, &process_name[..31]);
+    assert_eq!(decoded.message, &message[..128]);
+    assert_eq!(decoded.registers, long.registers);
+    assert_eq!(decoded.line, long.line);
+
+    // Strings are cut further to fit a short buffer, which still holds a
+    // valid record.
+    let mut short = [0xff; 60];
+    assert_eq!(long.encode(&mut short), 60);
+    let decoded = CrashRecord::decode(&short).unwrap();
+    assert_eq!(decoded.kernel_version, &kernel_version[..20]);
+    assert_eq!(decoded.file, "");
+    assert_eq!(decoded.message, "");
+
+    // A buffer without room for the header is left alone.
+    assert_eq!(long.encode(&mut [0xff; 39]), 0);
+}
+
+#[test]
+fn erased_page_has_no_record() {
+    assert_eq!(CrashRecord::decode(&[0xff; PAGE_SIZE]), None);
+    // A freshly programmed storage volume is all zeros.
+    assert_eq!(CrashRecord::decode(&[0; PAGE_SIZE]), None);
+
+    // A record cut short is not valid either.
+    let mut buffer = [0xff; PAGE_SIZE];
+    let len = record().encode(&mut buffer);
+    assert_eq!(CrashRecord::decode(&buffer[..len - 1]), None);
+}
+
+#[test]
+fn save_and_load_through_flash() {
+    let kernel = mock::boot();
+    let chip: &'static SimChip = mock::leak(SimChip::new());
+    let flash: &'static MockFlash = mock::leak(MockFlash::new(2));
+    let crash_log: &'static CrashLog<'static, MockFlash, SimChip> = mock::leak(CrashLog::new(
+        flash,
+        chip,
+        1,
+        mock::leak(MockFlashPage::default()),
+        kernel.create_grant(&Cap),
+    ));
+    flash.set_client(crash_log);
+
+    // The capsule saves the record during a panic, so it services the
+    // interrupt that completes the write itself.
+    chip.raise_interrupt(move || flash.handle_interrupt());
+    crash_log.save(&record());
+    assert!(!flash.is_busy());
+    assert!(!chip.has_pending_interrupts());
+    assert!(flash.page(0).iter().all(|&b| b == 0xff));
+    assert_eq!(CrashRecord::decode(&flash.page(1)), Some(record()));
+
+    // After the reboot, loading the record prints it on the debug console.
+    let uart: &'static MockUart = mock::leak(MockUart::new());
+    let writer: &'static DebugWriter = mock::leak(DebugWriter::new(
+        uart,
+        mock::leak([0; 64]),
+        mock::leak([0; 1024]),
+    ));
+    uart.set_client(writer);
+    unsafe {
+        debug::set_debug_writer_wrapper(mock::leak(DebugWriterWrapper::new(writer)));
+    }
+
+    assert_eq!(crash_log.load(), kernel::ReturnCode::SUCCESS);
+    flash.handle_interrupt();
+    while uart.is_transmitting() {
+        uart.handle_interrupt();
+    }
+    let output = String::from_utf8(uart.take_tx()).unwrap();
+    assert!(output.contains("Last crash"), "{}", output);
+    assert!(output.contains(&format!("{}", record())), "{}", output);
+}
@@ -0,0 +1,85 @@
+//! Tests that `ExtendedAlarm` counts every wrap of 32-bit and 24-bit counters,
+//! so that its 64-bit time never jumps, and fires alarms that are several
+//! wraps away at the right time.
+
+extern crate capsules;
+extern crate kernel;
+extern crate mock;
+
+use std::cell::Cell;
+
+use capsules::extended_alarm::ExtendedAlarm;
+use kernel::hil::time::{self, Alarm, Alarm64, Freq32KHz};
+use mock::MockAlarm;
+
+struct Recorder {
+    alarm: Cell<Option<&'static ExtendedAlarm<'static, MockAlarm<Freq32KHz>>>>,
+    fired_at: Cell<Option<u64>>,
+}
+
+impl time::Client for Recorder {
+    fn fired(&self) {
+        self.fired_at.set(self.alarm.get().map(|alarm| alarm.now64()));
+    }
+}
+
+/// Start an extended alarm on a `bits` wide counter just before it wraps, set
+/// an alarm three and a half wraps later, and move time forward one
+/// underlying alarm at a time.
+fn alarm_several_wraps_away(bits: u32) {
+    let wrap = 1u64 << bits;
+    let counter: &'static MockAlarm<Freq32KHz> = mock::leak(MockAlarm::with_width(bits));
+    counter.set_now((wrap - 16) as u32);
+    let alarm = mock::leak(ExtendedAlarm::new(counter, bits));
+    let recorder = mock::leak(Recorder {
+        alarm: Cell::new(Some(alarm)),
+        fired_at: Cell::new(None),
+    });
+    counter.set_client(alarm);
+    alarm.set_client(recorder);
+    alarm.start();
+
+    let start = alarm.now64();
+    assert_eq!(start, wrap - 16);
+    let when = start + 3 * wrap + wrap / 2;
+    alarm.set_alarm64(when);
+
+    let mut expected = start;
+    let mut interrupts = 0;
+    while recorder.fired_at.get().is_none() {
+        // The underlying alarm is never set more than half a wrap ahead, so
+        // no wrap goes unnoticed.
+        let ahead = counter.get_alarm().wrapping_sub(counter.now()) as u64 & (wrap - 1);
+        assert!(ahead > 0 && ahead < wrap / 2, "alarm set {} ticks ahead", ahead);
+        expected += ahead;
+        assert!(counter.advance_to_alarm());
+        assert_eq!(alarm.now64(), expected);
+        interrupts += 1;
+    }
+
+    assert_eq!(recorder.fired_at.get(), Some(when));
+    // A check every quarter of a wrap.
+    assert_eq!(interrupts, 14);
+}
+
+#[test]
+fn counts_wraps_of_32_bit_counter() {
+    alarm_several_wraps_away(32);
+}
+
+#[test]
+fn counts_wraps_of_24_bit_counter() {
+    alarm_several_wraps_away(24);
+}
+
+#[test]
+fn reading_the_time_notices_a_wrap() {
+    let counter: &'static MockAlarm<Freq32KHz> = mock::leak(MockAlarm::with_width(24));
+    let alarm = mock::leak(ExtendedAlarm::new(counter, 24));
+    counter.set_client(alarm);
+    counter.set_now(0xff_fff0);
+    alarm.start();
+
+    counter.set_now(0x10);
+    assert_eq!(alarm.now64(), 0x100_0010);
+}
@@ -0,0 +1,87 @@
+//! Tests reads and writes that are not aligned to pages through
+//! `NonvolatileToPages` on the mock flash.
+
+extern crate capsules;
+extern crate kernel;
+extern crate mock;
+
+use std::cell::Cell;
+
+use capsules::nonvolatile_to_pages::NonvolatileToPages;
+use kernel::common::cells::TakeCell;
+use kernel::hil::flash::HasClient;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use mock::flash::PAGE_SIZE;
+use mock::{MockFlash, MockFlashPage};
+
+struct Client {
+    buffer: TakeCell<'static, [u8]>,
+    read: Cell<Option<usize>>,
+    written: Cell<Option<usize>>,
+}
+
+impl NonvolatileStorageClient for Client {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        self.read.set(Some(length));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        self.written.set(Some(length));
+    }
+}
+
+fn complete(flash: &MockFlash) {
+    let mut operations = 0;
+    while flash.is_busy() {
+        flash.handle_interrupt();
+        operations += 1;
+        assert!(operations < 100, "flash operation never completed");
+    }
+}
+
+#[test]
+fn unaligned_write_and_read_back() {
+    let flash: &'static MockFlash = mock::leak(MockFlash::new(4));
+    let nv: &'static NonvolatileToPages<'static, MockFlash> = mock::leak(
+        NonvolatileToPages::new(flash, mock::leak(MockFlashPage::default())),
+    );
+    flash.set_client(nv);
+    let client = mock::leak(Client {
+        buffer: TakeCell::new(mock::leak([0; 2 * PAGE_SIZE])),
+        read: Cell::new(None),
+        written: Cell::new(None),
+    });
+    nv.set_client(client);
+
+    // Write from the end of page 0 over all of page 1 into page 2.
+    let address = PAGE_SIZE - 12;
+    let length = PAGE_SIZE + 40;
+    let buffer = client.buffer.take().unwrap();
+    for (i, byte) in buffer.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    assert_eq!(nv.write(buffer, address, length), kernel::ReturnCode::SUCCESS);
+    complete(flash);
+    assert_eq!(client.written.get(), Some(length));
+
+    // The bytes around the write are still erased.
+    let page0 = flash.page(0);
+    assert!(page0[..PAGE_SIZE - 12].iter().all(|&b| b == 0xff));
+    assert_eq!(page0[PAGE_SIZE - 12], 0);
+    assert_eq!(flash.page(1)[0], 12);
+    let page2 = flash.page(2);
+    assert_eq!(page2[27], (length - 1) as u8);
+    assert!(page2[28..].iter().all(|&b| b == 0xff));
+
+    let buffer = client.buffer.take().unwrap();
+    for byte in buffer.iter_mut() {
+        *byte = 0;
+    }
+    assert_eq!(nv.read(buffer, address, length), kernel::ReturnCode::SUCCESS);
+    complete(flash);
+    assert_eq!(client.read.get(), Some(length));
+    let buffer = client.buffer.take().unwrap();
+    assert!((0..length).all(|i| buffer[i] == i as u8));
+}
@@ -0,0 +1,89 @@
+//! Tests that `MuxI2C` runs the transactions of several devices one at a
+//! time on the mock I2C master.
+
+extern crate capsules;
+extern crate kernel;
+extern crate mock;
+
+use std::cell::RefCell;
+
+use capsules::virtual_i2c::{I2CDevice, MuxI2C};
+use kernel::hil::i2c::{self, I2CClient};
+use mock::{I2CTransaction, MockI2C};
+
+/// Records the buffers and errors of completed commands.
+struct Client {
+    completed: RefCell<Vec<(Vec<u8>, i2c::Error)>>,
+}
+
+impl I2CClient for Client {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        self.completed.borrow_mut().push((buffer.to_vec(), error));
+    }
+}
+
+fn device(
+    mux: &'static MuxI2C<'static>,
+    addr: u8,
+) -> (&'static I2CDevice<'static>, &'static Client) {
+    let device = mock::leak(I2CDevice::new(mux, addr));
+    let client = mock::leak(Client {
+        completed: RefCell::new(Vec::new()),
+    });
+    device.set_client(client);
+    (device, client)
+}
+
+#[test]
+fn devices_share_the_bus() {
+    use kernel::hil::i2c::I2CDevice;
+
+    let i2c: &'static MockI2C = mock::leak(MockI2C::new());
+    let mux: &'static MuxI2C<'static> = mock::leak(MuxI2C::new(i2c));
+    i2c.set_master_client(mux);
+    let (sensor, sensor_client) = device(mux, 0x40);
+    let (other, other_client) = device(mux, 0x18);
+
+    sensor.enable();
+    other.enable();
+    assert!(i2c.is_enabled());
+
+    i2c.push_response(&[0x12, 0x34]);
+    sensor.write_read(mock::leak([0xe3, 0, 0]), 1, 2);
+    other.write(mock::leak([0x01, 0x02]), 2);
+    // The second device waits until the first transaction is done.
+    assert_eq!(
+        i2c.take_transactions(),
+        vec![I2CTransaction {
+            addr: 0x40,
+            write: vec![0xe3],
+            read_len: 2,
+        }]
+    );
+    i2c.handle_interrupt();
+    i2c.inject_error(i2c::Error::AddressNak);
+    i2c.handle_interrupt();
+    assert!(!i2c.is_busy());
+
+    assert_eq!(
+        i2c.take_transactions(),
+        vec![I2CTransaction {
+            addr: 0x18,
+            write: vec![0x01, 0x02],
+            read_len: 0,
+        }]
+    );
+    assert_eq!(
+        *sensor_client.completed.borrow(),
+        vec![(vec![0x12, 0x34, 0], i2c::Error::CommandComplete)]
+    );
+    assert_eq!(
+        *other_client.completed.borrow(),
+        vec![(vec![0x01, 0x02], i2c::Error::AddressNak)]
+    );
+
+    sensor.disable();
+    assert!(i2c.is_enabled());
+    other.disable();
+    assert!(!i2c.is_enabled());
+}
@@ -0,0 +1,199 @@
+//! Tests how the IPv6 receiver skips extension headers: which headers and
+//! options it skips, which packets it drops, and the reason it counts for
+//! each dropped packet.
+
+extern crate capsules;
+extern crate kernel;
+
+use std::cell::RefCell;
+
+use capsules::net::ipv6::ip_utils::ip6_nh;
+use capsules::net::ipv6::ipv6::IP6Header;
+use capsules::net::ipv6::ipv6_recv::{DropReason, IP6RecvClient, IP6RecvStruct, IP6Receiver};
+use capsules::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use kernel::ReturnCode;
+
+const REASONS: [DropReason; 5] = [
+    DropReason::RxError,
+    DropReason::Malformed,
+    DropReason::Checksum,
+    DropReason::Unsupported,
+    DropReason::NoClient,
+];
+
+// Option types. The two highest bits of an option type say what to do with a
+// packet if the option is not recognized: 00 means skipping the option, and
+// 01 means discarding the packet.
+const PAD1: u8 = 0;
+const PADN: u8 = 1;
+const SKIPPABLE: u8 = 0x1e;
+const DISCARD: u8 = 0x5e;
+
+/// Records the header and payload of every packet it receives.
+struct Recorder {
+    packets: RefCell<Vec<(u8, u16, Vec<u8>)>>,
+}
+
+impl IP6RecvClient for Recorder {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        self.packets.borrow_mut().push((
+            header.get_next_header(),
+            header.get_payload_len(),
+            payload.to_vec(),
+        ));
+    }
+}
+
+/// An IPv6 packet with next header `next_header`, followed by `payload`.
+fn packet(next_header: u8, payload: &[u8]) -> Vec<u8> {
+    let mut header = IP6Header::new();
+    header.set_next_header(next_header);
+    header.set_payload_len(payload.len() as u16);
+    let mut buf = vec![0; 40];
+    assert!(header.encode(&mut buf).is_done());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+fn receive(recv: &IP6RecvStruct, buf: &[u8]) {
+    recv.receive(buf, buf.len(), ReturnCode::SUCCESS);
+}
+
+fn drop_counts(recv: &IP6RecvStruct) -> Vec<u32> {
+    REASONS.iter().map(|&r| recv.get_drop_count(r)).collect()
+}
+
+/// Check that receiving `buf` drops it for `reason`, and only counts that
+/// reason.
+fn assert_dropped(recv: &IP6RecvStruct, recorder: &Recorder, buf: &[u8], reason: DropReason) {
+    let mut expected = drop_counts(recv);
+    expected[reason as usize] += 1;
+    receive(recv, buf);
+    assert_eq!(drop_counts(recv), expected, "dropping {:?}", reason);
+    assert!(recorder.packets.borrow().is_empty());
+}
+
+fn recorder() -> Recorder {
+    Recorder {
+        packets: RefCell::new(Vec::new()),
+    }
+}
+
+#[test]
+fn skips_extension_headers() {
+    let recorder = recorder();
+    let recv = IP6RecvStruct::new();
+    recv.set_raw_client(&recorder);
+
+    let mut payload = Vec::new();
+    // Hop-by-hop options with a PadN option that fills the header exactly.
+    payload.extend_from_slice(&[ip6_nh::DST_OPTS, 0, PADN, 4, 0, 0, 0, 0]);
+    // Destination options with an option we may skip, Pad1 options and a
+    // PadN option without data, in 16 octets.
+    payload.extend_from_slice(&[ip6_nh::ROUTING, 1, SKIPPABLE, 4, 1, 2, 3, 4]);
+    payload.extend_from_slice(&[PAD1, PAD1, PAD1, PAD1, PAD1, PAD1, PADN, 0]);
+    // A routing header with no segments left.
+    payload.extend_from_slice(&[ip6_nh::NO_NEXT, 0, 0, 0, 0, 0, 0, 0]);
+    payload.extend_from_slice(&[0xaa, 0xbb]);
+    receive(&recv, &packet(ip6_nh::HOP_OPTS, &payload));
+
+    assert_eq!(
+        *recorder.packets.borrow(),
+        vec![(ip6_nh::NO_NEXT, 2, vec![0xaa, 0xbb])]
+    );
+    assert_eq!(drop_counts(&recv), vec![0; REASONS.len()]);
+}
+
+#[test]
+fn drops_truncated_extension_headers() {
+    let recorder = recorder();
+    let recv = IP6RecvStruct::new();
+    recv.set_raw_client(&recorder);
+
+    // Shorter than the first 8 octets of every extension header.
+    let buf = packet(ip6_nh::HOP_OPTS, &[ip6_nh::NO_NEXT, 0, PADN, 2]);
+    assert_dropped(&recv, &recorder, &buf, DropReason::Malformed);
+    // Shorter than its length says.
+    let buf = packet(ip6_nh::DST_OPTS, &[ip6_nh::NO_NEXT, 1, PADN, 4, 0, 0, 0, 0]);
+    assert_dropped(&recv, &recorder, &buf, DropReason::Malformed);
+    // A second header that is cut off.
+    let buf = packet(
+        ip6_nh::DST_OPTS,
+        &[ip6_nh::ROUTING, 0, PADN, 4, 0, 0, 0, 0, ip6_nh::NO_NEXT, 0],
+    );
+    assert_dropped(&recv, &recorder, &buf, DropReason::Malformed);
+}
+
+#[test]
+fn drops_hop_by_hop_options_that_are_not_first() {
+    let recorder = recorder();
+    let recv = IP6RecvStruct::new();
+    recv.set_raw_client(&recorder);
+
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&[ip6_nh::HOP_OPTS, 0, PADN, 4, 0, 0, 0, 0]);
+    payload.extend_from_slice(&[ip6_nh::NO_NEXT, 0, PADN, 4, 0, 0, 0, 0]);
+    let buf = packet(ip6_nh::DST_OPTS, &payload);
+    assert_dropped(&recv, &recorder, &buf, DropReason::Malformed);
+}
+
+#[test]
+fn drops_bad_options() {
+    let recorder = recorder();
+    let recv = IP6RecvStruct::new();
+    recv.set_raw_client(&recorder);
+
+    // A PadN option that runs past the end of the header.
+    let buf = packet(ip6_nh::HOP_OPTS, &[ip6_nh::NO_NEXT, 0, PADN, 5, 0, 0, 0, 0]);
+    assert_dropped(&recv, &recorder, &buf, DropReason::Malformed);
+    // An option without room for its length.
+    let buf = packet(
+        ip6_nh::DST_OPTS,
+        &[ip6_nh::NO_NEXT, 0, PAD1, PAD1, PAD1, PAD1, PAD1, SKIPPABLE],
+    );
+    assert_dropped(&recv, &recorder, &buf, DropReason::Malformed);
+    // An option that must be understood to process the packet.
+    let buf = packet(ip6_nh::DST_OPTS, &[ip6_nh::NO_NEXT, 0, DISCARD, 4, 0, 0, 0, 0]);
+    assert_dropped(&recv, &recorder, &buf, DropReason::Unsupported);
+}
+
+#[test]
+fn drops_routing_and_fragment_headers_it_cannot_handle() {
+    let recorder = recorder();
+    let recv = IP6RecvStruct::new();
+    recv.set_raw_client(&recorder);
+
+    // A routing header with segments left asks us to forward the packet.
+    let buf = packet(ip6_nh::ROUTING, &[ip6_nh::NO_NEXT, 0, 0, 1, 0, 0, 0, 0]);
+    assert_dropped(&recv, &recorder, &buf, DropReason::Unsupported);
+    // Fragments are not reassembled, even behind another header.
+    let fragment = [ip6_nh::NO_NEXT, 0, 0, 1, 0, 0, 0, 1];
+    let buf = packet(ip6_nh::FRAGMENT, &fragment);
+    assert_dropped(&recv, &recorder, &buf, DropReason::Unsupported);
+    let mut payload = vec![ip6_nh::FRAGMENT, 0, PADN, 4, 0, 0, 0, 0];
+    payload.extend_from_slice(&fragment);
+    let buf = packet(ip6_nh::HOP_OPTS, &payload);
+    assert_dropped(&recv, &recorder, &buf, DropReason::Unsupported);
+}
+
+#[test]
+fn counts_drop_reasons() {
+    let recorder = recorder();
+    let recv = IP6RecvStruct::new();
+
+    let buf = packet(ip6_nh::NO_NEXT, &[]);
+    assert_dropped(&recv, &recorder, &buf, DropReason::NoClient);
+    recv.receive(&buf, buf.len(), ReturnCode::FAIL);
+    recv.receive(&buf, buf.len() + 1, ReturnCode::SUCCESS);
+    assert_eq!(recv.get_drop_count(DropReason::RxError), 2);
+    assert_dropped(&recv, &recorder, &buf[..39], DropReason::Malformed);
+
+    recv.set_raw_client(&recorder);
+    // A UDP packet whose checksum is wrong.
+    let buf = packet(ip6_nh::UDP, &[0, 1, 0, 2, 0, 8, 0, 0]);
+    assert_dropped(&recv, &recorder, &buf, DropReason::Checksum);
+    assert_eq!(drop_counts(&recv), vec![2, 1, 1, 0, 1]);
+
+    recv.reset_drop_counts();
+    assert_eq!(drop_counts(&recv), vec![0; REASONS.len()]);
+}
@@ -0,0 +1,224 @@
+//! Tests the kernel log on the debug writer of a mock UART: filtering by level
+//! and module, the text format, the binary record layout that
+//! `tools/log_decode.py` decodes, and dropping messages that do not fit.
+
+#[macro_use]
+extern crate kernel;
+extern crate mock;
+
+use std::cell::Cell;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use kernel::debug::{self, DebugWriter, DebugWriterWrapper};
+use kernel::hil::uart::UART;
+use kernel::log::{self, Arg, Format, Level, LogSite, Logger};
+use mock::MockUart;
+
+/// The logger and the debug writer are global, so the tests take turns.
+static LOCKED: AtomicBool = AtomicBool::new(false);
+
+struct Lock;
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        LOCKED.store(false, Ordering::SeqCst);
+    }
+}
+
+fn lock() -> Lock {
+    while LOCKED.compare_and_swap(false, true, Ordering::SeqCst) {
+        thread::yield_now();
+    }
+    Lock
+}
+
+static NO_FILTERS: [(&str, Option<Level>); 0] = [];
+
+/// Set up a debug writer with a `buffer_len` byte buffer on a mock UART, and
+/// a logger for it.
+fn setup(
+    max_level: Option<Level>,
+    filters: &'static [(&'static str, Option<Level>)],
+    format: Format,
+    buffer_len: usize,
+) -> (&'static MockUart, &'static Logger) {
+    let uart: &'static MockUart = mock::leak(MockUart::new());
+    let writer: &'static DebugWriter = mock::leak(DebugWriter::new(
+        uart,
+        mock::leak([0; 64]),
+        Box::leak(vec![0; buffer_len].into_boxed_slice()),
+    ));
+    uart.set_client(writer);
+    let logger: &'static Logger = mock::leak(Logger::new(max_level, filters, format));
+    unsafe {
+        debug::set_debug_writer_wrapper(mock::leak(DebugWriterWrapper::new(writer)));
+        log::set_logger(logger);
+    }
+    (uart, logger)
+}
+
+/// Returns everything the debug writer sent.
+fn output(uart: &MockUart) -> Vec<u8> {
+    while uart.is_transmitting() {
+        uart.handle_interrupt();
+    }
+    uart.take_tx()
+}
+
+fn text(uart: &MockUart) -> String {
+    String::from_utf8(output(uart)).unwrap()
+}
+
+mod radio {
+    pub fn log() {
+        log_info!("radio info");
+        log_warn!("radio warning");
+    }
+}
+
+#[test]
+fn filters_by_level_and_module() {
+    let _lock = lock();
+    static FILTERS: [(&str, Option<Level>); 1] = [("logging::radio", Some(Level::Warn))];
+    let (uart, logger) = setup(Some(Level::Info), &FILTERS, Format::Text, 1024);
+
+    let evaluated = Cell::new(0);
+    let arg = || {
+        evaluated.set(evaluated.get() + 1);
+        7u32
+    };
+    log_info!("info {}", arg());
+    log_debug!("debug {}", arg());
+    radio::log();
+    assert_eq!(
+        text(uart),
+        "[INFO logging] info 7\r\n[WARN logging::radio] radio warning\r\n"
+    );
+    // The arguments of a message that is filtered out are not evaluated.
+    assert_eq!(evaluated.get(), 1);
+
+    logger.set_max_level(None);
+    log_error!("off");
+    assert_eq!(text(uart), "");
+}
+
+#[test]
+fn text_format_expands_placeholders() {
+    let _lock = lock();
+    let (uart, _) = setup(Some(Level::Trace), &NO_FILTERS, Format::Text, 1024);
+
+    log_info!("{{escaped}} {{{}}} }}", 1u8);
+    log_info!("{:#x} {:x} {:X} {}", 255u32, 255u32, 255u32, -1i8);
+    log_info!("{} {} {}", true, 'c', "str");
+    assert_eq!(
+        text(uart),
+        "[INFO logging] {escaped} {1} }\r\n\
+         [INFO logging] 0xff ff FF -1\r\n\
+         [INFO logging] true c str\r\n"
+    );
+
+    // The macros do not allow a missing argument, but a decoder may see a
+    // format string that does not match the arguments.
+    static MISSING: LogSite = LogSite {
+        fmt: "a {} b {:x} c {}",
+        module_path: "test",
+        file: "",
+        line: 0,
+        level: Level::Info,
+    };
+    log::log(&MISSING, &[Arg::U32(1), Arg::U32(0xab)]);
+    assert_eq!(text(uart), "[INFO test] a 1 b ab c \r\n");
+}
+
+#[test]
+fn binary_record_layout() {
+    let _lock = lock();
+    let (uart, _) = setup(Some(Level::Trace), &NO_FILTERS, Format::Binary, 1024);
+
+    static SITE: LogSite = LogSite {
+        fmt: "{} {} {} {} {} {} {}",
+        module_path: "test",
+        file: "",
+        line: 0,
+        level: Level::Info,
+    };
+    log::log(
+        &SITE,
+        &[
+            Arg::U32(0x12345678),
+            Arg::I32(-2),
+            Arg::U64(0x1122334455667788),
+            Arg::I64(-1),
+            Arg::Bool(true),
+            Arg::Char('\u{e9}'),
+            Arg::Str("hi"),
+        ],
+    );
+
+    let address = &SITE as *const LogSite as usize as u32;
+    let mut expected = vec![0xa5, 0];
+    expected.extend_from_slice(&[
+        address as u8,
+        (address >> 8) as u8,
+        (address >> 16) as u8,
+        (address >> 24) as u8,
+    ]);
+    expected.push(7);
+    expected.extend_from_slice(&[0, 0x78, 0x56, 0x34, 0x12]);
+    expected.extend_from_slice(&[1, 0xfe, 0xff, 0xff, 0xff]);
+    expected.extend_from_slice(&[2, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11]);
+    expected.extend_from_slice(&[3, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    expected.extend_from_slice(&[4, 1]);
+    expected.extend_from_slice(&[5, 0xe9, 0, 0, 0]);
+    expected.extend_from_slice(&[6, 2, b'h', b'i']);
+    assert_eq!(output(uart), expected);
+}
+
+#[test]
+fn log_site_layout() {
+    // `tools/log_decode.py` reads sites from a 32-bit ELF, where the strings
+    // are at offsets 0, 8 and 16, the line at 24 and the level at 28. Check
+    // the same layout in units of the host's pointer size.
+    let word = mem::size_of::<usize>();
+    let site = LogSite {
+        fmt: "fmt",
+        module_path: "test",
+        file: file!(),
+        line: 1,
+        level: Level::Info,
+    };
+    let base = &site as *const LogSite as usize;
+    let offset = |field: usize| field - base;
+    assert_eq!(offset(&site.fmt as *const _ as usize), 0);
+    assert_eq!(offset(&site.module_path as *const _ as usize), 2 * word);
+    assert_eq!(offset(&site.file as *const _ as usize), 4 * word);
+    assert_eq!(offset(&site.line as *const _ as usize), 6 * word);
+    assert_eq!(offset(&site.level as *const _ as usize), 6 * word + 4);
+    assert_eq!(site.level as u8, 3);
+}
+
+#[test]
+fn full_buffer_drops_and_reports_messages() {
+    let _lock = lock();
+    let (uart, logger) = setup(Some(Level::Trace), &NO_FILTERS, Format::Text, 64);
+
+    // Each message takes 26 bytes, and the buffer holds 63 until the UART
+    // finishes sending.
+    for i in 0..4u32 {
+        log_info!("message {}", i);
+    }
+    assert_eq!(logger.dropped_count(), 2);
+    assert_eq!(
+        text(uart),
+        "[INFO logging] message 0\r\n[INFO logging] message 1\r\n"
+    );
+
+    log_info!("message {}", 4u32);
+    assert_eq!(
+        text(uart),
+        "[log] 2 messages dropped\r\n[INFO logging] message 4\r\n"
+    );
+    assert_eq!(logger.dropped_count(), 2);
+}
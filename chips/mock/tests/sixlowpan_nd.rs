@@ -0,0 +1,108 @@
+//! Tests that neighbor discovery sends one message at a time on its ICMPv6
+//! sender, and sends a message again when the sender was busy.
+
+extern crate capsules;
+extern crate kernel;
+extern crate mock;
+
+use std::cell::{Cell, RefCell};
+
+use capsules::net::icmpv6::icmpv6::ICMP6Header;
+use capsules::net::icmpv6::icmpv6_send::{ICMP6SendClient, ICMP6Sender};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::sixlowpan::sixlowpan_compression::{Context, ContextTable};
+use capsules::net::sixlowpan::sixlowpan_nd::SixlowpanND;
+use kernel::hil::time::Freq1KHz;
+use kernel::ReturnCode;
+use mock::MockAlarm;
+
+/// Router Solicitation
+const RS: u8 = 133;
+
+/// Records the type of every message it accepts, and refuses messages while
+/// `busy` is set, like a sender that another client is using.
+struct FakeSender {
+    busy: Cell<bool>,
+    sent: RefCell<Vec<u8>>,
+}
+
+impl ICMP6Sender<'static> for FakeSender {
+    fn set_client(&self, _client: &'static ICMP6SendClient) {}
+
+    fn send(&self, _dest: IPAddr, icmp_header: ICMP6Header, _buf: &[u8]) -> ReturnCode {
+        if self.busy.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.sent.borrow_mut().push(icmp_header.get_type_as_int());
+        ReturnCode::SUCCESS
+    }
+}
+
+type ND = SixlowpanND<'static, MockAlarm<Freq1KHz>>;
+
+fn setup() -> (&'static MockAlarm<Freq1KHz>, &'static FakeSender, &'static ND) {
+    let alarm: &'static MockAlarm<Freq1KHz> = mock::leak(MockAlarm::new());
+    let sender: &'static FakeSender = mock::leak(FakeSender {
+        busy: Cell::new(false),
+        sent: RefCell::new(Vec::new()),
+    });
+    let ip_senders: &'static [&'static IP6Sender<'static>] = &[];
+    let ctx_store = mock::leak(ContextTable::new(Context {
+        prefix: [0; 16],
+        prefix_len: 0,
+        id: 0,
+        compress: false,
+    }));
+    let interface_list = mock::leak([Cell::new(IPAddr::new()), Cell::new(IPAddr::new())]);
+    let nd: &'static ND = mock::leak(SixlowpanND::new(
+        alarm,
+        sender,
+        ip_senders,
+        ctx_store,
+        interface_list,
+        MacAddress::Short(0x1234),
+        [0; 8],
+    ));
+    alarm.set_client(nd);
+    (alarm, sender, nd)
+}
+
+#[test]
+fn sends_one_message_at_a_time() {
+    let (alarm, sender, nd) = setup();
+    nd.start();
+    assert_eq!(*sender.sent.borrow(), vec![RS]);
+
+    // The solicitation is repeated while the first one is still being sent,
+    // so it is sent once the first one is done.
+    assert!(alarm.advance_to_alarm());
+    assert_eq!(*sender.sent.borrow(), vec![RS]);
+    nd.send_done(ReturnCode::SUCCESS);
+    assert_eq!(*sender.sent.borrow(), vec![RS, RS]);
+    nd.send_done(ReturnCode::SUCCESS);
+    assert_eq!(*sender.sent.borrow(), vec![RS, RS]);
+}
+
+#[test]
+fn sends_again_when_sender_was_busy() {
+    let (alarm, sender, nd) = setup();
+    sender.busy.set(true);
+    nd.start();
+    assert!(sender.sent.borrow().is_empty());
+
+    // The message is sent when a message on the sender is done.
+    sender.busy.set(false);
+    nd.send_done(ReturnCode::SUCCESS);
+    assert_eq!(*sender.sent.borrow(), vec![RS]);
+    nd.send_done(ReturnCode::SUCCESS);
+
+    // Or when the timer runs out, if no send was done.
+    sender.busy.set(true);
+    assert!(alarm.advance_to_alarm());
+    sender.busy.set(false);
+    assert_eq!(*sender.sent.borrow(), vec![RS]);
+    assert!(alarm.advance_to_alarm());
+    assert_eq!(*sender.sent.borrow(), vec![RS, RS]);
+}
@@ -0,0 +1,85 @@
+//! Tests that `MuxSpiMaster` selects the chip of each device for its
+//! transfers on the mock SPI master.
+
+extern crate capsules;
+extern crate kernel;
+extern crate mock;
+
+use std::cell::RefCell;
+
+use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMaster, SpiMasterClient, SpiMasterDevice};
+use mock::{MockSpi, SpiTransaction};
+
+/// Records what was read in completed transfers.
+struct Client {
+    read: RefCell<Vec<Option<Vec<u8>>>>,
+}
+
+impl SpiMasterClient for Client {
+    fn read_write_done(
+        &self,
+        _write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) {
+        self.read
+            .borrow_mut()
+            .push(read_buffer.map(|buffer| buffer[..len].to_vec()));
+    }
+}
+
+type Device = VirtualSpiMasterDevice<'static, MockSpi>;
+
+fn device(
+    mux: &'static MuxSpiMaster<'static, MockSpi>,
+    cs: u8,
+) -> (&'static Device, &'static Client) {
+    let device = mock::leak(VirtualSpiMasterDevice::new(mux, cs));
+    let client = mock::leak(Client {
+        read: RefCell::new(Vec::new()),
+    });
+    device.set_client(client);
+    (device, client)
+}
+
+#[test]
+fn devices_use_their_chip_select() {
+    let spi: &'static MockSpi = mock::leak(MockSpi::new());
+    let mux: &'static MuxSpiMaster<'static, MockSpi> = mock::leak(MuxSpiMaster::new(spi));
+    spi.set_client(mux);
+    let (flash, flash_client) = device(mux, 1);
+    let (radio, radio_client) = device(mux, 2);
+
+    flash.configure(ClockPolarity::IdleHigh, ClockPhase::SampleTrailing, 4000000);
+    assert_eq!(spi.get_clock(), ClockPolarity::IdleHigh);
+    assert_eq!(spi.get_phase(), ClockPhase::SampleTrailing);
+    assert_eq!(spi.get_rate(), 4000000);
+
+    spi.push_response(&[0, 0xc2, 0x28]);
+    flash.read_write_bytes(mock::leak([0x9f, 0, 0]), Some(mock::leak([0; 3])), 3);
+    radio.read_write_bytes(mock::leak([0x80, 0x55]), None, 2);
+    // The radio waits until the transfer of the flash is done.
+    assert_eq!(
+        spi.take_transactions(),
+        vec![SpiTransaction {
+            chip_select: Some(1),
+            write: vec![0x9f, 0, 0],
+            read: true,
+        }]
+    );
+    spi.handle_interrupt();
+    assert_eq!(
+        spi.take_transactions(),
+        vec![SpiTransaction {
+            chip_select: Some(2),
+            write: vec![0x80, 0x55],
+            read: false,
+        }]
+    );
+    spi.handle_interrupt();
+    assert!(!spi.is_busy());
+
+    assert_eq!(*flash_client.read.borrow(), vec![Some(vec![0, 0xc2, 0x28])]);
+    assert_eq!(*radio_client.read.borrow(), vec![None]);
+}
@@ -0,0 +1,343 @@
+//! Tests the TCP connection state machine by passing segments between two
+//! connections: opening, retransmitting, probing a closed window, closing and
+//! resetting connections, and sequence numbers that wrap around.
+
+extern crate capsules;
+
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::tcp::tcp::{tcp_flags, TCPHeader};
+use capsules::net::tcp::tcp_state::{
+    self, tcp_event, Received, Segment, TCPConnection, TCPEndpoint, TCPState, MAX_RETRIES,
+};
+
+const MSS: usize = 200;
+
+struct Peer {
+    connection: TCPConnection,
+    endpoint: TCPEndpoint,
+    /// Room in the receive buffer.
+    rcv_wnd: usize,
+}
+
+impl Peer {
+    fn new(addr: u8, port: u16) -> Peer {
+        let mut ip = IPAddr::new();
+        ip.set_unicast_link_local();
+        ip.0[15] = addr;
+        Peer {
+            connection: TCPConnection::new(),
+            endpoint: TCPEndpoint {
+                addr: ip,
+                port: port,
+            },
+            rcv_wnd: 1000,
+        }
+    }
+
+    fn connect(&mut self, remote: &Peer, iss: u32) {
+        self.connection
+            .connect(self.endpoint, remote.endpoint, iss, MSS);
+    }
+
+    fn next_segment(&mut self) -> Option<Segment> {
+        self.connection.next_segment(self.rcv_wnd)
+    }
+
+    fn state(&self) -> TCPState {
+        self.connection.get_state()
+    }
+}
+
+/// Hand a segment from `from` to `to`.
+fn deliver(from: &Peer, to: &mut Peer, segment: &Segment) -> Received {
+    to.connection.receive(
+        from.endpoint.addr,
+        &segment.header,
+        segment.data_len,
+        to.rcv_wnd,
+    )
+}
+
+/// Send the next segment of `from` to `to`.
+fn transfer(from: &mut Peer, to: &mut Peer) -> (Segment, Received) {
+    let segment = from.next_segment().expect("no segment to send");
+    let received = deliver(from, to, &segment);
+    (segment, received)
+}
+
+/// Open a connection from a client with initial sequence number `client_iss`
+/// to a listening server, which picks `server_iss` once it sees the SYN.
+fn open(client_iss: u32, server_iss: u32) -> (Peer, Peer) {
+    let mut client = Peer::new(1, 49152);
+    let mut server = Peer::new(2, 80);
+    server.connection.listen(server.endpoint, 0, MSS);
+    client.connect(&server, client_iss);
+
+    let (syn, received) = transfer(&mut client, &mut server);
+    assert_eq!(syn.header.get_flags(), tcp_flags::SYN);
+    assert_eq!(syn.header.get_seq_num(), client_iss);
+    assert_eq!(syn.header.get_mss(), Some(MSS as u16));
+    assert_eq!(received.events, 0);
+    assert_eq!(server.state(), TCPState::SynReceived);
+    assert_eq!(server.connection.get_remote(), client.endpoint);
+    server.connection.set_iss(server_iss);
+
+    let (syn_ack, received) = transfer(&mut server, &mut client);
+    assert_eq!(syn_ack.header.get_flags(), tcp_flags::SYN | tcp_flags::ACK);
+    assert_eq!(syn_ack.header.get_seq_num(), server_iss);
+    assert_eq!(syn_ack.header.get_ack_num(), client_iss.wrapping_add(1));
+    assert_eq!(received.events, tcp_event::CONNECTED);
+    assert_eq!(client.state(), TCPState::Established);
+
+    let (ack, received) = transfer(&mut client, &mut server);
+    assert_eq!(ack.header.get_flags(), tcp_flags::ACK);
+    assert_eq!(ack.header.get_ack_num(), server_iss.wrapping_add(1));
+    assert_eq!(received.events, tcp_event::CONNECTED);
+    assert_eq!(server.state(), TCPState::Established);
+
+    assert!(client.next_segment().is_none());
+    assert!(server.next_segment().is_none());
+    (client, server)
+}
+
+#[test]
+fn three_way_handshake() {
+    let (client, server) = open(1000, 7000);
+    assert!(!client.connection.timer_needed());
+    assert!(!server.connection.timer_needed());
+}
+
+#[test]
+fn simultaneous_open() {
+    let mut a = Peer::new(1, 1000);
+    let mut b = Peer::new(2, 2000);
+    a.connect(&b, 100);
+    b.connect(&a, 500);
+
+    // The SYNs cross.
+    let syn_a = a.next_segment().unwrap();
+    let syn_b = b.next_segment().unwrap();
+    assert_eq!(deliver(&a, &mut b, &syn_a).events, 0);
+    assert_eq!(deliver(&b, &mut a, &syn_b).events, 0);
+    assert_eq!(a.state(), TCPState::SynReceived);
+    assert_eq!(b.state(), TCPState::SynReceived);
+
+    // So do the SYN-ACKs, which each end answers with an ACK.
+    let syn_ack_a = a.next_segment().unwrap();
+    let syn_ack_b = b.next_segment().unwrap();
+    assert_eq!(syn_ack_a.header.get_flags(), tcp_flags::SYN | tcp_flags::ACK);
+    assert_eq!(syn_ack_a.header.get_seq_num(), 100);
+    assert_eq!(syn_ack_a.header.get_ack_num(), 501);
+    deliver(&a, &mut b, &syn_ack_a);
+    deliver(&b, &mut a, &syn_ack_b);
+
+    let (ack, received) = transfer(&mut a, &mut b);
+    assert_eq!(ack.header.get_flags(), tcp_flags::ACK);
+    assert_eq!(received.events, tcp_event::CONNECTED);
+    let (_, received) = transfer(&mut b, &mut a);
+    assert_eq!(received.events, tcp_event::CONNECTED);
+    assert_eq!(a.state(), TCPState::Established);
+    assert_eq!(b.state(), TCPState::Established);
+}
+
+#[test]
+fn syn_is_retransmitted_with_backoff_until_timeout() {
+    let mut client = Peer::new(1, 49152);
+    let server = Peer::new(2, 80);
+    client.connect(&server, 42);
+
+    // The SYN and every retransmission of it are lost.
+    assert!(client.next_segment().is_some());
+    for retry in 0..MAX_RETRIES {
+        assert!(client.connection.timer_needed());
+        assert_eq!(client.connection.timeout_ms(), 1000 << retry);
+        assert_eq!(client.connection.timeout(), 0);
+        let syn = client.next_segment().unwrap();
+        assert_eq!(syn.header.get_flags(), tcp_flags::SYN);
+        assert_eq!(syn.header.get_seq_num(), 42);
+        assert!(client.next_segment().is_none());
+    }
+    assert_eq!(client.connection.timeout_ms(), 64000);
+    assert_eq!(client.connection.timeout(), tcp_event::TIMED_OUT);
+    assert_eq!(client.state(), TCPState::Closed);
+    assert!(!client.connection.timer_needed());
+}
+
+#[test]
+fn lost_data_is_resent_and_backoff_resets() {
+    let (mut client, mut server) = open(1000, 7000);
+    client.connection.send(300);
+
+    // A full segment and the rest of the data, and both are lost.
+    let first = client.next_segment().unwrap();
+    assert_eq!((first.data_offset, first.data_len), (0, MSS));
+    assert_eq!(first.header.get_seq_num(), 1001);
+    let second = client.next_segment().unwrap();
+    assert_eq!((second.data_offset, second.data_len), (MSS, 100));
+    assert!(client.next_segment().is_none());
+    assert_eq!(client.connection.timeout(), 0);
+    assert_eq!(client.connection.timeout_ms(), 2000);
+
+    // Everything is resent from the oldest unacknowledged byte.
+    let (resent, received) = transfer(&mut client, &mut server);
+    assert_eq!(resent.header.get_seq_num(), 1001);
+    assert_eq!((received.data_offset, received.data_len), (0, MSS));
+    let (_, received) = transfer(&mut client, &mut server);
+    assert_eq!(received.data_len, 100);
+
+    let (ack, received) = transfer(&mut server, &mut client);
+    assert_eq!(ack.header.get_ack_num(), 1301);
+    assert_eq!(received.acked, 300);
+    assert_eq!(client.connection.get_queued(), 0);
+    assert_eq!(client.connection.timeout_ms(), 1000);
+    assert!(!client.connection.timer_needed());
+}
+
+#[test]
+fn closed_window_is_probed_without_giving_up() {
+    let mut client = Peer::new(1, 49152);
+    let mut server = Peer::new(2, 80);
+    server.rcv_wnd = 0;
+    server.connection.listen(server.endpoint, 7000, MSS);
+    client.connect(&server, 1000);
+    transfer(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    transfer(&mut client, &mut server);
+    assert_eq!(client.state(), TCPState::Established);
+
+    // The server has no room, so nothing is sent until the timer runs out,
+    // and then a single byte.
+    client.connection.send(10);
+    assert!(client.next_segment().is_none());
+    assert!(client.connection.timer_needed());
+    for _ in 0..2 * MAX_RETRIES {
+        assert_eq!(client.connection.timeout_ms(), 1000);
+        assert_eq!(client.connection.timeout(), 0);
+        let (probe, received) = transfer(&mut client, &mut server);
+        assert_eq!(probe.data_len, 1);
+        assert_eq!(probe.header.get_seq_num(), 1001);
+        assert_eq!(received.data_len, 0);
+        assert!(client.next_segment().is_none());
+
+        let (ack, _) = transfer(&mut server, &mut client);
+        assert_eq!(ack.header.get_ack_num(), 1001);
+        assert_eq!(ack.header.get_window(), 0);
+    }
+    assert_eq!(client.state(), TCPState::Established);
+
+    // Once the server makes room, it says so, and the client sends its data.
+    server.rcv_wnd = 100;
+    server.connection.window_opened();
+    let (update, _) = transfer(&mut server, &mut client);
+    assert_eq!(update.header.get_window(), 100);
+    assert_eq!(client.connection.timeout(), 0);
+    let (data, received) = transfer(&mut client, &mut server);
+    assert_eq!(data.header.get_seq_num(), 1001);
+    assert_eq!(received.data_len, 10);
+}
+
+#[test]
+fn both_ends_close() {
+    let (mut client, mut server) = open(1000, 7000);
+    client.connection.close();
+
+    let (fin, received) = transfer(&mut client, &mut server);
+    assert_eq!(fin.header.get_flags(), tcp_flags::FIN | tcp_flags::ACK);
+    assert_eq!(received.events, tcp_event::REMOTE_CLOSED);
+    assert_eq!(client.state(), TCPState::FinWait1);
+    assert_eq!(server.state(), TCPState::CloseWait);
+
+    transfer(&mut server, &mut client);
+    assert_eq!(client.state(), TCPState::FinWait2);
+    assert!(!client.connection.timer_needed());
+
+    server.connection.close();
+    let (_, received) = transfer(&mut server, &mut client);
+    assert_eq!(received.events, tcp_event::REMOTE_CLOSED | tcp_event::CLOSED);
+    assert_eq!(server.state(), TCPState::LastAck);
+    assert_eq!(client.state(), TCPState::TimeWait);
+
+    let (_, received) = transfer(&mut client, &mut server);
+    assert_eq!(received.events, tcp_event::CLOSED);
+    assert_eq!(server.state(), TCPState::Closed);
+
+    assert!(client.connection.timer_needed());
+    assert_eq!(client.connection.timeout_ms(), 10000);
+    assert_eq!(client.connection.timeout(), 0);
+    assert_eq!(client.state(), TCPState::Closed);
+}
+
+#[test]
+fn abort_resets_the_other_end() {
+    let (mut client, mut server) = open(1000, 7000);
+    let reset = client.connection.abort().unwrap();
+    assert_eq!(reset.get_flags(), tcp_flags::RST);
+    assert_eq!(client.state(), TCPState::Closed);
+
+    let received = server.connection.receive(client.endpoint.addr, &reset, 0, 1000);
+    assert_eq!(received.events, tcp_event::RESET);
+    assert!(!received.reset);
+    assert_eq!(server.state(), TCPState::Closed);
+}
+
+#[test]
+fn reset_with_wrong_sequence_number_is_ignored() {
+    let (client, mut server) = open(1000, 7000);
+    let mut reset = TCPHeader::new();
+    reset.set_src_port(client.endpoint.port);
+    reset.set_dst_port(server.endpoint.port);
+    reset.set_seq_num(5000);
+    reset.set_flags(tcp_flags::RST);
+    let received = server.connection.receive(client.endpoint.addr, &reset, 0, 1000);
+    assert_eq!(received.events, 0);
+    assert_eq!(server.state(), TCPState::Established);
+}
+
+#[test]
+fn connecting_to_a_closed_port_is_refused() {
+    let mut client = Peer::new(1, 49152);
+    let server = Peer::new(2, 80);
+    client.connect(&server, 1000);
+    let syn = client.next_segment().unwrap();
+
+    // Nothing listens on the port, so the server answers with a reset.
+    let reset = tcp_state::reset_reply(&syn.header, 0).unwrap();
+    assert_eq!(reset.get_flags(), tcp_flags::RST | tcp_flags::ACK);
+    assert_eq!(reset.get_ack_num(), 1001);
+    assert!(tcp_state::reset_reply(&reset, 0).is_none());
+
+    let received = client.connection.receive(server.endpoint.addr, &reset, 0, 1000);
+    assert_eq!(received.events, tcp_event::RESET);
+    assert_eq!(client.state(), TCPState::Closed);
+}
+
+#[test]
+fn sequence_numbers_wrap() {
+    let client_iss = u32::max_value() - 2;
+    let server_iss = u32::max_value();
+    let (mut client, mut server) = open(client_iss, server_iss);
+
+    // Each end sends data that runs past the largest sequence number.
+    client.connection.send(10);
+    server.connection.send(10);
+    let (data, received) = transfer(&mut client, &mut server);
+    assert_eq!(data.header.get_seq_num(), u32::max_value() - 1);
+    assert_eq!(received.data_len, 10);
+    let (data, received) = transfer(&mut server, &mut client);
+    assert_eq!(data.header.get_seq_num(), 0);
+    assert_eq!(data.header.get_ack_num(), 8);
+    assert_eq!(received.data_len, 10);
+    assert_eq!(received.acked, 10);
+
+    let (ack, received) = transfer(&mut client, &mut server);
+    assert_eq!(ack.header.get_ack_num(), 10);
+    assert_eq!(received.acked, 10);
+    assert!(!client.connection.timer_needed());
+    assert!(!server.connection.timer_needed());
+
+    // A duplicate of the server's data only gets an acknowledgment.
+    let received = deliver(&server, &mut client, &data);
+    assert_eq!(received.data_len, 0);
+    let (ack, _) = transfer(&mut client, &mut server);
+    assert_eq!(ack.header.get_ack_num(), 10);
+}
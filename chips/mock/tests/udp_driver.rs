@@ -0,0 +1,217 @@
+//! Tests that the UDP syscall driver releases the port of an app that was
+//! terminated, so that another app can bind it, with processes run by the
+//! host simulation.
+
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+extern crate mock;
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+use std::slice;
+
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::udp::udp::UDPHeader;
+use capsules::net::udp::udp_mux::{MuxUDP, UDPSocket};
+use capsules::net::udp::udp_send::{UDPSendClient, UDPSender};
+use capsules::net::udp::UDPDriver;
+use host::{Action, Event, SimApp, SimPlatform};
+use kernel::capabilities::{
+    MainLoopCapability, MemoryAllocationCapability, ProcessManagementCapability,
+};
+use kernel::procs::FaultResponse;
+use kernel::scheduler::RoundRobinScheduler;
+use kernel::{AppId, Callback, Driver, Grant, Kernel, ReturnCode};
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+unsafe impl MemoryAllocationCapability for Cap {}
+unsafe impl ProcessManagementCapability for Cap {}
+
+const WAKE_DRIVER: usize = 0x90000;
+const WAKE_CALLBACK: usize = 0x1000;
+/// Length of an address and port in the config buffers of the driver.
+const ENDPOINT_LEN: usize = 18;
+
+/// A sender that accepts every packet and never sends it.
+struct NullSender;
+
+impl UDPSender<'static> for NullSender {
+    fn set_client(&self, _client: &'static UDPSendClient) {}
+
+    fn set_addr(&self, _src_addr: IPAddr) {}
+
+    fn send_to(&self, _dest: IPAddr, _dst_port: u16, _src_port: u16, _buf: &[u8]) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn send(&self, _dest: IPAddr, _udp_header: UDPHeader, _buf: &[u8]) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+}
+
+/// Calls an app back when the test says so.
+struct Wake {
+    apps: Grant<Option<Callback>>,
+}
+
+impl Wake {
+    fn wake(&self, appid: AppId) {
+        self.apps
+            .enter(appid, |callback, _| {
+                callback.map(|mut cb| cb.schedule(0, 0, 0));
+            }).unwrap();
+    }
+}
+
+impl Driver for Wake {
+    fn subscribe(&self, _: usize, callback: Option<Callback>, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                **app = callback;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into())
+    }
+}
+
+fn udp_command(subdriver_number: usize) -> Action {
+    Action::Command {
+        driver_number: capsules::net::udp::DRIVER_NUM,
+        subdriver_number: subdriver_number,
+        arg0: 0,
+        arg1: 0,
+    }
+}
+
+/// An app that binds each of `ports` on the unspecified address, the first
+/// one when it starts and the next ones whenever it is woken up. It writes
+/// the results of the binds to `log`.
+fn binder(name: &str, ports: &'static [u16], log: &Rc<RefCell<Vec<isize>>>) -> SimApp {
+    let log = log.clone();
+    let mut next = VecDeque::new();
+    let mut rx_cfg: &'static mut [u8] = &mut [];
+    let mut bound = 0;
+    let mut binding = false;
+    let mut bind = move |rx_cfg: &mut [u8], next: &mut VecDeque<Action>| {
+        // The second endpoint of the config buffer is the one to bind.
+        let port = ports[bound];
+        bound += 1;
+        rx_cfg[2 * ENDPOINT_LEN - 2] = (port >> 8) as u8;
+        rx_cfg[2 * ENDPOINT_LEN - 1] = port as u8;
+        next.push_back(udp_command(3));
+    };
+    SimApp::new(name, move |event| {
+        match event {
+            Event::Start { mem_start, .. } => {
+                rx_cfg = unsafe { slice::from_raw_parts_mut(mem_start as *mut u8, 64) };
+                for byte in rx_cfg.iter_mut() {
+                    *byte = 0;
+                }
+                next.push_back(Action::Allow {
+                    driver_number: capsules::net::udp::DRIVER_NUM,
+                    subdriver_number: 3,
+                    allow_address: mem_start as *mut u8,
+                    allow_size: 2 * ENDPOINT_LEN,
+                });
+                bind(rx_cfg, &mut next);
+                next.push_back(Action::Subscribe {
+                    driver_number: WAKE_DRIVER,
+                    subdriver_number: 0,
+                    callback_ptr: WAKE_CALLBACK,
+                    appdata: 0,
+                });
+            }
+            // Only the results of the binds are logged.
+            Event::Return(value) => if binding {
+                log.borrow_mut().push(value);
+            },
+            Event::Callback { .. } => bind(rx_cfg, &mut next),
+            Event::Resume => {}
+        }
+        let action = next.pop_front().unwrap_or(Action::Yield);
+        binding = match action {
+            Action::Command { .. } => true,
+            _ => false,
+        };
+        action
+    })
+}
+
+#[test]
+fn terminated_app_releases_its_port() {
+    assert_eq!(mem::size_of::<capsules::net::udp::driver::UDPEndpoint>(), ENDPOINT_LEN);
+    static FIRST_PORTS: [u16; 1] = [1000];
+    static SECOND_PORTS: [u16; 3] = [2000, 1000, 1000];
+    let first = Rc::new(RefCell::new(Vec::new()));
+    let second = Rc::new(RefCell::new(Vec::new()));
+    let apps = vec![
+        binder("first", &FIRST_PORTS, &first),
+        binder("second", &SECOND_PORTS, &second),
+    ];
+
+    let (kernel, chip, procs, (udp, wake)) =
+        host::boot_with(apps, FaultResponse::Panic, |kernel| {
+            let interface_list: &'static [Cell<IPAddr>] = mock::leak([Cell::new(IPAddr([
+                0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+            ]))]);
+            let sender: &'static NullSender = mock::leak(NullSender);
+            let mux: &'static MuxUDP = mock::leak(MuxUDP::new(sender, interface_list));
+            let sockets: &'static [UDPSocket<'static>] = mock::leak([
+                UDPSocket::new(mux, mock::leak([0; 64])),
+                UDPSocket::new(mux, mock::leak([0; 64])),
+            ]);
+            let udp: &'static UDPDriver = mock::leak(UDPDriver::new(
+                sockets,
+                kernel.create_grant(&Cap),
+                interface_list,
+                64,
+            ));
+            for socket in sockets.iter() {
+                mux.add_socket(socket);
+                socket.set_client(udp);
+            }
+            let wake: &'static Wake = mock::leak(Wake {
+                apps: kernel.create_grant(&Cap),
+            });
+            (udp, wake)
+        });
+
+    let run = |kernel: &'static Kernel| {
+        let mut platform = SimPlatform::new();
+        platform.add_driver(capsules::net::udp::DRIVER_NUM, udp);
+        platform.add_driver(WAKE_DRIVER, wake);
+        let scheduler = RoundRobinScheduler::new(10000);
+        assert!(host::run_until_idle(
+            kernel,
+            &platform,
+            chip,
+            None,
+            &scheduler,
+            20,
+            &Cap
+        ));
+    };
+    run(kernel);
+    assert_eq!(*first.borrow(), vec![0]);
+    assert_eq!(*second.borrow(), vec![0]);
+
+    // The port is taken while the first app is alive.
+    let second_app = procs[1].unwrap().appid();
+    wake.wake(second_app);
+    run(kernel);
+    assert_eq!(*second.borrow(), vec![0, isize::from(ReturnCode::EBUSY)]);
+
+    // The second app already has a socket, and can still bind the port of
+    // the terminated app.
+    let first_app = procs[0].unwrap().appid();
+    assert_eq!(kernel.terminate_process(first_app, &Cap), ReturnCode::SUCCESS);
+    wake.wake(second_app);
+    run(kernel);
+    assert_eq!(
+        *second.borrow(),
+        vec![0, isize::from(ReturnCode::EBUSY), 0]
+    );
+}
@@ -0,0 +1,139 @@
+//! Tests that `MuxRng` runs the underlying `Rng` while any device wants
+//! randomness, and hands out every number to a single device.
+
+extern crate capsules;
+extern crate kernel;
+extern crate mock;
+
+use std::cell::{Cell, RefCell};
+
+use capsules::virtual_rng::{MuxRng, VirtualRngDevice};
+use kernel::common::cells::OptionalCell;
+use kernel::hil::rng::{self, Rng};
+use kernel::ReturnCode;
+
+/// An `Rng` that delivers numbers when the test says so.
+struct FakeRng {
+    running: Cell<bool>,
+    gets: Cell<usize>,
+    client: OptionalCell<&'static rng::Client>,
+}
+
+impl FakeRng {
+    /// Deliver `numbers`, and return whether the client wants more.
+    fn deliver(&self, numbers: &[u32]) -> bool {
+        assert!(self.running.get());
+        let more = self.client.map_or(false, |client| {
+            client.randomness_available(&mut numbers.iter().cloned(), ReturnCode::SUCCESS)
+                == rng::Continue::More
+        });
+        self.running.set(more);
+        more
+    }
+}
+
+impl Rng<'static> for FakeRng {
+    fn get(&self) -> ReturnCode {
+        self.running.set(true);
+        self.gets.set(self.gets.get() + 1);
+        ReturnCode::SUCCESS
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        self.running.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_client(&'static self, client: &'static rng::Client) {
+        self.client.set(client);
+    }
+}
+
+/// Takes `wanted` numbers.
+struct Taker {
+    wanted: usize,
+    taken: RefCell<Vec<u32>>,
+}
+
+impl rng::Client for Taker {
+    fn randomness_available(
+        &self,
+        randomness: &mut Iterator<Item = u32>,
+        _error: ReturnCode,
+    ) -> rng::Continue {
+        let mut taken = self.taken.borrow_mut();
+        while taken.len() < self.wanted {
+            match randomness.next() {
+                Some(number) => taken.push(number),
+                None => return rng::Continue::More,
+            }
+        }
+        rng::Continue::Done
+    }
+}
+
+fn setup() -> (
+    &'static FakeRng,
+    [&'static VirtualRngDevice<'static>; 2],
+    [&'static Taker; 2],
+) {
+    let fake = mock::leak(FakeRng {
+        running: Cell::new(false),
+        gets: Cell::new(0),
+        client: OptionalCell::empty(),
+    });
+    let mux = mock::leak(MuxRng::new(fake));
+    fake.set_client(mux);
+    let devices: [&'static VirtualRngDevice<'static>; 2] = [
+        mock::leak(VirtualRngDevice::new(mux)),
+        mock::leak(VirtualRngDevice::new(mux)),
+    ];
+    let takers: [&'static Taker; 2] = [
+        mock::leak(Taker {
+            wanted: 3,
+            taken: RefCell::new(Vec::new()),
+        }),
+        mock::leak(Taker {
+            wanted: 2,
+            taken: RefCell::new(Vec::new()),
+        }),
+    ];
+    for (device, taker) in devices.iter().zip(takers.iter()) {
+        device.set_client(*taker);
+    }
+    (fake, devices, takers)
+}
+
+#[test]
+fn devices_share_the_numbers() {
+    let (fake, devices, takers) = setup();
+    assert_eq!(devices[0].get(), ReturnCode::SUCCESS);
+    assert_eq!(devices[1].get(), ReturnCode::SUCCESS);
+    // The second device does not start the running `Rng` again.
+    assert_eq!(fake.gets.get(), 1);
+
+    assert!(fake.deliver(&[1, 2, 3, 4]));
+    assert!(!fake.deliver(&[5, 6]));
+    let mut taken: Vec<u32> = takers.iter().flat_map(|t| t.taken.borrow().clone()).collect();
+    taken.sort();
+    assert_eq!(taken, vec![1, 2, 3, 4, 5]);
+    assert!(!fake.running.get());
+}
+
+#[test]
+fn cancel_only_stops_the_last_device() {
+    let (fake, devices, takers) = setup();
+    devices[0].get();
+    devices[1].get();
+    devices[0].cancel();
+    assert!(fake.running.get());
+
+    assert!(!fake.deliver(&[1, 2]));
+    assert!(takers[0].taken.borrow().is_empty());
+    assert_eq!(*takers[1].taken.borrow(), vec![1, 2]);
+
+    devices[0].get();
+    devices[0].cancel();
+    assert!(!fake.running.get());
+    assert_eq!(fake.gets.get(), 2);
+}
@@ -0,0 +1,126 @@
+//! Tests that `UartMux` starts transmissions from a dynamic deferred call, and
+//! sends the transmissions of its devices one after another.
+
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+extern crate mock;
+
+use std::cell::Cell;
+
+use capsules::virtual_uart::{UartDevice, UartMux};
+use host::{SimChip, SimPlatform};
+use kernel::capabilities::MainLoopCapability;
+use kernel::common::cells::TakeCell;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
+use kernel::hil::uart::{self, UART};
+use kernel::scheduler::RoundRobinScheduler;
+use mock::MockUart;
+
+struct Cap;
+unsafe impl MainLoopCapability for Cap {}
+
+struct Client {
+    buffer: TakeCell<'static, [u8]>,
+    completed: Cell<usize>,
+}
+
+impl uart::Client for Client {
+    fn transmit_complete(&self, buffer: &'static mut [u8], error: uart::Error) {
+        assert_eq!(error, uart::Error::CommandComplete);
+        self.buffer.replace(buffer);
+        self.completed.set(self.completed.get() + 1);
+    }
+
+    fn receive_complete(&self, _buffer: &'static mut [u8], _rx_len: usize, _error: uart::Error) {}
+}
+
+fn device(
+    mux: &'static UartMux<'static>,
+    data: &[u8],
+) -> (&'static UartDevice<'static>, &'static Client) {
+    let device: &'static UartDevice<'static> = mock::leak(UartDevice::new(mux, false));
+    device.setup();
+    let client: &'static Client = mock::leak(Client {
+        buffer: TakeCell::new(mock::leak(data.to_vec()).as_mut_slice()),
+        completed: Cell::new(0),
+    });
+    device.set_client(client);
+    (device, client)
+}
+
+fn transmit(device: &UartDevice, client: &Client) {
+    let buffer = client.buffer.take().unwrap();
+    let len = buffer.len();
+    device.transmit(buffer, len);
+}
+
+#[test]
+fn transmissions_start_from_a_deferred_call() {
+    let kernel = mock::boot();
+    let chip: &'static SimChip = mock::leak(SimChip::new());
+    let platform = SimPlatform::new();
+    let scheduler = RoundRobinScheduler::new(10000);
+
+    let client_states: &'static [DynamicDeferredCallClientState] =
+        mock::leak([DynamicDeferredCallClientState::default()]);
+    let deferred_caller: &'static DynamicDeferredCall =
+        mock::leak(DynamicDeferredCall::new(client_states));
+    kernel.set_dynamic_deferred_call(deferred_caller, &Cap);
+
+    let uart: &'static MockUart = mock::leak(MockUart::new());
+    let mux: &'static UartMux<'static> = mock::leak(UartMux::new(
+        uart,
+        mock::leak([0; 64]),
+        115200,
+        deferred_caller,
+    ));
+    mux.initialize_callback_handle(deferred_caller.register(mux).unwrap());
+    uart.set_client(mux);
+
+    // Devices set up later come first in the mux's list.
+    let (second, second_client) = device(mux, b"second");
+    let (first, first_client) = device(mux, b"first ");
+
+    transmit(second, second_client);
+    transmit(first, first_client);
+    assert!(!uart.is_transmitting());
+    assert!(deferred_caller.has_pending());
+
+    assert!(host::run_until_idle(kernel, &platform, chip, None, &scheduler, 10, &Cap));
+    assert!(uart.is_transmitting());
+    assert!(!deferred_caller.has_pending());
+
+    // Completing the first transmission starts the next one right away.
+    uart.handle_interrupt();
+    assert_eq!(first_client.completed.get(), 1);
+    assert!(uart.is_transmitting());
+    uart.handle_interrupt();
+    assert_eq!(second_client.completed.get(), 1);
+    assert!(!uart.is_transmitting());
+    assert_eq!(uart.take_tx(), b"first second".to_vec());
+}
+
+#[test]
+fn transmissions_start_right_away_without_a_handle() {
+    let client_states: &'static [DynamicDeferredCallClientState] =
+        mock::leak([DynamicDeferredCallClientState::default()]);
+    let deferred_caller: &'static DynamicDeferredCall =
+        mock::leak(DynamicDeferredCall::new(client_states));
+    let uart: &'static MockUart = mock::leak(MockUart::new());
+    let mux: &'static UartMux<'static> = mock::leak(UartMux::new(
+        uart,
+        mock::leak([0; 64]),
+        115200,
+        deferred_caller,
+    ));
+    uart.set_client(mux);
+
+    let (device, client) = device(mux, b"now");
+    transmit(device, client);
+    assert!(uart.is_transmitting());
+    assert!(!deferred_caller.has_pending());
+    uart.handle_interrupt();
+    assert_eq!(client.completed.get(), 1);
+    assert_eq!(uart.take_tx(), b"now".to_vec());
+}
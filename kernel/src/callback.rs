@@ -58,14 +58,30 @@ impl AppId {
 #[derive(Clone, Copy)]
 pub struct Callback {
     app_id: AppId,
+    /// The driver and subscribe number the callback was registered with. They
+    /// add two words to every stored `Callback`, but they are what lets the
+    /// kernel recognize a repeated callback of the same subscription when the
+    /// queue of the process is full, and update the queued one instead of
+    /// dropping it. The function pointer and `appdata` cannot tell
+    /// subscriptions apart, since a process may pass the same ones to several.
+    driver_number: usize,
+    subscribe_number: usize,
     appdata: usize,
     fn_ptr: NonNull<*mut ()>,
 }
 
 impl Callback {
-    crate fn new(appid: AppId, appdata: usize, fn_ptr: NonNull<*mut ()>) -> Callback {
+    crate fn new(
+        appid: AppId,
+        driver_number: usize,
+        subscribe_number: usize,
+        appdata: usize,
+        fn_ptr: NonNull<*mut ()>,
+    ) -> Callback {
         Callback {
             app_id: appid,
+            driver_number: driver_number,
+            subscribe_number: subscribe_number,
             appdata: appdata,
             fn_ptr: fn_ptr,
         }
@@ -73,9 +89,10 @@ impl Callback {
 
     /// Actually trigger the callback.
     ///
-    /// This will queue the `Callback` for the associated process. It returns
-    /// `false` if the queue for the process is full and the callback could not
-    /// be scheduled.
+    /// This will queue the `Callback` for the associated process. If the queue
+    /// for the process is full but the callback is already queued, the queued
+    /// callback is updated to use the new arguments instead. It returns
+    /// `false` if the callback could not be scheduled.
     ///
     /// The arguments (`r0-r2`) are the values passed back to the process and
     /// are specific to the individual `Driver` interfaces.
@@ -84,6 +101,10 @@ impl Callback {
             .kernel
             .process_map_or(false, self.app_id.idx(), |process| {
                 process.enqueue_task(process::Task::FunctionCall(process::FunctionCall {
+                    source: process::FunctionCallSource::Driver {
+                        driver_number: self.driver_number,
+                        subscribe_number: self.subscribe_number,
+                    },
                     argument0: r0,
                     argument1: r1,
                     argument2: r2,
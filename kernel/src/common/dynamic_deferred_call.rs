@@ -0,0 +1,160 @@
+//! Dynamically registered deferred calls.
+//!
+//! Like `DeferredCall`, this lets code schedule a call that the kernel makes
+//! later from the main loop, for example to issue a callback without calling
+//! back into the code that started an operation. `DeferredCall` tasks are
+//! variants of a chip-specific enum, so only chips can use them. Any capsule
+//! can register with a `DynamicDeferredCall` instead.
+//!
+//! The board creates one `DynamicDeferredCall` with room for a fixed number of
+//! clients, and passes it to the kernel with
+//! `Kernel::set_dynamic_deferred_call()`. The kernel makes the pending calls
+//! when it services interrupts. Capsules that need deferred calls take a
+//! reference to the `DynamicDeferredCall` and register themselves with it.
+//! For example, `capsules::virtual_uart::UartMux` starts transmissions from a
+//! deferred call.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! # extern crate core;
+//! # #[macro_use] extern crate kernel;
+//! # use std::cell::Cell;
+//! use kernel::common::dynamic_deferred_call::{
+//!     DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+//!     DynamicDeferredCallClientState,
+//! };
+//!
+//! struct Capsule {
+//!     handle: Cell<Option<DeferredCallHandle>>,
+//!     deferred_caller: &'static DynamicDeferredCall,
+//! }
+//!
+//! impl DynamicDeferredCallClient for Capsule {
+//!     fn call(&self, _handle: DeferredCallHandle) {
+//!         // Issue the callback to the client here.
+//!     }
+//! }
+//!
+//! # unsafe {
+//! // In the board's `reset_handler`.
+//! let client_states = static_init!(
+//!     [DynamicDeferredCallClientState; 2],
+//!     Default::default()
+//! );
+//! let deferred_caller = static_init!(
+//!     DynamicDeferredCall,
+//!     DynamicDeferredCall::new(client_states)
+//! );
+//! // kernel.set_dynamic_deferred_call(deferred_caller, &main_loop_capability);
+//!
+//! let capsule = static_init!(
+//!     Capsule,
+//!     Capsule {
+//!         handle: Cell::new(None),
+//!         deferred_caller: deferred_caller,
+//!     }
+//! );
+//! capsule.handle.set(deferred_caller.register(capsule));
+//!
+//! // In the capsule, to have `call()` run later.
+//! capsule.handle.get().map(|handle| capsule.deferred_caller.set(handle));
+//! assert!(deferred_caller.has_pending());
+//! # }
+//! ```
+
+use core::cell::Cell;
+
+use common::cells::OptionalCell;
+
+/// Implemented by capsules that use dynamic deferred calls.
+pub trait DynamicDeferredCallClient {
+    /// Called by the kernel some time after the client asked for the call
+    /// with `DynamicDeferredCall::set()`.
+    fn call(&self, handle: DeferredCallHandle);
+}
+
+/// Identifies a client registered with a `DynamicDeferredCall`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DeferredCallHandle(usize);
+
+/// State kept for each client of a `DynamicDeferredCall`.
+///
+/// Boards allocate one of these for each client that will register.
+pub struct DynamicDeferredCallClientState {
+    scheduled: Cell<bool>,
+    client: OptionalCell<&'static DynamicDeferredCallClient>,
+}
+
+impl Default for DynamicDeferredCallClientState {
+    fn default() -> DynamicDeferredCallClientState {
+        DynamicDeferredCallClientState {
+            scheduled: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+/// Registry of deferred calls that clients register at runtime.
+pub struct DynamicDeferredCall {
+    client_states: &'static [DynamicDeferredCallClientState],
+    handle_counter: Cell<usize>,
+    call_pending: Cell<bool>,
+}
+
+impl DynamicDeferredCall {
+    /// Create a registry with room for as many clients as there are entries in
+    /// `client_states`.
+    pub fn new(client_states: &'static [DynamicDeferredCallClientState]) -> DynamicDeferredCall {
+        DynamicDeferredCall {
+            client_states: client_states,
+            handle_counter: Cell::new(0),
+            call_pending: Cell::new(false),
+        }
+    }
+
+    /// Register a client. Returns `None` if there is no room for another
+    /// client.
+    pub fn register(
+        &self,
+        client: &'static DynamicDeferredCallClient,
+    ) -> Option<DeferredCallHandle> {
+        let index = self.handle_counter.get();
+        self.client_states.get(index).map(|state| {
+            state.client.set(client);
+            self.handle_counter.set(index + 1);
+            DeferredCallHandle(index)
+        })
+    }
+
+    /// Ask for the client with the given handle to be called.
+    ///
+    /// Returns whether a call for the client was already pending, or `None` if
+    /// the handle is not valid.
+    pub fn set(&self, handle: DeferredCallHandle) -> Option<bool> {
+        let DeferredCallHandle(index) = handle;
+        self.client_states.get(index).map(|state| {
+            self.call_pending.set(true);
+            state.scheduled.replace(true)
+        })
+    }
+
+    /// Returns true if any client is waiting to be called.
+    pub fn has_pending(&self) -> bool {
+        self.call_pending.get()
+    }
+
+    /// Call every client that is waiting to be called.
+    ///
+    /// Clients that ask to be called again while this runs are called the next
+    /// time the kernel services interrupts.
+    crate fn call(&self) {
+        self.call_pending.set(false);
+        for (index, state) in self.client_states.iter().enumerate() {
+            if state.scheduled.replace(false) {
+                state.client.map(|client| client.call(DeferredCallHandle(index)));
+            }
+        }
+    }
+}
@@ -10,6 +10,7 @@
 pub use tock_registers::{macros, registers};
 
 pub mod deferred_call;
+pub mod dynamic_deferred_call;
 pub mod list;
 pub mod math;
 pub mod peripherals;
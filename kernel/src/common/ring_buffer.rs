@@ -16,6 +16,22 @@ impl<T: Copy> RingBuffer<'a, T> {
             ring: ring,
         }
     }
+
+    /// Returns the element closest to the back of the queue for which
+    /// `predicate` returns true.
+    pub fn find_last_mut<F>(&mut self, predicate: F) -> Option<&mut T>
+    where
+        F: Fn(&T) -> bool,
+    {
+        let mut index = self.tail;
+        while index != self.head {
+            index = (index + self.ring.len() - 1) % self.ring.len();
+            if predicate(&self.ring[index]) {
+                return Some(&mut self.ring[index]);
+            }
+        }
+        None
+    }
 }
 
 impl<T: Copy> queue::Queue<T> for RingBuffer<'a, T> {
@@ -39,7 +39,7 @@
 
 use core::cell::Cell;
 use core::cmp::{self, min};
-use core::fmt::{write, Arguments, Result, Write};
+use core::fmt::{self, write, Arguments, Result, Write};
 use core::panic::PanicInfo;
 use core::ptr;
 use core::slice;
@@ -65,6 +65,7 @@ pub unsafe fn panic<L: hil::led::Led, W: Write>(
 ) -> ! {
     panic_begin(nop);
     panic_banner(writer, panic_info);
+    panic_crash_log(panic_info);
     // Flush debug buffer if needed
     flush(writer);
     panic_process_info(processes, writer);
@@ -162,6 +163,290 @@ pub fn panic_blink_forever<L: hil::led::Led>(leds: &mut [&mut L]) -> ! {
 // panic! support routines
 ///////////////////////////////////////////////////////////////////
 
+///////////////////////////////////////////////////////////////////
+// crash log support
+
+/// What caused a crash.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CrashKind {
+    /// The kernel called `panic!`.
+    Panic = 0,
+    /// The kernel itself faulted.
+    KernelFault = 1,
+    /// A process faulted and the board panics on process faults.
+    ProcessFault = 2,
+}
+
+/// Registers that describe a fault. On Cortex-M these are the stacked PC and
+/// LR and the fault status and address registers of the SCB.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct FaultRegisters {
+    pub pc: u32,
+    pub lr: u32,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+}
+
+/// A record of a crash that can be kept across reboots.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CrashRecord<'a> {
+    pub kind: CrashKind,
+    /// All zero for a `CrashKind::Panic`.
+    pub registers: FaultRegisters,
+    pub kernel_version: &'a str,
+    /// Where the kernel panicked.
+    pub file: &'a str,
+    pub line: u32,
+    /// The process that faulted, or an empty string.
+    pub process_name: &'a str,
+    /// The panic message, possibly truncated.
+    pub message: &'a str,
+}
+
+/// Implemented by storage for crash records, such as
+/// `capsules::crash_log::CrashLog`. The kernel calls it when it panics.
+pub trait CrashHandler {
+    /// Save `record`. Called during a panic, so it must finish before
+    /// returning and must not rely on interrupts.
+    fn save(&self, record: &CrashRecord);
+}
+
+/// First word of an encoded crash record ("CRSH").
+const CRASH_RECORD_MAGIC: u32 = 0x4853_5243;
+
+/// Size of the encoded fields before the strings.
+const CRASH_RECORD_HEADER_LEN: usize = 36;
+
+/// Longest kernel version, file name, process name and message stored in a
+/// crash record. Longer strings are truncated.
+const CRASH_RECORD_STR_LENS: [usize; 4] = [32, 64, 32, 128];
+
+/// Longest encoded crash record.
+pub const MAX_CRASH_RECORD_LEN: usize = CRASH_RECORD_HEADER_LEN + 4 + 32 + 64 + 32 + 128;
+
+impl CrashRecord<'a> {
+    /// Encode the record into `buffer`, which should be at least
+    /// `MAX_CRASH_RECORD_LEN` bytes long. Strings that do not fit are
+    /// truncated. Returns the encoded length, or 0 if `buffer` is too small.
+    ///
+    /// The encoding is little endian: a magic word, then the kind, PC, LR,
+    /// CFSR, HFSR, MMFAR, BFAR and line as words, then the kernel version,
+    /// file, process name and message, each as a length byte followed by
+    /// UTF-8 bytes.
+    pub fn encode(&self, buffer: &mut [u8]) -> usize {
+        if buffer.len() < CRASH_RECORD_HEADER_LEN + CRASH_RECORD_STR_LENS.len() {
+            return 0;
+        }
+        let words = [
+            CRASH_RECORD_MAGIC,
+            self.kind as u32,
+            self.registers.pc,
+            self.registers.lr,
+            self.registers.cfsr,
+            self.registers.hfsr,
+            self.registers.mmfar,
+            self.registers.bfar,
+            self.line,
+        ];
+        for (i, word) in words.iter().enumerate() {
+            for j in 0..4 {
+                buffer[i * 4 + j] = (word >> (j * 8)) as u8;
+            }
+        }
+
+        let strings = [
+            self.kernel_version,
+            self.file,
+            self.process_name,
+            self.message,
+        ];
+        let mut offset = CRASH_RECORD_HEADER_LEN;
+        for (i, string) in strings.iter().enumerate() {
+            // Leave room for the length bytes of the remaining strings.
+            let room = buffer.len() - offset - (strings.len() - i);
+            let string = truncate_str(string, cmp::min(CRASH_RECORD_STR_LENS[i], room));
+            buffer[offset] = string.len() as u8;
+            buffer[offset + 1..offset + 1 + string.len()].copy_from_slice(string.as_bytes());
+            offset += 1 + string.len();
+        }
+        offset
+    }
+
+    /// Length of the record when encoded, if none of its strings need to be
+    /// truncated.
+    pub fn encoded_len(&self) -> usize {
+        CRASH_RECORD_HEADER_LEN
+            + 4
+            + self.kernel_version.len()
+            + self.file.len()
+            + self.process_name.len()
+            + self.message.len()
+    }
+
+    /// Decode a record that `encode()` wrote. Returns `None` if `buffer` does
+    /// not hold a valid record, e.g. because the flash page is erased.
+    pub fn decode(buffer: &'a [u8]) -> Option<CrashRecord<'a>> {
+        if buffer.len() < CRASH_RECORD_HEADER_LEN {
+            return None;
+        }
+        let word = |i: usize| {
+            (0..4).fold(0, |word, j| word | (buffer[i * 4 + j] as u32) << (j * 8))
+        };
+        if word(0) != CRASH_RECORD_MAGIC {
+            return None;
+        }
+        let kind = match word(1) {
+            0 => CrashKind::Panic,
+            1 => CrashKind::KernelFault,
+            2 => CrashKind::ProcessFault,
+            _ => return None,
+        };
+
+        let mut strings = [""; 4];
+        let mut offset = CRASH_RECORD_HEADER_LEN;
+        for string in strings.iter_mut() {
+            let len = *buffer.get(offset)? as usize;
+            let bytes = buffer.get(offset + 1..offset + 1 + len)?;
+            *string = str::from_utf8(bytes).ok()?;
+            offset += 1 + len;
+        }
+
+        Some(CrashRecord {
+            kind: kind,
+            registers: FaultRegisters {
+                pc: word(2),
+                lr: word(3),
+                cfsr: word(4),
+                hfsr: word(5),
+                mmfar: word(6),
+                bfar: word(7),
+            },
+            kernel_version: strings[0],
+            file: strings[1],
+            line: word(8),
+            process_name: strings[2],
+            message: strings[3],
+        })
+    }
+}
+
+impl fmt::Display for CrashRecord<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result {
+        match self.kind {
+            CrashKind::Panic => write!(f, "Kernel panic")?,
+            CrashKind::KernelFault => write!(f, "Kernel fault")?,
+            CrashKind::ProcessFault => write!(f, "Fault in process {}", self.process_name)?,
+        }
+        write!(
+            f,
+            " at {}:{}: \"{}\"\r\n\tKernel version {}",
+            self.file, self.line, self.message, self.kernel_version
+        )?;
+        if self.kind != CrashKind::Panic {
+            let r = &self.registers;
+            write!(
+                f,
+                "\r\n\tPC {:#010X} LR {:#010X} CFSR {:#010X} HFSR {:#010X} \
+                 MMFAR {:#010X} BFAR {:#010X}",
+                r.pc, r.lr, r.cfsr, r.hfsr, r.mmfar, r.bfar
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the longest prefix of `s` that is at most `len` bytes and ends on
+/// a character boundary.
+fn truncate_str(s: &str, len: usize) -> &str {
+    if s.len() <= len {
+        return s;
+    }
+    let mut end = len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+static mut CRASH_HANDLER: Option<&'static CrashHandler> = None;
+
+/// Why the kernel is about to panic, if it was a fault.
+static mut CRASH_FAULT: Option<(CrashKind, FaultRegisters, &'static str)> = None;
+
+/// Holds the panic message while the crash record is saved.
+static mut CRASH_MESSAGE: [u8; 128] = [0; 128];
+
+/// Function used by board main.rs to set where crash records are saved.
+pub unsafe fn set_crash_handler(handler: &'static CrashHandler) {
+    CRASH_HANDLER = Some(handler);
+}
+
+/// Record the registers of a kernel fault. The architecture's fault handler
+/// calls this before it panics.
+pub unsafe fn set_kernel_fault(registers: FaultRegisters) {
+    CRASH_FAULT = Some((CrashKind::KernelFault, registers, ""));
+}
+
+/// Record a process fault that the kernel is about to panic on.
+crate unsafe fn set_process_fault(process_name: &'static str, registers: FaultRegisters) {
+    CRASH_FAULT = Some((CrashKind::ProcessFault, registers, process_name));
+}
+
+/// Writes into a buffer and drops whatever does not fit.
+struct TruncatingWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for TruncatingWriter<'a> {
+    fn write_str(&mut self, s: &str) -> Result {
+        let s = truncate_str(s, self.buffer.len() - self.len);
+        self.buffer[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+/// Save a crash record with the crash handler, if the board set one.
+pub unsafe fn panic_crash_log(panic_info: &PanicInfo) {
+    let handler = match ptr::read(&CRASH_HANDLER) {
+        Some(handler) => handler,
+        None => return,
+    };
+
+    let mut message = TruncatingWriter {
+        buffer: &mut CRASH_MESSAGE,
+        len: 0,
+    };
+    if let Some(args) = panic_info.message() {
+        let _ = write(&mut message, *args);
+    }
+    let message_len = message.len;
+
+    let (kind, registers, process_name) = ptr::read(&CRASH_FAULT).unwrap_or((
+        CrashKind::Panic,
+        FaultRegisters::default(),
+        "",
+    ));
+    let (file, line) = panic_info
+        .location()
+        .map_or(("", 0), |location| (location.file(), location.line()));
+    handler.save(&CrashRecord {
+        kind: kind,
+        registers: registers,
+        kernel_version: env!("TOCK_KERNEL_VERSION"),
+        file: file,
+        line: line,
+        process_name: process_name,
+        message: str::from_utf8_unchecked(&CRASH_MESSAGE[..message_len]),
+    });
+}
+
+// crash log support
+///////////////////////////////////////////////////////////////////
+
 ///////////////////////////////////////////////////////////////////
 // debug_gpio! support
 
@@ -225,6 +510,11 @@ static mut DEBUG_WRITER: Option<&'static mut DebugWriterWrapper> = None;
 pub static mut OUTPUT_BUF: [u8; 64] = [0; 64];
 pub static mut INTERNAL_BUF: [u8; 1024] = [0; 1024];
 
+/// Returns the debug writer, or `None` if the board has not set one.
+crate unsafe fn try_get_debug_writer() -> Option<&'static mut DebugWriterWrapper> {
+    ptr::read(&DEBUG_WRITER)
+}
+
 pub unsafe fn get_debug_writer() -> &'static mut DebugWriterWrapper {
     match ptr::read(&DEBUG_WRITER) {
         Some(x) => x,
@@ -395,7 +685,7 @@ impl DebugWriterWrapper {
         self.dw.map_or(0, |dw| dw.get_count())
     }
 
-    fn publish_str(&self) {
+    crate fn publish_str(&self) {
         self.dw.map(|dw| {
             dw.publish_str();
         });
@@ -408,6 +698,32 @@ impl DebugWriterWrapper {
 
 impl Write for DebugWriterWrapper {
     fn write_str(&mut self, s: &str) -> Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl DebugWriterWrapper {
+    /// Number of bytes that can be added to the internal buffer before it is
+    /// full.
+    crate fn available_len(&self) -> usize {
+        self.dw.map_or(0, |dw| {
+            let head = dw.head.get();
+            let tail = dw.tail.get();
+            let len = dw.internal_buffer.map_or(0, |buffer| buffer.len());
+            if len == 0 {
+                0
+            } else if head >= tail {
+                len - head + tail - 1
+            } else {
+                tail - head - 1
+            }
+        })
+    }
+
+    /// Write bytes to the internal buffer. The caller starts the transmission
+    /// with `publish_str()`.
+    crate fn write_bytes(&self, s: &[u8]) {
         // Circular buffer.
         //
         // Note, we don't use the kernel's RingBuffer here because we want
@@ -427,7 +743,7 @@ impl Write for DebugWriterWrapper {
             let len = dw.internal_buffer.map_or(0, |buffer| buffer.len());
 
             let remaining_bytes = if head >= tail {
-                let bytes = s.as_bytes();
+                let bytes = s;
 
                 // First write from current head to end of buffer in memory
                 let mut backside_len = len - head;
@@ -453,7 +769,7 @@ impl Write for DebugWriterWrapper {
                 }
                 &bytes[written..]
             } else {
-                s.as_bytes()
+                s
             };
 
             // At this point, either
@@ -496,8 +812,6 @@ impl Write for DebugWriterWrapper {
 
             dw.head.set(head);
         });
-
-        Ok(())
     }
 }
 
@@ -4,6 +4,7 @@ use core::marker::PhantomData;
 use core::mem::size_of;
 use core::ops::{Deref, DerefMut};
 use core::ptr::{write, write_volatile, Unique};
+use core::slice;
 
 use callback::AppId;
 use process::Error;
@@ -17,6 +18,7 @@ pub struct Grant<T: Default> {
 
 pub struct AppliedGrant<T> {
     appid: AppId,
+    grant_num: usize,
     grant: *mut T,
     _phantom: PhantomData<T>,
 }
@@ -27,14 +29,22 @@ impl<T> AppliedGrant<T> {
         F: FnOnce(&mut Owned<T>, &mut Allocator) -> R,
         R: Copy,
     {
-        let mut allocator = Allocator { appid: self.appid };
+        let mut allocator = Allocator {
+            appid: self.appid,
+            grant_num: self.grant_num,
+        };
         let mut root = unsafe { Owned::new(self.grant, self.appid) };
         fun(&mut root, &mut allocator)
     }
 }
 
+/// Allocates memory for a capsule from the grant region of a process.
+///
+/// Memory allocated through an `Allocator` is counted towards the grant it
+/// was obtained from.
 pub struct Allocator {
     appid: AppId,
+    grant_num: usize,
 }
 
 pub struct Owned<T: ?Sized> {
@@ -87,15 +97,48 @@ impl Allocator {
             self.appid
                 .kernel
                 .process_map_or(Err(Error::NoSuchApp), self.appid.idx(), |process| {
-                    process
-                        .alloc(size_of::<T>())
-                        .map_or(Err(Error::OutOfMemory), |arr| {
+                    match process.alloc(size_of::<T>()) {
+                        Some(arr) => {
                             let ptr = arr.as_mut_ptr() as *mut T;
                             // We use `ptr::write` to avoid `Drop`ping the uninitialized memory in
                             // case `T` implements the `Drop` trait.
                             write(ptr, data);
+                            process.debug_add_grant_bytes(self.grant_num, size_of::<T>());
                             Ok(Owned::new(ptr, self.appid))
-                        })
+                        }
+                        None => {
+                            let driver_number = self.appid.kernel.syscall_driver();
+                            process.set_grant_allocation_failure(driver_number, size_of::<T>());
+                            Err(Error::OutOfMemory)
+                        }
+                    }
+                })
+        }
+    }
+
+    /// Allocate `len` copies of `value` as a slice, e.g. for data whose size
+    /// depends on the number of processes.
+    pub fn alloc_slice<T: Copy>(&mut self, len: usize, value: T) -> Result<Owned<[T]>, Error> {
+        let size = len * size_of::<T>();
+        unsafe {
+            self.appid
+                .kernel
+                .process_map_or(Err(Error::NoSuchApp), self.appid.idx(), |process| {
+                    match process.alloc(size) {
+                        Some(arr) => {
+                            let ptr = arr.as_mut_ptr() as *mut T;
+                            for i in 0..len {
+                                write(ptr.add(i), value);
+                            }
+                            process.debug_add_grant_bytes(self.grant_num, size);
+                            Ok(Owned::new(slice::from_raw_parts_mut(ptr, len), self.appid))
+                        }
+                        None => {
+                            let driver_number = self.appid.kernel.syscall_driver();
+                            process.set_grant_allocation_failure(driver_number, size);
+                            Err(Error::OutOfMemory)
+                        }
+                    }
                 })
         }
     }
@@ -150,6 +193,7 @@ impl<T: Default> Grant<T> {
                 } else {
                     Some(AppliedGrant {
                         appid: appid,
+                        grant_num: self.grant_num,
                         grant: cntr,
                         _phantom: PhantomData,
                     })
@@ -209,19 +253,30 @@ impl<T: Default> Grant<T> {
                             write(root_ptr, Default::default());
                             // Record the location in the grant pointer.
                             write_volatile(ctr_ptr, root_ptr);
+                            process.debug_add_grant_bytes(self.grant_num, size_of::<T>());
                             root_ptr
                         })
                     } else {
                         Some(*ctr_ptr)
                     };
 
+                    // Let the process find out which driver ran out of grant
+                    // memory.
+                    if new_grant.is_none() {
+                        let driver_number = self.kernel.syscall_driver();
+                        process.set_grant_allocation_failure(driver_number, size_of::<T>());
+                    }
+
                     // If the grant region already exists or there was enough
                     // memory to allocate it, call the passed in closure with
                     // the borrowed grant region.
                     new_grant.map_or(Err(Error::OutOfMemory), move |root_ptr| {
                         let root_ptr = root_ptr as *mut T;
                         let mut root = Borrowed::new(&mut *root_ptr, appid);
-                        let mut allocator = Allocator { appid: appid };
+                        let mut allocator = Allocator {
+                            appid: appid,
+                            grant_num: self.grant_num,
+                        };
                         let res = fun(&mut root, &mut allocator);
                         Ok(res)
                     })
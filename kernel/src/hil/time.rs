@@ -18,6 +18,61 @@ pub trait Frequency {
     fn frequency() -> u32;
 }
 
+const US_PER_S: u64 = 1_000_000;
+const MS_PER_S: u64 = 1_000;
+
+/// Convert `ticks` of a clock with frequency `F` to `units_per_s` units,
+/// rounding down and saturating.
+fn ticks_to_units<F: Frequency>(ticks: u64, units_per_s: u64) -> u64 {
+    let freq = F::frequency() as u64;
+    // Split into whole seconds and the rest so that the multiplications
+    // cannot overflow for the remainder.
+    (ticks / freq)
+        .saturating_mul(units_per_s)
+        .saturating_add((ticks % freq) * units_per_s / freq)
+}
+
+/// Convert `units` with `units_per_s` units per second to ticks of a clock
+/// with frequency `F`, rounding down and saturating.
+fn units_to_ticks<F: Frequency>(units: u64, units_per_s: u64) -> u64 {
+    let freq = F::frequency() as u64;
+    (units / units_per_s)
+        .saturating_mul(freq)
+        .saturating_add((units % units_per_s) * freq / units_per_s)
+}
+
+/// Convert ticks of a clock with frequency `F` to microseconds.
+///
+/// ```
+/// use kernel::hil::time::{self, Freq32KHz};
+///
+/// assert_eq!(time::ticks_to_us::<Freq32KHz>(32768 * 3 + 16384), 3_500_000);
+/// ```
+pub fn ticks_to_us<F: Frequency>(ticks: u64) -> u64 {
+    ticks_to_units::<F>(ticks, US_PER_S)
+}
+
+/// Convert ticks of a clock with frequency `F` to milliseconds.
+pub fn ticks_to_ms<F: Frequency>(ticks: u64) -> u64 {
+    ticks_to_units::<F>(ticks, MS_PER_S)
+}
+
+/// Convert microseconds to ticks of a clock with frequency `F`.
+pub fn us_to_ticks<F: Frequency>(us: u64) -> u64 {
+    units_to_ticks::<F>(us, US_PER_S)
+}
+
+/// Convert milliseconds to ticks of a clock with frequency `F`.
+///
+/// ```
+/// use kernel::hil::time::{self, Freq16KHz};
+///
+/// assert_eq!(time::ms_to_ticks::<Freq16KHz>(1500), 24000);
+/// ```
+pub fn ms_to_ticks<F: Frequency>(ms: u64) -> u64 {
+    units_to_ticks::<F>(ms, MS_PER_S)
+}
+
 /// 16MHz `Frequency`
 #[derive(Debug)]
 pub struct Freq16MHz;
@@ -83,6 +138,26 @@ pub trait Alarm: Time {
     fn get_alarm(&self) -> u32;
 }
 
+/// The `Alarm64` trait models a 64-bit counter capable of notifying when the
+/// counter reaches a certain value.
+///
+/// Unlike [`Alarm`](trait.Alarm.html), the counter does not wrap around in
+/// practice, so clients can compare times directly. Usually implemented on top
+/// of an `Alarm` by counting how often it wraps, see
+/// `capsules::extended_alarm`. Clients are signaled with the
+/// [`Client`](trait.Client.html) trait.
+pub trait Alarm64: Time {
+    /// Returns the current time in hardware clock units.
+    fn now64(&self) -> u64;
+
+    /// Sets a one-shot alarm to fire when the clock reaches `tics`. If `tics`
+    /// has already passed, the alarm fires as soon as possible.
+    fn set_alarm64(&self, tics: u64);
+
+    /// Returns the value set in [`set_alarm64`](#tymethod.set_alarm64)
+    fn get_alarm64(&self) -> u64;
+}
+
 /// A client of an implementor of the [`Alarm`](trait.Alarm.html) trait.
 pub trait Client {
     /// Callback signaled when the alarm's clock reaches the value set in
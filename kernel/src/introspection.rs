@@ -49,25 +49,42 @@ impl Introspection {
             .process_each(|process| match process.get_state() {
                 process::State::Running => count.increment(),
                 process::State::Yielded => count.increment(),
+                process::State::StoppedRunning => {}
+                process::State::StoppedYielded => {}
                 process::State::Fault => {}
+                process::State::Terminated => {}
             });
         count.get()
     }
 
     /// Returns how many processes are considered to be inactive. This includes
-    /// processes in the `Fault` state and processes which the kernel is not
-    /// scheduling for any reason.
+    /// processes in the `Fault` state, processes that have been stopped or
+    /// terminated, and processes which the kernel is not scheduling for any
+    /// reason.
     pub fn number_inactive_processes(&self, _capability: &ProcessManagementCapability) -> usize {
         let count: Cell<usize> = Cell::new(0);
         self.kernel
             .process_each(|process| match process.get_state() {
                 process::State::Running => {}
                 process::State::Yielded => {}
+                process::State::StoppedRunning => count.increment(),
+                process::State::StoppedYielded => count.increment(),
                 process::State::Fault => count.increment(),
+                process::State::Terminated => count.increment(),
             });
         count.get()
     }
 
+    /// Returns the state the process is in.
+    pub fn process_state(
+        &self,
+        app: AppId,
+        _capability: &ProcessManagementCapability,
+    ) -> Option<process::State> {
+        self.kernel
+            .process_map_or(None, app.idx(), |process| Some(process.get_state()))
+    }
+
     /// Get the name of the process.
     pub fn process_name(
         &self,
@@ -121,4 +138,78 @@ impl Introspection {
             process.debug_timeslice_expiration_count()
         })
     }
+
+    /// Returns the total number of microseconds this app has spent executing.
+    pub fn app_cpu_time_us(&self, app: AppId, _capability: &ProcessManagementCapability) -> u64 {
+        self.kernel
+            .process_map_or(0, app.idx(), |process| process.debug_cpu_time_us())
+    }
+
+    /// Returns the total number of microseconds the kernel has spent servicing
+    /// interrupts.
+    pub fn kernel_interrupt_time_us(&self, _capability: &ProcessManagementCapability) -> u64 {
+        self.kernel.debug_interrupt_time_us()
+    }
+
+    /// Returns the number of subscribe, command, and allow syscalls the app
+    /// has made to the driver with the given driver number.
+    pub fn number_app_driver_syscalls(
+        &self,
+        app: AppId,
+        driver_number: usize,
+        _capability: &ProcessManagementCapability,
+    ) -> usize {
+        self.kernel.process_map_or(0, app.idx(), |process| {
+            process.debug_driver_syscall_count(driver_number)
+        })
+    }
+
+    /// Returns one entry of the app's per-driver syscall histogram as a
+    /// `(driver_number, syscall_count)` pair. Entries are numbered from zero,
+    /// and `None` is returned after the last used entry. Only a limited number
+    /// of distinct drivers are tracked for each app.
+    pub fn app_driver_syscall_histogram_entry(
+        &self,
+        app: AppId,
+        index: usize,
+        _capability: &ProcessManagementCapability,
+    ) -> Option<(usize, usize)> {
+        self.kernel.process_map_or(None, app.idx(), |process| {
+            process.debug_driver_syscall_histogram_entry(index)
+        })
+    }
+
+    /// Returns how many grants the kernel has. Grants are numbered from zero
+    /// in the order the board created them.
+    pub fn number_grants(&self, _capability: &ProcessManagementCapability) -> usize {
+        self.kernel.get_grant_count()
+    }
+
+    /// Returns how many bytes of grant memory have been allocated for the app
+    /// in the given grant, including memory the capsule allocated through the
+    /// grant's allocator. The count is reset when the app restarts.
+    pub fn app_grant_bytes(
+        &self,
+        app: AppId,
+        grant_num: usize,
+        _capability: &ProcessManagementCapability,
+    ) -> usize {
+        self.kernel.process_map_or(0, app.idx(), |process| {
+            process.debug_grant_bytes(grant_num)
+        })
+    }
+
+    /// Returns how many bytes of grant memory have been allocated for the app
+    /// in all grants.
+    pub fn app_total_grant_bytes(
+        &self,
+        app: AppId,
+        _capability: &ProcessManagementCapability,
+    ) -> usize {
+        self.kernel.process_map_or(0, app.idx(), |process| {
+            (0..self.kernel.get_grant_count())
+                .map(|grant_num| process.debug_grant_bytes(grant_num))
+                .sum()
+        })
+    }
 }
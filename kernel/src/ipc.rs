@@ -1,24 +1,114 @@
 //! Inter-process communication mechanism for Tock.
 //!
 //! This is a special syscall driver that allows userspace applications to
-//! share memory.
+//! share memory and to send each other small messages.
+//!
+//! Services are found by name. A process can register a service under any
+//! name, and clients can look services up by name or list all of them. For
+//! compatibility, a process can also be found by its package name. Ids handed
+//! out to a client become stale when the service process restarts, faults, or
+//! is terminated, and stay stale until the client looks the service up again.
+//!
+//! Messages are copied by the kernel. A process sends a message from its send
+//! buffer to a service, where it waits in a bounded per-process queue until
+//! the service is ready to receive it. The service can reply to the sender
+//! using the sender id it received with the message.
+//!
+//! The message queue of a process, and the table of services it looked up,
+//! are allocated in its grant region when they are first needed.
 
 /// Syscall number
 pub const DRIVER_NUM: usize = 0x00010000;
 
+use core::cmp;
+
 use callback::{AppId, Callback};
 use capabilities::MemoryAllocationCapability;
 use driver::Driver;
-use grant::Grant;
+use grant::{Grant, Owned};
 use mem::{AppSlice, Shared};
 use process;
 use returncode::ReturnCode;
 use sched::Kernel;
 
+/// Maximum size of a message payload, in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 32;
+
+/// How many messages can wait for a process to receive them.
+pub const MESSAGE_QUEUE_LEN: usize = 4;
+
+/// Subscribe number of the callback that delivers messages, and allow number
+/// of the buffer messages are copied into.
+const MESSAGE_RECEIVE: usize = 256;
+
+/// Allow number of the buffer messages are sent from.
+const MESSAGE_SEND: usize = 257;
+
+/// Maximum length of a registered service name, in bytes.
+pub const MAX_SERVICE_NAME_LEN: usize = 32;
+
+/// Allow number of the name to register a service under. Allowing a null
+/// buffer unregisters the service.
+const SERVICE_REGISTER: usize = 258;
+
+/// Allow number of the buffer service names are copied into when listing
+/// services.
+const SERVICE_LIST: usize = 259;
+
+/// A message waiting in the queue of the receiving process.
+#[derive(Clone, Copy)]
+struct Message {
+    /// Id of the sending process.
+    sender_id: usize,
+    reply: bool,
+    len: usize,
+    payload: [u8; MAX_MESSAGE_SIZE],
+}
+
+/// Messages waiting for a process to receive them. The queue is allocated in
+/// the grant region of a process when the first message is sent to it, so
+/// processes that do not receive messages do not pay for it.
+struct MessageQueue {
+    messages: [Message; MESSAGE_QUEUE_LEN],
+    head: usize,
+    count: usize,
+}
+
+impl MessageQueue {
+    fn new() -> MessageQueue {
+        MessageQueue {
+            messages: [Message {
+                sender_id: 0,
+                reply: false,
+                len: 0,
+                payload: [0; MAX_MESSAGE_SIZE],
+            }; MESSAGE_QUEUE_LEN],
+            head: 0,
+            count: 0,
+        }
+    }
+}
+
 struct IPCData {
     shared_memory: [Option<AppSlice<Shared, u8>>; 8],
     client_callbacks: [Option<Callback>; 8],
     callback: Option<Callback>,
+    message_callback: Option<Callback>,
+    send_buffer: Option<AppSlice<Shared, u8>>,
+    receive_buffer: Option<AppSlice<Shared, u8>>,
+    messages: Option<Owned<MessageQueue>>,
+    /// A message was copied into the receive buffer and the process has not
+    /// said it is done with it yet.
+    message_pending: bool,
+    /// Name of the service this process registered, valid up to
+    /// `service_name_len`. A length of zero means no service is registered.
+    service_name: [u8; MAX_SERVICE_NAME_LEN],
+    service_name_len: usize,
+    service_list_buffer: Option<AppSlice<Shared, u8>>,
+    /// For each process, the restart count it had when this process looked
+    /// up its service, if it did. Allocated with one entry per process slot
+    /// on the first lookup.
+    known_services: Option<Owned<[Option<usize>]>>,
 }
 
 impl Default for IPCData {
@@ -27,6 +117,52 @@ impl Default for IPCData {
             shared_memory: [None, None, None, None, None, None, None, None],
             client_callbacks: [None, None, None, None, None, None, None, None],
             callback: None,
+            message_callback: None,
+            send_buffer: None,
+            receive_buffer: None,
+            messages: None,
+            message_pending: false,
+            service_name: [0; MAX_SERVICE_NAME_LEN],
+            service_name_len: 0,
+            service_list_buffer: None,
+            known_services: None,
+        }
+    }
+}
+
+impl IPCData {
+    fn service_name(&self) -> Option<&[u8]> {
+        if self.service_name_len == 0 {
+            None
+        } else {
+            Some(&self.service_name[..self.service_name_len])
+        }
+    }
+
+    /// If the process is ready for another message, copy the oldest queued
+    /// message into its receive buffer and tell it about the message.
+    fn deliver_message(&mut self) {
+        if self.message_pending {
+            return;
+        }
+        let message = match self.messages {
+            Some(ref queue) if queue.count > 0 => queue.messages[queue.head],
+            _ => return,
+        };
+        let callback = match (self.receive_buffer.as_mut(), self.message_callback.as_mut()) {
+            (Some(buffer), Some(callback)) => {
+                let len = cmp::min(message.len, buffer.len());
+                buffer.as_mut()[..len].copy_from_slice(&message.payload[..len]);
+                callback
+            }
+            _ => return,
+        };
+        if callback.schedule(message.sender_id, message.len, message.reply as usize) {
+            self.messages.as_mut().map(|queue| {
+                queue.head = (queue.head + 1) % MESSAGE_QUEUE_LEN;
+                queue.count -= 1;
+            });
+            self.message_pending = true;
         }
     }
 }
@@ -42,6 +178,200 @@ impl IPC {
         }
     }
 
+    /// Copy a message from the send buffer of `appid` into the message queue
+    /// of the process with id `target_id`.
+    fn send_message(&self, appid: AppId, target_id: usize, len: usize, reply: bool) -> ReturnCode {
+        if len > MAX_MESSAGE_SIZE {
+            return ReturnCode::ESIZE;
+        }
+        let target = match self
+            .data
+            .kernel
+            .process_map_or(None, target_id.wrapping_sub(1), |p| Some(p.appid()))
+        {
+            Some(target) => target,
+            None => return ReturnCode::EINVAL,
+        };
+
+        let mut message = Message {
+            sender_id: appid.idx() + 1,
+            reply: reply,
+            len: len,
+            payload: [0; MAX_MESSAGE_SIZE],
+        };
+        let res = self
+            .data
+            .enter(appid, |data, _| match data.send_buffer {
+                Some(ref buffer) if buffer.len() >= len => {
+                    message.payload[..len].copy_from_slice(&buffer.as_ref()[..len]);
+                    ReturnCode::SUCCESS
+                }
+                Some(_) => ReturnCode::ESIZE,
+                None => ReturnCode::ERESERVE,
+            }).unwrap_or(ReturnCode::EBUSY);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        self.data
+            .enter(target, |data, allocator| {
+                if data.messages.is_none() {
+                    data.messages = allocator.alloc(MessageQueue::new()).ok();
+                }
+                let res = match data.messages {
+                    Some(ref mut queue) if queue.count == MESSAGE_QUEUE_LEN => ReturnCode::EBUSY,
+                    Some(ref mut queue) => {
+                        let tail = (queue.head + queue.count) % MESSAGE_QUEUE_LEN;
+                        queue.messages[tail] = message;
+                        queue.count += 1;
+                        ReturnCode::SUCCESS
+                    }
+                    None => ReturnCode::ENOMEM,
+                };
+                data.deliver_message();
+                res
+            }).unwrap_or(ReturnCode::ENOMEM)
+    }
+
+    /// The restart count of the process at index `idx`, or `None` if the
+    /// process cannot provide a service right now.
+    fn service_generation(&self, idx: usize) -> Option<usize> {
+        self.data
+            .kernel
+            .process_map_or(None, idx, |p| match p.get_state() {
+                process::State::Fault | process::State::Terminated => None,
+                _ => Some(p.debug_restart_count()),
+            })
+    }
+
+    /// Find the `n`th registered service whose process can currently provide
+    /// it, in process order. `filter` decides which services are counted.
+    fn find_service<F>(
+        &self,
+        n: usize,
+        filter: F,
+    ) -> Option<(usize, [u8; MAX_SERVICE_NAME_LEN], usize)>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let mut remaining = n;
+        let mut found = None;
+        for app in self.data.iter() {
+            if found.is_some() {
+                break;
+            }
+            app.enter(|data, _| {
+                let idx = data.appid().idx();
+                let matches = data.service_name().map_or(false, |name| filter(name));
+                if matches && self.service_generation(idx).is_some() {
+                    if remaining == 0 {
+                        found = Some((idx, data.service_name, data.service_name_len));
+                    } else {
+                        remaining -= 1;
+                    }
+                }
+            });
+        }
+        found
+    }
+
+    /// Remember which incarnation of the service at index `idx` the client
+    /// `appid` looked up, and return the id of the service.
+    fn remember_service(&self, appid: AppId, idx: usize) -> ReturnCode {
+        let generation = self.service_generation(idx);
+        let num_procs = self.data.kernel.number_of_process_slots();
+        self.data
+            .enter(appid, |data, allocator| {
+                if data.known_services.is_none() {
+                    data.known_services = allocator.alloc_slice(num_procs, None).ok();
+                }
+                match data.known_services.as_mut().and_then(|known| known.get_mut(idx)) {
+                    Some(known) => {
+                        *known = generation;
+                        ReturnCode::SuccessWithValue { value: idx + 1 }
+                    }
+                    None => ReturnCode::ENOMEM,
+                }
+            }).unwrap_or(ReturnCode::EBUSY)
+    }
+
+    /// Check that the service with id `target_id` has not restarted, faulted,
+    /// or been terminated since `appid` looked it up. Returns ECANCEL if it
+    /// has.
+    fn check_service(&self, appid: AppId, target_id: usize) -> ReturnCode {
+        let idx = target_id.wrapping_sub(1);
+        let known = self
+            .data
+            .enter(appid, |data, _| {
+                data.known_services
+                    .as_ref()
+                    .and_then(|known| known.get(idx).cloned())
+                    .unwrap_or(None)
+            })
+            .unwrap_or(None);
+        match known {
+            Some(generation) if self.service_generation(idx) != Some(generation) => {
+                ReturnCode::ECANCEL
+            }
+            _ => ReturnCode::SUCCESS,
+        }
+    }
+
+    /// Register `appid` as providing the service called `name`, or
+    /// unregister its service if `name` is `None`.
+    fn register_service(&self, appid: AppId, name: Option<AppSlice<Shared, u8>>) -> ReturnCode {
+        let mut service_name = [0; MAX_SERVICE_NAME_LEN];
+        let len = match name {
+            Some(ref name) if name.len() > MAX_SERVICE_NAME_LEN => return ReturnCode::ESIZE,
+            Some(ref name) if name.len() > 0 => {
+                service_name[..name.len()].copy_from_slice(name.as_ref());
+                name.len()
+            }
+            Some(_) => return ReturnCode::EINVAL,
+            None => 0,
+        };
+
+        if len > 0 {
+            let existing = self.find_service(0, |other| other == &service_name[..len]);
+            if existing.map_or(false, |(idx, _, _)| idx != appid.idx()) {
+                return ReturnCode::EBUSY;
+            }
+        }
+
+        self.data
+            .enter(appid, |data, _| {
+                data.service_name = service_name;
+                data.service_name_len = len;
+                ReturnCode::SUCCESS
+            }).unwrap_or(ReturnCode::ENOMEM)
+    }
+
+    /// Copy the name of the `index`th available service into the list buffer
+    /// of `appid` and return the id of the service.
+    fn list_service(&self, appid: AppId, index: usize) -> ReturnCode {
+        let (idx, name, len) = match self.find_service(index, |_| true) {
+            Some(service) => service,
+            None => return ReturnCode::EINVAL,
+        };
+        let res = self
+            .data
+            .enter(appid, |data, _| match data.service_list_buffer {
+                Some(ref mut buffer) => {
+                    let copy_len = cmp::min(len, buffer.len());
+                    buffer.as_mut()[..copy_len].copy_from_slice(&name[..copy_len]);
+                    if copy_len < buffer.len() {
+                        buffer.as_mut()[copy_len] = 0;
+                    }
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::ERESERVE,
+            }).unwrap_or(ReturnCode::EBUSY);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+        self.remember_service(appid, idx)
+    }
+
     pub unsafe fn schedule_callback(
         &self,
         appid: AppId,
@@ -60,11 +390,11 @@ impl IPC {
                     .map(|mut callback| {
                         self.data
                             .enter(otherapp, |otherdata, _| {
-                                if appid.idx() >= otherdata.shared_memory.len() {
-                                    return;
-                                }
-                                match otherdata.shared_memory[appid.idx()] {
-                                    Some(ref slice) => {
+                                // Processes can only share buffers with the
+                                // first eight processes, but the others are
+                                // still notified.
+                                match otherdata.shared_memory.get(appid.idx()) {
+                                    Some(&Some(ref slice)) => {
                                         slice.expose_to(appid);
                                         callback.schedule(
                                             otherapp.idx() + 1,
@@ -72,7 +402,7 @@ impl IPC {
                                             slice.ptr() as usize,
                                         );
                                     }
-                                    None => {
+                                    _ => {
                                         callback.schedule(otherapp.idx() + 1, 0, 0);
                                     }
                                 }
@@ -97,7 +427,8 @@ impl Driver for IPC {
             // Subscribe with subscribe_num == 0 is how a process registers
             // itself as an IPC service. Each process can only register as a
             // single IPC service. The identifier for the IPC service is the
-            // application name stored in the TBF header of the application.
+            // name registered with allow(258), or the application name stored
+            // in the TBF header of the application.
             // The callback that is passed to subscribe is called when another
             // process notifies the server process.
             0 => self
@@ -107,6 +438,20 @@ impl Driver for IPC {
                     ReturnCode::SUCCESS
                 }).unwrap_or(ReturnCode::EBUSY),
 
+            // subscribe(256)
+            //
+            // Register the callback that is called when a message has been
+            // copied into the receive buffer. It receives the id of the
+            // sender, the length of the message, and whether the message is a
+            // reply (1) or not (0).
+            MESSAGE_RECEIVE => self
+                .data
+                .enter(app_id, |data, _| {
+                    data.message_callback = callback;
+                    data.deliver_message();
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ReturnCode::EBUSY),
+
             // subscribe(>=1)
             //
             // Subscribe with subscribe_num >= 1 is how a client registers
@@ -128,24 +473,57 @@ impl Driver for IPC {
         }
     }
 
-    /// command is how notify() is implemented.
+    /// command is how notify() and message passing are implemented.
     /// Notifying an IPC service is done by setting client_or_svc to 0,
     /// and notifying an IPC client is done by setting client_or_svc to 1.
     /// In either case, the target_id is the same number as provided in a notify
     /// callback or as returned by allow.
     ///
-    /// Returns EINVAL if the other process doesn't exist.
+    /// Setting client_or_svc to 2 sends the first `len` bytes of the send
+    /// buffer to target_id as a message, and 3 sends them as a reply. Setting
+    /// it to 4 tells the kernel the process is done with the last message it
+    /// received, so the next one can be delivered; target_id is ignored.
+    ///
+    /// Setting client_or_svc to 5 copies the name of the service with index
+    /// target_id (counting from 0) into the service list buffer, and returns
+    /// the id of that service or EINVAL if there are fewer services.
+    ///
+    /// Returns EINVAL if the other process doesn't exist, and ECANCEL when
+    /// notifying or sending a message to a service that has restarted,
+    /// faulted, or been terminated since it was looked up. Sending a message
+    /// returns ERESERVE if there is no send buffer, ESIZE if the message is
+    /// longer than `MAX_MESSAGE_SIZE` or the send buffer, and EBUSY if the
+    /// queue of the other process is full.
     fn command(
         &self,
         target_id: usize,
         client_or_svc: usize,
-        _: usize,
+        len: usize,
         appid: AppId,
     ) -> ReturnCode {
-        let cb_type = if client_or_svc == 0 {
-            process::IPCType::Service
-        } else {
-            process::IPCType::Client
+        if client_or_svc == 0 || client_or_svc == 2 {
+            let res = self.check_service(appid, target_id);
+            if res != ReturnCode::SUCCESS {
+                return res;
+            }
+        }
+
+        let cb_type = match client_or_svc {
+            0 => process::IPCType::Service,
+            1 => process::IPCType::Client,
+            2 => return self.send_message(appid, target_id, len, false),
+            3 => return self.send_message(appid, target_id, len, true),
+            4 => {
+                return self
+                    .data
+                    .enter(appid, |data, _| {
+                        data.message_pending = false;
+                        data.deliver_message();
+                        ReturnCode::SUCCESS
+                    }).unwrap_or(ReturnCode::EBUSY)
+            }
+            5 => return self.list_service(appid, target_id),
+            _ => return ReturnCode::ENOSUPPORT,
         };
 
         self.data
@@ -164,7 +542,8 @@ impl Driver for IPC {
     ///
     /// If allow is called with target_id == 0, it is an IPC service discover
     /// call. The contents of the slice should be the string name of the IPC
-    /// service. If this mechanism can find that service, allow will return
+    /// service. Registered service names are searched first, then package
+    /// names. If this mechanism can find that service, allow will return
     /// an ID that can be used to notify that service. Otherwise an error will
     /// be returned.
     ///
@@ -172,6 +551,12 @@ impl Driver for IPC {
     /// application is explicitly sharing a slice with an IPC service (as
     /// specified by the target_id). allow() simply allows both processes to
     /// access the buffer, it does not signal the service.
+    ///
+    /// Target ids 256 and 257 set the buffers messages are received into and
+    /// sent from. Target id 258 registers the process as a service with the
+    /// name in the slice, or unregisters it if the slice is null. Target id
+    /// 259 sets the buffer service names are copied into when listing
+    /// services.
     fn allow(
         &self,
         appid: AppId,
@@ -181,6 +566,11 @@ impl Driver for IPC {
         if target_id == 0 {
             match slice {
                 Some(slice_data) => {
+                    let registered = self.find_service(0, |name| name == slice_data.as_ref());
+                    if let Some((idx, _, _)) = registered {
+                        return self.remember_service(appid, idx);
+                    }
+
                     let ret = self.data.kernel.process_until(|p| {
                         let s = p.get_process_name().as_bytes();
                         // are slices equal?
@@ -194,8 +584,8 @@ impl Driver for IPC {
                             ReturnCode::FAIL
                         }
                     });
-                    if ret != ReturnCode::FAIL {
-                        return ret;
+                    if let ReturnCode::SuccessWithValue { value } = ret {
+                        return self.remember_service(appid, value - 1);
                     }
                 }
                 None => {}
@@ -203,6 +593,34 @@ impl Driver for IPC {
 
             return ReturnCode::EINVAL; /* AppSlice must have non-zero length */
         }
+        if target_id == SERVICE_REGISTER {
+            return self.register_service(appid, slice);
+        }
+        if target_id == SERVICE_LIST {
+            return self
+                .data
+                .enter(appid, |data, _| {
+                    data.service_list_buffer = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ReturnCode::EBUSY);
+        }
+        if target_id == MESSAGE_RECEIVE || target_id == MESSAGE_SEND {
+            return self
+                .data
+                .enter(appid, |data, _| {
+                    if target_id == MESSAGE_RECEIVE {
+                        data.receive_buffer = slice;
+                        data.deliver_message();
+                    } else {
+                        data.send_buffer = slice;
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or(ReturnCode::EBUSY);
+        }
+        let res = self.check_service(appid, target_id);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
         return self
             .data
             .enter(appid, |data, _| {
@@ -27,6 +27,9 @@ pub mod debug;
 pub mod hil;
 pub mod introspection;
 pub mod ipc;
+#[macro_use]
+pub mod log;
+pub mod scheduler;
 pub mod syscall;
 
 mod callback;
@@ -43,7 +46,7 @@ mod tbfheader;
 pub use callback::{AppId, Callback};
 pub use driver::Driver;
 pub use grant::Grant;
-pub use mem::{AppPtr, AppSlice, Private, Shared};
+pub use mem::{AppPtr, AppSlice, Private, ReadOnlyAppSlice, Shared};
 pub use platform::systick::SysTick;
 pub use platform::{mpu, Chip, Platform};
 pub use platform::{ClockInterface, NoClockControl, NO_CLOCK_CONTROL};
@@ -56,4 +59,6 @@ pub use sched::Kernel;
 // processes.
 pub mod procs {
     pub use process::{load_processes, FaultResponse, FunctionCall, Process, ProcessType};
+    pub use process::{AppCredentials, AppVerifier, DynamicProcessLoader, RestartTimer, State};
+    pub use process::{FunctionCallSource, GrantAllocationFailure};
 }
@@ -0,0 +1,537 @@
+//! Kernel logging with levels, per-module filtering and a binary transport.
+//!
+//! `debug!` is fine for a quick print, but it formats every message and
+//! panics when the debug buffer fills up. The logging macros in this module
+//! instead tag each message with a level and the module it comes from, so a
+//! board can choose which messages to keep, and they drop (and count) a
+//! message that does not fit in the buffer rather than overflowing it.
+//!
+//! Messages are written to the same debug writer that `debug!` uses, in one of
+//! two formats:
+//!
+//! - `Format::Text` writes a line such as
+//!   `[WARN capsules::rf233] tx failed: 3`.
+//! - `Format::Binary` writes a compact record with the address of the static
+//!   `LogSite` that describes the message, followed by the arguments. The
+//!   format string never leaves the chip. `tools/log_decode.py` reads the
+//!   sites back out of the kernel ELF and expands the records on the host.
+//!
+//! Arguments must implement `LogArg`, which covers integers, `bool`, `char`
+//! and `&str`. The format string may use `{}`, `{:x}`, `{:X}` and `{:#x}`;
+//! any other specifier prints the argument as with `{}`. Arguments are only
+//! evaluated if the message is enabled.
+//!
+//! Logging does nothing until the board provides a `Logger` with
+//! `set_logger()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! # extern crate core;
+//! # #[macro_use] extern crate kernel;
+//! # use kernel::log::{Format, Level, Logger};
+//! # fn main() {
+//! // In the board's `reset_handler`, after setting up the debug writer.
+//! // Keep the radio quiet unless something goes wrong.
+//! static LOG_FILTERS: [(&str, Option<Level>); 1] = [("capsules::rf233", Some(Level::Warn))];
+//! # unsafe {
+//! let logger = static_init!(
+//!     Logger,
+//!     Logger::new(Some(Level::Info), &LOG_FILTERS, Format::Binary)
+//! );
+//! kernel::log::set_logger(logger);
+//! # }
+//!
+//! // Anywhere in the kernel or capsules.
+//! let channel = 26;
+//! log_info!("radio on channel {}", channel);
+//! log_error!("tx failed: {:#x}", 0x3u8);
+//! # }
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::fmt::{self, Write};
+use core::ptr;
+
+use debug::{self, DebugWriterWrapper};
+
+/// How important a log message is. Lower levels are more important.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// How log messages are written to the debug writer.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// One line of text for each message.
+    Text,
+    /// Records that `tools/log_decode.py` expands on the host.
+    Binary,
+}
+
+/// Describes one log statement. The logging macros create a static `LogSite`
+/// for every statement, and the binary format identifies a message by the
+/// address of its site.
+///
+/// The layout is fixed because `tools/log_decode.py` reads sites from the
+/// kernel ELF: on a 32-bit chip `fmt`, `module_path` and `file` are each a
+/// pointer and a length at offsets 0, 8 and 16, `line` is at offset 24 and
+/// `level` is the byte at offset 28.
+#[repr(C)]
+pub struct LogSite {
+    pub fmt: &'static str,
+    pub module_path: &'static str,
+    pub file: &'static str,
+    pub line: u32,
+    pub level: Level,
+}
+
+/// A log message argument, in the form the logger can encode.
+#[derive(Copy, Clone, Debug)]
+pub enum Arg<'a> {
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    Bool(bool),
+    Char(char),
+    Str(&'a str),
+}
+
+/// Types that can be arguments to the logging macros.
+pub trait LogArg {
+    fn log_arg(&self) -> Arg;
+}
+
+macro_rules! log_arg_impl {
+    ($variant:ident, $as_ty:ty, $($ty:ty),+) => {
+        $(
+            impl LogArg for $ty {
+                fn log_arg(&self) -> Arg {
+                    Arg::$variant(*self as $as_ty)
+                }
+            }
+        )+
+    };
+}
+
+log_arg_impl!(U32, u32, u8, u16, u32);
+log_arg_impl!(I32, i32, i8, i16, i32);
+log_arg_impl!(U64, u64, u64);
+log_arg_impl!(I64, i64, i64);
+#[cfg(target_pointer_width = "32")]
+log_arg_impl!(U32, u32, usize);
+#[cfg(target_pointer_width = "32")]
+log_arg_impl!(I32, i32, isize);
+#[cfg(not(target_pointer_width = "32"))]
+log_arg_impl!(U64, u64, usize);
+#[cfg(not(target_pointer_width = "32"))]
+log_arg_impl!(I64, i64, isize);
+
+impl LogArg for bool {
+    fn log_arg(&self) -> Arg {
+        Arg::Bool(*self)
+    }
+}
+
+impl LogArg for char {
+    fn log_arg(&self) -> Arg {
+        Arg::Char(*self)
+    }
+}
+
+impl<'a> LogArg for &'a str {
+    fn log_arg(&self) -> Arg {
+        Arg::Str(self)
+    }
+}
+
+/// Start of every binary record. It is not valid UTF-8 on its own, so a
+/// decoder can tell records apart from `debug!` text on the same output.
+const RECORD_START: u8 = 0xa5;
+
+/// Binary record kinds.
+const RECORD_MESSAGE: u8 = 0;
+const RECORD_DROPPED: u8 = 1;
+
+/// Binary argument tags.
+const ARG_U32: u8 = 0;
+const ARG_I32: u8 = 1;
+const ARG_U64: u8 = 2;
+const ARG_I64: u8 = 3;
+const ARG_BOOL: u8 = 4;
+const ARG_CHAR: u8 = 5;
+const ARG_STR: u8 = 6;
+
+/// Longest `&str` argument the binary format encodes. Longer strings are
+/// truncated.
+const MAX_STR_ARG_LEN: usize = 255;
+
+/// Logging configuration and statistics.
+pub struct Logger {
+    /// Most verbose level logged from modules without a filter. `None` turns
+    /// logging off.
+    max_level: Cell<Option<Level>>,
+    /// Module path prefixes and the most verbose level logged from them. The
+    /// longest matching prefix wins.
+    filters: &'static [(&'static str, Option<Level>)],
+    format: Cell<Format>,
+    /// Messages dropped because the debug buffer was full.
+    dropped: Cell<usize>,
+    /// Messages dropped since the last drop notice was written.
+    unreported_dropped: Cell<usize>,
+}
+
+/// The logger that the logging macros use.
+static mut LOGGER: Option<&'static Logger> = None;
+
+/// Function used by board main.rs to set the logger.
+pub unsafe fn set_logger(logger: &'static Logger) {
+    LOGGER = Some(logger);
+}
+
+/// Returns the logger, if the board set one.
+pub fn logger() -> Option<&'static Logger> {
+    unsafe { ptr::read(&LOGGER) }
+}
+
+impl Logger {
+    pub fn new(
+        max_level: Option<Level>,
+        filters: &'static [(&'static str, Option<Level>)],
+        format: Format,
+    ) -> Logger {
+        Logger {
+            max_level: Cell::new(max_level),
+            filters: filters,
+            format: Cell::new(format),
+            dropped: Cell::new(0),
+            unreported_dropped: Cell::new(0),
+        }
+    }
+
+    pub fn set_max_level(&self, max_level: Option<Level>) {
+        self.max_level.set(max_level);
+    }
+
+    pub fn set_format(&self, format: Format) {
+        self.format.set(format);
+    }
+
+    /// Number of messages dropped because the debug buffer was full.
+    pub fn dropped_count(&self) -> usize {
+        self.dropped.get()
+    }
+
+    /// Returns whether messages from `site` are logged.
+    pub fn enabled(&self, site: &LogSite) -> bool {
+        let mut max_level = self.max_level.get();
+        let mut matched_len = 0;
+        for &(prefix, level) in self.filters.iter() {
+            if prefix.len() >= matched_len && module_matches(site.module_path, prefix) {
+                max_level = level;
+                matched_len = prefix.len();
+            }
+        }
+        max_level.map_or(false, |max_level| site.level <= max_level)
+    }
+
+    fn log(&self, writer: &DebugWriterWrapper, site: &'static LogSite, args: &[Arg]) {
+        let format = self.format.get();
+        let mut available = writer.available_len();
+        let mut written = false;
+
+        let unreported = self.unreported_dropped.get();
+        if unreported > 0 {
+            let len = write_dropped(&mut Sink::counter(), format, unreported);
+            if len > available {
+                self.drop_message();
+                return;
+            }
+            write_dropped(&mut Sink::writer(writer), format, unreported);
+            self.unreported_dropped.set(0);
+            available -= len;
+            written = true;
+        }
+
+        let len = write_message(&mut Sink::counter(), format, site, args);
+        if len <= available {
+            write_message(&mut Sink::writer(writer), format, site, args);
+            written = true;
+        } else {
+            self.drop_message();
+        }
+
+        if written {
+            writer.publish_str();
+        }
+    }
+
+    fn drop_message(&self) {
+        self.dropped.set(self.dropped.get() + 1);
+        self.unreported_dropped
+            .set(self.unreported_dropped.get() + 1);
+    }
+}
+
+/// Returns whether `module_path` is the module `prefix` or one of its
+/// submodules.
+fn module_matches(module_path: &str, prefix: &str) -> bool {
+    module_path.starts_with(prefix)
+        && (module_path.len() == prefix.len() || module_path[prefix.len()..].starts_with("::"))
+}
+
+/// Used by the logging macros to check whether to evaluate the arguments.
+pub fn enabled(site: &LogSite) -> bool {
+    logger().map_or(false, |logger| logger.enabled(site))
+}
+
+/// Used by the logging macros to write a message.
+pub fn log(site: &'static LogSite, args: &[Arg]) {
+    unsafe {
+        match (logger(), debug::try_get_debug_writer()) {
+            (Some(logger), Some(writer)) => logger.log(writer, site, args),
+            _ => {}
+        }
+    }
+}
+
+/// Either counts the bytes written to it, or writes them to the debug writer.
+struct Sink<'a> {
+    writer: Option<&'a DebugWriterWrapper>,
+    len: usize,
+}
+
+impl Sink<'a> {
+    fn counter() -> Sink<'a> {
+        Sink {
+            writer: None,
+            len: 0,
+        }
+    }
+
+    fn writer(writer: &'a DebugWriterWrapper) -> Sink<'a> {
+        Sink {
+            writer: Some(writer),
+            len: 0,
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.writer.map(|writer| writer.write_bytes(bytes));
+        self.len += bytes.len();
+    }
+
+    fn word(&mut self, value: u32) {
+        self.bytes(&[
+            value as u8,
+            (value >> 8) as u8,
+            (value >> 16) as u8,
+            (value >> 24) as u8,
+        ]);
+    }
+
+    fn double_word(&mut self, value: u64) {
+        self.word(value as u32);
+        self.word((value >> 32) as u32);
+    }
+}
+
+impl Write for Sink<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Write a drop notice and return its length.
+fn write_dropped(sink: &mut Sink, format: Format, count: usize) -> usize {
+    match format {
+        Format::Text => {
+            let _ = write!(sink, "[log] {} messages dropped\r\n", count);
+        }
+        Format::Binary => {
+            sink.bytes(&[RECORD_START, RECORD_DROPPED]);
+            sink.word(count as u32);
+        }
+    }
+    sink.len
+}
+
+/// Write a message and return its length.
+fn write_message(sink: &mut Sink, format: Format, site: &'static LogSite, args: &[Arg]) -> usize {
+    match format {
+        Format::Text => {
+            let _ = write!(sink, "[{} {}] ", site.level.as_str(), site.module_path);
+            write_text(sink, site.fmt, args);
+            sink.bytes(b"\r\n");
+        }
+        Format::Binary => {
+            sink.bytes(&[RECORD_START, RECORD_MESSAGE]);
+            sink.word(site as *const LogSite as usize as u32);
+            sink.bytes(&[args.len() as u8]);
+            for arg in args.iter() {
+                write_binary_arg(sink, arg);
+            }
+        }
+    }
+    sink.len
+}
+
+/// Expand the placeholders in `fmt` with `args`.
+fn write_text(sink: &mut Sink, fmt: &str, args: &[Arg]) {
+    let mut args = args.iter();
+    let mut rest = fmt;
+    while let Some(index) = rest.find(|c| c == '{' || c == '}') {
+        let _ = sink.write_str(&rest[..index]);
+        let (brace, after) = rest[index..].split_at(1);
+        if after.starts_with(brace) {
+            // An escaped `{{` or `}}`.
+            let _ = sink.write_str(brace);
+            rest = &after[1..];
+        } else if brace == "{" {
+            let end = after.find('}').unwrap_or(after.len());
+            let spec = after[..end].rsplit(':').next().unwrap_or("");
+            args.next().map(|arg| write_text_arg(sink, spec, arg));
+            rest = &after[cmp::min(end + 1, after.len())..];
+        } else {
+            let _ = sink.write_str(brace);
+            rest = after;
+        }
+    }
+    let _ = sink.write_str(rest);
+}
+
+fn write_text_arg(sink: &mut Sink, spec: &str, arg: &Arg) {
+    let _ = match (spec, *arg) {
+        ("x", Arg::U32(v)) => write!(sink, "{:x}", v),
+        ("x", Arg::I32(v)) => write!(sink, "{:x}", v),
+        ("x", Arg::U64(v)) => write!(sink, "{:x}", v),
+        ("x", Arg::I64(v)) => write!(sink, "{:x}", v),
+        ("X", Arg::U32(v)) => write!(sink, "{:X}", v),
+        ("X", Arg::I32(v)) => write!(sink, "{:X}", v),
+        ("X", Arg::U64(v)) => write!(sink, "{:X}", v),
+        ("X", Arg::I64(v)) => write!(sink, "{:X}", v),
+        ("#x", Arg::U32(v)) => write!(sink, "{:#x}", v),
+        ("#x", Arg::I32(v)) => write!(sink, "{:#x}", v),
+        ("#x", Arg::U64(v)) => write!(sink, "{:#x}", v),
+        ("#x", Arg::I64(v)) => write!(sink, "{:#x}", v),
+        (_, Arg::U32(v)) => write!(sink, "{}", v),
+        (_, Arg::I32(v)) => write!(sink, "{}", v),
+        (_, Arg::U64(v)) => write!(sink, "{}", v),
+        (_, Arg::I64(v)) => write!(sink, "{}", v),
+        (_, Arg::Bool(v)) => write!(sink, "{}", v),
+        (_, Arg::Char(v)) => write!(sink, "{}", v),
+        (_, Arg::Str(v)) => sink.write_str(v),
+    };
+}
+
+fn write_binary_arg(sink: &mut Sink, arg: &Arg) {
+    match *arg {
+        Arg::U32(v) => {
+            sink.bytes(&[ARG_U32]);
+            sink.word(v);
+        }
+        Arg::I32(v) => {
+            sink.bytes(&[ARG_I32]);
+            sink.word(v as u32);
+        }
+        Arg::U64(v) => {
+            sink.bytes(&[ARG_U64]);
+            sink.double_word(v);
+        }
+        Arg::I64(v) => {
+            sink.bytes(&[ARG_I64]);
+            sink.double_word(v as u64);
+        }
+        Arg::Bool(v) => sink.bytes(&[ARG_BOOL, v as u8]),
+        Arg::Char(v) => {
+            sink.bytes(&[ARG_CHAR]);
+            sink.word(v as u32);
+        }
+        Arg::Str(v) => {
+            let bytes = &v.as_bytes()[..cmp::min(v.len(), MAX_STR_ARG_LEN)];
+            sink.bytes(&[ARG_STR, bytes.len() as u8]);
+            sink.bytes(bytes);
+        }
+    }
+}
+
+/// Log a message at the given level.
+///
+/// ```
+/// # #[macro_use] extern crate kernel;
+/// # use kernel::log::Level;
+/// # fn main() {
+/// log!(Level::Warn, "retrying after {} failures", 3);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $fmt:expr $(, $arg:expr)* $(,)*) => ({
+        static _LOG_SITE: $crate::log::LogSite = $crate::log::LogSite {
+            fmt: $fmt,
+            module_path: module_path!(),
+            file: file!(),
+            line: line!(),
+            level: $level,
+        };
+        if $crate::log::enabled(&_LOG_SITE) {
+            if false {
+                // Have the compiler check the format string and arguments.
+                let _ = format_args!($fmt $(, $arg)*);
+            }
+            $crate::log::log(&_LOG_SITE, &[$($crate::log::LogArg::log_arg(&$arg)),*]);
+        }
+    });
+}
+
+/// Log a message at `Level::Error`.
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)+) => (log!($crate::log::Level::Error, $($arg)+));
+}
+
+/// Log a message at `Level::Warn`.
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)+) => (log!($crate::log::Level::Warn, $($arg)+));
+}
+
+/// Log a message at `Level::Info`.
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)+) => (log!($crate::log::Level::Info, $($arg)+));
+}
+
+/// Log a message at `Level::Debug`.
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)+) => (log!($crate::log::Level::Debug, $($arg)+));
+}
+
+/// Log a message at `Level::Trace`.
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)+) => (log!($crate::log::Level::Trace, $($arg)+));
+}
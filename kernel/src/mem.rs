@@ -128,3 +128,54 @@ impl<L, T> AsMut<[T]> for AppSlice<L, T> {
         unsafe { slice::from_raw_parts_mut(self.ptr.ptr.as_mut(), self.len) }
     }
 }
+
+/// Buffer of memory shared read-only from an app to the kernel.
+///
+/// This is the type created after an app calls the `allow_readonly` syscall.
+/// Unlike an `AppSlice`, the buffer may be in the app's flash, so the kernel
+/// can only read it.
+pub struct ReadOnlyAppSlice<T> {
+    ptr: *const T,
+    len: usize,
+    process: AppId,
+}
+
+impl<T> ReadOnlyAppSlice<T> {
+    crate fn new(ptr: *const T, len: usize, appid: AppId) -> ReadOnlyAppSlice<T> {
+        ReadOnlyAppSlice {
+            ptr: ptr,
+            len: len,
+            process: appid,
+        }
+    }
+
+    /// Number of bytes in the `ReadOnlyAppSlice`.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Get the raw pointer to the buffer. This will be a pointer inside of the
+    /// app's flash or memory region.
+    pub fn ptr(&self) -> *const T {
+        self.ptr
+    }
+
+    /// The app that shared the buffer.
+    pub fn appid(&self) -> AppId {
+        self.process
+    }
+
+    pub fn iter(&self) -> slice::Iter<T> {
+        self.as_ref().iter()
+    }
+
+    pub fn chunks(&self, size: usize) -> slice::Chunks<T> {
+        self.as_ref().chunks(size)
+    }
+}
+
+impl<T> AsRef<[T]> for ReadOnlyAppSlice<T> {
+    fn as_ref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
@@ -31,11 +31,28 @@ use returncode::ReturnCode;
 /// - `10`: Specify where the start of the app stack is. This tells the kernel
 ///   where the app has put the start of its stack. This is not strictly
 ///   necessary for correct operation, but allows for better debugging if the
-///   app crashes.
+///   app crashes. If the MPU supports it, the kernel also protects the bottom
+///   of the stack with a guard region, so a stack overflow faults as soon as
+///   it reaches the guard and is reported as such.
 /// - `11`: Specify where the start of the app heap is. This tells the kernel
 ///   where the app has put the start of its heap. This is not strictly
 ///   necessary for correct operation, but allows for better debugging if the
-///   app crashes.
+///   app crashes. The stack guard region is only used if the heap is above
+///   the stack.
+/// - `12`: Get the driver number of the driver that most recently failed to
+///   allocate grant memory for the app. Returns FAIL if no grant allocation
+///   has failed since the app started, or if the memory was not allocated
+///   while handling a system call, so the driver is not known.
+/// - `13`: Get the number of bytes requested by the most recent grant
+///   allocation that failed. Returns 0 if no grant allocation has failed since
+///   the app started.
+/// - `14`: Set the function the kernel calls when callbacks for the app were
+///   dropped because its callback queue was full. The function gets the number
+///   of dropped callbacks as its first argument, and is called as soon as
+///   there is room in the queue again. An address of 0 removes the function.
+///   Returns `EINVAL` if the address is not in the app's flash.
+/// - `15`: Get the number of callbacks that were dropped since the app
+///   started.
 crate fn memop(process: &ProcessType, op_type: usize, r1: usize) -> ReturnCode {
     match op_type {
         // Op Type 0: BRK
@@ -108,6 +125,25 @@ crate fn memop(process: &ProcessType, op_type: usize, r1: usize) -> ReturnCode {
             ReturnCode::SUCCESS
         }
 
+        // Op Type 12: Driver that failed to allocate grant memory.
+        12 => process
+            .grant_allocation_failure()
+            .and_then(|failure| failure.driver_number)
+            .map_or(ReturnCode::FAIL, |driver_number| ReturnCode::SuccessWithValue {
+                value: driver_number,
+            }),
+
+        // Op Type 13: Size of the grant allocation that failed.
+        13 => ReturnCode::SuccessWithValue {
+            value: process.grant_allocation_failure().map_or(0, |failure| failure.size),
+        },
+
+        // Op Type 14: Set the dropped callback handler.
+        14 => process.set_dropped_callback_handler(r1),
+
+        // Op Type 15: Number of dropped callbacks.
+        15 => ReturnCode::SuccessWithValue { value: process.debug_dropped_callback_count() },
+
         _ => ReturnCode::ENOSUPPORT,
     }
 }
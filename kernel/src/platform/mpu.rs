@@ -175,6 +175,44 @@ pub trait MPU {
         }
     }
 
+    /// Allocates a guard region that user mode cannot access.
+    ///
+    /// The guard region is placed inside app-owned memory, so unlike other
+    /// regions it overlaps the app memory region and takes precedence over
+    /// it. An implementation must place a region of at least `min_guard_size`
+    /// bytes within the specified stretch of memory and store it in `config`,
+    /// replacing any guard region already stored there.
+    ///
+    /// # Arguments
+    ///
+    /// `guard_memory_start`    : start of memory the guard may cover
+    /// `guard_memory_size`     : size of memory the guard may cover
+    /// `min_guard_size`        : minimum size of the guard region
+    /// `config`                : MPU region configuration
+    ///
+    /// # Return Value
+    ///
+    /// Returns the start and size of the guard region. If the MPU does not
+    /// support guard regions or it is infeasible to allocate one, returns None.
+    #[allow(unused_variables)]
+    fn allocate_guard_region(
+        &self,
+        guard_memory_start: *const u8,
+        guard_memory_size: usize,
+        min_guard_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<Region> {
+        None
+    }
+
+    /// Removes the guard region from `config`, if there is one.
+    ///
+    /// # Arguments
+    ///
+    /// `config`    : MPU region configuration
+    #[allow(unused_variables)]
+    fn remove_guard_region(&self, config: &mut Self::MpuConfig) {}
+
     /// Configures the MPU with the provided region configuration.
     ///
     /// An implementation must ensure that all memory locations not covered by
@@ -24,6 +24,13 @@ pub trait SysTick {
     /// Returns true if the timer has expired
     fn overflowed(&self) -> bool;
 
+    /// Returns the number of microseconds left until the timer expires.
+    ///
+    /// The kernel uses this to account for how long processes and interrupt
+    /// handling run. Implementations that cannot read the current timer value
+    /// may return 0, in which case no time is accounted.
+    fn get_value(&self) -> u32;
+
     /// Resets the timer
     ///
     /// Resets the timer to 0 and disables it
@@ -53,6 +60,10 @@ impl SysTick for () {
         false
     }
 
+    fn get_value(&self) -> u32 {
+        0
+    }
+
     fn greater_than(&self, _: u32) -> bool {
         true
     }
@@ -9,8 +9,9 @@ use callback::AppId;
 use capabilities::ProcessManagementCapability;
 use common::cells::MapCell;
 use common::{Queue, RingBuffer};
-use core::cmp::max;
-use mem::{AppSlice, Shared};
+use core::cmp::{self, max};
+use debug;
+use mem::{AppSlice, ReadOnlyAppSlice, Shared};
 use platform::mpu::{self, MPU};
 use returncode::ReturnCode;
 use sched::Kernel;
@@ -26,17 +27,21 @@ use tbfheader;
 /// `app_memory` buffer until either the memory is exhausted or the allocated
 /// number of processes are created, with process structures placed in the
 /// provided array. How process faults are handled by the kernel is also
-/// selected.
-pub fn load_processes<S: UserspaceKernelBoundary, M: MPU>(
+/// selected, although processes can override this in their TBF header.
+///
+/// Returns the part of `app_memory` that was not given to any process. Boards
+/// that want to load more processes at runtime can pass it to a
+/// `DynamicProcessLoader`.
+pub fn load_processes<'b, S: UserspaceKernelBoundary, M: MPU>(
     kernel: &'static Kernel,
     syscall: &'static S,
     mpu: &'static M,
     start_of_flash: *const u8,
-    app_memory: &mut [u8],
+    app_memory: &'b mut [u8],
     procs: &'static mut [Option<&'static ProcessType>],
     fault_response: FaultResponse,
     _capability: &ProcessManagementCapability,
-) {
+) -> &'b mut [u8] {
     let mut apps_in_flash_ptr = start_of_flash;
     let mut app_memory_ptr = app_memory.as_mut_ptr();
     let mut app_memory_size = app_memory.len();
@@ -70,6 +75,154 @@ pub fn load_processes<S: UserspaceKernelBoundary, M: MPU>(
             app_memory_size -= memory_offset;
         }
     }
+
+    let app_memory_used = app_memory.len() - app_memory_size;
+    &mut app_memory[app_memory_used..]
+}
+
+/// Loads processes after the kernel has started, for example after a new app
+/// has been written to flash.
+///
+/// Boards create the loader with the same process array they passed to
+/// `load_processes()` and the app memory that `load_processes()` did not use.
+/// New processes are placed in free (`None`) slots of the process array.
+pub struct DynamicProcessLoader<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> {
+    kernel: &'static Kernel,
+    syscall: &'static S,
+    mpu: &'static M,
+    procs: MapCell<&'static mut [Option<&'static ProcessType>]>,
+    /// Start of the app memory that has not been given to a process yet.
+    app_memory_ptr: Cell<*mut u8>,
+    /// Size of the app memory that has not been given to a process yet.
+    app_memory_size: Cell<usize>,
+    fault_response: FaultResponse,
+}
+
+impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> DynamicProcessLoader<S, M> {
+    pub fn new(
+        kernel: &'static Kernel,
+        syscall: &'static S,
+        mpu: &'static M,
+        procs: &'static mut [Option<&'static ProcessType>],
+        app_memory: &'static mut [u8],
+        fault_response: FaultResponse,
+    ) -> DynamicProcessLoader<S, M> {
+        DynamicProcessLoader {
+            kernel: kernel,
+            syscall: syscall,
+            mpu: mpu,
+            procs: MapCell::new(procs),
+            app_memory_ptr: Cell::new(app_memory.as_mut_ptr()),
+            app_memory_size: Cell::new(app_memory.len()),
+            fault_response: fault_response,
+        }
+    }
+
+    /// Validate the TBF image at the start of `app_flash` and start it as a
+    /// new process.
+    ///
+    /// `app_flash` must be the flash region the image was written to, and
+    /// must not overlap the flash of any existing process.
+    ///
+    /// ## Returns
+    ///
+    /// The `AppId` of the new process, or:
+    ///
+    /// - `EINVAL` if the TBF header is invalid, the app is disabled, the app
+    ///   fails verification, or `app_flash` overlaps an existing process.
+    /// - `ESIZE` if the app is larger than `app_flash`.
+    /// - `ENOMEM` if there is no free process slot or not enough free app
+    ///   memory for the process.
+    /// - `FAIL` if the process could not be created for any other reason.
+    pub fn load_process(
+        &self,
+        app_flash: &'static [u8],
+        _capability: &ProcessManagementCapability,
+    ) -> Result<AppId, ReturnCode> {
+        if app_flash.len() < mem::size_of::<tbfheader::TbfHeaderV2Base>() {
+            return Err(ReturnCode::EINVAL);
+        }
+
+        // Parsing the header and verifying the app read them through raw
+        // pointers, so check that both fit in `app_flash` first.
+        let (header_size, total_size) =
+            match unsafe { tbfheader::get_tbf_header_sizes(app_flash.as_ptr()) } {
+                Some(sizes) => sizes,
+                None => return Err(ReturnCode::EINVAL),
+            };
+        if header_size > app_flash.len() {
+            return Err(ReturnCode::EINVAL);
+        }
+        if total_size > app_flash.len() {
+            return Err(ReturnCode::ESIZE);
+        }
+
+        let header = match unsafe { tbfheader::parse_and_validate_tbf_header(app_flash.as_ptr()) }
+        {
+            Some(header) => header,
+            None => return Err(ReturnCode::EINVAL),
+        };
+        if !header.is_app() || !header.enabled() || !self.kernel.verify_app(&header) {
+            return Err(ReturnCode::EINVAL);
+        }
+
+        // Processes must start in Thumb mode. `Process::create()` treats this
+        // as a fatal error, which is fine at boot but not at runtime.
+        let init_fn = app_flash.as_ptr() as usize + header.get_init_function_offset() as usize;
+        if (init_fn & 0x1) != 1 {
+            return Err(ReturnCode::EINVAL);
+        }
+
+        // Don't load an app on top of one that is already running.
+        let flash_start = app_flash.as_ptr();
+        let flash_end = flash_start.wrapping_offset(header.get_total_size() as isize);
+        let overlaps = self.procs.map_or(true, |procs| {
+            procs.iter().any(|p| {
+                p.map_or(false, |process| {
+                    flash_start < process.flash_end() && process.flash_start() < flash_end
+                })
+            })
+        });
+        if overlaps {
+            return Err(ReturnCode::EINVAL);
+        }
+
+        self.procs
+            .map_or(Err(ReturnCode::FAIL), |procs| {
+                let index = match procs.iter().position(|p| p.is_none()) {
+                    Some(index) => index,
+                    None => return Err(ReturnCode::ENOMEM),
+                };
+
+                let (process, _, memory_offset) = unsafe {
+                    Process::create(
+                        self.kernel,
+                        self.syscall,
+                        self.mpu,
+                        flash_start,
+                        self.app_memory_ptr.get(),
+                        self.app_memory_size.get(),
+                        self.fault_response,
+                        index,
+                    )
+                };
+
+                match process {
+                    Some(process) => {
+                        procs[index] = Some(process);
+                        self.app_memory_ptr
+                            .set(self.app_memory_ptr.get().wrapping_offset(memory_offset as isize));
+                        self.app_memory_size
+                            .set(self.app_memory_size.get() - memory_offset);
+                        Ok(process.appid())
+                    }
+                    // We already checked that the header is valid, so the
+                    // process could only not be created because there is not
+                    // enough memory for it.
+                    None => Err(ReturnCode::ENOMEM),
+                }
+            })
+    }
 }
 
 /// This trait is implemented by process structs.
@@ -93,10 +246,24 @@ pub trait ProcessType {
     /// `None`.
     fn dequeue_task(&self) -> Option<Task>;
 
+    /// Set the function the kernel calls to tell the process that callbacks
+    /// were dropped because its queue was full. The function is called with
+    /// the number of dropped callbacks once there is room in the queue again.
+    /// A `handler` of 0 removes the function.
+    ///
+    /// Returns `EINVAL` if `handler` is not in the flash region of the app
+    /// (after its protected region), so the kernel never starts the process
+    /// somewhere else.
+    fn set_dropped_callback_handler(&self, handler: usize) -> ReturnCode;
+
     /// Returns the current state the process is in. Common states are "running"
     /// or "yielded".
     fn get_state(&self) -> State;
 
+    /// Returns whether this process has something to do. This is true if the
+    /// process is running, or if it is yielded and has pending tasks.
+    fn ready(&self) -> bool;
+
     /// Move this process from the running state to the yielded state.
     fn set_yielded_state(&self);
 
@@ -104,9 +271,40 @@ pub trait ProcessType {
     /// `FaultResponse` for this process to occur.
     fn set_fault_state(&self);
 
+    /// Stop the process. A stopped process is not scheduled, but keeps its
+    /// memory and any pending tasks, and can be resumed later. Has no effect if
+    /// the process is not running or yielded.
+    fn stop(&self);
+
+    /// Resume a stopped process. The process continues in the state it was in
+    /// when it was stopped. Has no effect if the process is not stopped.
+    fn resume(&self);
+
+    /// Terminate the process. Pending tasks are dropped and the grant memory
+    /// of the process is reclaimed. The process is not scheduled again unless
+    /// it is restarted.
+    fn terminate(&self);
+
+    /// Restart the process from its entry point. Pending tasks are dropped,
+    /// the grant memory of the process is reclaimed, and the process memory
+    /// break is reset to where it was when the process was first loaded.
+    fn restart(&self);
+
     /// Get the name of the process. Used for IPC.
     fn get_process_name(&self) -> &'static str;
 
+    /// Get the scheduling priority of the process from its TBF header. Lower
+    /// values are higher priority.
+    fn get_priority(&self) -> u32;
+
+    /// Returns whether the TBF header of the process allows it to use the
+    /// driver with number `driver_number`.
+    fn driver_permitted(&self, driver_number: usize) -> bool;
+
+    /// Returns whether the TBF header of the process allows it to call command
+    /// `command_number` of the driver with number `driver_number`.
+    fn command_permitted(&self, driver_number: usize, command_number: usize) -> bool;
+
     // memop operations
 
     /// Change the location of the program break and reallocate the MPU region
@@ -141,13 +339,13 @@ pub trait ProcessType {
     /// writeable flash region.
     fn get_writeable_flash_region(&self, region_index: usize) -> (u32, u32);
 
-    /// Debug function to update the kernel on where the stack starts for this
-    /// process. Processes are not required to call this through the memop
-    /// system call, but it aids in debugging the process.
+    /// Update the kernel on where the stack starts for this process.
+    /// Processes are not required to call this through the memop system call,
+    /// but it aids in debugging the process and lets the kernel place a guard
+    /// region below the stack.
     fn update_stack_start_pointer(&self, stack_pointer: *const u8);
 
-    /// Debug function to update the kernel on where the process heap starts.
-    /// Also optional.
+    /// Update the kernel on where the process heap starts. Also optional.
     fn update_heap_start_pointer(&self, heap_pointer: *const u8);
 
     // additional memop like functions
@@ -165,6 +363,20 @@ pub trait ProcessType {
         size: usize,
     ) -> Result<Option<AppSlice<Shared, u8>>, ReturnCode>;
 
+    /// Creates a `ReadOnlyAppSlice` from the given offset and size in process
+    /// flash or memory.
+    ///
+    /// ## Returns
+    ///
+    /// If the buffer is null, return None, signaling the capsule to delete the entry. If the
+    /// buffer is within the process's flash or accessible memory, returns a ReadOnlyAppSlice
+    /// wrapping that buffer. Otherwise, returns an error `ReturnCode`.
+    fn allow_readonly(
+        &self,
+        buf_start_addr: *const u8,
+        size: usize,
+    ) -> Result<Option<ReadOnlyAppSlice<u8>>, ReturnCode>;
+
     /// Get the first address of process's flash that isn't protected by the
     /// kernel. The protected range of flash contains the TBF header and
     /// potentially other state the kernel is storing on behalf of the process,
@@ -196,6 +408,15 @@ pub trait ProcessType {
     /// Get a pointer to the grant pointer for this grant number.
     unsafe fn grant_ptr(&self, grant_num: usize) -> *mut *mut u8;
 
+    /// Record that `size` bytes of grant memory could not be allocated for
+    /// the process. `driver_number` is the driver that asked for the memory,
+    /// if it is known.
+    fn set_grant_allocation_failure(&self, driver_number: Option<usize>, size: usize);
+
+    /// Returns the most recent grant allocation that failed since the process
+    /// started, if any.
+    fn grant_allocation_failure(&self) -> Option<GrantAllocationFailure>;
+
     // functions for processes that are architecture specific
 
     /// Get the syscall that the process called.
@@ -229,8 +450,39 @@ pub trait ProcessType {
     /// Returns how many times this process has been restarted.
     fn debug_restart_count(&self) -> usize;
 
-    /// Returns how many times this process has exceeded its timeslice.
+    /// Returns how many times this process has exceeded its timeslice and
+    /// was preempted.
     fn debug_timeslice_expiration_count(&self) -> usize;
+
+    /// Count that this process exceeded its timeslice. Called by the
+    /// scheduler when it preempts the process.
+    fn debug_timeslice_expired(&self);
+
+    /// Returns the total number of microseconds this process has spent
+    /// executing.
+    fn debug_cpu_time_us(&self) -> u64;
+
+    /// Add to the total time this process has spent executing. Called by the
+    /// scheduler after the process switches back to the kernel.
+    fn debug_add_cpu_time(&self, us: u32);
+
+    /// Returns how many subscribe, command, and allow syscalls this process
+    /// has made to the given driver number.
+    fn debug_driver_syscall_count(&self, driver_number: usize) -> usize;
+
+    /// Returns the driver number and syscall count of one entry in this
+    /// process's per-driver syscall histogram, or `None` if `index` does not
+    /// refer to a used entry.
+    fn debug_driver_syscall_histogram_entry(&self, index: usize) -> Option<(usize, usize)>;
+
+    /// Add to the number of bytes of grant memory allocated for the given
+    /// grant. Called when the grant region is created and when a capsule
+    /// allocates memory through the grant's `Allocator`.
+    fn debug_add_grant_bytes(&self, grant_num: usize, bytes: usize);
+
+    /// Returns how many bytes of grant memory have been allocated for the
+    /// given grant since the process started.
+    fn debug_grant_bytes(&self, grant_num: usize) -> usize;
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -253,17 +505,147 @@ impl From<Error> for ReturnCode {
     }
 }
 
+/// A grant allocation that failed because the process was out of memory.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct GrantAllocationFailure {
+    /// The driver that asked for the memory, or `None` if the memory was not
+    /// allocated while handling a system call (e.g. from an interrupt).
+    pub driver_number: Option<usize>,
+    /// How many bytes were requested.
+    pub size: usize,
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum State {
+    /// The process expects to be running code.
     Running,
+
+    /// The process is waiting for a callback.
     Yielded,
+
+    /// The process was running when it was stopped. It will be running again
+    /// when it is resumed.
+    StoppedRunning,
+
+    /// The process was yielded when it was stopped. It will be yielded again
+    /// when it is resumed.
+    StoppedYielded,
+
+    /// The process faulted and has not been restarted.
     Fault,
+
+    /// The process was terminated and its grant memory reclaimed. It can only
+    /// run again if it is restarted.
+    Terminated,
 }
 
+/// How the kernel responds when a process faults.
+///
+/// Boards choose a default response in `load_processes()`. Individual
+/// processes can override it with a fault response TLV in their TBF header,
+/// but only with a response that is no stricter than the board's, and never
+/// with `Panic`.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum FaultResponse {
+    /// Panic the kernel.
     Panic,
+
+    /// Restart the process immediately, no matter how many times it has
+    /// already been restarted.
     Restart,
+
+    /// Restart the process immediately until it has been restarted
+    /// `max_restarts` times. After that the process is left in the `Fault`
+    /// state.
+    RestartWithLimit { max_restarts: usize },
+
+    /// Restart the process after a delay that doubles with every restart,
+    /// starting at `initial_delay_ms`, until it has been restarted
+    /// `max_restarts` times. After that the process is left in the `Fault`
+    /// state.
+    ///
+    /// The delay requires a `RestartTimer` to be registered with the kernel.
+    /// Without one the process is restarted immediately.
+    RestartWithBackoff {
+        max_restarts: usize,
+        initial_delay_ms: u32,
+    },
+
+    /// Leave the process in the `Fault` state, with its memory intact, so it
+    /// can be inspected.
+    Stop,
+}
+
+impl FaultResponse {
+    /// How strict the response is, from restarting the process without
+    /// limit to panicking the kernel.
+    fn strictness(&self) -> usize {
+        match *self {
+            FaultResponse::Restart => 0,
+            FaultResponse::RestartWithLimit { .. } => 1,
+            FaultResponse::RestartWithBackoff { .. } => 1,
+            FaultResponse::Stop => 2,
+            FaultResponse::Panic => 3,
+        }
+    }
+}
+
+/// Restarts processes after a delay.
+///
+/// The kernel has no notion of time, so to support
+/// `FaultResponse::RestartWithBackoff` a board registers an implementation of
+/// this trait (typically backed by an alarm) with
+/// `Kernel::set_restart_timer()`. The implementation must restart the process
+/// with `Kernel::restart_process()` once the delay has passed.
+pub trait RestartTimer {
+    /// Restart process `app` after `delay_ms` milliseconds.
+    fn restart_after(&self, app: AppId, delay_ms: u32);
+
+    /// Forget a pending restart of process `app`, if there is one. The kernel
+    /// calls this when the process is terminated, restarted or resumed by
+    /// other means.
+    fn cancel(&self, app: AppId);
+}
+
+/// Credentials from an app's TBF header that show the app is authentic.
+///
+/// Each variant holds the raw credential data from the header.
+#[derive(Copy, Clone, Debug)]
+pub enum AppCredentials {
+    /// SHA-256 hash of the signed regions of the app (32 bytes).
+    Sha256(&'static [u8]),
+    /// Ed25519 signature over the signed regions of the app (64 bytes).
+    Ed25519(&'static [u8]),
+    /// ECDSA NIST P-256 signature over the SHA-256 hash of the signed regions
+    /// of the app, as `r` followed by `s` (64 bytes).
+    EcdsaP256(&'static [u8]),
+}
+
+impl AppCredentials {
+    /// The length of the credential data for this format.
+    pub fn len(&self) -> usize {
+        match *self {
+            AppCredentials::Sha256(_) => 32,
+            AppCredentials::Ed25519(_) => 64,
+            AppCredentials::EcdsaP256(_) => 64,
+        }
+    }
+}
+
+/// Decides whether an app is allowed to run.
+///
+/// A board that should only run trusted apps registers an implementation of
+/// this trait with `Kernel::set_app_verifier()` before loading processes. Once
+/// a verifier is set, the kernel only starts apps that have credentials in
+/// their TBF header that the verifier accepts. Apps without credentials are
+/// not started.
+pub trait AppVerifier {
+    /// Check `credentials` against the parts of the app they cover.
+    ///
+    /// `signed_regions` are the app's flash in order, leaving out the TBF
+    /// header checksum and the credential data. Returns `true` if the app may
+    /// run.
+    fn verify(&self, credentials: AppCredentials, signed_regions: &[&'static [u8]]) -> bool;
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -278,6 +660,18 @@ pub enum Task {
     IPC((AppId, IPCType)),
 }
 
+/// Where a function call for a process came from.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FunctionCallSource {
+    /// The kernel, for example to start the process.
+    Kernel,
+    /// A driver, through a callback the process passed with `subscribe`.
+    Driver {
+        driver_number: usize,
+        subscribe_number: usize,
+    },
+}
+
 /// Struct that defines a callback that can be passed to a process. The callback
 /// takes four arguments that are `Driver` and callback specific, so they are
 /// represented generically here.
@@ -286,6 +680,7 @@ pub enum Task {
 /// values, but this is architecture-dependent.
 #[derive(Copy, Clone, Debug)]
 pub struct FunctionCall {
+    pub source: FunctionCallSource,
     pub argument0: usize,
     pub argument1: usize,
     pub argument2: usize,
@@ -293,6 +688,33 @@ pub struct FunctionCall {
     pub pc: usize,
 }
 
+/// How many distinct driver numbers are tracked in the per-process syscall
+/// histogram. Syscalls to additional drivers are still counted in the total
+/// syscall count.
+const DRIVER_SYSCALL_HISTOGRAM_SIZE: usize = 8;
+
+/// Size of the region below the process stack that the process cannot access.
+/// 32 bytes is the smallest region the Cortex-M MPU supports.
+const STACK_GUARD_SIZE: usize = 32;
+
+/// How many callbacks can be queued for a process that does not set the queue
+/// length in its TBF header.
+const DEFAULT_CALLBACK_QUEUE_LENGTH: usize = 9;
+
+/// The most callbacks that can be queued for a process.
+const MAX_CALLBACK_QUEUE_LENGTH: usize = 255;
+
+/// Why a process faulted, as far as the kernel can tell.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum FaultCause {
+    /// The stack pointer was below the end of the stack guard region, or below
+    /// the start of process memory if there is no guard region.
+    StackOverflow,
+
+    /// Any other fault.
+    Other,
+}
+
 /// State for helping with debugging apps.
 ///
 /// These pointers and counters are not strictly required for kernel operation,
@@ -323,9 +745,21 @@ struct ProcessDebug {
     /// kernel has restarted it.
     restart_count: usize,
 
+    /// Why the process most recently faulted. This is not reset when the
+    /// process restarts.
+    last_fault: Option<FaultCause>,
+
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// Total time in microseconds the process has spent executing. This is not
+    /// reset when the process restarts.
+    cpu_time_us: u64,
+
+    /// Number of syscalls since the process started, per driver number. Each
+    /// used entry is a `(driver_number, count)` pair.
+    driver_syscall_counts: [Option<(usize, usize)>; DRIVER_SYSCALL_HISTOGRAM_SIZE],
 }
 
 pub struct Process<'a, S: 'static + UserspaceKernelBoundary, M: 'static + MPU> {
@@ -370,6 +804,14 @@ pub struct Process<'a, S: 'static + UserspaceKernelBoundary, M: 'static + MPU> {
     /// Pointer to the end of the allocated (and MPU protected) grant region.
     kernel_memory_break: Cell<*const u8>,
 
+    /// Number of bytes of grant memory allocated for each grant. This is kept
+    /// in the kernel-owned part of process memory, below the grant pointers.
+    grant_bytes: &'static [Cell<usize>],
+
+    /// The last grant allocation that failed, reported to the process through
+    /// the memop syscall.
+    grant_allocation_failure: Cell<Option<GrantAllocationFailure>>,
+
     /// Copy of where the kernel memory break is when the app is first started.
     /// This is handy if the app is restarted so we know where to reset
     /// the kernel_memory break to without having to recalculate it.
@@ -412,6 +854,18 @@ pub struct Process<'a, S: 'static + UserspaceKernelBoundary, M: 'static + MPU> {
     /// MPU regions are saved as a pointer-size pair.
     mpu_regions: [Cell<Option<mpu::Region>>; 6],
 
+    /// Region below the stack that the process cannot access, if the MPU
+    /// supports it and the process told us where its stack starts.
+    stack_guard: Cell<Option<mpu::Region>>,
+
+    /// Function in the process to call after callbacks were dropped, set
+    /// with the memop syscall.
+    dropped_callback_handler: Cell<Option<usize>>,
+
+    /// How many callbacks were dropped since the process was last told about
+    /// it.
+    unreported_dropped_callbacks: Cell<usize>,
+
     /// Essentially a list of callbacks that want to call functions in the
     /// process.
     tasks: MapCell<RingBuffer<'a, Task>>,
@@ -429,31 +883,68 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
     }
 
     fn enqueue_task(&self, task: Task) -> bool {
-        // If this app is in the `Fault` state then we shouldn't schedule
-        // any work for it.
-        if self.state.get() == State::Fault {
-            return false;
+        // If this app is in the `Fault` or `Terminated` state then we
+        // shouldn't schedule any work for it.
+        match self.state.get() {
+            State::Fault | State::Terminated => return false,
+            _ => {}
         }
 
-        self.kernel.increment_work();
-
         let ret = self.tasks.map_or(false, |tasks| tasks.enqueue(task));
 
-        // Make a note that we lost this callback if the enqueue function
-        // fails.
-        if ret == false {
+        if ret {
+            // Tasks for a stopped process are kept, but are not work for the
+            // kernel until the process is resumed.
+            if !self.is_stopped() {
+                self.kernel.increment_work();
+            }
+            return true;
+        }
+
+        // The queue is full. If a callback from the same driver and subscribe
+        // number is already queued, update it to use the new arguments rather
+        // than dropping this one.
+        let coalesced = match task {
+            Task::FunctionCall(FunctionCall {
+                source: source @ FunctionCallSource::Driver { .. },
+                ..
+            }) => self.tasks.map_or(false, |tasks| {
+                tasks
+                    .find_last_mut(|queued| match *queued {
+                        Task::FunctionCall(call) => call.source == source,
+                        Task::IPC(_) => false,
+                    })
+                    .map(|queued| *queued = task)
+                    .is_some()
+            }),
+            _ => false,
+        };
+
+        if !coalesced {
+            // Make a note that we lost this callback, and tell the process
+            // about it once there is room in the queue.
             self.debug.map(|debug| {
                 debug.dropped_callback_count += 1;
             });
+            self.unreported_dropped_callbacks
+                .set(self.unreported_dropped_callbacks.get() + 1);
         }
 
-        ret
+        coalesced
     }
 
     fn get_state(&self) -> State {
         self.state.get()
     }
 
+    fn ready(&self) -> bool {
+        match self.state.get() {
+            State::Running => true,
+            State::Yielded => self.tasks.map_or(false, |tasks| tasks.has_elements()),
+            _ => false,
+        }
+    }
+
     fn set_yielded_state(&self) {
         if self.state.get() == State::Running {
             self.state.set(State::Yielded);
@@ -462,85 +953,204 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
     }
 
     fn set_fault_state(&self) {
-        self.state.set(State::Fault);
+        let cause = if self.stack_overflowed() {
+            FaultCause::StackOverflow
+        } else {
+            FaultCause::Other
+        };
+        self.debug.map(|debug| {
+            debug.last_fault = Some(cause);
+        });
 
         match self.fault_response {
             FaultResponse::Panic => {
+                // Keep the fault for the crash log.
+                unsafe {
+                    let stored_state = self.stored_state.get();
+                    let registers = self.syscall.fault_registers(self.sp(), &stored_state);
+                    debug::set_process_fault(self.process_name, registers);
+                }
+                // Keep the pending tasks, so they are still there when the
+                // panic handler prints the state of the process.
+                self.remove_work();
+                self.state.set(State::Fault);
+
                 // process faulted. Panic and print status
                 panic!("Process {} had a fault", self.process_name);
             }
             FaultResponse::Restart => {
-                // Remove the tasks that were scheduled for the app from the
-                // amount of work queue.
-                let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
-                for _ in 0..tasks_len {
-                    self.kernel.decrement_work();
+                self.restart();
+            }
+            FaultResponse::RestartWithLimit { max_restarts } => {
+                if self.debug_restart_count() < max_restarts {
+                    self.restart();
+                } else {
+                    self.enter_fault_state();
                 }
+            }
+            FaultResponse::RestartWithBackoff {
+                max_restarts,
+                initial_delay_ms,
+            } => {
+                let restart_count = self.debug_restart_count();
+                if restart_count < max_restarts {
+                    // Double the delay for every restart so far, without
+                    // letting it overflow.
+                    let shift = if restart_count > 16 { 16 } else { restart_count };
+                    let delay_ms = initial_delay_ms.saturating_mul(1 << shift);
+
+                    // Wait in the fault state until the timer restarts us.
+                    self.enter_fault_state();
+                    if !self.kernel.restart_after(self.appid(), delay_ms) {
+                        self.restart();
+                    }
+                } else {
+                    self.enter_fault_state();
+                }
+            }
+            FaultResponse::Stop => {
+                self.enter_fault_state();
+            }
+        }
+    }
 
-                // And remove those tasks
-                self.tasks.map(|tasks| {
-                    tasks.empty();
-                });
+    fn stop(&self) {
+        match self.state.get() {
+            State::Running => {
+                self.remove_work();
+                self.state.set(State::StoppedRunning);
+            }
+            State::Yielded => {
+                self.remove_work();
+                self.state.set(State::StoppedYielded);
+            }
+            _ => {}
+        }
+    }
 
-                // Update debug information
-                self.debug.map(|debug| {
-                    // Mark that we restarted this process.
-                    debug.restart_count += 1;
+    fn resume(&self) {
+        match self.state.get() {
+            State::StoppedRunning => {
+                self.state.set(State::Running);
+                self.add_work();
+            }
+            State::StoppedYielded => {
+                self.state.set(State::Yielded);
+                self.add_work();
+            }
+            _ => {}
+        }
+    }
 
-                    // Reset some state for the process.
-                    debug.syscall_count = 0;
-                    debug.last_syscall = None;
-                    debug.dropped_callback_count = 0;
-                });
+    fn terminate(&self) {
+        self.remove_work();
+        self.tasks.map(|tasks| {
+            tasks.empty();
+        });
+        self.state.set(State::Terminated);
 
-                // We are going to start this process over again, so need
-                // the init_fn location.
-                let app_flash_address = self.flash_start();
-                let init_fn = unsafe {
-                    app_flash_address.offset(self.header.get_init_function_offset() as isize)
-                        as usize
-                };
-                self.state.set(State::Yielded);
+        // Reclaim the grant region and the memory the process had sbrk'd.
+        unsafe {
+            self.reset_memory();
+        }
+    }
 
-                // Need to reset the grant region.
-                unsafe {
-                    self.grant_ptrs_reset();
-                }
-                self.kernel_memory_break
-                    .set(self.original_kernel_memory_break);
-
-                // Reset other memory pointers.
-                self.app_break.set(self.original_app_break);
-                self.current_stack_pointer.set(self.original_stack_pointer);
-
-                // And queue up this app to be restarted.
-                let flash_protected_size = self.header.get_protected_size() as usize;
-                let flash_app_start = app_flash_address as usize + flash_protected_size;
-
-                self.tasks.map(|tasks| {
-                    tasks.enqueue(Task::FunctionCall(FunctionCall {
-                        pc: init_fn,
-                        argument0: flash_app_start,
-                        argument1: self.memory.as_ptr() as usize,
-                        argument2: self.memory.len() as usize,
-                        argument3: self.app_break.get() as usize,
-                    }));
-                });
+    fn restart(&self) {
+        // Remove the tasks that were scheduled for the app from the amount of
+        // work queue, and remove those tasks.
+        self.remove_work();
+        self.tasks.map(|tasks| {
+            tasks.empty();
+        });
 
-                self.kernel.increment_work();
-            }
+        // Update debug information
+        self.debug.map(|debug| {
+            // Mark that we restarted this process.
+            debug.restart_count += 1;
+
+            // Reset some state for the process.
+            debug.min_stack_pointer = self.original_stack_pointer;
+            debug.syscall_count = 0;
+            debug.last_syscall = None;
+            debug.dropped_callback_count = 0;
+            debug.driver_syscall_counts = [None; DRIVER_SYSCALL_HISTOGRAM_SIZE];
+        });
+        self.dropped_callback_handler.set(None);
+        self.unreported_dropped_callbacks.set(0);
+
+        // We are going to start this process over again, so need the init_fn
+        // location.
+        let app_flash_address = self.flash_start();
+        let init_fn = unsafe {
+            app_flash_address.offset(self.header.get_init_function_offset() as isize) as usize
+        };
+        self.state.set(State::Yielded);
+
+        // Need to reset the grant region and other memory pointers.
+        unsafe {
+            self.reset_memory();
         }
+
+        // And queue up this app to be restarted.
+        let flash_protected_size = self.header.get_protected_size() as usize;
+        let flash_app_start = app_flash_address as usize + flash_protected_size;
+
+        self.tasks.map(|tasks| {
+            tasks.enqueue(Task::FunctionCall(FunctionCall {
+                source: FunctionCallSource::Kernel,
+                pc: init_fn,
+                argument0: flash_app_start,
+                argument1: self.memory.as_ptr() as usize,
+                argument2: self.memory.len() as usize,
+                argument3: self.app_break.get() as usize,
+            }));
+        });
+
+        self.kernel.increment_work();
     }
 
     fn dequeue_task(&self) -> Option<Task> {
         self.tasks.map_or(None, |tasks| {
             tasks.dequeue().map(|cb| {
                 self.kernel.decrement_work();
+
+                // Now that there is room in the queue, tell the process if
+                // callbacks were dropped.
+                let dropped = self.unreported_dropped_callbacks.get();
+                if dropped > 0 {
+                    self.dropped_callback_handler.get().map(|handler| {
+                        let notification = Task::FunctionCall(FunctionCall {
+                            source: FunctionCallSource::Kernel,
+                            pc: handler,
+                            argument0: dropped,
+                            argument1: 0,
+                            argument2: 0,
+                            argument3: 0,
+                        });
+                        if tasks.enqueue(notification) {
+                            self.kernel.increment_work();
+                            self.unreported_dropped_callbacks.set(0);
+                        }
+                    });
+                }
                 cb
             })
         })
     }
 
+    fn set_dropped_callback_handler(&self, handler: usize) -> ReturnCode {
+        if handler == 0 {
+            self.dropped_callback_handler.set(None);
+        } else if handler >= self.flash_non_protected_start() as usize
+            && handler < self.flash_end() as usize
+        {
+            self.dropped_callback_handler.set(Some(handler));
+        } else {
+            return ReturnCode::EINVAL;
+        }
+        ReturnCode::SUCCESS
+    }
+
     fn mem_start(&self) -> *const u8 {
         self.memory.as_ptr()
     }
@@ -582,6 +1192,7 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
                 // we had could be entirely wrong by now.
                 debug.min_stack_pointer = stack_pointer;
             });
+            self.update_stack_guard();
         }
     }
 
@@ -590,6 +1201,7 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
             self.debug.map(|debug| {
                 debug.app_heap_start_pointer = Some(heap_pointer);
             });
+            self.update_stack_guard();
         }
     }
 
@@ -640,7 +1252,9 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
             .map_or(Err(Error::KernelError), |mut config| {
                 if new_break < self.allow_high_water_mark.get() || new_break >= self.mem_end() {
                     Err(Error::AddressOutOfBounds)
-                } else if new_break > self.kernel_memory_break.get() {
+                } else if new_break > self.kernel_memory_break.get()
+                    || new_break > self.grant_reserve_start()
+                {
                     Err(Error::OutOfMemory)
                 } else if let Err(_) = self.mpu.update_app_memory_region(
                     new_break,
@@ -682,7 +1296,37 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
         }
     }
 
+    fn allow_readonly(
+        &self,
+        buf_start_addr: *const u8,
+        size: usize,
+    ) -> Result<Option<ReadOnlyAppSlice<u8>>, ReturnCode> {
+        if buf_start_addr == ptr::null() {
+            // A null buffer means pass in `None` to the capsule
+            Ok(None)
+        } else if self.in_app_flash(buf_start_addr, size) {
+            // Flash cannot change underneath the capsule, so there is no
+            // watermark to adjust.
+            Ok(Some(ReadOnlyAppSlice::new(buf_start_addr, size, self.appid())))
+        } else if self.in_app_owned_memory(buf_start_addr, size) {
+            // Valid slice in memory, we need to adjust the app's watermark
+            // so the buffer stays accessible to the capsule.
+            let buf_end_addr = buf_start_addr.wrapping_offset(size as isize);
+            let new_water_mark = max(self.allow_high_water_mark.get(), buf_end_addr);
+            self.allow_high_water_mark.set(new_water_mark);
+            Ok(Some(ReadOnlyAppSlice::new(buf_start_addr, size, self.appid())))
+        } else {
+            Err(ReturnCode::EINVAL)
+        }
+    }
+
     unsafe fn alloc(&self, size: usize) -> Option<&mut [u8]> {
+        // A terminated process has had its grant memory reclaimed, and should
+        // not have any allocated until it is restarted.
+        if self.state.get() == State::Terminated {
+            return None;
+        }
+
         self.mpu_config.and_then(|mut config| {
             let new_break = self.kernel_memory_break.get().offset(-(size as isize));
             if new_break < self.app_break.get() {
@@ -708,10 +1352,33 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
         (self.mem_end() as *mut *mut u8).offset(-(grant_num + 1))
     }
 
+    fn set_grant_allocation_failure(&self, driver_number: Option<usize>, size: usize) {
+        self.grant_allocation_failure.set(Some(GrantAllocationFailure {
+            driver_number: driver_number,
+            size: size,
+        }));
+    }
+
+    fn grant_allocation_failure(&self) -> Option<GrantAllocationFailure> {
+        self.grant_allocation_failure.get()
+    }
+
     fn get_process_name(&self) -> &'static str {
         self.process_name
     }
 
+    fn get_priority(&self) -> u32 {
+        self.header.get_priority()
+    }
+
+    fn driver_permitted(&self, driver_number: usize) -> bool {
+        self.header.driver_permitted(driver_number)
+    }
+
+    fn command_permitted(&self, driver_number: usize, command_number: usize) -> bool {
+        self.header.command_permitted(driver_number, command_number)
+    }
+
     unsafe fn get_syscall(&self) -> Option<Syscall> {
         let last_syscall = self.syscall.get_syscall(self.sp());
 
@@ -719,6 +1386,25 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
         self.debug.map(|debug| {
             debug.syscall_count += 1;
             debug.last_syscall = last_syscall;
+
+            let driver_number = last_syscall.and_then(|syscall| syscall.driver_number());
+            driver_number.map(|driver_number| {
+                // Find the entry for this driver or the first free entry. If
+                // the histogram is full the syscall is not tracked per driver.
+                for entry in debug.driver_syscall_counts.iter_mut() {
+                    match *entry {
+                        Some((num, count)) if num == driver_number => {
+                            *entry = Some((num, count + 1));
+                            break;
+                        }
+                        None => {
+                            *entry = Some((driver_number, 1));
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            });
         });
 
         last_syscall
@@ -806,13 +1492,6 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
             if self.current_stack_pointer.get() < debug.min_stack_pointer {
                 debug.min_stack_pointer = self.current_stack_pointer.get();
             }
-
-            // More debugging help. If this occurred because of a timeslice
-            // expiration, mark that so we can check later if a process is
-            // exceeding its timeslices too often.
-            if switch_reason == syscall::ContextSwitchReason::TimesliceExpired {
-                debug.timeslice_expiration_count += 1;
-            }
         });
 
         Some(switch_reason)
@@ -835,6 +1514,54 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
             .map_or(0, |debug| debug.timeslice_expiration_count)
     }
 
+    fn debug_cpu_time_us(&self) -> u64 {
+        self.debug.map_or(0, |debug| debug.cpu_time_us)
+    }
+
+    fn debug_timeslice_expired(&self) {
+        self.debug.map(|debug| {
+            debug.timeslice_expiration_count += 1;
+        });
+    }
+
+    fn debug_add_cpu_time(&self, us: u32) {
+        self.debug.map(|debug| {
+            debug.cpu_time_us += us as u64;
+        });
+    }
+
+    fn debug_driver_syscall_count(&self, driver_number: usize) -> usize {
+        self.debug.map_or(0, |debug| {
+            debug
+                .driver_syscall_counts
+                .iter()
+                .filter_map(|entry| *entry)
+                .find(|&(num, _)| num == driver_number)
+                .map_or(0, |(_, count)| count)
+        })
+    }
+
+    fn debug_driver_syscall_histogram_entry(&self, index: usize) -> Option<(usize, usize)> {
+        self.debug.map_or(None, |debug| {
+            debug
+                .driver_syscall_counts
+                .get(index)
+                .map_or(None, |entry| *entry)
+        })
+    }
+
+    fn debug_add_grant_bytes(&self, grant_num: usize, bytes: usize) {
+        self.grant_bytes
+            .get(grant_num)
+            .map(|count| count.set(count.get() + bytes));
+    }
+
+    fn debug_grant_bytes(&self, grant_num: usize) -> usize {
+        self.grant_bytes
+            .get(grant_num)
+            .map_or(0, |count| count.get())
+    }
+
     unsafe fn fault_fmt(&self, writer: &mut Write) {
         self.syscall.fault_fmt(writer);
     }
@@ -893,18 +1620,27 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
         let last_syscall = self.debug.map(|debug| debug.last_syscall);
         let dropped_callback_count = self.debug.map_or(0, |debug| debug.dropped_callback_count);
         let restart_count = self.debug.map_or(0, |debug| debug.restart_count);
+        let cpu_time_us = self.debug.map_or(0, |debug| debug.cpu_time_us);
+        let last_fault = match self.debug.map_or(None, |debug| debug.last_fault) {
+            Some(FaultCause::StackOverflow) => "stack overflow",
+            Some(FaultCause::Other) => "generic fault",
+            None => "none",
+        };
 
         let _ = writer.write_fmt(format_args!(
             "\
              App: {}   -   [{:?}]\
              \r\n Events Queued: {}   Syscall Count: {}   Dropped Callback Count: {}\
-             \n Restart Count: {}\n",
+             \n Restart Count: {}   CPU Time (us): {}\
+             \n Last Fault: {}\n",
             self.process_name,
             self.state.get(),
             events_queued,
             syscall_count,
             dropped_callback_count,
             restart_count,
+            cpu_time_us,
+            last_fault,
         ));
 
         let _ = match last_syscall {
@@ -912,6 +1648,41 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
             None => writer.write_fmt(format_args!(" Last Syscall: None")),
         };
 
+        // Grant memory each capsule is using, numbered in the order the
+        // grants were created.
+        let _ = writer.write_fmt(format_args!("\r\n Grant Usage (bytes):"));
+        let mut grants_used = false;
+        for (grant_num, count) in self.grant_bytes.iter().enumerate() {
+            if count.get() > 0 {
+                let _ = writer.write_fmt(format_args!(" #{}: {}", grant_num, count.get()));
+                grants_used = true;
+            }
+        }
+        if !grants_used {
+            let _ = writer.write_fmt(format_args!(" None"));
+        }
+        match self.grant_allocation_failure.get() {
+            Some(GrantAllocationFailure {
+                driver_number: Some(driver_number),
+                size,
+            }) => {
+                let _ = writer.write_fmt(format_args!(
+                    "\r\n Grant Allocation Failed: {} bytes for driver {:#x}",
+                    size, driver_number
+                ));
+            }
+            Some(GrantAllocationFailure {
+                driver_number: None,
+                size,
+            }) => {
+                let _ = writer.write_fmt(format_args!(
+                    "\r\n Grant Allocation Failed: {} bytes",
+                    size
+                ));
+            }
+            None => {}
+        }
+
         let _ = writer.write_fmt(format_args!("\
 \r\n\
 \r\n ╔═══════════╤══════════════════════════════════════════╗\
@@ -985,6 +1756,11 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
                 return (None, app_flash_size, 0);
             }
 
+            // Apps that the board does not trust are skipped as well.
+            if !kernel.verify_app(&tbf_header) {
+                return (None, app_flash_size, 0);
+            }
+
             // Otherwise, actually load the app.
             let mut min_app_ram_size = tbf_header.get_minimum_app_ram_size() as usize;
             let process_name = tbf_header.get_package_name();
@@ -1014,9 +1790,18 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
             let grant_ptrs_num = kernel.get_grant_count_and_finalize();
             let grant_ptrs_offset = grant_ptrs_num * grant_ptr_size;
 
+            // Make room to count how much memory each grant uses.
+            let grant_bytes_offset = grant_ptrs_num * mem::size_of::<Cell<usize>>();
+
             // Allocate memory for callback ring buffer.
             let callback_size = mem::size_of::<Task>();
-            let callback_len = 10;
+            // The ring buffer holds one callback less than its length.
+            let callback_queue_length = tbf_header
+                .get_callback_queue_length()
+                .map_or(DEFAULT_CALLBACK_QUEUE_LENGTH, |length| {
+                    cmp::min(cmp::max(length as usize, 1), MAX_CALLBACK_QUEUE_LENGTH)
+                });
+            let callback_len = callback_queue_length + 1;
             let callbacks_offset = callback_len * callback_size;
 
             // Make room to store this process's metadata.
@@ -1025,15 +1810,19 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
             // Initial sizes of the app-owned and kernel-owned parts of process memory.
             // Provide the app with plenty of initial process accessible memory.
             let initial_kernel_memory_size =
-                grant_ptrs_offset + callbacks_offset + process_struct_offset;
+                grant_ptrs_offset + grant_bytes_offset + callbacks_offset + process_struct_offset;
             let initial_app_memory_size = 3 * 1024;
 
             if min_app_ram_size < initial_app_memory_size {
                 min_app_ram_size = initial_app_memory_size;
             }
 
+            // Grant memory the app asked the kernel to keep free for it.
+            let grant_reserve_size = tbf_header.get_grant_reserve_size() as usize;
+
             // Minimum memory size for the process.
-            let min_total_memory_size = min_app_ram_size + initial_kernel_memory_size;
+            let min_total_memory_size =
+                min_app_ram_size + grant_reserve_size + initial_kernel_memory_size;
 
             // Determine where process memory will go and allocate MPU region for app-owned memory.
             let (memory_start, memory_size) = match mpu.allocate_app_memory_region(
@@ -1076,6 +1865,14 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
                 *opt = ptr::null()
             }
 
+            // Followed by the grant memory counters, which start at zero.
+            kernel_memory_break = kernel_memory_break.offset(-(grant_bytes_offset as isize));
+            let grant_bytes =
+                slice::from_raw_parts_mut(kernel_memory_break as *mut Cell<usize>, grant_ptrs_num);
+            for count in grant_bytes.iter_mut() {
+                ptr::write(count, Cell::new(0));
+            }
+
             // Now that we know we have the space we can setup the memory
             // for the callbacks.
             kernel_memory_break = kernel_memory_break.offset(-(callbacks_offset as isize));
@@ -1106,6 +1903,8 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
             process.header = tbf_header;
             process.kernel_memory_break = Cell::new(kernel_memory_break);
             process.original_kernel_memory_break = kernel_memory_break;
+            process.grant_bytes = grant_bytes;
+            process.grant_allocation_failure = Cell::new(None);
             process.app_break = Cell::new(initial_sbrk_pointer);
             process.original_app_break = initial_sbrk_pointer;
             process.allow_high_water_mark = Cell::new(remaining_app_memory);
@@ -1116,7 +1915,15 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
 
             process.stored_state = Cell::new(Default::default());
             process.state = Cell::new(State::Yielded);
-            process.fault_response = fault_response;
+            process.fault_response = match process.header.get_fault_response() {
+                Some(response)
+                    if response != FaultResponse::Panic
+                        && response.strictness() <= fault_response.strictness() =>
+                {
+                    response
+                }
+                _ => fault_response,
+            };
 
             process.mpu = mpu;
             process.mpu_config = MapCell::new(mpu_config);
@@ -1128,6 +1935,9 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
                 Cell::new(None),
                 Cell::new(None),
             ];
+            process.stack_guard = Cell::new(None);
+            process.dropped_callback_handler = Cell::new(None);
+            process.unreported_dropped_callbacks = Cell::new(0);
             process.tasks = MapCell::new(tasks);
             process.process_name = process_name;
 
@@ -1139,7 +1949,10 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
                 last_syscall: None,
                 dropped_callback_count: 0,
                 restart_count: 0,
+                last_fault: None,
                 timeslice_expiration_count: 0,
+                cpu_time_us: 0,
+                driver_syscall_counts: [None; DRIVER_SYSCALL_HISTOGRAM_SIZE],
             });
 
             if (init_fn & 0x1) != 1 {
@@ -1155,6 +1968,7 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
 
             process.tasks.map(|tasks| {
                 tasks.enqueue(Task::FunctionCall(FunctionCall {
+                    source: FunctionCallSource::Kernel,
                     pc: init_fn,
                     argument0: flash_app_start,
                     argument1: process.memory.as_ptr() as usize,
@@ -1191,6 +2005,141 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
             && buf_end_addr <= self.app_break.get()
     }
 
+    /// Checks if the buffer represented by the passed in base pointer and size
+    /// is within the process's flash region.
+    fn in_app_flash(&self, buf_start_addr: *const u8, size: usize) -> bool {
+        let buf_end_addr = buf_start_addr.wrapping_offset(size as isize);
+
+        buf_end_addr >= buf_start_addr
+            && buf_start_addr >= self.flash_start()
+            && buf_end_addr <= self.flash_end()
+    }
+
+    /// Put the process in the `Fault` state without restarting it. Pending
+    /// tasks are dropped, but the process memory is left as is.
+    fn enter_fault_state(&self) {
+        self.remove_work();
+        self.tasks.map(|tasks| {
+            tasks.empty();
+        });
+        self.state.set(State::Fault);
+    }
+
+    /// Whether the process is in one of the stopped states.
+    fn is_stopped(&self) -> bool {
+        match self.state.get() {
+            State::StoppedRunning | State::StoppedYielded => true,
+            _ => false,
+        }
+    }
+
+    /// How many units of outstanding work this process has in its current
+    /// state: one if it is running, plus one for each pending task. Stopped,
+    /// faulted, and terminated processes have no outstanding work.
+    fn work_count(&self) -> usize {
+        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
+        match self.state.get() {
+            State::Running => tasks_len + 1,
+            State::Yielded => tasks_len,
+            _ => 0,
+        }
+    }
+
+    /// Remove this process's outstanding work from the kernel's work count.
+    /// Must be called before moving the process out of a state where it counts
+    /// as having work.
+    fn remove_work(&self) {
+        for _ in 0..self.work_count() {
+            self.kernel.decrement_work();
+        }
+    }
+
+    /// Add this process's outstanding work to the kernel's work count. Must be
+    /// called after moving the process into a state where it counts as having
+    /// work.
+    fn add_work(&self) {
+        for _ in 0..self.work_count() {
+            self.kernel.increment_work();
+        }
+    }
+
+    /// Reclaim grant memory and reset the app and kernel memory breaks to where
+    /// they were when the process was loaded.
+    unsafe fn reset_memory(&self) {
+        self.grant_ptrs_reset();
+        self.kernel_memory_break
+            .set(self.original_kernel_memory_break);
+        self.app_break.set(self.original_app_break);
+        self.current_stack_pointer.set(self.original_stack_pointer);
+
+        self.mpu_config.map(|config| {
+            let _ = self.mpu.update_app_memory_region(
+                self.original_app_break,
+                self.original_kernel_memory_break,
+                mpu::Permissions::ReadWriteExecute,
+                config,
+            );
+            self.mpu.remove_guard_region(config);
+        });
+        self.stack_guard.set(None);
+    }
+
+    /// Place the stack guard region at the bottom of the stack, below the
+    /// stack start the process told us about.
+    ///
+    /// The stack grows down from its start towards the start of process
+    /// memory, with data and the heap above it. If the process put its heap
+    /// below its stack instead, we do not know where the stack ends and do
+    /// not place a guard.
+    fn update_stack_guard(&self) {
+        let (stack_start, heap_start) = self.debug.map_or((None, None), |debug| {
+            (debug.app_stack_start_pointer, debug.app_heap_start_pointer)
+        });
+        let stack_start = match stack_start {
+            Some(stack_start) => stack_start,
+            None => return,
+        };
+
+        self.mpu_config.map(|config| {
+            let guard = if heap_start.map_or(false, |heap_start| heap_start < stack_start) {
+                None
+            } else {
+                self.mpu.allocate_guard_region(
+                    self.mem_start(),
+                    stack_start as usize - self.mem_start() as usize,
+                    STACK_GUARD_SIZE,
+                    config,
+                )
+            };
+            if guard.is_none() {
+                self.mpu.remove_guard_region(config);
+            }
+            self.stack_guard.set(guard);
+        });
+    }
+
+    /// Whether the stack pointer is, or has been since the process started,
+    /// below the end of the stack guard region. Without a guard region,
+    /// whether it has been below the start of process memory.
+    fn stack_overflowed(&self) -> bool {
+        let stack_limit = self.stack_guard.get().map_or(self.mem_start() as usize, |guard| {
+            guard.start_address() as usize + guard.size()
+        });
+        let min_stack_pointer = self
+            .debug
+            .map_or(self.current_stack_pointer.get(), |debug| debug.min_stack_pointer);
+        (self.current_stack_pointer.get() as usize) < stack_limit
+            || (min_stack_pointer as usize) < stack_limit
+    }
+
+    /// The lowest address of the grant memory reserved for the process in its
+    /// TBF header. The app break cannot move above this address.
+    fn grant_reserve_start(&self) -> *const u8 {
+        let grant_reserve_size = self.header.get_grant_reserve_size() as usize;
+        self.original_kernel_memory_break
+            .wrapping_offset(-(grant_reserve_size as isize))
+    }
+
     /// Reset all `grant_ptr`s to NULL.
     unsafe fn grant_ptrs_reset(&self) {
         let grant_ptrs_num = self.kernel.get_grant_count_and_finalize();
@@ -1199,6 +2148,10 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
             let ctr_ptr = (self.mem_end() as *mut *mut usize).offset(-(grant_num + 1));
             write_volatile(ctr_ptr, ptr::null_mut());
         }
+        for count in self.grant_bytes.iter() {
+            count.set(0);
+        }
+        self.grant_allocation_failure.set(None);
     }
 
     fn debug_set_max_stack_depth(&self) {
@@ -3,23 +3,44 @@
 use core::cell::Cell;
 use core::ptr::NonNull;
 
-use callback::Callback;
+use callback::{AppId, Callback};
 use capabilities;
-use common::cells::NumericCellExt;
+use common::cells::{NumericCellExt, OptionalCell};
+use common::dynamic_deferred_call::DynamicDeferredCall;
 use grant::Grant;
 use ipc;
 use memop;
 use platform::mpu::MPU;
 use platform::systick::SysTick;
 use platform::{Chip, Platform};
-use process::{self, Task};
+use process::{self, AppVerifier, RestartTimer, Task};
 use returncode::ReturnCode;
+use scheduler::{Scheduler, StoppedExecutingReason};
 use syscall::{ContextSwitchReason, Syscall};
+use tbfheader::TbfHeader;
 
-/// The time a process is permitted to run before being pre-empted
-const KERNEL_TICK_DURATION_US: u32 = 10000;
 /// Skip re-scheduling a process if its quanta is nearly exhausted
 const MIN_QUANTA_THRESHOLD_US: u32 = 500;
+/// Timer period used to measure execution time when the system tick timer is
+/// not being used to preempt processes
+const ACCOUNTING_TIMER_US: u32 = 100000;
+
+/// Returns how much time passed between two readings of the system tick timer,
+/// which counts down from `period_us` and reloaded `reloads` times in between.
+///
+/// The timer reads 0 after it is started until it loads `period_us`, and
+/// always reads 0 if it cannot be read. A start reading of 0 is therefore
+/// taken as `period_us`, and an end reading of 0 without a reload means that
+/// no time can be accounted.
+fn systick_elapsed_us(start_us: u32, end_us: u32, period_us: u32, reloads: u32) -> u32 {
+    if end_us == 0 && reloads == 0 {
+        return 0;
+    }
+    let start_us = if start_us == 0 { period_us } else { start_us };
+    start_us
+        .saturating_add(period_us.saturating_mul(reloads))
+        .saturating_sub(end_us)
+}
 
 /// Main object for the kernel. Each board will need to create one.
 pub struct Kernel {
@@ -37,6 +58,22 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+    /// Total time in microseconds the kernel has spent servicing interrupts.
+    interrupt_time_us: Cell<u64>,
+    /// Used to restart processes after a delay when their fault response asks
+    /// for it.
+    restart_timer: OptionalCell<&'static RestartTimer>,
+
+    /// Decides which apps are allowed to run. If not set, all apps are.
+    app_verifier: OptionalCell<&'static AppVerifier>,
+
+    /// The driver currently handling a system call, if any. Used to tell a
+    /// process which driver failed to allocate grant memory.
+    syscall_driver: Cell<Option<usize>>,
+
+    /// Deferred calls that capsules registered for. Pending calls are made
+    /// when the kernel services interrupts.
+    dynamic_deferred_call: OptionalCell<&'static DynamicDeferredCall>,
 }
 
 impl Kernel {
@@ -46,6 +83,11 @@ impl Kernel {
             processes: processes,
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            interrupt_time_us: Cell::new(0),
+            restart_timer: OptionalCell::empty(),
+            app_verifier: OptionalCell::empty(),
+            syscall_driver: Cell::new(None),
+            dynamic_deferred_call: OptionalCell::empty(),
         }
     }
 
@@ -74,7 +116,7 @@ impl Kernel {
     where
         F: FnOnce(&process::ProcessType) -> R,
     {
-        if process_index > self.processes.len() {
+        if process_index >= self.processes.len() {
             return default;
         }
         self.processes[process_index].map_or(default, |process| closure(process))
@@ -160,6 +202,96 @@ impl Kernel {
         self.grant_counter.get()
     }
 
+    /// Returns the number of grants that have been created, without
+    /// finalizing them.
+    crate fn get_grant_count(&self) -> usize {
+        self.grant_counter.get()
+    }
+
+    /// Returns the number of the driver that is handling a system call, or
+    /// `None` if the kernel is not handling a system call for a driver.
+    crate fn syscall_driver(&self) -> Option<usize> {
+        self.syscall_driver.get()
+    }
+
+    /// Returns the total number of microseconds the kernel has spent servicing
+    /// interrupts.
+    crate fn debug_interrupt_time_us(&self) -> u64 {
+        self.interrupt_time_us.get()
+    }
+
+    /// Set the timer used to restart processes after a delay. This is needed
+    /// for processes that use `FaultResponse::RestartWithBackoff`.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can call this
+    /// function.
+    pub fn set_restart_timer<C: capabilities::ProcessManagementCapability>(
+        &self,
+        restart_timer: &'static RestartTimer,
+        _c: &C,
+    ) {
+        self.restart_timer.set(restart_timer);
+    }
+
+    /// Ask the restart timer to restart a process after `delay_ms`
+    /// milliseconds. Returns `false` if no restart timer is set.
+    crate fn restart_after(&self, app: AppId, delay_ms: u32) -> bool {
+        self.restart_timer.map_or(false, |timer| {
+            timer.restart_after(app, delay_ms);
+            true
+        })
+    }
+
+    /// Tell the restart timer that it must not restart process `app` anymore.
+    fn cancel_restart(&self, app: AppId) {
+        self.restart_timer.map(|timer| timer.cancel(app));
+    }
+
+    /// Only load apps that `app_verifier` accepts.
+    ///
+    /// This must be called before processes are loaded to have any effect on
+    /// them.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can call this
+    /// function.
+    pub fn set_app_verifier<C: capabilities::ProcessManagementCapability>(
+        &self,
+        app_verifier: &'static AppVerifier,
+        _c: &C,
+    ) {
+        self.app_verifier.set(app_verifier);
+    }
+
+    /// Set the registry of deferred calls that capsules use. The kernel makes
+    /// pending deferred calls whenever it services interrupts.
+    ///
+    /// Only callers with the `MainLoopCapability` can call this function.
+    pub fn set_dynamic_deferred_call<C: capabilities::MainLoopCapability>(
+        &self,
+        dynamic_deferred_call: &'static DynamicDeferredCall,
+        _c: &C,
+    ) {
+        self.dynamic_deferred_call.set(dynamic_deferred_call);
+    }
+
+    /// Returns true if the chip has interrupts to service or a capsule is
+    /// waiting for a deferred call. The kernel stops running processes when
+    /// this is the case.
+    fn has_pending_interrupts<C: Chip>(&self, chip: &C) -> bool {
+        chip.has_pending_interrupts()
+            || self.dynamic_deferred_call.map_or(false, |ddc| ddc.has_pending())
+    }
+
+    /// Check whether the app with this header is allowed to run. Returns
+    /// `true` if no app verifier is set.
+    crate fn verify_app(&self, header: &TbfHeader) -> bool {
+        self.app_verifier.map_or(true, |verifier| {
+            header.get_credentials().map_or(false, |credentials| {
+                verifier.verify(credentials, &header.get_signed_regions())
+            })
+        })
+    }
+
     /// Cause all apps to fault.
     ///
     /// This will call `set_fault_state()` on each app, causing the app to enter
@@ -178,55 +310,191 @@ impl Kernel {
         }
     }
 
+    /// Stop a process. The process will not be scheduled until it is resumed
+    /// with `resume_process()`. Pending callbacks for the process are kept.
+    ///
+    /// Returns `EINVAL` if there is no process for `app`.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can call this
+    /// function.
+    pub fn stop_process<C: capabilities::ProcessManagementCapability>(
+        &self,
+        app: AppId,
+        _c: &C,
+    ) -> ReturnCode {
+        self.process_map_or(ReturnCode::EINVAL, app.idx(), |process| {
+            process.stop();
+            ReturnCode::SUCCESS
+        })
+    }
+
+    /// Resume a process that was stopped with `stop_process()`.
+    ///
+    /// This also cancels a delayed restart of the process, so a process that
+    /// faulted and is waiting for its `RestartWithBackoff` restart stays in
+    /// the fault state until it is restarted with `restart_process()`.
+    ///
+    /// Returns `EINVAL` if there is no process for `app`.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can call this
+    /// function.
+    pub fn resume_process<C: capabilities::ProcessManagementCapability>(
+        &self,
+        app: AppId,
+        _c: &C,
+    ) -> ReturnCode {
+        self.process_map_or(ReturnCode::EINVAL, app.idx(), |process| {
+            self.cancel_restart(app);
+            process.resume();
+            ReturnCode::SUCCESS
+        })
+    }
+
+    /// Terminate a process. Its pending callbacks are dropped and its grant
+    /// memory is reclaimed. The process does not run again unless it is
+    /// restarted with `restart_process()`, even if it was waiting for a
+    /// delayed restart after a fault.
+    ///
+    /// Returns `EINVAL` if there is no process for `app`.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can call this
+    /// function.
+    pub fn terminate_process<C: capabilities::ProcessManagementCapability>(
+        &self,
+        app: AppId,
+        _c: &C,
+    ) -> ReturnCode {
+        self.process_map_or(ReturnCode::EINVAL, app.idx(), |process| {
+            self.cancel_restart(app);
+            process.terminate();
+            ReturnCode::SUCCESS
+        })
+    }
+
+    /// Restart a process from its entry point, regardless of what state it is
+    /// in. Its pending callbacks are dropped and its grant memory is
+    /// reclaimed.
+    ///
+    /// Returns `EINVAL` if there is no process for `app`.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can call this
+    /// function.
+    pub fn restart_process<C: capabilities::ProcessManagementCapability>(
+        &self,
+        app: AppId,
+        _c: &C,
+    ) -> ReturnCode {
+        self.process_map_or(ReturnCode::EINVAL, app.idx(), |process| {
+            self.cancel_restart(app);
+            process.restart();
+            ReturnCode::SUCCESS
+        })
+    }
+
     /// Main loop.
-    pub fn kernel_loop<P: Platform, C: Chip>(
+    ///
+    /// The `scheduler` decides which process runs next and for how long.
+    pub fn kernel_loop<P: Platform, C: Chip, SC: Scheduler>(
         &'static self,
         platform: &P,
         chip: &C,
         ipc: Option<&ipc::IPC>,
-        _capability: &capabilities::MainLoopCapability,
+        scheduler: &SC,
+        capability: &capabilities::MainLoopCapability,
     ) {
         loop {
-            unsafe {
-                chip.service_pending_interrupts();
-
-                for p in self.processes.iter() {
-                    p.map(|process| {
-                        self.do_process(platform, chip, process, ipc);
-                    });
-                    if chip.has_pending_interrupts() {
-                        break;
-                    }
-                }
-
-                chip.atomic(|| {
-                    if !chip.has_pending_interrupts() && self.processes_blocked() {
-                        chip.sleep();
-                    }
-                });
-            };
+            self.kernel_loop_operation(platform, chip, ipc, scheduler, capability);
         }
     }
 
+    /// One iteration of the main loop: service pending interrupts, run
+    /// processes until there is an interrupt to service or no process is
+    /// ready, and then sleep if there is no work left.
+    ///
+    /// `kernel_loop()` calls this forever. It is public so that environments
+    /// that need to stop the kernel, such as host-side simulations, can drive
+    /// the main loop themselves.
+    pub fn kernel_loop_operation<P: Platform, C: Chip, SC: Scheduler>(
+        &'static self,
+        platform: &P,
+        chip: &C,
+        ipc: Option<&ipc::IPC>,
+        scheduler: &SC,
+        _capability: &capabilities::MainLoopCapability,
+    ) {
+        unsafe {
+            // Service interrupts, keeping track of how long it takes. The
+            // timer starts at the full period, so it is not sampled before
+            // servicing interrupts, when it may not have loaded the period
+            // yet. Without its interrupt, the timer can only tell whether it
+            // reloaded, not how often, so longer periods are undercounted.
+            let systick = chip.systick();
+            systick.reset();
+            systick.set_timer(ACCOUNTING_TIMER_US);
+            systick.enable(false);
+            chip.service_pending_interrupts();
+            self.dynamic_deferred_call.map(|ddc| ddc.call());
+            let reloads = if systick.overflowed() { 1 } else { 0 };
+            let elapsed_us = systick_elapsed_us(
+                ACCOUNTING_TIMER_US,
+                systick.get_value(),
+                ACCOUNTING_TIMER_US,
+                reloads,
+            );
+            systick.reset();
+            self.interrupt_time_us
+                .set(self.interrupt_time_us.get() + elapsed_us as u64);
+
+            while !self.has_pending_interrupts(chip) {
+                let decision = match scheduler.next(self) {
+                    Some(decision) => decision,
+                    None => break,
+                };
+                let reason = self.process_map_or(
+                    StoppedExecutingReason::NotRunnable,
+                    decision.process_index,
+                    |process| self.do_process(platform, chip, process, ipc, decision.timeslice_us),
+                );
+                scheduler.result(reason);
+            }
+
+            chip.atomic(|| {
+                if !self.has_pending_interrupts(chip) && self.processes_blocked() {
+                    chip.sleep();
+                }
+            });
+        };
+    }
+
+    /// Run a process until it has no more work to do, it exceeds its
+    /// timeslice, or an interrupt needs to be serviced. If `timeslice_us` is
+    /// `None` the process is not preempted by the system tick timer.
     unsafe fn do_process<P: Platform, C: Chip>(
         &self,
         platform: &P,
         chip: &C,
         process: &process::ProcessType,
         ipc: Option<&::ipc::IPC>,
-    ) {
+        timeslice_us: Option<u32>,
+    ) -> StoppedExecutingReason {
         let appid = process.appid();
         let systick = chip.systick();
+        let preemptive = timeslice_us.is_some();
+        let period_us = timeslice_us.unwrap_or(ACCOUNTING_TIMER_US);
         systick.reset();
-        systick.set_timer(KERNEL_TICK_DURATION_US);
-        systick.enable(true);
+        // The timer is started even if the process will not be preempted so
+        // that we can measure how long the process runs for.
+        systick.set_timer(period_us);
+        systick.enable(preemptive);
 
-        loop {
-            if chip.has_pending_interrupts()
-                || systick.overflowed()
-                || !systick.greater_than(MIN_QUANTA_THRESHOLD_US)
+        let reason = loop {
+            if self.has_pending_interrupts(chip) {
+                break StoppedExecutingReason::KernelPreemption;
+            }
+            if preemptive
+                && (systick.overflowed() || !systick.greater_than(MIN_QUANTA_THRESHOLD_US))
             {
-                break;
+                break StoppedExecutingReason::TimesliceExpired;
             }
 
             match process.get_state() {
@@ -236,11 +504,27 @@ impl Kernel {
                     // the process.
                     process.setup_mpu();
                     chip.mpu().enable_mpu();
+                    // The timer interrupt is enabled even if the process will
+                    // not be preempted, so that every reload of the timer is
+                    // counted. A process that is not preempted is resumed
+                    // right away.
                     systick.enable(true);
+                    let start_us = systick.get_value();
                     let context_switch_reason = process.switch_to();
+                    let end_us = systick.get_value();
                     systick.enable(false);
                     chip.mpu().disable_mpu();
 
+                    // Account for how long the process ran. If the timer
+                    // expired it has reloaded once since the process started.
+                    let reloads = match context_switch_reason {
+                        Some(ContextSwitchReason::TimesliceExpired) => 1,
+                        _ => 0,
+                    };
+                    process.debug_add_cpu_time(systick_elapsed_us(
+                        start_us, end_us, period_us, reloads,
+                    ));
+
                     // Now the process has returned back to the kernel. Check
                     // why and handle the process as appropriate.
                     match context_switch_reason {
@@ -249,8 +533,14 @@ impl Kernel {
                             process.set_fault_state();
                         }
                         Some(ContextSwitchReason::SyscallFired) => {
+                            // Remember which driver handles the syscall, so
+                            // grant allocation failures can name it.
+                            let syscall = process.get_syscall();
+                            self.syscall_driver
+                                .set(syscall.and_then(|syscall| syscall.driver_number()));
+
                             // Handle each of the syscalls.
-                            match process.get_syscall() {
+                            match syscall {
                                 Some(Syscall::MEMOP { operand, arg0 }) => {
                                     let res = memop::memop(process, operand, arg0);
                                     process.set_syscall_return_value(res.into());
@@ -269,19 +559,26 @@ impl Kernel {
                                     appdata,
                                 }) => {
                                     let callback_ptr = NonNull::new(callback_ptr);
-                                    let callback = callback_ptr
-                                        .map(|ptr| Callback::new(appid, appdata, ptr.cast()));
-
-                                    let res =
-                                        platform.with_driver(
+                                    let callback = callback_ptr.map(|ptr| {
+                                        Callback::new(
+                                            appid,
                                             driver_number,
-                                            |driver| match driver {
-                                                Some(d) => {
-                                                    d.subscribe(subdriver_number, callback, appid)
-                                                }
-                                                None => ReturnCode::ENODEVICE,
-                                            },
-                                        );
+                                            subdriver_number,
+                                            appdata,
+                                            ptr.cast(),
+                                        )
+                                    });
+
+                                    let res = if process.driver_permitted(driver_number) {
+                                        platform.with_driver(driver_number, |driver| match driver {
+                                            Some(d) => {
+                                                d.subscribe(subdriver_number, callback, appid)
+                                            }
+                                            None => ReturnCode::ENODEVICE,
+                                        })
+                                    } else {
+                                        ReturnCode::ENODEVICE
+                                    };
                                     process.set_syscall_return_value(res.into());
                                 }
                                 Some(Syscall::COMMAND {
@@ -290,16 +587,20 @@ impl Kernel {
                                     arg0,
                                     arg1,
                                 }) => {
-                                    let res =
-                                        platform.with_driver(
-                                            driver_number,
-                                            |driver| match driver {
-                                                Some(d) => {
-                                                    d.command(subdriver_number, arg0, arg1, appid)
-                                                }
-                                                None => ReturnCode::ENODEVICE,
-                                            },
-                                        );
+                                    let res = if !process.driver_permitted(driver_number) {
+                                        ReturnCode::ENODEVICE
+                                    } else if !process
+                                        .command_permitted(driver_number, subdriver_number)
+                                    {
+                                        ReturnCode::ENOSUPPORT
+                                    } else {
+                                        platform.with_driver(driver_number, |driver| match driver {
+                                            Some(d) => {
+                                                d.command(subdriver_number, arg0, arg1, appid)
+                                            }
+                                            None => ReturnCode::ENODEVICE,
+                                        })
+                                    };
                                     process.set_syscall_return_value(res.into());
                                 }
                                 Some(Syscall::ALLOW {
@@ -308,8 +609,8 @@ impl Kernel {
                                     allow_address,
                                     allow_size,
                                 }) => {
-                                    let res = platform.with_driver(driver_number, |driver| {
-                                        match driver {
+                                    let res = if process.driver_permitted(driver_number) {
+                                        platform.with_driver(driver_number, |driver| match driver {
                                             Some(d) => {
                                                 match process.allow(allow_address, allow_size) {
                                                     Ok(oslice) => {
@@ -319,20 +620,54 @@ impl Kernel {
                                                 }
                                             }
                                             None => ReturnCode::ENODEVICE,
-                                        }
-                                    });
+                                        })
+                                    } else {
+                                        ReturnCode::ENODEVICE
+                                    };
+                                    process.set_syscall_return_value(res.into());
+                                }
+                                Some(Syscall::ALLOW_READONLY {
+                                    driver_number,
+                                    subdriver_number,
+                                    allow_address,
+                                    allow_size,
+                                }) => {
+                                    let res = if process.driver_permitted(driver_number) {
+                                        platform.with_driver(driver_number, |driver| match driver {
+                                            Some(d) => match process
+                                                .allow_readonly(allow_address, allow_size)
+                                            {
+                                                Ok(slice) => {
+                                                    d.allow_readonly(appid, subdriver_number, slice)
+                                                }
+                                                Err(err) => err, /* memory not valid */
+                                            },
+                                            None => ReturnCode::ENODEVICE,
+                                        })
+                                    } else {
+                                        ReturnCode::ENODEVICE
+                                    };
                                     process.set_syscall_return_value(res.into());
                                 }
                                 _ => {}
                             }
+                            self.syscall_driver.set(None);
                         }
                         Some(ContextSwitchReason::TimesliceExpired) => {
+                            if !preemptive {
+                                // The timer only expired so that the time the
+                                // process ran is counted. Keep running it.
+                                continue;
+                            }
+                            // Mark this so we can check later if a process
+                            // is exceeding its timeslices too often.
+                            process.debug_timeslice_expired();
                             // break to handle other processes.
-                            break;
+                            break StoppedExecutingReason::TimesliceExpired;
                         }
                         Some(ContextSwitchReason::Interrupted) => {
                             // break to handle other processes.
-                            break;
+                            break StoppedExecutingReason::KernelPreemption;
                         }
                         None => {
                             // Something went wrong when switching to this
@@ -346,7 +681,7 @@ impl Kernel {
                     // If the process is yielded it might be waiting for a
                     // callback. If there is a task scheduled for this process
                     // go ahead and set the process to execute it.
-                    None => break,
+                    None => break StoppedExecutingReason::NoWorkLeft,
                     Some(cb) => match cb {
                         Task::FunctionCall(ccb) => {
                             process.push_function_call(ccb);
@@ -366,12 +701,18 @@ impl Kernel {
                         }
                     },
                 },
-                process::State::Fault => {
-                    // We should never be scheduling a process in fault.
-                    panic!("Attempted to schedule a faulty process");
+                process::State::Fault
+                | process::State::StoppedRunning
+                | process::State::StoppedYielded
+                | process::State::Terminated => {
+                    // The process faulted and was not restarted, or it was
+                    // stopped or terminated, possibly by a syscall it just
+                    // made. Move on to other processes.
+                    break StoppedExecutingReason::NotRunnable;
                 }
             }
-        }
+        };
         systick.reset();
+        reason
     }
 }
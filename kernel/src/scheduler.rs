@@ -0,0 +1,228 @@
+//! Scheduling policies for the kernel main loop.
+//!
+//! The kernel main loop does not decide on its own which process to run next.
+//! Instead, each time it is ready to execute a process it asks a `Scheduler`
+//! for a `SchedulingDecision`, runs the chosen process, and then reports back
+//! why the process stopped executing. Boards select a policy by passing the
+//! scheduler they want to `Kernel::kernel_loop()`.
+//!
+//! Three policies are provided:
+//!
+//! - `RoundRobinScheduler`: Processes take turns in process-slot order. The
+//!   scheduler keeps a cursor across calls, so a process that uses its entire
+//!   timeslice does not get to run again until every other ready process has
+//!   had a turn.
+//! - `PriorityScheduler`: The ready process with the highest priority (the
+//!   lowest value of the TBF header priority field) always runs. Processes
+//!   with equal priority are scheduled round-robin.
+//! - `CooperativeScheduler`: Round-robin, but processes are never preempted
+//!   by a timer. A process runs until it yields.
+//!
+//! Example board setup:
+//!
+//! ```ignore
+//! let scheduler = kernel::scheduler::RoundRobinScheduler::new(
+//!     kernel::scheduler::DEFAULT_TIMESLICE_US,
+//! );
+//! board_kernel.kernel_loop(&platform, chip, Some(&platform.ipc), &scheduler, &main_cap);
+//! ```
+
+use core::cell::Cell;
+
+use sched::Kernel;
+
+/// The default time a process is permitted to run before being preempted.
+pub const DEFAULT_TIMESLICE_US: u32 = 10000;
+
+/// What the kernel should do next, as decided by the scheduler.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SchedulingDecision {
+    /// Index in the process array of the process to run.
+    pub process_index: usize,
+
+    /// How long the process may run before it is preempted, in microseconds.
+    /// `None` means the process will not be preempted by the system tick timer
+    /// and runs until it yields or an interrupt occurs.
+    pub timeslice_us: Option<u32>,
+}
+
+/// Why a process returned control to the kernel loop.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StoppedExecutingReason {
+    /// The process yielded and has no more pending tasks.
+    NoWorkLeft,
+
+    /// The process used up its timeslice.
+    TimesliceExpired,
+
+    /// The kernel stopped the process to service a pending interrupt. The
+    /// process still has work to do.
+    KernelPreemption,
+
+    /// The process is no longer in a state where it can be scheduled, for
+    /// example because it faulted.
+    NotRunnable,
+}
+
+/// Interface for scheduling policies.
+pub trait Scheduler {
+    /// Decide which process should run next.
+    ///
+    /// Returns `None` if no process is ready to run, in which case the kernel
+    /// will go to sleep if it has no other work.
+    fn next(&self, kernel: &Kernel) -> Option<SchedulingDecision>;
+
+    /// Inform the scheduler why the process it selected with the most recent
+    /// call to `next()` stopped executing.
+    fn result(&self, reason: StoppedExecutingReason);
+}
+
+/// Find the first ready process, starting at `start` and wrapping around the
+/// process array.
+fn next_ready_from(kernel: &Kernel, start: usize) -> Option<usize> {
+    let num_procs = kernel.number_of_process_slots();
+    for i in 0..num_procs {
+        let index = (start + i) % num_procs;
+        if kernel.process_map_or(false, index, |process| process.ready()) {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Round-robin scheduler with a persistent cursor.
+pub struct RoundRobinScheduler {
+    timeslice_us: u32,
+    /// The process that is running or should run next.
+    cursor: Cell<usize>,
+}
+
+impl RoundRobinScheduler {
+    pub fn new(timeslice_us: u32) -> RoundRobinScheduler {
+        RoundRobinScheduler {
+            timeslice_us: timeslice_us,
+            cursor: Cell::new(0),
+        }
+    }
+}
+
+impl Scheduler for RoundRobinScheduler {
+    fn next(&self, kernel: &Kernel) -> Option<SchedulingDecision> {
+        next_ready_from(kernel, self.cursor.get()).map(|index| {
+            self.cursor.set(index);
+            SchedulingDecision {
+                process_index: index,
+                timeslice_us: Some(self.timeslice_us),
+            }
+        })
+    }
+
+    fn result(&self, reason: StoppedExecutingReason) {
+        // If the process was only interrupted it gets to continue when the
+        // kernel is done servicing the interrupt. Otherwise it is the next
+        // process's turn.
+        if reason != StoppedExecutingReason::KernelPreemption {
+            self.cursor.set(self.cursor.get() + 1);
+        }
+    }
+}
+
+/// Fixed priority scheduler.
+///
+/// Priorities come from the process's TBF header. Lower values are higher
+/// priority, and processes without a priority in their header run at the
+/// lowest priority.
+pub struct PriorityScheduler {
+    timeslice_us: u32,
+    /// The most recently scheduled process.
+    last: Cell<usize>,
+    /// Where to start looking for the next process. Processes with the same
+    /// priority take turns because this moves past `last` unless the process
+    /// was only interrupted.
+    start: Cell<usize>,
+}
+
+impl PriorityScheduler {
+    pub fn new(timeslice_us: u32) -> PriorityScheduler {
+        PriorityScheduler {
+            timeslice_us: timeslice_us,
+            last: Cell::new(0),
+            start: Cell::new(0),
+        }
+    }
+}
+
+impl Scheduler for PriorityScheduler {
+    fn next(&self, kernel: &Kernel) -> Option<SchedulingDecision> {
+        let num_procs = kernel.number_of_process_slots();
+        let mut best: Option<(usize, u32)> = None;
+
+        for i in 0..num_procs {
+            let index = (self.start.get() + i) % num_procs;
+            let candidate = kernel.process_map_or(None, index, |process| {
+                if process.ready() {
+                    Some(process.get_priority())
+                } else {
+                    None
+                }
+            });
+            if let Some(priority) = candidate {
+                if best.map_or(true, |(_, best_priority)| priority < best_priority) {
+                    best = Some((index, priority));
+                }
+            }
+        }
+
+        best.map(|(index, _)| {
+            self.last.set(index);
+            SchedulingDecision {
+                process_index: index,
+                timeslice_us: Some(self.timeslice_us),
+            }
+        })
+    }
+
+    fn result(&self, reason: StoppedExecutingReason) {
+        // An interrupted process is preferred over other processes of the same
+        // priority when the kernel is done servicing the interrupt.
+        if reason == StoppedExecutingReason::KernelPreemption {
+            self.start.set(self.last.get());
+        } else {
+            self.start.set(self.last.get() + 1);
+        }
+    }
+}
+
+/// Cooperative scheduler.
+///
+/// Processes are selected round-robin but are never preempted by the system
+/// tick timer. A process keeps running until it yields.
+pub struct CooperativeScheduler {
+    cursor: Cell<usize>,
+}
+
+impl CooperativeScheduler {
+    pub fn new() -> CooperativeScheduler {
+        CooperativeScheduler {
+            cursor: Cell::new(0),
+        }
+    }
+}
+
+impl Scheduler for CooperativeScheduler {
+    fn next(&self, kernel: &Kernel) -> Option<SchedulingDecision> {
+        next_ready_from(kernel, self.cursor.get()).map(|index| {
+            self.cursor.set(index);
+            SchedulingDecision {
+                process_index: index,
+                timeslice_us: None,
+            }
+        })
+    }
+
+    fn result(&self, reason: StoppedExecutingReason) {
+        if reason != StoppedExecutingReason::KernelPreemption {
+            self.cursor.set(self.cursor.get() + 1);
+        }
+    }
+}
@@ -2,6 +2,7 @@
 
 use core::fmt::Write;
 
+use debug;
 use process;
 
 /// The syscall number assignments.
@@ -47,6 +48,31 @@ pub enum Syscall {
     ///
     /// SVC_NUM = 4
     MEMOP { operand: usize, arg0: usize },
+
+    /// Share a read-only memory buffer, which may be in flash, with the
+    /// kernel.
+    ///
+    /// SVC_NUM = 5
+    #[allow(non_camel_case_types)]
+    ALLOW_READONLY {
+        driver_number: usize,
+        subdriver_number: usize,
+        allow_address: *const u8,
+        allow_size: usize,
+    },
+}
+
+impl Syscall {
+    /// The driver the syscall is for, if it is for a driver.
+    pub fn driver_number(&self) -> Option<usize> {
+        match *self {
+            Syscall::SUBSCRIBE { driver_number, .. } => Some(driver_number),
+            Syscall::COMMAND { driver_number, .. } => Some(driver_number),
+            Syscall::ALLOW { driver_number, .. } => Some(driver_number),
+            Syscall::ALLOW_READONLY { driver_number, .. } => Some(driver_number),
+            _ => None,
+        }
+    }
 }
 
 /// Why the process stopped executing and execution returned to the kernel.
@@ -123,6 +149,14 @@ pub trait UserspaceKernelBoundary {
     /// Display any general information about the fault.
     unsafe fn fault_fmt(&self, writer: &mut Write);
 
+    /// Returns the registers that describe the last fault of a process
+    /// identified by its stack pointer, for the crash log.
+    unsafe fn fault_registers(
+        &self,
+        stack_pointer: *const usize,
+        state: &Self::StoredState,
+    ) -> debug::FaultRegisters;
+
     /// Display architecture specific (e.g. CPU registers or status flags) data
     /// for a process identified by its stack pointer.
     unsafe fn process_detail_fmt(
@@ -2,6 +2,8 @@
 
 use core::{mem, slice, str};
 
+use process::{AppCredentials, FaultResponse};
+
 /// Takes a value and rounds it up to be aligned % 4
 macro_rules! align4 {
     ($e:expr) => {
@@ -28,7 +30,13 @@ crate enum TbfHeaderTypes {
     TbfHeaderMain = 1,
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
-    Unused = 5,
+    TbfHeaderPriority = 5,
+    TbfHeaderFaultResponse = 6,
+    TbfHeaderCredentials = 7,
+    TbfHeaderPermittedDrivers = 8,
+    TbfHeaderGrantReserve = 9,
+    TbfHeaderCallbackQueue = 10,
+    Unused = 11,
 }
 
 /// The TLV header (T and L).
@@ -62,6 +70,69 @@ crate struct TbfHeaderV2WriteableFlashRegion {
     writeable_flash_region_size: u32,
 }
 
+/// Scheduling priority of the app.
+///
+/// Used by schedulers that support priorities. Lower values are higher
+/// priority.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2Priority {
+    priority: u32,
+}
+
+/// How the kernel should respond when the app faults.
+///
+/// `response` selects the `FaultResponse` variant, and the other fields are
+/// only used by the variants that need them.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2FaultResponse {
+    response: u32,
+    max_restarts: u32,
+    initial_delay_ms: u32,
+}
+
+/// A driver the app is allowed to use, and the range of commands (inclusive)
+/// it may call on that driver.
+///
+/// There can be multiple entries, including for the same driver, so this is
+/// its own struct.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2PermittedDriver {
+    driver_number: u32,
+    min_command: u32,
+    max_command: u32,
+}
+
+/// Grant memory the kernel keeps free for the app.
+///
+/// The app cannot grow its heap into the last `reserved_size` bytes of grant
+/// space, so capsules can always allocate at least that much grant memory for
+/// the app.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2GrantReserve {
+    reserved_size: u32,
+}
+
+/// How many callbacks the kernel can queue for the app.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2CallbackQueue {
+    queue_length: u32,
+}
+
+/// Hash or signature over the app, used to decide whether the app may run.
+///
+/// The TLV is a `format` word followed by the credential data. See
+/// `AppCredentials` for the supported formats.
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2Credentials {
+    format: u32,
+    data: &'static [u8],
+}
+
 /// Single header that can contain all parts of a v2 header.
 #[derive(Clone, Copy, Debug)]
 crate struct TbfHeaderV2 {
@@ -69,6 +140,12 @@ crate struct TbfHeaderV2 {
     main: Option<&'static TbfHeaderV2Main>,
     package_name: Option<&'static str>,
     writeable_regions: Option<&'static [TbfHeaderV2WriteableFlashRegion]>,
+    priority: Option<&'static TbfHeaderV2Priority>,
+    fault_response: Option<&'static TbfHeaderV2FaultResponse>,
+    credentials: Option<TbfHeaderV2Credentials>,
+    permitted_drivers: Option<&'static [TbfHeaderV2PermittedDriver]>,
+    grant_reserve: Option<&'static TbfHeaderV2GrantReserve>,
+    callback_queue: Option<&'static TbfHeaderV2CallbackQueue>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -152,6 +229,131 @@ impl TbfHeader {
         }
     }
 
+    /// Get the scheduling priority of the app. Apps that do not specify a
+    /// priority get the lowest possible priority.
+    crate fn get_priority(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.priority.map_or(u32::max_value(), |p| p.priority),
+            _ => u32::max_value(),
+        }
+    }
+
+    /// Get the fault response the app asked for, if it specified a valid one.
+    crate fn get_fault_response(&self) -> Option<FaultResponse> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.fault_response.and_then(|fr| match fr.response {
+                0 => Some(FaultResponse::Panic),
+                1 => Some(FaultResponse::Restart),
+                2 => Some(FaultResponse::RestartWithLimit {
+                    max_restarts: fr.max_restarts as usize,
+                }),
+                3 => Some(FaultResponse::RestartWithBackoff {
+                    max_restarts: fr.max_restarts as usize,
+                    initial_delay_ms: fr.initial_delay_ms,
+                }),
+                4 => Some(FaultResponse::Stop),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get the number of bytes of grant memory the app asked the kernel to
+    /// reserve. Apps without a reservation get 0.
+    crate fn get_grant_reserve_size(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.grant_reserve.map_or(0, |gr| gr.reserved_size),
+            _ => 0,
+        }
+    }
+
+    /// Get how many callbacks the app wants the kernel to be able to queue
+    /// for it, if it specified a queue length.
+    crate fn get_callback_queue_length(&self) -> Option<u32> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.callback_queue.map(|cq| cq.queue_length),
+            _ => None,
+        }
+    }
+
+    /// Get the credentials the app was signed with, if it has credentials in
+    /// a supported format.
+    crate fn get_credentials(&self) -> Option<AppCredentials> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.credentials.and_then(|c| {
+                let credentials = match c.format {
+                    1 => AppCredentials::Sha256(c.data),
+                    2 => AppCredentials::Ed25519(c.data),
+                    3 => AppCredentials::EcdsaP256(c.data),
+                    _ => return None,
+                };
+                if c.data.len() == credentials.len() {
+                    Some(credentials)
+                } else {
+                    None
+                }
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get the parts of the app that its credentials cover: the entire app
+    /// except the header checksum and the credential data itself, as those
+    /// can only be filled in after signing.
+    ///
+    /// Returns empty regions if the app has no credentials.
+    crate fn get_signed_regions(&self) -> [&'static [u8]; 3] {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.credentials.map_or([&[], &[], &[]], |c| unsafe {
+                let start = hd.base as *const TbfHeaderV2Base as *const u8;
+                let checksum_offset = 12;
+                let data_start = c.data.as_ptr() as usize - start as usize;
+                let data_end = data_start + align4!(c.data.len());
+                let total_size = hd.base.total_size as usize;
+                [
+                    slice::from_raw_parts(start, checksum_offset),
+                    slice::from_raw_parts(
+                        start.offset(checksum_offset as isize + 4),
+                        data_start - checksum_offset - 4,
+                    ),
+                    slice::from_raw_parts(
+                        start.offset(data_end as isize),
+                        total_size.saturating_sub(data_end),
+                    ),
+                ]
+            }),
+            _ => [&[], &[], &[]],
+        }
+    }
+
+    /// Return whether the app may use the driver with number `driver_number`.
+    /// Apps that do not list permitted drivers may use all drivers.
+    crate fn driver_permitted(&self, driver_number: usize) -> bool {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.permitted_drivers.map_or(true, |pd| {
+                pd.iter().any(|d| d.driver_number as usize == driver_number)
+            }),
+            _ => false,
+        }
+    }
+
+    /// Return whether the app may call command `command_number` of the driver
+    /// with number `driver_number`. Command 0, which checks whether the driver
+    /// exists, is permitted for every permitted driver.
+    crate fn command_permitted(&self, driver_number: usize, command_number: usize) -> bool {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.permitted_drivers.map_or(true, |pd| {
+                pd.iter().any(|d| {
+                    d.driver_number as usize == driver_number
+                        && (command_number == 0
+                            || (d.min_command as usize <= command_number
+                                && command_number <= d.max_command as usize))
+                })
+            }),
+            _ => false,
+        }
+    }
+
     /// Get the number of flash regions this app has specified in its header.
     crate fn number_writeable_flash_regions(&self) -> usize {
         match *self {
@@ -178,6 +380,24 @@ impl TbfHeader {
     }
 }
 
+/// Returns the header size and the total size of the app from the base of a
+/// TBF header, without validating the header, or `None` if the header is not
+/// a version this kernel can parse.
+crate unsafe fn get_tbf_header_sizes(address: *const u8) -> Option<(usize, usize)> {
+    let version = *(address as *const u16);
+
+    match version {
+        2 => {
+            let tbf_header_base = &*(address as *const TbfHeaderV2Base);
+            Some((
+                tbf_header_base.header_size as usize,
+                tbf_header_base.total_size as usize,
+            ))
+        }
+        _ => None,
+    }
+}
+
 /// Converts a pointer to memory to a TbfHeader struct
 ///
 /// This function takes a pointer to arbitrary memory and optionally returns a
@@ -246,6 +466,14 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
                     &'static [TbfHeaderV2WriteableFlashRegion],
                 > = None;
                 let mut app_name_str = "";
+                let mut priority_pointer: Option<&TbfHeaderV2Priority> = None;
+                let mut fault_response_pointer: Option<&TbfHeaderV2FaultResponse> = None;
+                let mut credentials: Option<TbfHeaderV2Credentials> = None;
+                let mut permitted_drivers_pointer: Option<
+                    &'static [TbfHeaderV2PermittedDriver],
+                > = None;
+                let mut grant_reserve_pointer: Option<&TbfHeaderV2GrantReserve> = None;
+                let mut callback_queue_pointer: Option<&TbfHeaderV2CallbackQueue> = None;
 
                 // Loop through the header looking for known options.
                 while remaining_length > mem::size_of::<TbfHeaderTlv>() {
@@ -304,6 +532,89 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
                                         });
                                 }
                             }
+                            TbfHeaderTypes::TbfHeaderPriority =>
+                            /* Priority */
+                            {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2Priority>()
+                                    && tbf_tlv_header.length as usize
+                                        == mem::size_of::<TbfHeaderV2Priority>()
+                                {
+                                    let tbf_priority =
+                                        &*(address.offset(offset) as *const TbfHeaderV2Priority);
+                                    priority_pointer = Some(tbf_priority);
+                                }
+                            }
+                            TbfHeaderTypes::TbfHeaderFaultResponse =>
+                            /* Fault Response */
+                            {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2FaultResponse>()
+                                    && tbf_tlv_header.length as usize
+                                        == mem::size_of::<TbfHeaderV2FaultResponse>()
+                                {
+                                    let tbf_fault_response = &*(address.offset(offset)
+                                        as *const TbfHeaderV2FaultResponse);
+                                    fault_response_pointer = Some(tbf_fault_response);
+                                }
+                            }
+                            TbfHeaderTypes::TbfHeaderCredentials =>
+                            /* Credentials */
+                            {
+                                if remaining_length >= tbf_tlv_header.length as usize
+                                    && tbf_tlv_header.length as usize >= mem::size_of::<u32>()
+                                {
+                                    let format = *(address.offset(offset) as *const u32);
+                                    let data = slice::from_raw_parts(
+                                        address.offset(offset + mem::size_of::<u32>() as isize),
+                                        tbf_tlv_header.length as usize - mem::size_of::<u32>(),
+                                    );
+                                    credentials = Some(TbfHeaderV2Credentials {
+                                        format: format,
+                                        data: data,
+                                    });
+                                }
+                            }
+                            TbfHeaderTypes::TbfHeaderPermittedDrivers =>
+                            /* Permitted Drivers */
+                            {
+                                // Length must be a multiple of the size of an entry.
+                                if remaining_length >= tbf_tlv_header.length as usize
+                                    && tbf_tlv_header.length as usize
+                                        % mem::size_of::<TbfHeaderV2PermittedDriver>()
+                                        == 0
+                                {
+                                    let number_drivers = tbf_tlv_header.length as usize
+                                        / mem::size_of::<TbfHeaderV2PermittedDriver>();
+                                    let drivers_start = &*(address.offset(offset)
+                                        as *const TbfHeaderV2PermittedDriver);
+                                    let drivers =
+                                        slice::from_raw_parts(drivers_start, number_drivers);
+                                    permitted_drivers_pointer = Some(drivers);
+                                }
+                            }
+                            TbfHeaderTypes::TbfHeaderGrantReserve =>
+                            /* Grant Reserve */
+                            {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2GrantReserve>()
+                                    && tbf_tlv_header.length as usize
+                                        == mem::size_of::<TbfHeaderV2GrantReserve>()
+                                {
+                                    let tbf_grant_reserve = &*(address.offset(offset)
+                                        as *const TbfHeaderV2GrantReserve);
+                                    grant_reserve_pointer = Some(tbf_grant_reserve);
+                                }
+                            }
+                            TbfHeaderTypes::TbfHeaderCallbackQueue =>
+                            /* Callback Queue */
+                            {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2CallbackQueue>()
+                                    && tbf_tlv_header.length as usize
+                                        == mem::size_of::<TbfHeaderV2CallbackQueue>()
+                                {
+                                    let tbf_callback_queue = &*(address.offset(offset)
+                                        as *const TbfHeaderV2CallbackQueue);
+                                    callback_queue_pointer = Some(tbf_callback_queue);
+                                }
+                            }
                             TbfHeaderTypes::Unused => {}
                         }
                     }
@@ -319,6 +630,12 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
                     main: main_pointer,
                     package_name: Some(app_name_str),
                     writeable_regions: wfr_pointer,
+                    priority: priority_pointer,
+                    fault_response: fault_response_pointer,
+                    credentials: credentials,
+                    permitted_drivers: permitted_drivers_pointer,
+                    grant_reserve: grant_reserve_pointer,
+                    callback_queue: callback_queue_pointer,
                 };
 
                 Some(TbfHeader::TbfHeaderV2(tbf_header))
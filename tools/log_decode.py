@@ -0,0 +1,206 @@
+#!/usr/bin/env python3
+#
+# usage: log_decode.py [-h] elf [input]
+#
+# Expand the binary log records that `kernel::log` writes with
+# `Format::Binary`.
+#
+# positional arguments:
+#   elf     Kernel ELF file for the board that produced the log
+#   input   File with the captured output (default: stdin)
+#
+# Bytes outside of records, such as `debug!` output, are passed through
+# unchanged.
+#
+# Examples:
+#   Decode a capture
+#     log_decode.py target/thumbv7em-none-eabi/release/hail capture.bin
+#
+#   Decode the console of a board as it runs
+#     stty -F /dev/ttyUSB0 115200 raw && \
+#       log_decode.py target/thumbv7em-none-eabi/release/hail /dev/ttyUSB0
+
+import argparse
+import struct
+import sys
+
+RECORD_START = 0xa5
+RECORD_MESSAGE = 0
+RECORD_DROPPED = 1
+
+LEVELS = {1: 'ERROR', 2: 'WARN', 3: 'INFO', 4: 'DEBUG', 5: 'TRACE'}
+
+PT_LOAD = 1
+
+
+class Elf(object):
+    """Reads memory of a 32-bit little-endian ELF file by address."""
+
+    def __init__(self, path):
+        with open(path, 'rb') as f:
+            self.data = f.read()
+        if self.data[:4] != b'\x7fELF' or self.data[4] != 1 or self.data[5] != 1:
+            raise ValueError('{} is not a 32-bit little-endian ELF file'.format(path))
+        (phoff,) = struct.unpack_from('<I', self.data, 28)
+        phentsize, phnum = struct.unpack_from('<HH', self.data, 42)
+        self.segments = []
+        for i in range(phnum):
+            (p_type, p_offset, p_vaddr, _, p_filesz) = struct.unpack_from(
+                '<IIIII', self.data, phoff + i * phentsize)
+            if p_type == PT_LOAD:
+                self.segments.append((p_vaddr, p_offset, p_filesz))
+
+    def read(self, address, length):
+        for (vaddr, offset, size) in self.segments:
+            if vaddr <= address and address + length <= vaddr + size:
+                start = offset + address - vaddr
+                return self.data[start:start + length]
+        raise ValueError('address {:#x} is not in the ELF file'.format(address))
+
+    def read_str(self, address):
+        (ptr, length) = struct.unpack('<II', self.read(address, 8))
+        return self.read(ptr, length).decode('utf-8', 'replace')
+
+
+class Site(object):
+    """A `LogSite` from the kernel."""
+
+    def __init__(self, elf, address):
+        self.fmt = elf.read_str(address)
+        self.module_path = elf.read_str(address + 8)
+        self.file = elf.read_str(address + 16)
+        (self.line, level) = struct.unpack('<IB', elf.read(address + 24, 5))
+        self.level = LEVELS.get(level, str(level))
+
+
+def format_arg(spec, arg):
+    if isinstance(arg, int) and not isinstance(arg, bool):
+        if spec == 'x':
+            return '{:x}'.format(arg)
+        if spec == 'X':
+            return '{:X}'.format(arg)
+        if spec == '#x':
+            return '{:#x}'.format(arg)
+    if isinstance(arg, bool):
+        return 'true' if arg else 'false'
+    return str(arg)
+
+
+def format_message(fmt, args):
+    """Expand the placeholders in `fmt` the same way the kernel does."""
+    out = []
+    args = iter(args)
+    i = 0
+    while i < len(fmt):
+        c = fmt[i]
+        if c in '{}' and fmt[i + 1:i + 2] == c:
+            out.append(c)
+            i += 2
+        elif c == '{':
+            end = fmt.find('}', i)
+            end = len(fmt) if end < 0 else end
+            spec = fmt[i + 1:end].split(':')[-1]
+            arg = next(args, None)
+            if arg is not None:
+                out.append(format_arg(spec, arg))
+            i = end + 1
+        else:
+            out.append(c)
+            i += 1
+    return ''.join(out)
+
+
+def unsigned_to_signed(value, bits):
+    return value - (1 << bits) if value & (1 << (bits - 1)) else value
+
+
+class Reader(object):
+    def __init__(self, stream):
+        self.stream = stream
+
+    def bytes(self, length):
+        data = self.stream.read(length)
+        if len(data) != length:
+            raise EOFError()
+        return data
+
+    def byte(self):
+        return self.bytes(1)[0]
+
+    def word(self):
+        return struct.unpack('<I', self.bytes(4))[0]
+
+    def double_word(self):
+        return struct.unpack('<Q', self.bytes(8))[0]
+
+    def arg(self):
+        tag = self.byte()
+        if tag == 0:
+            return self.word()
+        if tag == 1:
+            return unsigned_to_signed(self.word(), 32)
+        if tag == 2:
+            return self.double_word()
+        if tag == 3:
+            return unsigned_to_signed(self.double_word(), 64)
+        if tag == 4:
+            return self.byte() != 0
+        if tag == 5:
+            return chr(self.word())
+        if tag == 6:
+            return self.bytes(self.byte()).decode('utf-8', 'replace')
+        raise ValueError('unknown argument tag {}'.format(tag))
+
+
+def decode(elf, stream, out):
+    reader = Reader(stream)
+    sites = {}
+    text = bytearray()
+    try:
+        while True:
+            byte = reader.byte()
+            if byte != RECORD_START:
+                text.append(byte)
+                if byte == ord('\n'):
+                    out.write(text.decode('utf-8', 'replace'))
+                    out.flush()
+                    text = bytearray()
+                continue
+            if text:
+                out.write(text.decode('utf-8', 'replace'))
+                text = bytearray()
+
+            kind = reader.byte()
+            if kind == RECORD_MESSAGE:
+                address = reader.word()
+                args = [reader.arg() for _ in range(reader.byte())]
+                if address not in sites:
+                    sites[address] = Site(elf, address)
+                site = sites[address]
+                out.write('[{} {}] {}\r\n'.format(
+                    site.level, site.module_path, format_message(site.fmt, args)))
+            elif kind == RECORD_DROPPED:
+                out.write('[log] {} messages dropped\r\n'.format(reader.word()))
+            else:
+                out.write('[log] unknown record {}\r\n'.format(kind))
+            out.flush()
+    except EOFError:
+        out.write(text.decode('utf-8', 'replace'))
+
+
+def main():
+    parser = argparse.ArgumentParser(description='Decode binary kernel logs.')
+    parser.add_argument('elf', help='Kernel ELF file for the board that produced the log')
+    parser.add_argument('input', nargs='?', help='File with the captured output (default: stdin)')
+    args = parser.parse_args()
+
+    elf = Elf(args.elf)
+    if args.input:
+        with open(args.input, 'rb') as stream:
+            decode(elf, stream, sys.stdout)
+    else:
+        decode(elf, sys.stdin.buffer, sys.stdout)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,116 @@
+#!/usr/bin/env python3
+#
+# usage: tbf_sha256.py [-h] tbf [output]
+#
+# Add SHA-256 credentials (TBF element `7`, format `1`) to a TBF app image, so
+# that a kernel with `capsules::sha256_verifier::Sha256Verifier` starts it.
+#
+# positional arguments:
+#   tbf     TBF file of the app
+#   output  File to write the app with credentials to (default: overwrite tbf)
+#
+# The credentials element takes 40 bytes. They come out of the app's protected
+# region, so the app must be built with at least that much, e.g. with
+# `elf2tab --protected-region-size 64`. The app's code does not move. If the
+# app already has SHA-256 credentials, the hash is updated.
+#
+# Examples:
+#   Add credentials to an app before installing it
+#     tbf_sha256.py build/cortex-m4/cortex-m4.tbf
+
+import argparse
+import hashlib
+import struct
+import sys
+
+TLV_MAIN = 1
+TLV_CREDENTIALS = 7
+FORMAT_SHA256 = 1
+HASH_LEN = 32
+CHECKSUM_OFFSET = 12
+# TLV header, format and hash.
+CREDENTIALS_LEN = 4 + 4 + HASH_LEN
+
+
+def tlvs(image, header_size):
+    """Returns (offset of the value, type, length) of every header element."""
+    offset = 16
+    while offset + 4 <= header_size:
+        (tipe, length) = struct.unpack_from('<HH', image, offset)
+        yield (offset + 4, tipe, length)
+        offset += 4 + (length + 3) // 4 * 4
+
+
+def add_credentials(image):
+    (version, header_size, total_size) = struct.unpack_from('<HHI', image, 0)
+    if version != 2:
+        raise ValueError('only version 2 TBF headers are supported')
+    if len(image) < total_size:
+        raise ValueError('the file is shorter than the app')
+    image = bytearray(image[:total_size])
+
+    main = None
+    data_offset = None
+    for (offset, tipe, length) in tlvs(image, header_size):
+        if tipe == TLV_MAIN:
+            main = offset
+        elif tipe == TLV_CREDENTIALS:
+            (fmt,) = struct.unpack_from('<I', image, offset)
+            if fmt != FORMAT_SHA256 or length != 4 + HASH_LEN:
+                raise ValueError('the app has other credentials already')
+            data_offset = offset + 4
+    if main is None:
+        raise ValueError('the app has no main element')
+
+    if data_offset is None:
+        # Move the end of the header into the protected region, and keep the
+        # code where it is.
+        (init_fn_offset, protected_size) = struct.unpack_from('<II', image, main)
+        if protected_size < CREDENTIALS_LEN:
+            raise ValueError('the protected region is smaller than {} bytes'
+                             .format(CREDENTIALS_LEN))
+        struct.pack_into('<II', image, main, init_fn_offset - CREDENTIALS_LEN,
+                         protected_size - CREDENTIALS_LEN)
+        struct.pack_into('<HHI', image, header_size, TLV_CREDENTIALS,
+                         4 + HASH_LEN, FORMAT_SHA256)
+        data_offset = header_size + 8
+        header_size += CREDENTIALS_LEN
+        struct.pack_into('<H', image, 2, header_size)
+
+    # The hash covers the whole app except the checksum and the hash itself.
+    sha = hashlib.sha256()
+    sha.update(image[:CHECKSUM_OFFSET])
+    sha.update(image[CHECKSUM_OFFSET + 4:data_offset])
+    sha.update(image[data_offset + HASH_LEN:])
+    image[data_offset:data_offset + HASH_LEN] = sha.digest()
+
+    checksum = 0
+    for offset in range(0, header_size, 4):
+        if offset != CHECKSUM_OFFSET:
+            (word,) = struct.unpack_from('<I', image, offset)
+            checksum ^= word
+    struct.pack_into('<I', image, CHECKSUM_OFFSET, checksum)
+    return bytes(image)
+
+
+def main():
+    parser = argparse.ArgumentParser(
+        description='Add SHA-256 credentials to a TBF app image.')
+    parser.add_argument('tbf', help='TBF file of the app')
+    parser.add_argument('output', nargs='?',
+                        help='File to write the app with credentials to '
+                             '(default: overwrite tbf)')
+    args = parser.parse_args()
+
+    with open(args.tbf, 'rb') as f:
+        image = f.read()
+    try:
+        image = add_credentials(image)
+    except ValueError as e:
+        sys.exit('{}: {}'.format(args.tbf, e))
+    with open(args.output or args.tbf, 'wb') as f:
+        f.write(image)
+
+
+if __name__ == '__main__':
+    main()